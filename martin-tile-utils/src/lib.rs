@@ -14,6 +14,9 @@ pub const MAX_ZOOM: u8 = 30;
 mod decoders;
 pub use decoders::*;
 
+mod mvt;
+pub use mvt::count_mvt_features;
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct TileCoord {
     pub z: u8,
@@ -21,6 +24,27 @@ pub struct TileCoord {
     pub y: u32,
 }
 
+impl TileCoord {
+    /// Decode a Bing Maps quadkey, e.g. `"213"` is tile `3/3/5`.
+    /// Each digit from `0` to `3` selects a quadrant of the parent tile, so the length is the zoom level.
+    #[must_use]
+    pub fn from_quadkey(quadkey: &str) -> Option<Self> {
+        let z = u8::try_from(quadkey.len())
+            .ok()
+            .filter(|z| *z <= MAX_ZOOM)?;
+        let (mut x, mut y) = (0_u32, 0_u32);
+        for digit in quadkey.bytes() {
+            let quadrant = match digit {
+                b'0'..=b'3' => u32::from(digit - b'0'),
+                _ => return None,
+            };
+            x = (x << 1) | (quadrant & 1);
+            y = (y << 1) | (quadrant >> 1);
+        }
+        Some(Self { z, x, y })
+    }
+}
+
 impl Display for TileCoord {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         if f.alternate() {
@@ -340,6 +364,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_quadkey() {
+        let xyz = |z, x, y| Some(TileCoord { z, x, y });
+        assert_eq!(TileCoord::from_quadkey(""), xyz(0, 0, 0));
+        assert_eq!(TileCoord::from_quadkey("0"), xyz(1, 0, 0));
+        assert_eq!(TileCoord::from_quadkey("1"), xyz(1, 1, 0));
+        assert_eq!(TileCoord::from_quadkey("2"), xyz(1, 0, 1));
+        assert_eq!(TileCoord::from_quadkey("3"), xyz(1, 1, 1));
+        assert_eq!(TileCoord::from_quadkey("213"), xyz(3, 3, 5));
+        // the example of the Bing Maps tile system documentation
+        assert_eq!(
+            TileCoord::from_quadkey("1202102332221212"),
+            xyz(16, 35210, 21493)
+        );
+        assert_eq!(
+            TileCoord::from_quadkey(&"3".repeat(30)),
+            xyz(30, (1 << 30) - 1, (1 << 30) - 1)
+        );
+
+        assert_eq!(TileCoord::from_quadkey("124"), None);
+        assert_eq!(TileCoord::from_quadkey("1a"), None);
+        assert_eq!(TileCoord::from_quadkey(&"0".repeat(31)), None);
+    }
+
     #[test]
     fn test_tile_colrow() {
         assert_eq!((0, 0), tile_index(-180.0, 85.0511, 0));
@@ -0,0 +1,112 @@
+/// Field number of the layers of a vector tile
+const TILE_LAYERS: u64 = 3;
+/// Field number of the features of a vector tile layer
+const LAYER_FEATURES: u64 = 2;
+
+/// Count the features of all layers of a Mapbox Vector Tile, without decoding the features.
+/// Concatenated tiles, e.g. of a composite source, are counted as one tile with the layers of all of them.
+/// Returns `None` if the data is not a valid vector tile.
+#[must_use]
+pub fn count_mvt_features(data: &[u8]) -> Option<u64> {
+    let mut count = 0;
+    for_each_field(data, |field, value| {
+        if let (TILE_LAYERS, Some(layer)) = (field, value) {
+            for_each_field(layer, |field, _| {
+                if field == LAYER_FEATURES {
+                    count += 1;
+                }
+                Some(())
+            })?;
+        }
+        Some(())
+    })?;
+    Some(count)
+}
+
+/// Call `f` with the number of each field of a protobuf message, and with the value of the length-delimited ones
+fn for_each_field<'a>(
+    mut data: &'a [u8],
+    mut f: impl FnMut(u64, Option<&'a [u8]>) -> Option<()>,
+) -> Option<()> {
+    while !data.is_empty() {
+        let key = read_varint(&mut data)?;
+        let value = match key & 0x7 {
+            // varint
+            0 => {
+                read_varint(&mut data)?;
+                None
+            }
+            // 64-bit
+            1 => {
+                data = data.get(8..)?;
+                None
+            }
+            // length-delimited
+            2 => {
+                let len = usize::try_from(read_varint(&mut data)?).ok()?;
+                let (value, rest) = data.split_at_checked(len)?;
+                data = rest;
+                Some(value)
+            }
+            // 32-bit
+            5 => {
+                data = data.get(4..)?;
+                None
+            }
+            _ => None?,
+        };
+        f(key >> 3, value)?;
+    }
+    Some(())
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tile with a single layer with the given name and number of empty features
+    fn tile(name: &str, features: usize) -> Vec<u8> {
+        // version 2
+        let mut layer = vec![0x78, 0x02];
+        layer.extend([0x0a, u8::try_from(name.len()).unwrap()]);
+        layer.extend(name.as_bytes());
+        for _ in 0..features {
+            layer.extend([0x12, 0x00]);
+        }
+        // extent 4096
+        layer.extend([0x28, 0x80, 0x20]);
+        let mut tile = vec![0x1a, u8::try_from(layer.len()).unwrap()];
+        tile.extend(layer);
+        tile
+    }
+
+    #[test]
+    fn test_count_mvt_features() {
+        assert_eq!(count_mvt_features(&[]), Some(0));
+        assert_eq!(count_mvt_features(&tile("roads", 0)), Some(0));
+        assert_eq!(count_mvt_features(&tile("roads", 3)), Some(3));
+        let composite = [tile("roads", 3), tile("rivers", 2)].concat();
+        assert_eq!(count_mvt_features(&composite), Some(5));
+    }
+
+    #[test]
+    fn test_count_invalid_mvt_features() {
+        let data = tile("roads", 3);
+        assert_eq!(count_mvt_features(&data[..data.len() - 1]), None);
+        assert_eq!(count_mvt_features(b"\x89PNG"), None);
+        assert_eq!(count_mvt_features(&[0xff; 11]), None);
+    }
+}
@@ -101,7 +101,22 @@ fn webui() {
         .change_detection();
 }
 
+/// Generate the gRPC server and client of the tiles service.
+/// The schema is compiled with `protox`, so that building Martin does not require `protoc`.
+#[cfg(feature = "grpc")]
+fn grpc() {
+    let proto = "proto/tiles.proto";
+    println!("cargo:rerun-if-changed={proto}");
+    let descriptors = protox::compile([proto], ["proto"])
+        .unwrap_or_else(|e| panic!("failed to parse {proto}: {e}"));
+    tonic_build::configure()
+        .compile_fds(descriptors)
+        .expect("failed to generate the gRPC code");
+}
+
 fn main() {
     #[cfg(feature = "webui")]
     webui();
+    #[cfg(feature = "grpc")]
+    grpc();
 }
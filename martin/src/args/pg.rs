@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use crate::args::connections::Arguments;
 use crate::args::connections::State::{Ignore, Take};
 use crate::args::environment::Env;
-use crate::pg::{POOL_SIZE_DEFAULT, PgConfig, PgSslCerts};
+use crate::pg::{IntrospectedTables, POOL_SIZE_DEFAULT, PgConfig, PgSslCerts};
 use crate::utils::{OptBoolObj, OptOneMany};
 
 // Must match the help string for BoundsType::Quick
@@ -62,14 +62,25 @@ impl PgArgs {
             .into_iter()
             .map(|s| PgConfig {
                 connection_string: Some(s),
+                tenant: None,
                 ssl_certificates: certs.clone(),
                 default_srid,
                 auto_bounds: self.auto_bounds,
                 max_feature_count: self.max_feature_count,
                 pool_size: self.pool_size,
+                warmup_connections: None,
+                slow_query_threshold_ms: None,
+                slow_query_log_sql: None,
+                query_comments: None,
+                duplicate_geometry_columns: None,
+                introspection_concurrency: None,
+                empty_tables: None,
+                missing_columns: None,
+                index_recommendations: None,
                 auto_publish: OptBoolObj::NoValue,
                 tables: None,
                 functions: None,
+                introspected: IntrospectedTables::default(),
             })
             .collect();
 
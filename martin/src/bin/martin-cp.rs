@@ -12,9 +12,9 @@ use futures::TryStreamExt;
 use futures::stream::{self, StreamExt};
 use log::{debug, error, info, log_enabled};
 use martin::args::{Args, ExtraArgs, MetaArgs, OsEnv, SrvArgs};
-use martin::srv::{DynTileSource, merge_tilejson};
+use martin::srv::{CompositeBounds, DynTileSource, LayerFieldConflicts, merge_tilejson};
 use martin::{
-    Config, MartinError, MartinResult, ServerState, TileData, TileInfoSource, TileRect,
+    Config, MartinError, MartinResult, TileData, TileInfoSource, TileRect, TileScheme, TileSources,
     append_rect, read_config,
 };
 use martin_tile_utils::{TileCoord, TileInfo, bbox_to_xyz};
@@ -154,7 +154,7 @@ async fn start(copy_args: CopierArgs) -> MartinCpResult<()> {
         info!("Use --save-config to save or print configuration.");
     }
 
-    run_tile_copy(copy_args.copy, sources).await
+    run_tile_copy(copy_args.copy, &sources.tiles).await
 }
 
 fn compute_tile_ranges(args: &CopyArgs) -> Vec<TileRect> {
@@ -273,19 +273,21 @@ fn iterate_tiles(tiles: Vec<TileRect>) -> impl Iterator<Item = TileCoord> {
     })
 }
 
-async fn run_tile_copy(args: CopyArgs, state: ServerState) -> MartinCpResult<()> {
+async fn run_tile_copy(args: CopyArgs, sources: &TileSources) -> MartinCpResult<()> {
     let output_file = &args.output_file;
     let concurrency = args.concurrency.unwrap_or(1);
 
+    // The tiles to copy are computed in the XYZ scheme, whatever the scheme of the sources
     let src = DynTileSource::new(
-        &state.tiles,
+        sources,
         args.source.as_str(),
         None,
         args.url_query.as_deref().unwrap_or_default(),
         Some(parse_encoding(args.encoding.as_str())?),
         None,
         None,
-    )?;
+    )?
+    .with_scheme(TileScheme::Xyz);
     // parallel async below uses move, so we must only use copyable types
     let src = &src;
 
@@ -404,7 +406,12 @@ async fn init_schema(
             MbtTypeCli::Normalized => MbtType::Normalized { hash_view: true },
         };
         init_mbtiles_schema(&mut *conn, mbt_type).await?;
-        let mut tj = merge_tilejson(sources, String::new());
+        let mut tj = merge_tilejson(
+            sources,
+            String::new(),
+            CompositeBounds::default(),
+            LayerFieldConflicts::default(),
+        )?;
         tj.other.insert(
             "format".to_string(),
             serde_json::Value::String(tile_info.format.metadata_format_value().to_string()),
@@ -447,10 +454,73 @@ async fn main() {
 mod tests {
     use std::str::FromStr;
 
+    use async_trait::async_trait;
     use insta::assert_yaml_snapshot;
+    use martin::{Source, UrlQuery};
+    use martin_tile_utils::{Encoding, Format};
+    use tilejson::{TileJSON, tilejson};
 
     use super::*;
 
+    /// Returns the requested tile coordinates as the tile content
+    #[derive(Debug, Clone)]
+    struct XyzSource {
+        id: &'static str,
+        tj: TileJSON,
+    }
+
+    #[async_trait]
+    impl Source for XyzSource {
+        fn get_id(&self) -> &str {
+            self.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            TileInfo::new(Format::Mvt, Encoding::Uncompressed)
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            _url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            Ok(xyz.to_string().into_bytes())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_copy_tms_source() {
+        let mut tj = tilejson! { tiles: vec![] };
+        tj.scheme = Some("tms".to_string());
+        let sources = TileSources::new(vec![vec![Box::new(XyzSource { id: "tms", tj })]]);
+        let dir = tempfile::tempdir().unwrap();
+        let output_file = dir.path().join("tms.mbtiles");
+        let args = CopyArgs {
+            source: "tms".to_string(),
+            output_file: output_file.clone(),
+            zoom_levels: vec![2],
+            encoding: "identity".to_string(),
+            ..Default::default()
+        };
+        run_tile_copy(args, &sources).await.unwrap();
+
+        // each tile is stored at the XYZ coordinates it was requested with
+        let mbt = Mbtiles::new(output_file).unwrap();
+        let mut conn = mbt.open().await.unwrap();
+        for (x, y) in [(0, 0), (1, 2), (3, 1)] {
+            let tile = mbt.get_tile(&mut conn, 2, x, y).await.unwrap();
+            assert_eq!(tile, Some(format!("2,{x},{y}").into_bytes()));
+        }
+    }
+
     #[test]
     fn test_compute_tile_ranges() {
         let world = Bounds::MAX_TILED;
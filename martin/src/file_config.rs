@@ -42,6 +42,12 @@ pub enum FileError {
     #[error(r"Unable to acquire connection to file: {0}")]
     AcquireConnError(String),
 
+    #[error("Source {0} file {1} is larger than the {2} MB limit for loading into memory")]
+    InMemoryFileTooLarge(String, PathBuf, u64),
+
+    #[error(r"Unable to load the tiles of file {1} into memory: {0}")]
+    InMemoryLoadError(String, PathBuf),
+
     #[cfg(feature = "pmtiles")]
     #[error(r"PMTiles error {0} processing {1}")]
     PmtError(pmtiles::PmtError, String),
@@ -2,11 +2,12 @@
 #![forbid(unsafe_code)]
 
 mod config;
-pub use config::{Config, ServerState, read_config};
+pub use config::{Config, ReloadSummary, ServerState, SourceReloader, read_config};
 
 mod source;
 pub use source::{
-    CatalogSourceEntry, Source, Tile, TileData, TileInfoSource, TileSources, UrlQuery,
+    CatalogLink, CatalogSourceConfig, CatalogSourceEntry, Source, TILE_SIZE_DEFAULT, TILE_SIZES,
+    Tile, TileData, TileInfoSource, TileScheme, TileSources, UrlQuery,
 };
 
 mod utils;
@@ -23,6 +24,8 @@ pub mod file_config;
 pub mod fonts;
 #[cfg(feature = "mbtiles")]
 pub mod mbtiles;
+#[cfg(feature = "object_store")]
+pub mod object_store;
 #[cfg(feature = "postgres")]
 pub mod pg;
 #[cfg(feature = "pmtiles")]
@@ -1,10 +1,11 @@
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use log::trace;
+use log::{info, trace};
 use martin_tile_utils::{TileCoord, TileInfo};
 use mbtiles::MbtilesPool;
 use serde::{Deserialize, Serialize};
@@ -12,18 +13,32 @@ use tilejson::TileJSON;
 use url::Url;
 
 use crate::config::UnrecognizedValues;
-use crate::file_config::FileError::{AcquireConnError, InvalidMetadata, IoError};
+use crate::file_config::FileError::{
+    AcquireConnError, InMemoryFileTooLarge, InMemoryLoadError, InvalidMetadata, IoError,
+};
 use crate::file_config::{ConfigExtras, FileResult, SourceConfigExtras};
 use crate::source::{TileData, TileInfoSource, UrlQuery};
 use crate::{MartinResult, Source};
 
+/// Default limit for the size of an `MBTiles` file loaded into memory, in MB
+pub const IN_MEMORY_MAX_SIZE_MB_DEFAULT: u64 = 256;
+
+#[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct MbtConfig {
+    /// IDs of the sources to load entirely into memory on startup
+    pub in_memory: Option<Vec<String>>,
+    /// Refuse to load files larger than this into memory, in MB [default: 256]
+    pub in_memory_max_size_mb: Option<u64>,
     #[serde(flatten)]
     pub unrecognized: UnrecognizedValues,
 }
 
 impl ConfigExtras for MbtConfig {
+    fn is_default(&self) -> bool {
+        self.in_memory.is_none() && self.in_memory_max_size_mb.is_none()
+    }
+
     fn get_unrecognized(&self) -> &UnrecognizedValues {
         &self.unrecognized
     }
@@ -31,7 +46,16 @@ impl ConfigExtras for MbtConfig {
 
 impl SourceConfigExtras for MbtConfig {
     async fn new_sources(&self, id: String, path: PathBuf) -> FileResult<TileInfoSource> {
-        Ok(Box::new(MbtSource::new(id, path).await?))
+        if self.in_memory.iter().flatten().any(|v| *v == id) {
+            let max_size_mb = self
+                .in_memory_max_size_mb
+                .unwrap_or(IN_MEMORY_MAX_SIZE_MB_DEFAULT);
+            Ok(Box::new(
+                InMemoryMbtilesSource::new(id, path, max_size_mb).await?,
+            ))
+        } else {
+            Ok(Box::new(MbtSource::new(id, path).await?))
+        }
     }
 
     // TODO: Remove #[allow] after switching to Rust/Clippy v1.78+ in CI
@@ -122,6 +146,86 @@ impl Source for MbtSource {
     }
 }
 
+/// An `MBTiles` source with all of its tiles loaded into memory on startup
+#[derive(Clone)]
+pub struct InMemoryMbtilesSource {
+    id: String,
+    tiles: Arc<HashMap<TileCoord, TileData>>,
+    tilejson: TileJSON,
+    tile_info: TileInfo,
+}
+
+impl Debug for InMemoryMbtilesSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "InMemoryMbtilesSource {{ id: {}, tiles: {} }}",
+            self.id,
+            self.tiles.len()
+        )
+    }
+}
+
+impl InMemoryMbtilesSource {
+    async fn new(id: String, path: PathBuf, max_size_mb: u64) -> FileResult<Self> {
+        let size = std::fs::metadata(&path)
+            .map_err(|e| IoError(e, path.clone()))?
+            .len();
+        if size > max_size_mb * 1024 * 1024 {
+            return Err(InMemoryFileTooLarge(id, path, max_size_mb));
+        }
+
+        let mbt = MbtSource::new(id, path.clone()).await?;
+        let tiles = mbt
+            .mbtiles
+            .get_all_tiles()
+            .await
+            .map_err(|e| InMemoryLoadError(e.to_string(), path))?
+            .into_iter()
+            .map(|(z, x, y, data)| (TileCoord { z, x, y }, data))
+            .collect::<HashMap<_, _>>();
+        info!(
+            "Loaded {} tiles of source {} into memory",
+            tiles.len(),
+            mbt.id
+        );
+
+        Ok(Self {
+            id: mbt.id,
+            tiles: Arc::new(tiles),
+            tilejson: mbt.tilejson,
+            tile_info: mbt.tile_info,
+        })
+    }
+}
+
+#[async_trait]
+impl Source for InMemoryMbtilesSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_tilejson(&self) -> &TileJSON {
+        &self.tilejson
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        self.tile_info
+    }
+
+    fn clone_source(&self) -> TileInfoSource {
+        Box::new(self.clone())
+    }
+
+    async fn get_tile(
+        &self,
+        xyz: TileCoord,
+        _url_query: Option<&UrlQuery>,
+    ) -> MartinResult<TileData> {
+        Ok(self.tiles.get(&xyz).cloned().unwrap_or_default())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
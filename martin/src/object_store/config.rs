@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use log::info;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tilejson::Bounds;
+
+use super::ObjectStoreError::ClientError;
+use super::{ObjectStoreResult, ObjectStoreSource};
+use crate::config::{UnrecognizedValues, copy_unrecognized_config};
+use crate::source::TileInfoSources;
+use crate::utils::IdResolver;
+
+/// Timeout of each tile request to an object store in milliseconds, unless configured
+pub const OBJECT_STORE_TIMEOUT_MS_DEFAULT: u64 = 10_000;
+
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjectStoreConfig {
+    /// Timeout of each tile request in milliseconds [default: 10000]
+    pub timeout_ms: Option<u64>,
+    /// Tile sources, keyed by the source ID
+    #[serde(default)]
+    pub sources: BTreeMap<String, ObjectStoreSourceConfig>,
+    #[serde(flatten, skip_serializing)]
+    pub unrecognized: UnrecognizedValues,
+}
+
+/// A tile source reading prebuilt tiles from S3 or any HTTP server, one object per tile
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjectStoreSourceConfig {
+    /// Tile URL template with the `{z}`, `{x}`, and `{y}` placeholders,
+    /// e.g. `s3://bucket/tiles/{z}/{x}/{y}.pbf` or `https://example.org/tiles/{z}/{x}/{y}.png`
+    pub url: String,
+    /// Format of the tiles, e.g. `mvt` or `png` [default: the extension of the URL template]
+    pub format: Option<String>,
+    /// Compression of the stored tiles, e.g. `gzip` [default: none]
+    pub encoding: Option<String>,
+    /// Lowest zoom level with tiles [default: 0]
+    pub minzoom: Option<u8>,
+    /// Highest zoom level with tiles [default: 30]
+    pub maxzoom: Option<u8>,
+    /// Area covered by the tiles, in WGS84 [default: the whole world]
+    pub bounds: Option<Bounds>,
+    /// Additional request headers, e.g. `Authorization` to read from a private HTTP server
+    pub headers: Option<BTreeMap<String, String>>,
+    /// Region of the S3 bucket [default: us-east-1]
+    pub region: Option<String>,
+    /// Endpoint of an S3-compatible store, e.g. `http://localhost:9000`.
+    /// Objects are requested as `{endpoint}/{bucket}/{key}` [default: AWS S3 in the region]
+    pub endpoint: Option<String>,
+    /// Access key ID to sign S3 requests. Requests are not signed if not set.
+    pub access_key_id: Option<String>,
+    /// Secret access key to sign S3 requests
+    pub secret_access_key: Option<String>,
+    /// Session token of temporary S3 credentials
+    pub session_token: Option<String>,
+    /// Group of related sources in the catalog, e.g. `basemaps` [default: none]
+    pub group: Option<String>,
+}
+
+impl ObjectStoreConfig {
+    /// Get the unrecognized config values, prefixed with the given string
+    #[must_use]
+    pub fn finalize(&self, prefix: &str) -> UnrecognizedValues {
+        let mut res = UnrecognizedValues::new();
+        copy_unrecognized_config(&mut res, prefix, &self.unrecognized);
+        res
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Create the tile sources. Tiles are only requested when needed, so the stores are not contacted here.
+    pub fn resolve(&self, idr: &IdResolver) -> ObjectStoreResult<TileInfoSources> {
+        let timeout = self.timeout_ms.unwrap_or(OBJECT_STORE_TIMEOUT_MS_DEFAULT);
+        let client = Client::builder()
+            .timeout(Duration::from_millis(timeout))
+            .build()
+            .map_err(ClientError)?;
+
+        let mut sources = TileInfoSources::default();
+        for (id, cfg) in &self.sources {
+            let id = idr.resolve(id, cfg.url.clone());
+            info!("Configured object store source {id} from {}", cfg.url);
+            sources.push(Box::new(ObjectStoreSource::new(id, cfg, client.clone())?));
+        }
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let cfg: ObjectStoreConfig = serde_yaml::from_str(indoc! {"
+            timeout_ms: 5000
+            sources:
+              basemap:
+                url: s3://tiles/basemap/{z}/{x}/{y}.pbf
+                encoding: gzip
+                region: eu-central-1
+                maxzoom: 14
+              satellite:
+                url: https://example.org/{z}/{x}/{y}.webp
+                headers:
+                  Authorization: Bearer secret
+        "})
+        .unwrap();
+        assert_eq!(cfg.timeout_ms, Some(5000));
+        assert_eq!(
+            cfg.sources["basemap"].region.as_deref(),
+            Some("eu-central-1")
+        );
+        assert_eq!(cfg.sources["basemap"].maxzoom, Some(14));
+        assert_eq!(
+            cfg.sources["satellite"].headers.as_ref().unwrap()["Authorization"],
+            "Bearer secret"
+        );
+
+        let sources = cfg.resolve(&IdResolver::new(&[])).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].get_id(), "basemap");
+        assert_eq!(sources[0].get_tilejson().maxzoom, Some(14));
+    }
+}
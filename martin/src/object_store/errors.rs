@@ -0,0 +1,33 @@
+use martin_tile_utils::TileCoord;
+use reqwest::StatusCode;
+
+pub type ObjectStoreResult<T> = Result<T, ObjectStoreError>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ObjectStoreError {
+    #[error("Unable to create the object store HTTP client: {0}")]
+    ClientError(reqwest::Error),
+
+    #[error("Source {0} has an invalid URL template {1}: {2}")]
+    InvalidUrlTemplate(String, String, &'static str),
+
+    #[error(
+        "Source {0} has an unknown tile format {1}, use the format setting, e.g. `mvt` or `png`"
+    )]
+    UnknownFormat(String, String),
+
+    #[error("Source {0} has an unknown tile encoding {1}, e.g. `gzip` or `none`")]
+    UnknownEncoding(String, String),
+
+    #[error("Source {0} has an invalid request header {1}")]
+    InvalidHeader(String, String),
+
+    #[error("Source {0} must have both access_key_id and secret_access_key, or neither")]
+    IncompleteCredentials(String),
+
+    #[error("Unable to fetch tile {1} of source {0}: {2}")]
+    RequestError(String, TileCoord, reqwest::Error),
+
+    #[error("Unable to fetch tile {1} of source {0}: HTTP status {2}")]
+    UnexpectedStatus(String, TileCoord, StatusCode),
+}
@@ -0,0 +1,9 @@
+mod config;
+mod errors;
+mod s3;
+mod source;
+
+pub use config::{OBJECT_STORE_TIMEOUT_MS_DEFAULT, ObjectStoreConfig, ObjectStoreSourceConfig};
+pub use errors::{ObjectStoreError, ObjectStoreResult};
+pub use s3::S3Signer;
+pub use source::ObjectStoreSource;
@@ -0,0 +1,282 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use log::trace;
+use martin_tile_utils::{Encoding, Format, TileCoord, TileInfo};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, StatusCode};
+use tilejson::{TileJSON, tilejson};
+use url::Url;
+
+use super::ObjectStoreError::{
+    IncompleteCredentials, InvalidHeader, InvalidUrlTemplate, RequestError, UnexpectedStatus,
+    UnknownEncoding, UnknownFormat,
+};
+use super::{ObjectStoreResult, ObjectStoreSourceConfig, S3Signer};
+use crate::source::{TileInfoSource, UrlQuery};
+use crate::{MartinResult, Source, TileData};
+
+#[derive(Clone, Debug)]
+pub struct ObjectStoreSource {
+    id: String,
+    client: Client,
+    /// HTTP URL template of the tiles, with S3 URLs already converted to their HTTP endpoint
+    url_template: String,
+    headers: HeaderMap,
+    signer: Option<S3Signer>,
+    tilejson: TileJSON,
+    tile_info: TileInfo,
+    group: Option<String>,
+}
+
+impl ObjectStoreSource {
+    pub fn new(
+        id: String,
+        cfg: &ObjectStoreSourceConfig,
+        client: Client,
+    ) -> ObjectStoreResult<Self> {
+        let err = |msg| InvalidUrlTemplate(id.clone(), cfg.url.clone(), msg);
+        if ["{z}", "{x}", "{y}"].iter().any(|v| !cfg.url.contains(v)) {
+            return Err(err("must contain the {z}, {x}, and {y} placeholders"));
+        }
+
+        let (url_template, signer) = if let Some(path) = cfg.url.strip_prefix("s3://") {
+            let Some((bucket, key)) = path.split_once('/').filter(|(b, _)| !b.is_empty()) else {
+                return Err(err("must contain a bucket name and a key"));
+            };
+            let region = cfg.region.as_deref().unwrap_or("us-east-1");
+            let url_template = match &cfg.endpoint {
+                Some(endpoint) => format!("{}/{bucket}/{key}", endpoint.trim_end_matches('/')),
+                // Bucket names with dots do not match the TLS certificate of virtual-hosted URLs
+                None if bucket.contains('.') => {
+                    format!("https://s3.{region}.amazonaws.com/{bucket}/{key}")
+                }
+                None => format!("https://{bucket}.s3.{region}.amazonaws.com/{key}"),
+            };
+            let signer = match (&cfg.access_key_id, &cfg.secret_access_key) {
+                (Some(key_id), Some(secret)) => Some(S3Signer::new(
+                    key_id.clone(),
+                    secret.clone(),
+                    cfg.session_token.clone(),
+                    region.to_string(),
+                )),
+                (None, None) => None,
+                _ => return Err(IncompleteCredentials(id)),
+            };
+            (url_template, signer)
+        } else if cfg.url.starts_with("http://") || cfg.url.starts_with("https://") {
+            (cfg.url.clone(), None)
+        } else {
+            return Err(err("must begin with s3://, http://, or https://"));
+        };
+
+        let format = if let Some(format) = &cfg.format {
+            Format::parse(format).ok_or_else(|| UnknownFormat(id.clone(), format.clone()))?
+        } else {
+            let path = url_template.split('?').next().unwrap_or_default();
+            let extension = path.rsplit_once('.').map_or("", |(_, ext)| ext);
+            Format::parse(extension)
+                .ok_or_else(|| UnknownFormat(id.clone(), extension.to_string()))?
+        };
+        let encoding = match &cfg.encoding {
+            Some(encoding) => Encoding::parse(encoding)
+                .ok_or_else(|| UnknownEncoding(id.clone(), encoding.clone()))?,
+            None => Encoding::Uncompressed,
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in cfg.headers.iter().flatten() {
+            let name =
+                HeaderName::try_from(name).map_err(|_| InvalidHeader(id.clone(), name.clone()))?;
+            let value = HeaderValue::try_from(value)
+                .map_err(|_| InvalidHeader(id.clone(), name.to_string()))?;
+            headers.insert(name, value);
+        }
+
+        let mut tilejson = tilejson! { tiles: vec![] };
+        tilejson.minzoom = cfg.minzoom;
+        tilejson.maxzoom = cfg.maxzoom;
+        tilejson.bounds = cfg.bounds;
+
+        Ok(Self {
+            id,
+            client,
+            url_template,
+            headers,
+            signer,
+            tilejson,
+            tile_info: TileInfo::new(format, encoding),
+            group: cfg.group.clone(),
+        })
+    }
+
+    fn get_tile_url(&self, xyz: TileCoord) -> String {
+        self.url_template
+            .replace("{z}", &xyz.z.to_string())
+            .replace("{x}", &xyz.x.to_string())
+            .replace("{y}", &xyz.y.to_string())
+    }
+
+    async fn fetch_tile(&self, xyz: TileCoord) -> ObjectStoreResult<TileData> {
+        let url = self.get_tile_url(xyz);
+        let mut request = self.client.get(&url).headers(self.headers.clone());
+        if let Some(signer) = &self.signer {
+            let url = Url::parse(&url)
+                .map_err(|_| InvalidUrlTemplate(self.id.clone(), url.clone(), "invalid URL"))?;
+            for (name, value) in signer.get_headers(&url, Utc::now()) {
+                request = request.header(name, value);
+            }
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| RequestError(self.id.clone(), xyz, e))?;
+        match response.status() {
+            // Missing tiles are empty, and are cached the same way as any other tile
+            StatusCode::NOT_FOUND | StatusCode::NO_CONTENT => {
+                trace!("Tile {xyz} of {} is not in the object store", self.id);
+                Ok(Vec::new())
+            }
+            status if status.is_success() => Ok(response
+                .bytes()
+                .await
+                .map_err(|e| RequestError(self.id.clone(), xyz, e))?
+                .to_vec()),
+            status => Err(UnexpectedStatus(self.id.clone(), xyz, status)),
+        }
+    }
+}
+
+#[async_trait]
+impl Source for ObjectStoreSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_tilejson(&self) -> &TileJSON {
+        &self.tilejson
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        self.tile_info
+    }
+
+    fn clone_source(&self) -> TileInfoSource {
+        Box::new(self.clone())
+    }
+
+    fn get_group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    async fn get_tile(
+        &self,
+        xyz: TileCoord,
+        _url_query: Option<&UrlQuery>,
+    ) -> MartinResult<TileData> {
+        Ok(self.fetch_tile(xyz).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::web::Path;
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+
+    use super::*;
+
+    fn source(url: &str) -> ObjectStoreResult<ObjectStoreSource> {
+        let cfg = ObjectStoreSourceConfig {
+            url: url.to_string(),
+            ..Default::default()
+        };
+        ObjectStoreSource::new("src".to_string(), &cfg, Client::new())
+    }
+
+    #[test]
+    fn url_templates() {
+        let src = source("s3://bucket/tiles/{z}/{x}/{y}.pbf").unwrap();
+        assert_eq!(
+            src.get_tile_url(TileCoord { z: 1, x: 2, y: 3 }),
+            "https://bucket.s3.us-east-1.amazonaws.com/tiles/1/2/3.pbf"
+        );
+        assert_eq!(
+            src.tile_info,
+            TileInfo::new(Format::Mvt, Encoding::Uncompressed)
+        );
+        assert!(src.signer.is_none());
+
+        // path-style URLs for bucket names with dots
+        let src = source("s3://tiles.example.org/{z}/{x}/{y}.pbf").unwrap();
+        assert_eq!(
+            src.get_tile_url(TileCoord { z: 1, x: 2, y: 3 }),
+            "https://s3.us-east-1.amazonaws.com/tiles.example.org/1/2/3.pbf"
+        );
+
+        let cfg = ObjectStoreSourceConfig {
+            url: "s3://bucket/{z}/{x}/{y}".to_string(),
+            format: Some("png".to_string()),
+            endpoint: Some("http://localhost:9000/".to_string()),
+            access_key_id: Some("key".to_string()),
+            secret_access_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let src = ObjectStoreSource::new("src".to_string(), &cfg, Client::new()).unwrap();
+        assert_eq!(
+            src.get_tile_url(TileCoord { z: 1, x: 2, y: 3 }),
+            "http://localhost:9000/bucket/1/2/3"
+        );
+        assert_eq!(src.tile_info.format, Format::Png);
+        assert!(src.signer.is_some());
+
+        let src = source("https://example.org/{z}/{x}/{y}.webp?key=1").unwrap();
+        assert_eq!(src.tile_info.format, Format::Webp);
+
+        assert!(source("https://example.org/{z}/{x}.png").is_err());
+        assert!(source("ftp://example.org/{z}/{x}/{y}.png").is_err());
+        assert!(source("s3:///{z}/{x}/{y}.png").is_err());
+        assert!(source("https://example.org/{z}/{x}/{y}").is_err());
+    }
+
+    async fn tile(path: Path<(u8, u32, u32)>, req: HttpRequest) -> HttpResponse {
+        if req.headers().get("x-api-key").is_none_or(|v| v != "secret") {
+            return HttpResponse::Forbidden().finish();
+        }
+        match path.into_inner() {
+            (0, 0, 0) => HttpResponse::Ok().body(vec![1_u8, 2, 3]),
+            (1, _, _) => HttpResponse::InternalServerError().finish(),
+            _ => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn fetch_tiles() {
+        let server = HttpServer::new(|| App::new().route("/{z}/{x}/{y}.pbf", web::get().to(tile)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let addr = server.addrs()[0];
+        actix_rt::spawn(server.run());
+
+        let url = format!("http://{addr}/{{z}}/{{x}}/{{y}}.pbf");
+        let cfg = ObjectStoreSourceConfig {
+            url: url.clone(),
+            headers: Some([("x-api-key".to_string(), "secret".to_string())].into()),
+            ..Default::default()
+        };
+        let src = ObjectStoreSource::new("src".to_string(), &cfg, Client::new()).unwrap();
+        let get = |z, x, y| src.fetch_tile(TileCoord { z, x, y });
+        assert_eq!(get(0, 0, 0).await.unwrap(), vec![1_u8, 2, 3]);
+        assert_eq!(get(2, 0, 0).await.unwrap(), Vec::<u8>::new());
+        assert!(matches!(
+            get(1, 0, 0).await,
+            Err(UnexpectedStatus(_, _, StatusCode::INTERNAL_SERVER_ERROR))
+        ));
+
+        let src = source(&url).unwrap();
+        assert!(matches!(
+            src.fetch_tile(TileCoord { z: 0, x: 0, y: 0 }).await,
+            Err(UnexpectedStatus(_, _, StatusCode::FORBIDDEN))
+        ));
+    }
+}
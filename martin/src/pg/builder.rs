@@ -1,29 +1,58 @@
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use futures::future::join_all;
 use itertools::Itertools as _;
 use log::{debug, error, info, warn};
 
 use crate::OptBoolObj::{Bool, NoValue, Object};
 use crate::args::BoundsCalcType;
-use crate::pg::PgError::InvalidTableExtent;
-use crate::pg::config::{PgConfig, PgInfo};
+use crate::pg::PgError::{
+    ConflictingTablePrecision, InvalidQueryLimit, InvalidTableExtent, InvalidTablePrecision,
+};
+use crate::pg::config::{
+    DuplicateGeometryPolicy, EmptyTablePolicy, MissingColumnPolicy, PgConfig, PgInfo,
+};
 use crate::pg::config_function::{FuncInfoSources, FunctionInfo};
-use crate::pg::config_table::{TableInfo, TableInfoSources};
-use crate::pg::pg_source::{PgSource, PgSqlInfo};
-use crate::pg::pool::PgPool;
+use crate::pg::config_table::{MAX_PRECISION, MIN_PRECISION, TableInfo, TableInfoSources};
+use crate::pg::matview::Matview;
+use crate::pg::pg_source::{PgSource, PgSqlInfo, SlowQueryLog};
+use crate::pg::pool::{POOL_SIZE_DEFAULT, PgPool};
 use crate::pg::query_functions::query_available_function;
-use crate::pg::query_tables::{query_available_tables, table_to_query};
-use crate::pg::utils::{InfoMap, find_info, find_kv_ignore_case, normalize_key};
+use crate::pg::query_tables::{
+    query_available_tables, spatial_index_recommendation, table_to_query,
+};
+use crate::pg::utils::{InfoMap, find_info, find_kv_ignore_case, join_all_limited, normalize_key};
 use crate::pg::{PgCfgPublish, PgCfgPublishFuncs, PgResult};
-use crate::source::TileInfoSources;
-use crate::utils::IdResolver;
+use crate::source::{TileInfoSources, UrlQuery};
 use crate::utils::OptOneMany::NoVals;
+use crate::utils::{IdResolver, QueryLimit};
 
 pub type SqlFuncInfoMapMap = InfoMap<InfoMap<(PgSqlInfo, FunctionInfo)>>;
 pub type SqlTableInfoMapMapMap = InfoMap<InfoMap<InfoMap<TableInfo>>>;
 
+/// Results of the introspection of the table sources of a connection, i.e. their SQL queries and bounds, by source ID.
+/// Resolving the sources again, e.g. with `/reload`, reuses the results of the tables whose configured
+/// and discovered info did not change, so that their bounds are not computed again. Clones share the same results.
+#[derive(Clone, Debug, Default)]
+pub struct IntrospectedTables(Arc<Mutex<HashMap<String, IntrospectedTable>>>);
+
+#[derive(Debug)]
+struct IntrospectedTable {
+    /// Table info before the introspection, and the options of the builder used by the introspection
+    input: (TableInfo, BoundsCalcType, Option<usize>, bool),
+    sql_info: PgSqlInfo,
+    info: TableInfo,
+}
+
+/// The results are not part of the configuration, so they never make two configurations different
+impl PartialEq for IntrospectedTables {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
 /// A builder for creating a set of sources from a Postgres database
 #[derive(Debug)]
 pub struct PgBuilder {
@@ -31,11 +60,19 @@ pub struct PgBuilder {
     default_srid: Option<i32>,
     auto_bounds: BoundsCalcType,
     max_feature_count: Option<usize>,
+    slow_query: Option<SlowQueryLog>,
+    query_comments: bool,
+    duplicate_geometry_columns: DuplicateGeometryPolicy,
+    empty_tables: EmptyTablePolicy,
+    missing_columns: MissingColumnPolicy,
+    index_recommendations: bool,
+    introspection_concurrency: usize,
     auto_functions: Option<PgBuilderFuncs>,
     auto_tables: Option<PgBuilderTables>,
     id_resolver: IdResolver,
     tables: TableInfoSources,
     functions: FuncInfoSources,
+    introspected: IntrospectedTables,
 }
 
 #[derive(Debug, PartialEq)]
@@ -79,6 +116,13 @@ macro_rules! get_auto_schemas {
 
 impl PgBuilder {
     pub async fn new(config: &PgConfig, id_resolver: IdResolver) -> PgResult<Self> {
+        for (id, inf) in config.tables.iter().flatten() {
+            validate_query_limit(id, inf.max_concurrent_queries, inf.max_queued_queries)?;
+        }
+        for (id, inf) in config.functions.iter().flatten() {
+            validate_query_limit(id, inf.max_concurrent_queries, inf.max_queued_queries)?;
+        }
+
         let pool = PgPool::new(config).await?;
 
         let (auto_tables, auto_functions) = calc_auto(config);
@@ -88,9 +132,25 @@ impl PgBuilder {
             default_srid: config.default_srid,
             auto_bounds: config.auto_bounds.unwrap_or_default(),
             max_feature_count: config.max_feature_count,
+            slow_query: config
+                .slow_query_threshold_ms
+                .map(|threshold| SlowQueryLog {
+                    threshold: Duration::from_millis(threshold),
+                    include_sql: config.slow_query_log_sql.unwrap_or_default(),
+                }),
+            query_comments: config.query_comments.unwrap_or_default(),
+            duplicate_geometry_columns: config.duplicate_geometry_columns.unwrap_or_default(),
+            empty_tables: config.empty_tables.unwrap_or_default(),
+            missing_columns: config.missing_columns.unwrap_or_default(),
+            index_recommendations: config.index_recommendations.unwrap_or_default(),
+            introspection_concurrency: config
+                .introspection_concurrency
+                .or(config.pool_size)
+                .unwrap_or(POOL_SIZE_DEFAULT),
             id_resolver,
             tables: config.tables.clone().unwrap_or_default(),
             functions: config.functions.clone().unwrap_or_default(),
+            introspected: config.introspected.clone(),
             auto_functions,
             auto_tables,
         })
@@ -107,7 +167,8 @@ impl PgBuilder {
     // FIXME: this function has gotten too long due to the new formatting rules, need to be refactored
     #[allow(clippy::too_many_lines)]
     pub async fn instantiate_tables(&self) -> PgResult<(TileInfoSources, TableInfoSources)> {
-        let mut db_tables_info = query_available_tables(&self.pool).await?;
+        let mut db_tables_info =
+            query_available_tables(&self.pool, self.duplicate_geometry_columns).await?;
 
         // Match configured sources with the discovered ones and add them to the pending list.
         let mut used = HashSet::<(&str, &str, &str)>::new();
@@ -119,6 +180,22 @@ impl PgBuilder {
                     return Err(InvalidTableExtent(id.to_string(), cfg_inf.format_id()));
                 }
             }
+            if let Some(precision) = cfg_inf.precision {
+                if cfg_inf.extent.is_some() {
+                    return Err(ConflictingTablePrecision(id.clone(), cfg_inf.format_id()));
+                }
+                if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+                    return Err(InvalidTablePrecision(
+                        id.clone(),
+                        cfg_inf.format_id(),
+                        precision,
+                    ));
+                }
+                info!(
+                    "Source {id} uses precision={precision}, i.e. the tile extent is {}",
+                    1_u32 << precision
+                );
+            }
 
             let Some(db_tables) = find_info(&db_tables_info, &cfg_inf.schema, "schema", id) else {
                 continue;
@@ -144,13 +221,7 @@ impl PgBuilder {
             };
             warn_on_rename(id, &id2, "Table");
             info!("Configured {dup}source {id2} from {}", summary(&merged_inf));
-            pending.push(table_to_query(
-                id2,
-                merged_inf,
-                self.pool.clone(),
-                self.auto_bounds,
-                self.max_feature_count,
-            ));
+            pending.push(self.introspect_table(id2, merged_inf));
         }
 
         // Sort the discovered sources by schema, table and geometry column to ensure a consistent behavior
@@ -187,13 +258,7 @@ impl PgBuilder {
                         db_inf.srid = srid;
                         update_auto_fields(&id2, &mut db_inf, auto_tables);
                         info!("Discovered source {id2} from {}", summary(&db_inf));
-                        pending.push(table_to_query(
-                            id2,
-                            db_inf,
-                            self.pool.clone(),
-                            self.auto_bounds,
-                            self.max_feature_count,
-                        ));
+                        pending.push(self.introspect_table(id2, db_inf));
                     }
                 }
             }
@@ -201,23 +266,121 @@ impl PgBuilder {
 
         let mut res = TileInfoSources::default();
         let mut info_map = TableInfoSources::new();
-        let pending = join_all(pending).await;
+        let start = Instant::now();
+        let count = pending.len();
+        let pending = join_all_limited(pending, self.introspection_concurrency).await;
+        info!(
+            "Introspected {count} table sources in {:.2?} with up to {} concurrent queries",
+            start.elapsed(),
+            self.introspection_concurrency
+        );
         for src in pending {
             match src {
                 Err(v) => {
                     error!("Failed to create a source: {v}");
                 }
                 Ok((id, pg_sql, src_inf)) => {
+                    if src_inf.is_empty == Some(true) {
+                        let table = src_inf.format_id();
+                        match self.empty_tables {
+                            EmptyTablePolicy::Keep => {}
+                            EmptyTablePolicy::Warn => {
+                                warn!(
+                                    "Source {id} will only serve empty tiles because table {table} has no data"
+                                );
+                            }
+                            EmptyTablePolicy::Exclude => {
+                                warn!("Skipping source {id} because table {table} has no data");
+                                continue;
+                            }
+                        }
+                    }
+                    if self.index_recommendations {
+                        if let Some(sql) = spatial_index_recommendation(&src_inf) {
+                            warn!(
+                                "Source {id} has no spatial index on its geometry column, tile queries may be slow. Consider creating one with: {sql}"
+                            );
+                        }
+                    }
+                    let matview = match &src_inf.refresh {
+                        Some(cfg) => {
+                            let table = (src_inf.schema.as_str(), src_inf.table.as_str());
+                            Some(Matview::new(&self.pool, &id, cfg, Some(table)).await?)
+                        }
+                        None => None,
+                    };
                     debug!("{id} query: {}", pg_sql.sql_query);
-                    self.add_func_src(&mut res, id.clone(), &src_inf, pg_sql.clone());
+                    self.add_table_src(&mut res, id.clone(), &src_inf, pg_sql.clone(), matview);
                     info_map.insert(id, src_inf);
                 }
             }
         }
 
+        // Forget the removed sources, so that a source added again later is introspected again
+        self.introspected
+            .0
+            .lock()
+            .expect("introspected tables lock poisoned")
+            .retain(|id, _| info_map.contains_key(id));
+
         Ok((res, info_map))
     }
 
+    /// Introspect a table with [`table_to_query`], unless the same table info was already introspected for this source
+    async fn introspect_table(
+        &self,
+        id: String,
+        info: TableInfo,
+    ) -> PgResult<(String, PgSqlInfo, TableInfo)> {
+        let input = (
+            info,
+            self.auto_bounds,
+            self.max_feature_count,
+            self.pool.supports_tile_margin(),
+        );
+        let reused = {
+            let introspected = self
+                .introspected
+                .0
+                .lock()
+                .expect("introspected tables lock poisoned");
+            introspected
+                .get(&id)
+                .filter(|v| v.input == input)
+                .map(|v| (v.sql_info.clone(), v.info.clone()))
+        };
+        if let Some((sql_info, info)) = reused {
+            debug!("Reusing the introspection of source {id}, as its table info did not change");
+            return Ok((id, sql_info, info));
+        }
+
+        let (id, sql_info, info) = table_to_query(
+            id,
+            input.0.clone(),
+            self.pool.clone(),
+            self.auto_bounds,
+            self.max_feature_count,
+        )
+        .await?;
+        // Bounds whose calculation timed out are computed again next time
+        if info.bounds.is_some()
+            || info.is_empty.is_some()
+            || self.auto_bounds == BoundsCalcType::Skip
+        {
+            let table = IntrospectedTable {
+                input,
+                sql_info: sql_info.clone(),
+                info: info.clone(),
+            };
+            self.introspected
+                .0
+                .lock()
+                .expect("introspected tables lock poisoned")
+                .insert(id.clone(), table);
+        }
+        Ok((id, sql_info, info))
+    }
+
     pub async fn instantiate_functions(&self) -> PgResult<(TileInfoSources, FuncInfoSources)> {
         let mut db_funcs_info = query_available_function(&self.pool).await?;
         let mut res = TileInfoSources::default();
@@ -245,7 +408,11 @@ impl PgBuilder {
             let dup = !used.insert((&cfg_inf.schema, func_name));
             let dup = if dup { "duplicate " } else { "" };
             let id2 = self.resolve_id(id, &merged_inf);
-            self.add_func_src(&mut res, id2.clone(), &merged_inf, pg_sql.clone());
+            let matview = match &merged_inf.refresh {
+                Some(cfg) => Some(Matview::new(&self.pool, &id2, cfg, None).await?),
+                None => None,
+            };
+            self.add_func_src(&mut res, id2.clone(), &merged_inf, pg_sql.clone(), matview);
             warn_on_rename(id, &id2, "Function");
             let signature = &pg_sql.signature;
             info!("Configured {dup}source {id2} from the function {signature}");
@@ -279,7 +446,7 @@ impl PgBuilder {
                         .replace("{schema}", &schema)
                         .replace("{function}", &func);
                     let id2 = self.resolve_id(&source_id, &db_inf);
-                    self.add_func_src(&mut res, id2.clone(), &db_inf, pg_sql.clone());
+                    self.add_func_src(&mut res, id2.clone(), &db_inf, pg_sql.clone(), None);
                     info!("Discovered source {id2} from function {}", pg_sql.signature);
                     debug!("{id2} query: {}", pg_sql.sql_query);
                     info_map.insert(id2, db_inf);
@@ -298,15 +465,84 @@ impl PgBuilder {
         &self,
         sources: &mut TileInfoSources,
         id: String,
-        pg_info: &impl PgInfo,
+        func_info: &FunctionInfo,
+        sql_info: PgSqlInfo,
+        matview: Option<Matview>,
+    ) {
+        let tilejson = func_info.to_tilejson(id.clone());
+        let source = PgSource::new(id, sql_info, tilejson, self.pool.clone(), self.slow_query)
+            .with_query_defaults(to_url_query(func_info.query_defaults.as_ref()))
+            .with_immutable(func_info.immutable.unwrap_or_default())
+            .with_compress(func_info.compress.unwrap_or(true))
+            .with_matview(matview)
+            .with_query_limit(to_query_limit(
+                func_info.max_concurrent_queries,
+                func_info.max_queued_queries,
+            ))
+            .with_statement_timeout(func_info.statement_timeout_ms.map(Duration::from_millis))
+            .with_debug(func_info.debug.unwrap_or_default())
+            .with_query_comment(self.query_comments)
+            .with_group(func_info.group.clone())
+            .with_schema(func_info.schema.clone());
+        sources.push(Box::new(source));
+    }
+
+    fn add_table_src(
+        &self,
+        sources: &mut TileInfoSources,
+        id: String,
+        table_info: &TableInfo,
         sql_info: PgSqlInfo,
+        matview: Option<Matview>,
     ) {
-        let tilejson = pg_info.to_tilejson(id.clone());
-        let source = PgSource::new(id, sql_info, tilejson, self.pool.clone());
+        let tilejson = table_info.to_tilejson(id.clone());
+        let source = PgSource::new(id, sql_info, tilejson, self.pool.clone(), self.slow_query)
+            .with_table_info(table_info.clone(), self.max_feature_count)
+            .with_missing_columns(self.missing_columns)
+            .with_query_defaults(to_url_query(table_info.query_defaults.as_ref()))
+            .with_immutable(table_info.immutable.unwrap_or_default())
+            .with_compress(table_info.compress.unwrap_or(true))
+            .with_matview(matview)
+            .with_query_limit(to_query_limit(
+                table_info.max_concurrent_queries,
+                table_info.max_queued_queries,
+            ))
+            .with_statement_timeout(table_info.statement_timeout_ms.map(Duration::from_millis))
+            .with_debug(table_info.debug.unwrap_or_default())
+            .with_query_comment(self.query_comments)
+            .with_group(table_info.group.clone())
+            .with_schema(table_info.schema.clone());
         sources.push(Box::new(source));
     }
 }
 
+fn to_url_query(query_defaults: Option<&BTreeMap<String, String>>) -> Option<UrlQuery> {
+    query_defaults.map(|v| v.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+}
+
+/// A source without permits would wait forever for each query, and queued queries need a concurrency limit
+fn validate_query_limit(
+    id: &str,
+    max_concurrent: Option<usize>,
+    max_queued: Option<usize>,
+) -> PgResult<()> {
+    match (max_concurrent, max_queued) {
+        (Some(0), _) => Err(InvalidQueryLimit(
+            id.to_string(),
+            "max_concurrent_queries must be at least 1",
+        )),
+        (None, Some(_)) => Err(InvalidQueryLimit(
+            id.to_string(),
+            "max_queued_queries requires max_concurrent_queries",
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn to_query_limit(max_concurrent: Option<usize>, max_queued: Option<usize>) -> Option<QueryLimit> {
+    max_concurrent.map(|v| QueryLimit::new(v, max_queued))
+}
+
 fn update_auto_fields(id: &str, inf: &mut TableInfo, auto_tables: &PgBuilderTables) {
     if inf.clip_geom.is_none() {
         inf.clip_geom = auto_tables.clip_geom;
@@ -487,6 +723,7 @@ mod tests {
     use insta::assert_yaml_snapshot;
 
     use super::*;
+    use crate::pg::pool::tests::test_config;
 
     #[derive(serde::Serialize)]
     struct AutoCfg {
@@ -632,4 +869,61 @@ mod tests {
         auto_funcs: ~
         "#);
     }
+
+    #[tokio::test]
+    async fn reuse_introspected_tables() -> anyhow::Result<()> {
+        let (_node, config) = test_config().await?;
+        let config = PgConfig {
+            auto_bounds: Some(BoundsCalcType::Calc),
+            ..config
+        };
+        PgPool::new(&config)
+            .await?
+            .get()
+            .await?
+            .batch_execute(
+                "CREATE TABLE points (gid int, geom geometry(Point, 4326));
+                 INSERT INTO points VALUES (1, 'SRID=4326;POINT(0 0)');",
+            )
+            .await?;
+        // each reload builds the sources of a clone of the configuration
+        let bounds = async || -> anyhow::Result<_> {
+            let builder = PgBuilder::new(&config.clone(), IdResolver::new(&[])).await?;
+            let (sources, _) = builder.instantiate_tables().await?;
+            Ok(sources[0].get_tilejson().bounds.unwrap())
+        };
+        let initial = bounds().await?;
+
+        // the bounds of an unchanged table are not queried again
+        let conn = PgPool::new(&config).await?.get().await?;
+        conn.batch_execute("INSERT INTO points VALUES (2, 'SRID=4326;POINT(10 10)')")
+            .await?;
+        assert_eq!(bounds().await?, initial);
+
+        // a table with another column is introspected again
+        conn.batch_execute("ALTER TABLE points ADD COLUMN name text")
+            .await?;
+        assert_ne!(bounds().await?, initial);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_query_limit() {
+        assert!(validate_query_limit("src", None, None).is_ok());
+        assert!(validate_query_limit("src", Some(1), None).is_ok());
+        assert!(validate_query_limit("src", Some(4), Some(0)).is_ok());
+        assert!(matches!(
+            validate_query_limit("src", Some(0), None),
+            Err(InvalidQueryLimit(..))
+        ));
+        assert!(matches!(
+            validate_query_limit("src", Some(0), Some(2)),
+            Err(InvalidQueryLimit(..))
+        ));
+        assert!(matches!(
+            validate_query_limit("src", None, Some(2)),
+            Err(InvalidQueryLimit(..))
+        ));
+    }
 }
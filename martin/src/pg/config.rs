@@ -13,7 +13,7 @@ use crate::pg::builder::PgBuilder;
 use crate::pg::config_function::FuncInfoSources;
 use crate::pg::config_table::TableInfoSources;
 use crate::pg::utils::on_slow;
-use crate::pg::{PgError, PgResult};
+use crate::pg::{IntrospectedTables, PgError, PgResult};
 use crate::source::TileInfoSources;
 use crate::utils::{IdResolver, OptBoolObj, OptOneMany};
 
@@ -40,16 +40,82 @@ pub struct PgSslCerts {
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct PgConfig {
     pub connection_string: Option<String>,
+    /// Tenant whose requests, selected by the `tenant_header`, may use the sources of this connection.
+    /// The sources are not available to other requests. Sources of connections without a tenant are shared.
+    pub tenant: Option<String>,
     #[serde(flatten)]
     pub ssl_certificates: PgSslCerts,
     pub default_srid: Option<i32>,
     pub auto_bounds: Option<BoundsCalcType>,
     pub max_feature_count: Option<usize>,
     pub pool_size: Option<usize>,
+    /// Number of connections opened on startup, before the server accepts requests.
+    /// Limited to the pool size [default: 0]
+    pub warmup_connections: Option<usize>,
+    /// Log a warning for tile queries that take longer than this many milliseconds.
+    /// Slow query logging is disabled if not set.
+    pub slow_query_threshold_ms: Option<u64>,
+    /// Include the generated SQL in the slow query warning
+    pub slow_query_log_sql: Option<bool>,
+    /// Prefix each tile query with a `/* martin: {source_id} */` comment, so that the load of each source
+    /// can be told apart in `pg_stat_statements` and in the server logs [default: false]
+    pub query_comments: Option<bool>,
+    /// What to do if the database reports the same table geometry column more than once
+    pub duplicate_geometry_columns: Option<DuplicateGeometryPolicy>,
+    /// Maximum number of table introspection queries, e.g. bounds calculations, running at the same time on startup.
+    /// Defaults to the connection pool size.
+    pub introspection_concurrency: Option<usize>,
+    /// What to do with table sources whose bounds detection found no data
+    pub empty_tables: Option<EmptyTablePolicy>,
+    /// What to do when a table source query fails because a column no longer exists
+    pub missing_columns: Option<MissingColumnPolicy>,
+    /// Log a `CREATE INDEX` statement for each table source without a spatial index on its geometry column.
+    /// This is a read-only check, Martin never creates indexes [default: false]
+    pub index_recommendations: Option<bool>,
     #[serde(default, skip_serializing_if = "OptBoolObj::is_none")]
     pub auto_publish: OptBoolObj<PgCfgPublish>,
     pub tables: Option<TableInfoSources>,
     pub functions: Option<FuncInfoSources>,
+    /// Introspection of the table sources, shared by the clones of this configuration to be reused when reloading them
+    #[serde(skip)]
+    pub introspected: IntrospectedTables,
+}
+
+/// How to handle a table geometry column that is reported more than once during discovery
+#[derive(PartialEq, Eq, Default, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateGeometryPolicy {
+    /// Use the last reported column, replacing the previous one
+    #[default]
+    Overwrite,
+    /// Use the first reported column, ignoring the following ones
+    KeepFirst,
+    /// Fail with an error
+    Error,
+}
+
+/// How to handle a table source whose bounds detection shows that the table has no data
+#[derive(PartialEq, Eq, Default, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyTablePolicy {
+    /// Publish the source as usual
+    Keep,
+    /// Publish the source, but log a warning
+    #[default]
+    Warn,
+    /// Do not publish the source, and log a warning
+    Exclude,
+}
+
+/// How to handle a table source query that fails because a column was dropped or renamed after startup
+#[derive(PartialEq, Eq, Default, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingColumnPolicy {
+    /// Reload the table columns, remove the missing properties from the tiles, and retry the query once
+    #[default]
+    Refresh,
+    /// Fail the tile request with an error
+    Error,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -102,6 +168,11 @@ impl PgConfig {
                 ));
             }
         }
+        if self.introspection_concurrency == Some(0) {
+            return Err(PgError::ConfigError(
+                "introspection_concurrency must be greater than or equal to 1.",
+            ));
+        }
         if self.connection_string.is_none() {
             return Err(PgError::ConfigError(
                 "A connection string must be provided.",
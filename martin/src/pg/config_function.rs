@@ -1,9 +1,13 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use tilejson::{Bounds, TileJSON};
 
 use crate::config::UnrecognizedValues;
 use crate::pg::config::PgInfo;
+use crate::pg::matview::MatviewRefresh;
 use crate::pg::utils::{InfoMap, patch_json};
+use crate::source::{CREATED_KEY, TileScheme, UPDATED_KEY};
 
 pub type FuncInfoSources = InfoMap<FunctionInfo>;
 
@@ -28,6 +32,52 @@ pub struct FunctionInfo {
     /// Values may be integers or floating point numbers.
     pub bounds: Option<Bounds>,
 
+    /// Tile addressing scheme used in tile requests and advertised in `TileJSON` [default: xyz]
+    pub scheme: Option<TileScheme>,
+
+    /// Tiles of this source never change, e.g. because the source ID embeds a version like `roads.v3`.
+    /// Tiles are served with `Cache-Control: public, max-age=31536000, immutable` [default: false]
+    pub immutable: Option<bool>,
+
+    /// Compress the tiles of this source with gzip or brotli if the client accepts it.
+    /// Disable for tiny tiles that are not worth the CPU time of compressing them [default: true]
+    pub compress: Option<bool>,
+
+    /// Log the SQL query, parameters, and timing of each tile request of this source
+    /// at the `info` level, without raising the global log level [default: false]
+    pub debug: Option<bool>,
+
+    /// Group of related sources in the catalog, e.g. `transport` [default: none]
+    pub group: Option<String>,
+
+    /// Version of the source data, advertised in `TileJSON` [default: none]
+    pub version: Option<String>,
+
+    /// Time the source data was created, advertised as `created` in `TileJSON`, e.g. `2024-01-31T12:00:00Z` [default: none]
+    pub created: Option<String>,
+
+    /// Time the source data was last updated, advertised as `updated` in `TileJSON` and the catalog [default: none]
+    pub updated: Option<String>,
+
+    /// Maximum number of tile queries of this source running at the same time, independent of the pool size.
+    /// Must be at least 1. Additional requests wait for their turn [default: unlimited]
+    pub max_concurrent_queries: Option<usize>,
+
+    /// Maximum number of tile requests waiting for `max_concurrent_queries`, which must be set.
+    /// Additional requests fail with `503 Service Unavailable` [default: unlimited]
+    pub max_queued_queries: Option<usize>,
+
+    /// Cancel the tile queries of this source running longer than this many milliseconds with `SET LOCAL statement_timeout`.
+    /// The request fails with `504 Gateway Timeout` [default: no timeout]
+    pub statement_timeout_ms: Option<u64>,
+
+    /// Refresh the materialized view behind this source on a schedule or with the `/refresh/{source_id}` endpoint,
+    /// serving the previous contents meanwhile. Disabled if not set.
+    pub refresh: Option<MatviewRefresh>,
+
+    /// Default values of the URL query parameters, used when the request does not provide them
+    pub query_defaults: Option<BTreeMap<String, String>>,
+
     /// TileJSON provided by the SQL function comment. Not serialized.
     #[serde(skip)]
     pub tilejson: Option<serde_json::Value>,
@@ -80,6 +130,13 @@ impl PgInfo for FunctionInfo {
         tilejson.minzoom = self.minzoom;
         tilejson.maxzoom = self.maxzoom;
         tilejson.bounds = self.bounds;
+        tilejson.scheme = self.scheme.map(|v| v.to_string());
+        tilejson.version.clone_from(&self.version);
+        for (key, value) in [(CREATED_KEY, &self.created), (UPDATED_KEY, &self.updated)] {
+            if let Some(value) = value {
+                tilejson.other.insert(key.to_string(), value.clone().into());
+            }
+        }
         patch_json(tilejson, self.tilejson.as_ref())
     }
 }
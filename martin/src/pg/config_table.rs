@@ -1,12 +1,18 @@
 use std::collections::{BTreeMap, HashMap};
 
 use log::{info, warn};
+use postgres_protocol::escape::escape_literal;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tilejson::{Bounds, TileJSON, VectorLayer};
 
 use crate::config::UnrecognizedValues;
 use crate::pg::config::PgInfo;
+use crate::pg::matview::MatviewRefresh;
 use crate::pg::utils::{InfoMap, normalize_key, patch_json};
+use crate::source::{
+    CREATED_KEY, TILE_MATRIX_SET_KEY, TileGrid, TileOffset, TileScheme, UPDATED_KEY,
+};
 
 pub type TableInfoSources = InfoMap<TableInfo>;
 
@@ -36,6 +42,22 @@ pub struct TableInfo {
     #[serde(skip)]
     pub is_view: Option<bool>,
 
+    /// Geometry column is a `PostGIS` `geography` column, i.e. geodetic coordinates that are cast to `geometry` in tile queries
+    #[serde(skip)]
+    pub is_geography: Option<bool>,
+
+    /// Geometry column has no SRID constraint, so its geometries may have different SRIDs
+    #[serde(skip)]
+    pub srid_unconstrained: Option<bool>,
+
+    /// Geometry column contains geometries in several SRIDs, which are transformed to `srid` in the tile query
+    #[serde(skip)]
+    pub has_mixed_srids: Option<bool>,
+
+    /// Flag indicating that the bounds detection found no data in the table
+    #[serde(skip)]
+    pub is_empty: Option<bool>,
+
     /// Feature id column name
     pub id_column: Option<String>,
 
@@ -51,21 +73,150 @@ pub struct TableInfo {
     /// Values may be integers or floating point numbers.
     pub bounds: Option<Bounds>,
 
+    /// Tile addressing scheme used in tile requests and advertised in `TileJSON` [default: xyz]
+    pub scheme: Option<TileScheme>,
+
+    /// OGC tile matrix set used to serve the tiles, either `WebMercatorQuad`, `WorldCRS84Quad`,
+    /// or a regional `custom` grid with its `srid`, `origin`, and `tile_extent` [default: `WebMercatorQuad`]
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub tile_grid: Option<TileGrid>,
+
+    /// Tile of the `tile_grid` with the `zoom`, `x`, and `y` coordinates that is served as the zoom 0 tile of this source,
+    /// for sources covering a single region of the grid. Tiles of lower zoom levels of the grid are not available.
+    pub tile_offset: Option<TileOffset>,
+
     /// Tile extent in tile coordinate space
     pub extent: Option<u32>,
 
+    /// Number of bits of coordinate precision within a tile, i.e. the extent as a power of two.
+    /// For example, `precision: 10` is the same as `extent: 1024`. Cannot be used together with `extent`.
+    pub precision: Option<u8>,
+
     /// Buffer distance in tile coordinate space to optionally clip geometries
     pub buffer: Option<u32>,
 
+    /// Largest buffer that a tile request may ask for with the `buffer` URL query parameter, e.g. `?buffer=128`.
+    /// Larger values are reduced to this maximum. Requests cannot change the buffer if not set.
+    pub max_buffer: Option<u32>,
+
     /// Boolean to control if geometries should be clipped or encoded as is
     pub clip_geom: Option<bool>,
 
     /// Geometry type
     pub geometry_type: Option<String>,
 
+    /// Whether curved geometries are converted to lines with `ST_CurveToLine` [default: always]
+    pub curve_to_line: Option<CurveToLine>,
+
+    /// Maximum distance between a curve and the lines replacing it, in the units of the table SRID.
+    /// Only used if curves are converted [default: 32 segments per quarter circle]
+    pub curve_tolerance: Option<f64>,
+
+    /// Add vertices along the great circles between the points of lines and polygons, so that no segment
+    /// is longer than this many meters, and long segments are drawn as arcs. Disabled if not set, as it adds vertices.
+    pub densify: Option<f64>,
+
+    /// SQL condition selecting the published features, e.g. `status = 'active' AND region = 'north'`,
+    /// combined with the tile bounds filter. It is inserted as is into the tile queries without any escaping,
+    /// so it must only come from trusted configuration [default: all features]
+    #[serde(alias = "where")]
+    pub filter: Option<String>,
+
+    /// Transformations applied to each geometry in the given order, before encoding it in the tile
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    pub geometry_transforms: Option<Vec<GeometryTransform>>,
+
+    /// How geometries crossing the antimeridian (±180° longitude) are handled [default: keep]
+    pub antimeridian: Option<Antimeridian>,
+
+    /// How geometries in several SRIDs are handled, if the geometry column has no SRID constraint [default: error]
+    pub mixed_srids: Option<MixedSrids>,
+
+    /// How duplicate values of the `id_column` are handled, checked on startup unless ignored [default: warn]
+    pub duplicate_ids: Option<DuplicateIds>,
+
+    /// Add a `{layer}_labels` layer with a point inside each polygon, with the same properties,
+    /// so that clients can place labels without computing them. Disabled if not set.
+    pub label_points: Option<LabelPoints>,
+
+    /// Replace the points with clusters of nearby points up to a zoom level, each with the number of its points
+    /// in the `point_count` property. Disabled if not set.
+    pub point_clusters: Option<PointClusters>,
+
+    /// Number of coordinate dimensions of the geometry column, as reported by the database (2, 3 or 4)
+    #[serde(skip)]
+    pub coord_dimension: Option<i32>,
+
     /// List of columns, that should be encoded as tile properties
     pub properties: Option<BTreeMap<String, String>>,
 
+    /// Encode only the geometry and the feature ID of each feature, without any properties,
+    /// for minimal tiles of background layers like land or coastlines [default: false]
+    pub geometry_only: Option<bool>,
+
+    /// How NULL property values are encoded in tiles [default: omit]
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub null_properties: Option<NullProperties>,
+
+    /// Report the number of features in a tile with the `X-Feature-Count` header of `HEAD` tile requests.
+    /// The features are counted in the generated tile, which is generated for each such request [default: false]
+    pub feature_count: Option<bool>,
+
+    /// Serve no tile (HTTP 204) instead of a tile with fewer features, e.g. to avoid cluttering
+    /// low zoom levels with sparse data. Disabled if not set.
+    pub min_features_per_tile: Option<MinFeaturesPerTile>,
+
+    /// Name of a property with the original, unclipped geometry as hex-encoded EWKB.
+    /// Full geometries may significantly increase the tile size. Disabled if not set.
+    pub ewkb_property: Option<String>,
+
+    /// Name of a property with the Z coordinate (elevation) of point geometries, which is otherwise
+    /// lost because tile geometries are always 2D. Empty for other geometry types. Disabled if not set.
+    pub z_property: Option<String>,
+
+    /// Tiles of this source never change, e.g. because the source ID embeds a version like `roads.v3`.
+    /// Tiles are served with `Cache-Control: public, max-age=31536000, immutable` [default: false]
+    pub immutable: Option<bool>,
+
+    /// Compress the tiles of this source with gzip or brotli if the client accepts it.
+    /// Disable for tiny tiles that are not worth the CPU time of compressing them [default: true]
+    pub compress: Option<bool>,
+
+    /// Log the SQL query, parameters, and timing of each tile request of this source
+    /// at the `info` level, without raising the global log level [default: false]
+    pub debug: Option<bool>,
+
+    /// Group of related sources in the catalog, e.g. `transport` [default: none]
+    pub group: Option<String>,
+
+    /// Version of the source data, advertised in `TileJSON` [default: none]
+    pub version: Option<String>,
+
+    /// Time the source data was created, advertised as `created` in `TileJSON`, e.g. `2024-01-31T12:00:00Z` [default: none]
+    pub created: Option<String>,
+
+    /// Time the source data was last updated, advertised as `updated` in `TileJSON` and the catalog [default: none]
+    pub updated: Option<String>,
+
+    /// Maximum number of tile queries of this source running at the same time, independent of the pool size.
+    /// Must be at least 1. Additional requests wait for their turn [default: unlimited]
+    pub max_concurrent_queries: Option<usize>,
+
+    /// Maximum number of tile requests waiting for `max_concurrent_queries`, which must be set.
+    /// Additional requests fail with `503 Service Unavailable` [default: unlimited]
+    pub max_queued_queries: Option<usize>,
+
+    /// Cancel the tile queries of this source running longer than this many milliseconds with `SET LOCAL statement_timeout`.
+    /// The request fails with `504 Gateway Timeout` [default: no timeout]
+    pub statement_timeout_ms: Option<u64>,
+
+    /// Refresh the materialized view behind this source on a schedule or with the `/refresh/{source_id}` endpoint,
+    /// serving the previous contents meanwhile. Disabled if not set.
+    pub refresh: Option<MatviewRefresh>,
+
+    /// Default values of the URL query parameters, e.g. `fields`, used when the request does not provide them
+    pub query_defaults: Option<BTreeMap<String, String>>,
+
     /// Mapping of properties to the actual table columns
     #[serde(skip)]
     pub prop_mapping: HashMap<String, String>,
@@ -75,7 +226,7 @@ pub struct TableInfo {
 
     /// TileJSON provider by the SQL comment. Shouldn't be serialized
     #[serde(skip)]
-    pub tilejson: Option<serde_json::Value>,
+    pub tilejson: Option<Value>,
 }
 
 impl PgInfo for TableInfo {
@@ -94,6 +245,20 @@ impl PgInfo for TableInfo {
         tilejson.minzoom = self.minzoom;
         tilejson.maxzoom = self.maxzoom;
         tilejson.bounds = self.bounds;
+        tilejson.scheme = self.scheme.map(|v| v.to_string());
+        tilejson.version.clone_from(&self.version);
+        for (key, value) in [(CREATED_KEY, &self.created), (UPDATED_KEY, &self.updated)] {
+            if let Some(value) = value {
+                tilejson.other.insert(key.to_string(), value.clone().into());
+            }
+        }
+        if let Some(grid) = self.tile_grid.filter(|v| *v != TileGrid::default()) {
+            // OGC grids are advertised by their name, custom grids with all their parameters
+            let value = serde_json::to_value(grid).unwrap_or_default();
+            tilejson
+                .other
+                .insert(TILE_MATRIX_SET_KEY.to_string(), value);
+        }
 
         let id = if let Some(id) = &self.layer_id {
             id.clone()
@@ -101,20 +266,322 @@ impl PgInfo for TableInfo {
             source_id
         };
 
+        let mut fields = self.properties.clone().unwrap_or_default();
+        if let Some(ewkb_property) = &self.ewkb_property {
+            fields.insert(ewkb_property.clone(), "text".to_string());
+        }
+        if let Some(z_property) = &self.z_property {
+            fields.insert(z_property.clone(), "number".to_string());
+        }
+        if self.point_clusters.is_some() {
+            fields.insert(POINT_COUNT_PROPERTY.to_string(), "number".to_string());
+        }
+
+        let labels = self.label_points.map(|_| VectorLayer {
+            id: format!("{id}{LABELS_LAYER_SUFFIX}"),
+            fields: self.properties.clone().unwrap_or_default(),
+            description: None,
+            maxzoom: None,
+            minzoom: None,
+            other: BTreeMap::default(),
+        });
         let layer = VectorLayer {
             id,
-            fields: self.properties.clone().unwrap_or_default(),
+            fields,
             description: None,
             maxzoom: None,
             minzoom: None,
             other: BTreeMap::default(),
         };
-        tilejson.vector_layers = Some(vec![layer]);
+        tilejson.vector_layers = Some([Some(layer), labels].into_iter().flatten().collect());
         patch_json(tilejson, self.tilejson.as_ref())
     }
 }
 
+/// Whether curved geometries are converted to lines, because vector tiles cannot store curves
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CurveToLine {
+    /// Always convert, even if the table has no curved geometries
+    #[default]
+    Always,
+    /// Convert only if the geometry type may contain curves, e.g. `CURVEPOLYGON` or `GEOMETRY`
+    Auto,
+    /// Never convert. Curved geometries of the table cannot be encoded in tiles.
+    Never,
+}
+
+/// How table geometries crossing the antimeridian (±180° longitude) are handled
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Antimeridian {
+    /// Use the geometries as they are
+    #[default]
+    Keep,
+    /// Split the geometries at the antimeridian with `ST_ShiftLongitude` and `ST_WrapX`,
+    /// so that both parts are shown in the tiles on either side. Requires a longitude/latitude SRID, e.g. 4326.
+    Split,
+}
+
+/// How a table whose geometry column contains geometries in several SRIDs is handled
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MixedSrids {
+    /// Do not publish the source, so that no tiles are served in a wrong projection
+    #[default]
+    Error,
+    /// Transform all geometries to the SRID of the source. Geometries without an SRID are assumed to use it already.
+    /// Tile queries cannot use a spatial index on the geometry column.
+    Transform,
+}
+
+/// How a table whose `id_column` has duplicate values is handled.
+/// Features with the same ID are treated as parts of the same feature by clients, e.g. for their feature state.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateIds {
+    /// Do not check the IDs
+    Ignore,
+    /// Log a warning and publish the source anyway
+    #[default]
+    Warn,
+    /// Do not publish the source
+    Error,
+}
+
+/// Suffix of the layer ID of the label points, e.g. `buildings_labels`
+pub const LABELS_LAYER_SUFFIX: &str = "_labels";
+
+/// How the label point of each polygon is computed
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelPoints {
+    /// A point guaranteed to be inside the polygon, computed with `ST_PointOnSurface`
+    PointOnSurface,
+    /// The center of mass with `ST_Centroid`, which may be outside of concave polygons
+    Centroid,
+}
+
+impl LabelPoints {
+    /// Wrap the SQL geometry expression to compute its label point
+    #[must_use]
+    pub fn apply(self, geometry: &str) -> String {
+        match self {
+            Self::PointOnSurface => format!("ST_PointOnSurface({geometry})"),
+            Self::Centroid => format!("ST_Centroid({geometry})"),
+        }
+    }
+}
+
+/// Name of the property with the number of points in a cluster
+pub const POINT_COUNT_PROPERTY: &str = "point_count";
+
+/// Clusters of nearby points, served instead of the individual points at low zoom levels
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PointClusters {
+    /// Highest zoom level with clusters. Points are served individually at higher zoom levels.
+    pub maxzoom: u8,
+    /// Size of the grid cells grouping the points into clusters, in tile coordinate space like `buffer` [default: 256]
+    pub radius: Option<u32>,
+}
+
+impl PointClusters {
+    /// Ensure that the cells are not empty, and that the table has points if its geometry type is known
+    pub fn validate(self, geometry_type: Option<&str>) -> Result<(), String> {
+        if self.radius == Some(0) {
+            return Err("radius must be positive".to_string());
+        }
+        match geometry_type {
+            Some(v)
+                if !v.eq_ignore_ascii_case("POINT") && !v.eq_ignore_ascii_case("MULTIPOINT") =>
+            {
+                Err(format!(
+                    "only points can be clustered, but the geometry type is {v}"
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Minimum number of features of a rendered tile, checked with a count query before the tile query
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MinFeaturesPerTile {
+    /// Tiles with fewer features are not rendered. The count stops at this number, so it stays cheap.
+    pub count: usize,
+    /// Highest zoom level with the minimum. Higher zoom levels are always rendered [default: all zoom levels]
+    pub maxzoom: Option<u8>,
+}
+
+impl MinFeaturesPerTile {
+    #[must_use]
+    pub fn applies_to(self, zoom: u8) -> bool {
+        self.maxzoom.is_none_or(|v| zoom <= v)
+    }
+}
+
+/// A geometry transformation, applied in the table SRID before the geometry is encoded in a tile.
+/// Only these transformations are supported, arbitrary SQL is not allowed.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GeometryTransform {
+    /// Repair invalid geometries with `ST_MakeValid`
+    MakeValid,
+    /// Snap all points to a grid with this cell size with `ST_SnapToGrid`
+    SnapToGrid(f64),
+    /// Simplify geometries with `ST_Simplify` using this tolerance. The result may be invalid.
+    Simplify(f64),
+    /// Simplify geometries with `ST_SimplifyPreserveTopology` using this tolerance, keeping them valid
+    SimplifyPreserveTopology(f64),
+}
+
+impl GeometryTransform {
+    /// Wrap the SQL geometry expression with this transformation
+    #[must_use]
+    pub fn apply(self, geometry: &str) -> String {
+        match self {
+            Self::MakeValid => format!("ST_MakeValid({geometry})"),
+            Self::SnapToGrid(size) => format!("ST_SnapToGrid({geometry}, {size})"),
+            Self::Simplify(tolerance) => format!("ST_Simplify({geometry}, {tolerance})"),
+            Self::SimplifyPreserveTopology(tolerance) => {
+                format!("ST_SimplifyPreserveTopology({geometry}, {tolerance})")
+            }
+        }
+    }
+
+    /// Ensure that the parameter of the transformation is a positive number
+    pub fn validate(self) -> Result<(), String> {
+        match self {
+            Self::MakeValid => Ok(()),
+            Self::SnapToGrid(v) | Self::Simplify(v) | Self::SimplifyPreserveTopology(v) => {
+                if v.is_finite() && v > 0.0 {
+                    Ok(())
+                } else {
+                    Err(format!("{self:?} must have a positive parameter"))
+                }
+            }
+        }
+    }
+}
+
+/// How NULL property values are encoded. Vector tiles cannot store NULL, so by default such properties are omitted.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NullProperties {
+    /// Leave out the property, so the feature does not have it
+    Omit,
+    /// Replace NULL values of text columns with this string. Other columns are still omitted.
+    Marker(String),
+    /// Replace NULL values with a default value per property, which must match the column type
+    Default(BTreeMap<String, Value>),
+}
+
+impl NullProperties {
+    /// Get the SQL literal replacing NULL values of a property with the given column type, if any
+    #[must_use]
+    pub fn get_sql_value(&self, property: &str, column_type: &str) -> Option<String> {
+        match self {
+            Self::Omit => None,
+            Self::Marker(marker) => is_text_type(column_type).then(|| escape_literal(marker)),
+            Self::Default(defaults) => match defaults.get(property)? {
+                Value::String(v) => Some(escape_literal(v)),
+                Value::Bool(true) => Some("TRUE".to_string()),
+                Value::Bool(false) => Some("FALSE".to_string()),
+                v => Some(v.to_string()),
+            },
+        }
+    }
+
+    /// Ensure that every default value belongs to a known property, and matches its column type
+    pub fn validate(&self, properties: &BTreeMap<String, String>) -> Result<(), (String, String)> {
+        let Self::Default(defaults) = self else {
+            return Ok(());
+        };
+        for (property, value) in defaults {
+            let Some(column_type) = properties.get(property) else {
+                return Err((property.clone(), "no such property".to_string()));
+            };
+            let is_valid = match column_type.as_str() {
+                "int2" | "int4" | "int8" => value.is_i64() || value.is_u64(),
+                "float4" | "float8" | "numeric" => value.is_number(),
+                "bool" => value.is_boolean(),
+                t => is_text_type(t) && value.is_string(),
+            };
+            if !is_valid {
+                return Err((
+                    property.clone(),
+                    format!("{value} does not match the column type {column_type}"),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_text_type(column_type: &str) -> bool {
+    matches!(column_type, "text" | "varchar" | "bpchar" | "char" | "name")
+}
+
+/// Smallest allowed `precision` value, equivalent to an extent of 256
+pub const MIN_PRECISION: u8 = 8;
+/// Largest allowed `precision` value, equivalent to an extent of 65536
+pub const MAX_PRECISION: u8 = 16;
+
 impl TableInfo {
+    /// The tile extent to use in the tile query, either set directly or derived from the `precision`
+    #[must_use]
+    pub fn get_extent(&self) -> Option<u32> {
+        self.extent
+            .or_else(|| self.precision.map(|v| 1_u32 << v.min(MAX_PRECISION)))
+    }
+
+    /// Coordinate dimensions of the geometry column, e.g. `XYZ`, or `None` if unknown.
+    /// XYM geometries are reported as 3-dimensional with an `M` suffix of the geometry type.
+    #[must_use]
+    pub fn get_dimensions(&self) -> Option<&'static str> {
+        let is_measured = || {
+            self.geometry_type
+                .as_ref()
+                .is_some_and(|v| v.ends_with('M'))
+        };
+        match self.coord_dimension? {
+            2 => Some("XY"),
+            3 if is_measured() => Some("XYM"),
+            3 => Some("XYZ"),
+            4 => Some("XYZM"),
+            _ => None,
+        }
+    }
+
+    /// Whether the geometry column has a Z coordinate, or `None` if unknown
+    #[must_use]
+    pub fn has_z(&self) -> Option<bool> {
+        self.get_dimensions().map(|v| v.contains('Z'))
+    }
+
+    /// Whether the tile query converts curved geometries to lines, based on `curve_to_line` and the geometry type
+    #[must_use]
+    pub fn uses_curve_to_line(&self) -> bool {
+        match self.curve_to_line.unwrap_or_default() {
+            CurveToLine::Always => true,
+            CurveToLine::Never => false,
+            CurveToLine::Auto => self.geometry_type.as_deref().is_none_or(|v| {
+                let v = v.to_ascii_uppercase();
+                !matches!(
+                    v.strip_suffix('M').unwrap_or(&v),
+                    "POINT"
+                        | "MULTIPOINT"
+                        | "LINESTRING"
+                        | "MULTILINESTRING"
+                        | "POLYGON"
+                        | "MULTIPOLYGON"
+                )
+            }),
+        }
+    }
+
     /// For a given table info discovered from the database, append the configuration info provided by the user
     #[must_use]
     pub fn append_cfg_info(
@@ -132,6 +599,9 @@ impl TableInfo {
             // These values are not serialized, so copy auto-detected values from the database
             geometry_index: self.geometry_index,
             is_view: self.is_view,
+            is_geography: self.is_geography,
+            srid_unconstrained: self.srid_unconstrained,
+            coord_dimension: self.coord_dimension,
             tilejson: self.tilejson.clone(),
             // Srid requires some logic
             srid: self.calc_srid(new_id, cfg_inf.srid, default_srid)?,
@@ -196,3 +666,153 @@ impl TableInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    #[test]
+    fn test_precision_extent() {
+        let info = |extent, precision| TableInfo {
+            extent,
+            precision,
+            ..Default::default()
+        };
+        assert_eq!(info(None, None).get_extent(), None);
+        assert_eq!(info(Some(512), None).get_extent(), Some(512));
+        assert_eq!(info(None, Some(10)).get_extent(), Some(1024));
+        assert_eq!(info(None, Some(MIN_PRECISION)).get_extent(), Some(256));
+        assert_eq!(info(None, Some(MAX_PRECISION)).get_extent(), Some(65536));
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let info = |geometry_type: &str, coord_dimension| TableInfo {
+            geometry_type: Some(geometry_type.to_string()),
+            coord_dimension,
+            ..Default::default()
+        };
+        assert_eq!(info("POINT", None).get_dimensions(), None);
+        assert_eq!(info("POINT", None).has_z(), None);
+        assert_eq!(info("POINT", Some(2)).get_dimensions(), Some("XY"));
+        assert_eq!(info("POINT", Some(3)).get_dimensions(), Some("XYZ"));
+        assert_eq!(info("POINTM", Some(3)).get_dimensions(), Some("XYM"));
+        assert_eq!(info("POINT", Some(4)).get_dimensions(), Some("XYZM"));
+        assert_eq!(info("POINT", Some(3)).has_z(), Some(true));
+        assert_eq!(info("POINTM", Some(3)).has_z(), Some(false));
+    }
+
+    #[test]
+    fn test_null_properties() {
+        let properties: BTreeMap<String, String> = [
+            ("name", "varchar"),
+            ("population", "int8"),
+            ("area", "float8"),
+            ("capital", "bool"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let defaults = |v: Value| {
+            NullProperties::Default(serde_json::from_value::<BTreeMap<_, _>>(v).unwrap())
+        };
+
+        let cfg = defaults(
+            serde_json::json!({"name": "n/a", "population": 0, "area": 0.5, "capital": false}),
+        );
+        assert!(cfg.validate(&properties).is_ok());
+        assert_eq!(cfg.get_sql_value("name", "varchar").unwrap(), "'n/a'");
+        assert_eq!(cfg.get_sql_value("population", "int8").unwrap(), "0");
+        assert_eq!(cfg.get_sql_value("area", "float8").unwrap(), "0.5");
+        assert_eq!(cfg.get_sql_value("capital", "bool").unwrap(), "FALSE");
+
+        let invalid = |v: Value| defaults(v).validate(&properties).unwrap_err().0;
+        assert_eq!(
+            invalid(serde_json::json!({"population": 0.5})),
+            "population"
+        );
+        assert_eq!(invalid(serde_json::json!({"name": 1})), "name");
+        assert_eq!(invalid(serde_json::json!({"capital": "no"})), "capital");
+        assert_eq!(invalid(serde_json::json!({"unknown": 1})), "unknown");
+
+        let cfg = NullProperties::Marker("null".to_string());
+        assert!(cfg.validate(&properties).is_ok());
+        assert_eq!(cfg.get_sql_value("name", "varchar").unwrap(), "'null'");
+        assert_eq!(cfg.get_sql_value("population", "int8"), None);
+        assert_eq!(NullProperties::Omit.get_sql_value("name", "varchar"), None);
+    }
+
+    #[test]
+    fn test_geometry_transforms_yaml() {
+        let info = serde_yaml::from_str::<TableInfo>(indoc! {"
+            schema: public
+            table: t
+            srid: 4326
+            geometry_column: geom
+            geometry_transforms:
+              - make_valid
+              - snap_to_grid: 0.001
+              - simplify_preserve_topology: 0.01
+        "})
+        .unwrap();
+        assert_eq!(
+            info.geometry_transforms,
+            Some(vec![
+                GeometryTransform::MakeValid,
+                GeometryTransform::SnapToGrid(0.001),
+                GeometryTransform::SimplifyPreserveTopology(0.01),
+            ])
+        );
+        assert!(serde_yaml::from_str::<Vec<GeometryTransform>>("- st_buffer: 1").is_err());
+        assert!(GeometryTransform::Simplify(0.0).validate().is_err());
+        assert!(GeometryTransform::SnapToGrid(f64::NAN).validate().is_err());
+        assert!(GeometryTransform::MakeValid.validate().is_ok());
+    }
+
+    #[test]
+    fn test_null_properties_yaml() {
+        let parse = |v: &str| {
+            serde_yaml::from_str::<TableInfo>(v)
+                .unwrap()
+                .null_properties
+        };
+        let base = "schema: public\ntable: t\nsrid: 4326\ngeometry_column: geom\n";
+        assert_eq!(parse(base), None);
+        assert_eq!(
+            parse(&format!("{base}null_properties: omit")),
+            Some(NullProperties::Omit)
+        );
+        assert_eq!(
+            parse(&format!("{base}null_properties:\n  marker: 'null'")),
+            Some(NullProperties::Marker("null".to_string()))
+        );
+        assert!(matches!(
+            parse(&format!("{base}null_properties:\n  default:\n    name: ''")),
+            Some(NullProperties::Default(v)) if v["name"] == ""
+        ));
+    }
+
+    #[test]
+    fn test_freshness_tilejson() {
+        let info = serde_yaml::from_str::<TableInfo>(indoc! {"
+            schema: public
+            table: roads
+            srid: 4326
+            geometry_column: geom
+            version: 2.1.0
+            updated: '2024-01-31T12:00:00Z'
+        "})
+        .unwrap();
+        let tj = info.to_tilejson("roads".to_string());
+        assert_eq!(tj.version.as_deref(), Some("2.1.0"));
+        assert_eq!(tj.other[UPDATED_KEY], "2024-01-31T12:00:00Z");
+        assert!(!tj.other.contains_key(CREATED_KEY));
+
+        // absent unless configured
+        let tj = TableInfo::default().to_tilejson("roads".to_string());
+        assert_eq!(tj.version, None);
+        assert!(!tj.other.contains_key(UPDATED_KEY));
+    }
+}
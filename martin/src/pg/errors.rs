@@ -1,11 +1,13 @@
 use std::io;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use deadpool_postgres::tokio_postgres::Error as TokioPgError;
 use deadpool_postgres::{BuildError, PoolError};
 use martin_tile_utils::TileCoord;
 use semver::Version;
 
+use crate::pg::config_table::{MAX_PRECISION, MIN_PRECISION};
 use crate::pg::utils::query_to_json;
 use crate::source::UrlQuery;
 
@@ -61,6 +63,15 @@ pub enum PgError {
     #[error("Invalid extent setting in source {0} for table {1}: extent=0")]
     InvalidTableExtent(String, String),
 
+    #[error("Invalid precision setting in source {0} for table {1}: precision={2}, must be between {min} and {max}", min = MIN_PRECISION, max = MAX_PRECISION)]
+    InvalidTablePrecision(String, String, u8),
+
+    #[error("Source {0} for table {1} cannot set both extent and precision")]
+    ConflictingTablePrecision(String, String),
+
+    #[error("Invalid query limits of source {0}: {1}")]
+    InvalidQueryLimit(String, &'static str),
+
     #[error("Error preparing a query for the tile '{1}' ({2}): {3} {0}")]
     PrepareQueryError(#[source] TokioPgError, String, String, String),
 
@@ -70,6 +81,69 @@ pub enum PgError {
     #[error(r"Unable to get tile {2:#} with {json_query:?} params from {1}: {0}", json_query=query_to_json(.3.as_ref()))]
     GetTileWithQueryError(#[source] TokioPgError, String, TileCoord, Option<UrlQuery>),
 
+    #[error(r"Unable to count features of tile {2:#} from {1}: {0}")]
+    GetFeatureCountError(#[source] TokioPgError, String, TileCoord),
+
+    #[error(r"Unable to explain the query of tile {2:#} from {1}: {0}")]
+    ExplainQueryError(#[source] TokioPgError, String, TileCoord),
+
+    #[error("Table geometry column {0} was reported more than once by the database")]
+    DuplicateGeometryColumn(String),
+
     #[error("Configuration error: {0}")]
     ConfigError(&'static str),
+
+    #[error("Source {0} has an invalid NULL default for property {1}: {2}")]
+    InvalidNullDefault(String, String, String),
+
+    #[error("Source {0} has an invalid curve_tolerance={1}, must be a positive number")]
+    InvalidCurveTolerance(String, f64),
+
+    #[error("Source {0} has an invalid densify={1}, must be a positive number of meters")]
+    InvalidDensify(String, f64),
+
+    #[error("Source {0} has an invalid geometry transform: {1}")]
+    InvalidGeometryTransform(String, String),
+
+    #[error("Source {0} has invalid point_clusters: {1}")]
+    InvalidPointClusters(String, String),
+
+    #[error("Source {0} has an invalid tile_grid: {1}")]
+    InvalidTileGrid(String, String),
+
+    #[error("Source {0} has an invalid tile_offset: {1}")]
+    InvalidTileOffset(String, String),
+
+    #[error("Tile {1:#} of source {0} is outside of its tile grid after applying the tile_offset")]
+    TileOutsideOffsetGrid(String, TileCoord),
+
+    #[error(
+        "Source {0} is not published because table {1} has geometries in SRIDs {2:?}. Fix the data, or set mixed_srids: transform to transform them to the source SRID"
+    )]
+    MixedGeometrySrids(String, String, Vec<i32>),
+
+    #[error(
+        "Source {0} is not published because its id_column {1} has duplicate values in table {2}. Use a unique column, or set duplicate_ids: warn to publish it anyway"
+    )]
+    DuplicateFeatureIds(String, String, String),
+
+    #[error("Source {0} cannot generate tiles because its table column {1} no longer exists")]
+    MissingTableColumn(String, String),
+
+    #[error("Tile query of source {0} was canceled after its statement timeout of {1:?}")]
+    StatementTimeout(String, Duration),
+
+    #[error("Source {0} must set refresh.materialized_view because it is not a table source")]
+    MatviewRequired(String),
+
+    #[error("Source {0} cannot refresh {1} because it is not a materialized view")]
+    MatviewNotFound(String, String),
+
+    #[error(
+        "Source {0} cannot refresh materialized view {1} concurrently because it has no unique index on plain columns without a WHERE clause"
+    )]
+    MatviewWithoutUniqueIndex(String, String),
+
+    #[error(r"Unable to refresh materialized view {2} of source {1}: {0}")]
+    MatviewRefreshError(#[source] TokioPgError, String, String),
 }
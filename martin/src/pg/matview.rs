@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::info;
+use postgres_protocol::escape::escape_identifier;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::pg::PgError::{
+    MatviewNotFound, MatviewRefreshError, MatviewRequired, MatviewWithoutUniqueIndex, PostgresError,
+};
+use crate::pg::PgResult;
+use crate::pg::pool::PgPool;
+
+/// Refresh of the materialized view behind a source with `REFRESH MATERIALIZED VIEW CONCURRENTLY`.
+/// Tiles are served from the previous contents of the view while the refresh runs.
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatviewRefresh {
+    /// Materialized view to refresh, e.g. `public.roads_summary`. Required for function sources [default: the table of a table source]
+    pub materialized_view: Option<String>,
+    /// How often to refresh the view, in seconds. If not set, the view is only refreshed
+    /// with the `/refresh/{source_id}` endpoint, which requires the `refresh_token` setting.
+    pub interval_secs: Option<u64>,
+}
+
+/// A materialized view that was checked to support concurrent refreshes.
+/// Clones share a lock, so that only one refresh of the view runs at a time.
+#[derive(Clone, Debug)]
+pub struct Matview {
+    /// Schema-qualified and quoted name of the view
+    name: String,
+    interval: Option<Duration>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Matview {
+    /// Check that the configured view is a materialized view with a unique index,
+    /// as required by `REFRESH MATERIALIZED VIEW CONCURRENTLY`.
+    /// Table sources refresh their own table unless another view is configured.
+    pub async fn new(
+        pool: &PgPool,
+        id: &str,
+        cfg: &MatviewRefresh,
+        table: Option<(&str, &str)>,
+    ) -> PgResult<Self> {
+        let view = match (&cfg.materialized_view, table) {
+            (Some(view), _) => view.clone(),
+            (None, Some((schema, table))) => {
+                format!("{}.{}", escape_identifier(schema), escape_identifier(table))
+            }
+            (None, None) => return Err(MatviewRequired(id.to_string())),
+        };
+        let conn = pool.get().await?;
+        let row = conn
+            .query_opt(
+                r"
+SELECT format('%I.%I', ns.nspname, class.relname),
+       class.relkind = 'm',
+       EXISTS (SELECT 1
+               FROM pg_index ix
+               WHERE ix.indrelid = class.oid
+                 AND ix.indisunique
+                 AND ix.indpred IS NULL
+                 AND ix.indexprs IS NULL)
+FROM pg_class class
+         JOIN pg_namespace ns ON ns.oid = class.relnamespace
+WHERE class.oid = to_regclass($1)",
+                &[&view],
+            )
+            .await
+            .map_err(|e| PostgresError(e, "querying materialized view"))?;
+        let Some(row) = row.filter(|row| row.get::<_, bool>(1)) else {
+            return Err(MatviewNotFound(id.to_string(), view));
+        };
+        if !row.get::<_, bool>(2) {
+            return Err(MatviewWithoutUniqueIndex(id.to_string(), view));
+        }
+        Ok(Self {
+            name: row.get(0),
+            interval: cfg.interval_secs.map(|v| Duration::from_secs(v.max(1))),
+            lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    #[must_use]
+    pub fn get_interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// Refresh the view, waiting for a refresh that is already running to complete first.
+    /// Tile queries keep reading the previous contents until the refresh is done.
+    pub async fn refresh(&self, pool: &PgPool, id: &str) -> PgResult<Duration> {
+        let _guard = self.lock.lock().await;
+        let conn = pool.get().await?;
+        let start = Instant::now();
+        conn.batch_execute(&format!(
+            "REFRESH MATERIALIZED VIEW CONCURRENTLY {}",
+            self.name
+        ))
+        .await
+        .map_err(|e| MatviewRefreshError(e, id.to_string(), self.name.clone()))?;
+        let elapsed = start.elapsed();
+        info!(
+            "Refreshed materialized view {} of source {id} in {elapsed:.2?}",
+            self.name
+        );
+        Ok(elapsed)
+    }
+}
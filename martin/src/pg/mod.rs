@@ -3,16 +3,26 @@ mod config;
 mod config_function;
 mod config_table;
 mod errors;
+mod matview;
 mod pg_source;
 mod pool;
 mod query_functions;
 mod query_tables;
 mod tls;
+pub use tls::redact_conn_str;
 mod utils;
 
-pub use config::{PgCfgPublish, PgCfgPublishFuncs, PgCfgPublishTables, PgConfig, PgSslCerts};
+pub use builder::IntrospectedTables;
+pub use config::{
+    DuplicateGeometryPolicy, EmptyTablePolicy, MissingColumnPolicy, PgCfgPublish,
+    PgCfgPublishFuncs, PgCfgPublishTables, PgConfig, PgSslCerts,
+};
 pub use config_function::FunctionInfo;
-pub use config_table::TableInfo;
+pub use config_table::{
+    Antimeridian, CurveToLine, DuplicateIds, GeometryTransform, LABELS_LAYER_SUFFIX, LabelPoints,
+    MinFeaturesPerTile, MixedSrids, NullProperties, POINT_COUNT_PROPERTY, PointClusters, TableInfo,
+};
 pub use errors::{PgError, PgResult};
+pub use matview::MatviewRefresh;
 pub use pool::{POOL_SIZE_DEFAULT, PgPool};
 pub use query_functions::query_available_function;
@@ -1,16 +1,47 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
 use async_trait::async_trait;
+use deadpool_postgres::GenericClient;
+use deadpool_postgres::tokio_postgres::error::SqlState;
 use deadpool_postgres::tokio_postgres::types::{ToSql, Type};
-use log::debug;
+use log::{Level, log, warn};
 use martin_tile_utils::Encoding::Uncompressed;
 use martin_tile_utils::Format::Mvt;
 use martin_tile_utils::{TileCoord, TileInfo};
 use tilejson::TileJSON;
 
 use crate::MartinResult;
-use crate::pg::PgError::{GetTileError, GetTileWithQueryError, PrepareQueryError};
+use crate::pg::PgError::{
+    ExplainQueryError, GetFeatureCountError, GetTileError, GetTileWithQueryError,
+    MissingTableColumn, PostgresError, PrepareQueryError, StatementTimeout, TileOutsideOffsetGrid,
+};
+use crate::pg::config::MissingColumnPolicy;
+use crate::pg::config_table::TableInfo;
+use crate::pg::matview::Matview;
 use crate::pg::pool::PgPool;
-use crate::pg::utils::query_to_json;
-use crate::source::{Source, TileData, TileInfoSource, UrlQuery};
+use crate::pg::query_tables::{
+    DEFAULT_BUFFER, DEFAULT_CLIP_GEOM, DEFAULT_EXTENT, scale_to_tile_size, table_to_count_sql,
+    table_to_sql,
+};
+use crate::pg::utils::{on_slow_completion, query_to_json};
+use crate::pg::{PgError, PgResult};
+use crate::source::{
+    CatalogSourceConfig, Source, TILE_SIZE_DEFAULT, TILE_SIZES, TileData, TileInfoSource, UrlQuery,
+};
+use crate::utils::QueryLimit;
+
+/// URL query parameters used by table sources to re-generate their query, see [`PgSource::get_sql`]
+const TABLE_QUERY_PARAMS: [&str; 4] = ["fields", "tile_size", "buffer", "clip"];
+
+/// Settings for logging tile queries that take too long to complete
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlowQueryLog {
+    pub threshold: Duration,
+    pub include_sql: bool,
+}
 
 #[derive(Clone, Debug)]
 pub struct PgSource {
@@ -18,82 +49,361 @@ pub struct PgSource {
     info: PgSqlInfo,
     pool: PgPool,
     tilejson: TileJSON,
+    slow_query: Option<SlowQueryLog>,
+    /// Shared by all clones of the source, so that a refresh after a schema change applies to all of them
+    table: Option<Arc<RwLock<Arc<TableQuery>>>>,
+    missing_columns: MissingColumnPolicy,
+    query_limit: Option<QueryLimit>,
+    query_defaults: Option<UrlQuery>,
+    immutable: bool,
+    compress: bool,
+    matview: Option<Matview>,
+    schema: Option<String>,
+    group: Option<String>,
+    debug: bool,
+    /// Comment prefixed to each query of this source, if enabled
+    query_comment: Option<String>,
+    statement_timeout: Option<Duration>,
+}
+
+/// Table source definition, used to re-generate the SQL query for per-request options
+#[derive(Clone, Debug)]
+struct TableQuery {
+    info: TableInfo,
+    /// SQL query of the tiles without any per-request options
+    sql: String,
+    max_feature_count: Option<usize>,
+    /// SQL query to count the features in a tile up to the minimum of a rendered tile, if configured
+    min_features_sql: Option<String>,
+}
+
+impl TableQuery {
+    fn new(
+        info: TableInfo,
+        sql: String,
+        supports_tile_margin: bool,
+        max_feature_count: Option<usize>,
+    ) -> Self {
+        let min_features_sql = info
+            .min_features_per_tile
+            .map(|v| table_to_count_sql(&info, supports_tile_margin, Some(v.count)));
+        Self {
+            info,
+            sql,
+            max_feature_count,
+            min_features_sql,
+        }
+    }
+
+    /// Tile of the grid to query for the requested tile, i.e. the requested tile moved by the `tile_offset` if any.
+    /// Returns `None` if the moved tile does not exist in the grid.
+    fn grid_tile(&self, xyz: TileCoord) -> Option<TileCoord> {
+        match self.info.tile_offset {
+            Some(offset) => offset.apply(xyz, self.info.tile_grid.unwrap_or_default()),
+            None => Some(xyz),
+        }
+    }
 }
 
 impl PgSource {
     #[must_use]
-    pub fn new(id: String, info: PgSqlInfo, tilejson: TileJSON, pool: PgPool) -> Self {
+    pub fn new(
+        id: String,
+        info: PgSqlInfo,
+        tilejson: TileJSON,
+        pool: PgPool,
+        slow_query: Option<SlowQueryLog>,
+    ) -> Self {
         Self {
             id,
             info,
             pool,
             tilejson,
+            slow_query,
+            table: None,
+            missing_columns: MissingColumnPolicy::default(),
+            query_limit: None,
+            query_defaults: None,
+            immutable: false,
+            compress: true,
+            matview: None,
+            schema: None,
+            group: None,
+            debug: false,
+            query_comment: None,
+            statement_timeout: None,
         }
     }
-}
 
-#[async_trait]
-impl Source for PgSource {
-    fn get_id(&self) -> &str {
-        &self.id
+    /// Set the database schema of the table or function
+    #[must_use]
+    pub fn with_schema(mut self, schema: String) -> Self {
+        self.schema = Some(schema);
+        self
     }
 
-    fn get_tilejson(&self) -> &TileJSON {
-        &self.tilejson
+    /// Set the catalog group of related sources
+    #[must_use]
+    pub fn with_group(mut self, group: Option<String>) -> Self {
+        self.group = group;
+        self
     }
 
-    fn get_tile_info(&self) -> TileInfo {
-        TileInfo::new(Mvt, Uncompressed)
+    /// Log the queries of this source at the `info` level instead of `debug`,
+    /// and allow table sources to disable geometry clipping per request with `?clip=false`
+    #[must_use]
+    pub fn with_debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
     }
 
-    fn clone_source(&self) -> TileInfoSource {
-        Box::new(self.clone())
+    /// Prefix each query of this source with a comment containing the source ID,
+    /// so that its load can be attributed in `pg_stat_statements`
+    #[must_use]
+    pub fn with_query_comment(mut self, enabled: bool) -> Self {
+        self.query_comment = enabled.then(|| query_comment(&self.id));
+        self
     }
 
-    fn support_url_query(&self) -> bool {
-        self.info.use_url_query
+    /// Cancel the tile queries of this source running longer than the timeout
+    #[must_use]
+    pub fn with_statement_timeout(mut self, statement_timeout: Option<Duration>) -> Self {
+        self.statement_timeout = statement_timeout;
+        self
     }
 
-    async fn get_tile(
+    /// Mark the tiles of this source as never changing
+    #[must_use]
+    pub fn with_immutable(mut self, immutable: bool) -> Self {
+        self.immutable = immutable;
+        self
+    }
+
+    /// Allow the tiles of this source to be compressed for clients that accept it
+    #[must_use]
+    pub fn with_compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Refresh the materialized view behind this source on a schedule or on request
+    #[must_use]
+    pub fn with_matview(mut self, matview: Option<Matview>) -> Self {
+        self.matview = matview;
+        self
+    }
+
+    /// Limit the number of tile queries of this source running at the same time
+    #[must_use]
+    pub fn with_query_limit(mut self, query_limit: Option<QueryLimit>) -> Self {
+        self.query_limit = query_limit;
+        self
+    }
+
+    /// Use these URL query parameter values unless the request provides its own
+    #[must_use]
+    pub fn with_query_defaults(mut self, query_defaults: Option<UrlQuery>) -> Self {
+        self.query_defaults = query_defaults;
+        self
+    }
+
+    /// Allow the table source query to be customized per request, e.g. with the `fields` URL parameter
+    #[must_use]
+    pub fn with_table_info(mut self, info: TableInfo, max_feature_count: Option<usize>) -> Self {
+        self.table = Some(Arc::new(RwLock::new(Arc::new(TableQuery::new(
+            info,
+            self.info.sql_query.clone(),
+            self.pool.supports_tile_margin(),
+            max_feature_count,
+        )))));
+        self
+    }
+
+    /// Set what happens when a table source query fails because a column no longer exists
+    #[must_use]
+    pub fn with_missing_columns(mut self, missing_columns: MissingColumnPolicy) -> Self {
+        self.missing_columns = missing_columns;
+        self
+    }
+
+    /// Get the current table source definition, if this is a table source
+    fn get_table(&self) -> Option<Arc<TableQuery>> {
+        self.table
+            .as_ref()
+            .map(|v| v.read().expect("table query lock poisoned").clone())
+    }
+
+    /// Get the SQL query for this request. Table sources re-generate their query
+    /// if the request limits the returned properties with `?fields=a,b`,
+    /// asks for a non-default logical tile size with `?tile_size=512`,
+    /// overrides the buffer with `?buffer=128` up to the source's `max_buffer`,
+    /// or overrides geometry clipping with `?clip=false` in debug mode.
+    fn get_sql<'a>(
+        &'a self,
+        table: Option<&'a TableQuery>,
+        url_query: Option<&UrlQuery>,
+    ) -> Cow<'a, str> {
+        let Some(table) = table else {
+            return Cow::Borrowed(&self.info.sql_query);
+        };
+        let fields = url_query.and_then(|q| q.get("fields"));
+        let tile_size = url_query
+            .and_then(|q| q.get("tile_size"))
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|v| *v != TILE_SIZE_DEFAULT && TILE_SIZES.contains(v));
+        let clip_geom = self
+            .debug
+            .then(|| url_query.and_then(|q| q.get("clip")))
+            .flatten()
+            .and_then(|v| v.parse::<bool>().ok())
+            .filter(|v| *v != table.info.clip_geom.unwrap_or(DEFAULT_CLIP_GEOM));
+        let buffer = requested_buffer(&table.info, url_query)
+            .filter(|v| *v != table.info.buffer.unwrap_or(DEFAULT_BUFFER));
+        if fields.is_none() && tile_size.is_none() && clip_geom.is_none() && buffer.is_none() {
+            return Cow::Borrowed(&table.sql);
+        }
+
+        let mut info = match fields {
+            Some(fields) => select_fields(&table.info, fields),
+            None => table.info.clone(),
+        };
+        // The requested buffer is relative to the default tile size, like the configured one
+        if buffer.is_some() {
+            info.buffer = buffer;
+        }
+        if let Some(tile_size) = tile_size {
+            scale_to_tile_size(&mut info, tile_size);
+        }
+        if clip_geom.is_some() {
+            info.clip_geom = clip_geom;
+        }
+        let supports_tile_margin = self.pool.supports_tile_margin();
+        Cow::Owned(table_to_sql(
+            &self.id,
+            &info,
+            supports_tile_margin,
+            table.max_feature_count,
+        ))
+    }
+
+    /// Prefix the query with the comment of this source, if enabled
+    fn with_comment<'a>(&self, sql: Cow<'a, str>) -> Cow<'a, str> {
+        match &self.query_comment {
+            Some(comment) => Cow::Owned(format!("{comment}\n{sql}")),
+            None => sql,
+        }
+    }
+
+    async fn query_tile(
         &self,
+        table: Option<&TableQuery>,
         xyz: TileCoord,
         url_query: Option<&UrlQuery>,
-    ) -> MartinResult<TileData> {
-        let conn = self.pool.get().await?;
-        let param_types: &[Type] = if self.support_url_query() {
-            &[Type::INT2, Type::INT8, Type::INT8, Type::JSON]
-        } else {
-            &[Type::INT2, Type::INT8, Type::INT8]
+    ) -> PgResult<TileData> {
+        let mut conn = self.pool.get().await?;
+        let Some(timeout) = self.statement_timeout else {
+            return self.query_tile_with(&conn, table, xyz, url_query).await;
         };
 
-        let sql = &self.info.sql_query;
-        let prep_query = conn
-            .prepare_typed_cached(sql, param_types)
+        // SET LOCAL only lasts until the end of the transaction, so the pooled connection keeps its own timeout
+        let tx = conn
+            .transaction()
+            .await
+            .map_err(|e| PostgresError(e, "starting a tile query transaction"))?;
+        tx.batch_execute(&format!(
+            "SET LOCAL statement_timeout = {}",
+            timeout.as_millis()
+        ))
+        .await
+        .map_err(|e| PostgresError(e, "setting the statement timeout"))?;
+        let tile = self
+            .query_tile_with(&tx, table, xyz, url_query)
             .await
             .map_err(|e| {
-                PrepareQueryError(
-                    e,
-                    self.id.to_string(),
-                    self.info.signature.to_string(),
-                    self.info.sql_query.to_string(),
-                )
+                if is_query_canceled(&e) {
+                    StatementTimeout(self.id.clone(), timeout)
+                } else {
+                    e
+                }
             })?;
+        tx.commit()
+            .await
+            .map_err(|e| PostgresError(e, "completing a tile query transaction"))?;
+        Ok(tile)
+    }
+
+    async fn query_tile_with(
+        &self,
+        conn: &impl GenericClient,
+        table: Option<&TableQuery>,
+        xyz: TileCoord,
+        url_query: Option<&UrlQuery>,
+    ) -> PgResult<TileData> {
+        // Counted with the same connection, so that the statement timeout also applies to the count
+        if let Some(table) = table {
+            if !self.has_min_features(conn, table, xyz).await? {
+                return Ok(TileData::new());
+            }
+        }
+
+        let param_types: &[Type] = if self.info.use_url_query {
+            &[Type::INT2, Type::INT8, Type::INT8, Type::JSON]
+        } else {
+            &[Type::INT2, Type::INT8, Type::INT8]
+        };
 
-        let tile = if self.support_url_query() {
+        let sql = self.get_sql(table, url_query);
+        // Only the configured query is kept prepared on the connection. Queries generated for the
+        // URL query parameters, e.g. one for each `?buffer=` value, would grow the statement cache without bounds.
+        let is_configured = matches!(sql, Cow::Borrowed(_));
+        let sql = self.with_comment(sql);
+        let sql = sql.as_ref();
+        let prep_query = if is_configured {
+            conn.prepare_typed_cached(sql, param_types).await
+        } else {
+            conn.prepare_typed(sql, param_types).await
+        };
+        let prep_query = prep_query.map_err(|e| {
+            PrepareQueryError(
+                e,
+                self.id.to_string(),
+                self.info.signature.to_string(),
+                sql.to_string(),
+            )
+        })?;
+
+        let tile = if self.info.use_url_query {
             let json = query_to_json(url_query);
-            debug!("SQL: {sql} [{xyz}, {json:?}]");
+            log!(
+                self.get_log_level(),
+                "SQL for source {}: {sql} [{xyz}, {json:?}]",
+                self.id
+            );
             let params: &[&(dyn ToSql + Sync)] = &[
                 &i16::from(xyz.z),
                 &i64::from(xyz.x),
                 &i64::from(xyz.y),
                 &json,
             ];
-            conn.query_opt(&prep_query, params).await
+            on_slow_completion(
+                conn.query_opt(&prep_query, params),
+                self.slow_query.map(|v| v.threshold),
+                |elapsed| self.warn_slow_query(xyz, sql, elapsed),
+            )
+            .await
         } else {
-            debug!("SQL: {sql} [{xyz}]");
-            conn.query_opt(
-                &prep_query,
-                &[&i16::from(xyz.z), &i64::from(xyz.x), &i64::from(xyz.y)],
+            log!(
+                self.get_log_level(),
+                "SQL for source {}: {sql} [{xyz}]",
+                self.id
+            );
+            on_slow_completion(
+                conn.query_opt(
+                    &prep_query,
+                    &[&i16::from(xyz.z), &i64::from(xyz.x), &i64::from(xyz.y)],
+                ),
+                self.slow_query.map(|v| v.threshold),
+                |elapsed| self.warn_slow_query(xyz, sql, elapsed),
             )
             .await
         };
@@ -101,7 +411,7 @@ impl Source for PgSource {
         let tile = tile
             .map(|row| row.and_then(|r| r.get::<_, Option<TileData>>(0)))
             .map_err(|e| {
-                if self.support_url_query() {
+                if self.info.use_url_query {
                     GetTileWithQueryError(e, self.id.to_string(), xyz, url_query.cloned())
                 } else {
                     GetTileError(e, self.id.to_string(), xyz)
@@ -111,6 +421,324 @@ impl Source for PgSource {
 
         Ok(tile)
     }
+
+    /// Reload the columns of a table source after its query failed because of a missing column,
+    /// and remove the properties whose columns no longer exist, e.g. because a migration dropped them.
+    /// Returns `None` if all columns still exist, i.e. the query failed for another reason.
+    async fn refresh_table(&self, table: &TableQuery) -> PgResult<Option<Arc<TableQuery>>> {
+        let conn = self.pool.get().await?;
+        let columns: HashSet<String> = conn
+            .query(
+                r"
+SELECT attname::text
+FROM pg_attribute
+WHERE attrelid = to_regclass(quote_ident($1) || '.' || quote_ident($2))
+  AND attnum > 0
+  AND NOT attisdropped",
+                &[&table.info.schema, &table.info.table],
+            )
+            .await
+            .map_err(|e| PostgresError(e, "querying table columns"))?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        let mut info = table.info.clone();
+        let exists =
+            |name: &str| columns.contains(info.prop_mapping.get(name).map_or(name, String::as_str));
+        // Tiles cannot be generated without these columns
+        for column in [Some(&info.geometry_column), info.id_column.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if !exists(column) {
+                return Err(MissingTableColumn(self.id.clone(), column.clone()));
+            }
+        }
+        let missing: Vec<String> = info
+            .properties
+            .iter()
+            .flatten()
+            .map(|(name, _)| name.clone())
+            .filter(|name| !exists(name))
+            .collect();
+        if missing.is_empty() {
+            return Ok(None);
+        }
+
+        warn!(
+            "Columns {} of source {} no longer exist, removing them from the tiles",
+            missing.join(", "),
+            self.id
+        );
+        if let Some(properties) = &mut info.properties {
+            properties.retain(|name, _| !missing.contains(name));
+        }
+        let supports_tile_margin = self.pool.supports_tile_margin();
+        let sql = table_to_sql(
+            &self.id,
+            &info,
+            supports_tile_margin,
+            table.max_feature_count,
+        );
+        let refreshed = Arc::new(TableQuery::new(
+            info,
+            sql,
+            supports_tile_margin,
+            table.max_feature_count,
+        ));
+        if let Some(lock) = &self.table {
+            *lock.write().expect("table query lock poisoned") = refreshed.clone();
+        }
+        Ok(Some(refreshed))
+    }
+
+    /// Count the features of a tile with the count query of a table source
+    async fn query_count(
+        &self,
+        conn: &impl GenericClient,
+        sql: &str,
+        xyz: TileCoord,
+    ) -> PgResult<u64> {
+        let sql = self.with_comment(Cow::Borrowed(sql));
+        let sql = sql.as_ref();
+        let prep_query = conn
+            .prepare_typed_cached(sql, &[Type::INT2, Type::INT8, Type::INT8])
+            .await
+            .map_err(|e| {
+                PrepareQueryError(
+                    e,
+                    self.id.clone(),
+                    self.info.signature.clone(),
+                    sql.to_string(),
+                )
+            })?;
+        log!(
+            self.get_log_level(),
+            "SQL for source {}: {sql} [{xyz}]",
+            self.id
+        );
+        let count: i64 = conn
+            .query_one(
+                &prep_query,
+                &[&i16::from(xyz.z), &i64::from(xyz.x), &i64::from(xyz.y)],
+            )
+            .await
+            .and_then(|row| row.try_get(0))
+            .map_err(|e| GetFeatureCountError(e, self.id.clone(), xyz))?;
+        Ok(count.unsigned_abs())
+    }
+
+    /// Check whether the tile has the configured minimum of features to be rendered at its zoom level
+    async fn has_min_features(
+        &self,
+        conn: &impl GenericClient,
+        table: &TableQuery,
+        xyz: TileCoord,
+    ) -> PgResult<bool> {
+        let Some((min_features, sql)) = table
+            .info
+            .min_features_per_tile
+            .filter(|v| v.applies_to(xyz.z))
+            .zip(table.min_features_sql.as_deref())
+        else {
+            return Ok(true);
+        };
+        let count = self.query_count(conn, sql, xyz).await?;
+        Ok(count >= min_features.count as u64)
+    }
+
+    fn warn_slow_query(&self, xyz: TileCoord, sql: &str, elapsed: Duration) {
+        let ms = elapsed.as_millis();
+        if self.slow_query.is_some_and(|v| v.include_sql) {
+            warn!(
+                "Slow tile query for source {} at {xyz:#} took {ms}ms: {sql}",
+                self.id
+            );
+        } else {
+            warn!(
+                "Slow tile query for source {} at {xyz:#} took {ms}ms",
+                self.id
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Source for PgSource {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn get_tilejson(&self) -> &TileJSON {
+        &self.tilejson
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        TileInfo::new(Mvt, Uncompressed)
+    }
+
+    fn clone_source(&self) -> TileInfoSource {
+        Box::new(self.clone())
+    }
+
+    fn support_url_query(&self) -> bool {
+        self.info.use_url_query
+    }
+
+    /// Functions receive the `tile_size` parameter as part of the URL query, and may handle it as needed.
+    fn supports_tile_size(&self) -> bool {
+        self.info.use_url_query || self.table.is_some()
+    }
+
+    fn url_query_params(&self) -> &[&'static str] {
+        if self.table.is_some() {
+            &TABLE_QUERY_PARAMS
+        } else {
+            &[]
+        }
+    }
+
+    fn get_schema(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    fn get_group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    fn is_immutable(&self) -> bool {
+        self.immutable
+    }
+
+    fn is_compressible(&self) -> bool {
+        self.compress
+    }
+
+    fn get_log_level(&self) -> Level {
+        if self.debug {
+            Level::Info
+        } else {
+            Level::Debug
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.get_table()
+            .is_some_and(|t| t.info.is_empty == Some(true))
+    }
+
+    fn get_dimensions(&self) -> Option<&'static str> {
+        self.get_table().and_then(|t| t.info.get_dimensions())
+    }
+
+    fn get_catalog_config(&self) -> Option<CatalogSourceConfig> {
+        let table = self.get_table()?;
+        let info = &table.info;
+        Some(CatalogSourceConfig {
+            srid: Some(info.srid),
+            extent: Some(info.get_extent().unwrap_or(DEFAULT_EXTENT)),
+            buffer: Some(info.buffer.unwrap_or(DEFAULT_BUFFER)),
+            clip_geom: Some(info.clip_geom.unwrap_or(DEFAULT_CLIP_GEOM)),
+            geometry_type: info.geometry_type.clone(),
+            id_column: info.id_column.clone(),
+            prop_mapping: Some(info.prop_mapping.clone().into_iter().collect()),
+        })
+    }
+
+    /// Functions receive the `fields` parameter as part of the URL query, and may handle it as needed.
+    fn supports_fields_filter(&self) -> bool {
+        self.info.use_url_query || self.table.is_some()
+    }
+
+    async fn get_tile(
+        &self,
+        xyz: TileCoord,
+        url_query: Option<&UrlQuery>,
+    ) -> MartinResult<TileData> {
+        let url_query = merge_query_defaults(self.query_defaults.as_ref(), url_query);
+        let url_query = url_query.as_deref();
+        // Wait for a permit before taking a connection from the pool, so waiting requests do not hold one
+        let _permit = match &self.query_limit {
+            Some(limit) => Some(limit.acquire(&self.id).await?),
+            None => None,
+        };
+
+        let table = self.get_table();
+        let xyz = match &table {
+            Some(table) => match table.grid_tile(xyz) {
+                Some(xyz) => xyz,
+                None => return Ok(TileData::new()),
+            },
+            None => xyz,
+        };
+        let result = self.query_tile(table.as_deref(), xyz, url_query).await;
+        if let (Err(e), Some(table)) = (&result, &table) {
+            if self.missing_columns == MissingColumnPolicy::Refresh && is_undefined_column(e) {
+                if let Some(table) = self.refresh_table(table).await? {
+                    // Retry only once, so a column missing for another reason fails the request
+                    return Ok(self.query_tile(Some(&table), xyz, url_query).await?);
+                }
+            }
+        }
+        Ok(result?)
+    }
+
+    fn supports_feature_count(&self) -> bool {
+        self.get_table()
+            .is_some_and(|v| v.info.feature_count.unwrap_or_default())
+    }
+
+    fn get_refresh_interval(&self) -> Option<Duration> {
+        self.matview.as_ref().and_then(Matview::get_interval)
+    }
+
+    async fn refresh(&self) -> MartinResult<Option<Duration>> {
+        match &self.matview {
+            Some(matview) => Ok(Some(matview.refresh(&self.pool, &self.id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Table sources run `EXPLAIN (FORMAT JSON)` on the same query used to generate the tile.
+    async fn explain_tile(
+        &self,
+        xyz: TileCoord,
+        url_query: Option<&UrlQuery>,
+    ) -> MartinResult<Option<serde_json::Value>> {
+        let Some(table) = self.get_table() else {
+            return Ok(None);
+        };
+        let xyz = table
+            .grid_tile(xyz)
+            .ok_or_else(|| TileOutsideOffsetGrid(self.id.clone(), xyz))?;
+        let url_query = merge_query_defaults(self.query_defaults.as_ref(), url_query);
+        let sql = format!(
+            "EXPLAIN (FORMAT JSON) {}",
+            self.with_comment(self.get_sql(Some(&table), url_query.as_deref()))
+        );
+        let conn = self.pool.get().await?;
+        let prep_query = conn
+            .prepare_typed(&sql, &[Type::INT2, Type::INT8, Type::INT8])
+            .await
+            .map_err(|e| {
+                PrepareQueryError(e, self.id.clone(), self.info.signature.clone(), sql.clone())
+            })?;
+        log!(
+            self.get_log_level(),
+            "SQL for source {}: {sql} [{xyz}]",
+            self.id
+        );
+        let plan = conn
+            .query_one(
+                &prep_query,
+                &[&i16::from(xyz.z), &i64::from(xyz.x), &i64::from(xyz.y)],
+            )
+            .await
+            .and_then(|row| row.try_get(0))
+            .map_err(|e| ExplainQueryError(e, self.id.clone(), xyz))?;
+        Ok(Some(plan))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -130,3 +758,404 @@ impl PgSqlInfo {
         }
     }
 }
+
+/// SQL comment identifying the queries of a source. Source IDs may contain any character,
+/// so the end of the comment is escaped.
+fn query_comment(id: &str) -> String {
+    format!("/* martin: {} */", id.replace("*/", "* /"))
+}
+
+/// Check if a tile query failed because the table no longer has one of the queried columns
+fn is_undefined_column(err: &PgError) -> bool {
+    match err {
+        PrepareQueryError(e, ..) | GetTileError(e, ..) | GetTileWithQueryError(e, ..) => {
+            e.code() == Some(&SqlState::UNDEFINED_COLUMN)
+        }
+        _ => false,
+    }
+}
+
+fn is_query_canceled(err: &PgError) -> bool {
+    match err {
+        GetTileError(e, ..) | GetTileWithQueryError(e, ..) | GetFeatureCountError(e, ..) => {
+            e.code() == Some(&SqlState::QUERY_CANCELED)
+        }
+        _ => false,
+    }
+}
+
+/// Add default values for any URL query parameters missing from the request.
+/// Values provided by the request always take precedence over the defaults.
+fn merge_query_defaults<'a>(
+    defaults: Option<&UrlQuery>,
+    url_query: Option<&'a UrlQuery>,
+) -> Option<Cow<'a, UrlQuery>> {
+    let Some(defaults) = defaults else {
+        return url_query.map(Cow::Borrowed);
+    };
+    let mut query = defaults.clone();
+    if let Some(url_query) = url_query {
+        query.extend(url_query.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+    Some(Cow::Owned(query))
+}
+
+/// Get the buffer requested with `?buffer=`, limited to the source's `max_buffer`.
+/// Invalid values are ignored, as is the parameter itself if the source has no `max_buffer`.
+fn requested_buffer(info: &TableInfo, url_query: Option<&UrlQuery>) -> Option<u32> {
+    let max_buffer = info.max_buffer?;
+    let buffer = url_query?.get("buffer")?.parse::<u32>().ok()?;
+    Some(buffer.min(max_buffer))
+}
+
+/// Keep only the requested properties of a table, including the EWKB and Z properties. Unknown field names are ignored.
+/// The geometry and the feature ID column are always included.
+fn select_fields(info: &TableInfo, fields: &str) -> TableInfo {
+    let fields: HashSet<&str> = fields.split(',').map(str::trim).collect();
+    let mut info = info.clone();
+    if let Some(props) = &mut info.properties {
+        props.retain(|name, _| fields.contains(name.as_str()));
+    }
+    if let Some(ewkb_property) = &info.ewkb_property {
+        if !fields.contains(ewkb_property.as_str()) {
+            info.ewkb_property = None;
+        }
+    }
+    if let Some(z_property) = &info.z_property {
+        if !fields.contains(z_property.as_str()) {
+            info.z_property = None;
+        }
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::pg::config::PgInfo as _;
+    use crate::pg::pool::tests::{test_config, test_pool};
+    use crate::pg::{MinFeaturesPerTile, PgConfig};
+
+    #[test]
+    fn test_select_fields() {
+        let props = |v: &[&str]| {
+            Some(
+                v.iter()
+                    .map(|v| ((*v).to_string(), "text".to_string()))
+                    .collect::<BTreeMap<_, _>>(),
+            )
+        };
+        let info = TableInfo {
+            id_column: Some("gid".to_string()),
+            properties: props(&["gid", "name", "type"]),
+            ..Default::default()
+        };
+
+        let res = select_fields(&info, "name, type");
+        assert_eq!(res.properties, props(&["name", "type"]));
+        assert_eq!(res.id_column, info.id_column);
+
+        let res = select_fields(&info, "name,unknown");
+        assert_eq!(res.properties, props(&["name"]));
+
+        let res = select_fields(&info, "");
+        assert_eq!(res.properties, props(&[]));
+        assert_eq!(res.id_column, info.id_column);
+    }
+
+    #[test]
+    fn test_query_comment() {
+        assert_eq!(query_comment("roads"), "/* martin: roads */");
+        // a source ID cannot end the comment early
+        assert_eq!(query_comment("a*/b"), "/* martin: a* /b */");
+    }
+
+    #[test]
+    fn test_requested_buffer() {
+        let query = |v: &str| UrlQuery::from([("buffer".to_string(), v.to_string())]);
+        let mut info = TableInfo {
+            max_buffer: Some(256),
+            ..Default::default()
+        };
+        let buffer = |info: &TableInfo, v| requested_buffer(info, Some(&query(v)));
+
+        assert_eq!(buffer(&info, "0"), Some(0));
+        assert_eq!(buffer(&info, "255"), Some(255));
+        assert_eq!(buffer(&info, "256"), Some(256));
+        assert_eq!(buffer(&info, "257"), Some(256));
+        assert_eq!(buffer(&info, "4294967296"), None);
+        assert_eq!(buffer(&info, "-1"), None);
+        assert_eq!(buffer(&info, "abc"), None);
+        assert_eq!(requested_buffer(&info, None), None);
+        assert_eq!(requested_buffer(&info, Some(&UrlQuery::new())), None);
+
+        info.max_buffer = None;
+        assert_eq!(buffer(&info, "128"), None);
+    }
+
+    #[test]
+    fn test_merge_query_defaults() {
+        let query = |v: &[(&str, &str)]| {
+            v.iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect::<UrlQuery>()
+        };
+        let defaults = query(&[("year", "2024"), ("kind", "road")]);
+
+        assert_eq!(merge_query_defaults(None, None), None);
+        let req = query(&[("year", "2020")]);
+        assert_eq!(
+            merge_query_defaults(None, Some(&req)).as_deref(),
+            Some(&req)
+        );
+        assert_eq!(
+            merge_query_defaults(Some(&defaults), None).as_deref(),
+            Some(&defaults)
+        );
+        assert_eq!(
+            merge_query_defaults(Some(&defaults), Some(&req)).as_deref(),
+            Some(&query(&[("year", "2020"), ("kind", "road")]))
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_dropped_columns() -> anyhow::Result<()> {
+        let (_node, pool) = test_pool().await?;
+        let conn = pool.get().await?;
+        conn.batch_execute(
+            "CREATE TABLE points (gid int, name text, kind text, geom geometry(Point, 4326));
+             INSERT INTO points VALUES (1, 'a', 'b', ST_SetSRID(ST_MakePoint(0, 0), 4326));",
+        )
+        .await?;
+
+        let info = TableInfo {
+            schema: "public".to_string(),
+            table: "points".to_string(),
+            srid: 4326,
+            geometry_column: "geom".to_string(),
+            id_column: Some("gid".to_string()),
+            properties: Some(
+                [("name", "text"), ("kind", "text")]
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .into(),
+            ),
+            ..Default::default()
+        };
+        let source = |policy| {
+            let sql = table_to_sql("points", &info, pool.supports_tile_margin(), None);
+            PgSource::new(
+                "points".to_string(),
+                PgSqlInfo::new(sql, false, "public.points.geom".to_string()),
+                info.to_tilejson("points".to_string()),
+                pool.clone(),
+                None,
+            )
+            .with_table_info(info.clone(), None)
+            .with_missing_columns(policy)
+        };
+        let refreshed = source(MissingColumnPolicy::Refresh);
+        let failing = source(MissingColumnPolicy::Error);
+        let xyz = TileCoord { z: 0, x: 0, y: 0 };
+        let tile = refreshed.get_tile(xyz, None).await.unwrap();
+        assert!(!tile.is_empty());
+
+        conn.batch_execute("ALTER TABLE points DROP COLUMN kind")
+            .await?;
+        assert!(failing.get_tile(xyz, None).await.is_err());
+        let tile = refreshed.get_tile(xyz, None).await.unwrap();
+        assert!(!tile.is_empty());
+        let table = refreshed.get_table().unwrap();
+        assert_eq!(table.info.properties.as_ref().unwrap().len(), 1);
+        // clones of the source share the refreshed query
+        let clone = refreshed.clone_source();
+        assert!(!clone.get_tile(xyz, None).await.unwrap().is_empty());
+
+        conn.batch_execute("ALTER TABLE points DROP COLUMN gid")
+            .await?;
+        let err = refreshed.get_tile(xyz, None).await.unwrap_err();
+        assert!(err.to_string().contains("column gid no longer exists"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_comments() -> anyhow::Result<()> {
+        let (_node, pool) = test_pool().await?;
+        pool.get()
+            .await?
+            .batch_execute(
+                "CREATE TABLE roads (gid int, geom geometry(LineString, 4326));
+                 INSERT INTO roads VALUES (1, 'SRID=4326;LINESTRING(0 0, 1 1)');",
+            )
+            .await?;
+
+        let info = TableInfo {
+            schema: "public".to_string(),
+            table: "roads".to_string(),
+            srid: 4326,
+            geometry_column: "geom".to_string(),
+            id_column: Some("gid".to_string()),
+            ..Default::default()
+        };
+        let sql = table_to_sql("roads", &info, pool.supports_tile_margin(), None);
+        let source = |enabled| {
+            PgSource::new(
+                "roads".to_string(),
+                PgSqlInfo::new(sql.clone(), false, "public.roads.geom".to_string()),
+                info.to_tilejson("roads".to_string()),
+                pool.clone(),
+                None,
+            )
+            .with_table_info(info.clone(), None)
+            .with_query_comment(enabled)
+        };
+
+        let commented = source(true);
+        let table = commented.get_table();
+        let query = commented.with_comment(commented.get_sql(table.as_deref(), None));
+        assert!(query.starts_with("/* martin: roads */\nSELECT"));
+        // the commented query returns the same tile
+        let xyz = TileCoord { z: 0, x: 0, y: 0 };
+        let tile = commented.get_tile(xyz, None).await.unwrap();
+        assert!(!tile.is_empty());
+        assert_eq!(source(false).get_tile(xyz, None).await.unwrap(), tile);
+
+        let plain = source(false);
+        let query = plain.with_comment(plain.get_sql(table.as_deref(), None));
+        assert!(!query.contains("/*"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn buffer_variants_not_cached() -> anyhow::Result<()> {
+        let (_node, config) = test_config().await?;
+        // a single connection, so that all queries use the same statement cache
+        let pool = PgPool::new(&PgConfig {
+            pool_size: Some(1),
+            ..config
+        })
+        .await?;
+        pool.get()
+            .await?
+            .batch_execute(
+                "CREATE TABLE roads (gid int, geom geometry(LineString, 4326));
+                 INSERT INTO roads VALUES (1, 'SRID=4326;LINESTRING(0 0, 1 1)');",
+            )
+            .await?;
+
+        let info = TableInfo {
+            schema: "public".to_string(),
+            table: "roads".to_string(),
+            srid: 4326,
+            geometry_column: "geom".to_string(),
+            id_column: Some("gid".to_string()),
+            max_buffer: Some(256),
+            ..Default::default()
+        };
+        let sql = table_to_sql("roads", &info, pool.supports_tile_margin(), None);
+        let source = PgSource::new(
+            "roads".to_string(),
+            PgSqlInfo::new(sql, false, "public.roads.geom".to_string()),
+            info.to_tilejson("roads".to_string()),
+            pool.clone(),
+            None,
+        )
+        .with_table_info(info.clone(), None);
+        let xyz = TileCoord { z: 0, x: 0, y: 0 };
+        source.get_tile(xyz, None).await.unwrap();
+        let cached = pool.get().await?.statement_cache.size();
+
+        // each buffer generates another query, which is not kept prepared on the connection
+        for buffer in 0..20 {
+            let query = UrlQuery::from([("buffer".to_string(), buffer.to_string())]);
+            source.get_tile(xyz, Some(&query)).await.unwrap();
+        }
+        assert_eq!(pool.get().await?.statement_cache.size(), cached);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn statement_timeout() -> anyhow::Result<()> {
+        let (_node, pool) = test_pool().await?;
+
+        // a slow source, e.g. a foreign table, taking half a second for each tile
+        let source = |timeout_ms: Option<u64>| {
+            PgSource::new(
+                "slow".to_string(),
+                PgSqlInfo::new(
+                    "SELECT convert_to('tile', 'UTF8') FROM pg_sleep(0.5)".to_string(),
+                    false,
+                    "public.slow".to_string(),
+                ),
+                tilejson! { tiles: vec![] },
+                pool.clone(),
+                None,
+            )
+            .with_statement_timeout(timeout_ms.map(Duration::from_millis))
+        };
+        let xyz = TileCoord { z: 0, x: 0, y: 0 };
+
+        let err = source(Some(50)).get_tile(xyz, None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::MartinError::PostgresError(StatementTimeout(..))
+        ));
+        assert_eq!(
+            source(Some(5000)).get_tile(xyz, None).await.unwrap(),
+            b"tile"
+        );
+        assert_eq!(source(None).get_tile(xyz, None).await.unwrap(), b"tile");
+
+        // counting the minimum features of a tile is limited by the same timeout
+        pool.get()
+            .await?
+            .batch_execute(
+                "CREATE TABLE points (gid int, geom geometry(Point, 4326));
+                 INSERT INTO points VALUES (1, 'SRID=4326;POINT(0 0)');
+                 CREATE VIEW slow_points AS SELECT points.* FROM points, pg_sleep(0.5);",
+            )
+            .await?;
+        let info = TableInfo {
+            schema: "public".to_string(),
+            table: "slow_points".to_string(),
+            srid: 4326,
+            geometry_column: "geom".to_string(),
+            min_features_per_tile: Some(MinFeaturesPerTile {
+                count: 5,
+                maxzoom: None,
+            }),
+            ..Default::default()
+        };
+        let source = PgSource::new(
+            "slow_points".to_string(),
+            PgSqlInfo::new(
+                "SELECT convert_to('tile', 'UTF8')".to_string(),
+                false,
+                "public.slow_points.geom".to_string(),
+            ),
+            tilejson! { tiles: vec![] },
+            pool.clone(),
+            None,
+        )
+        .with_table_info(info, None)
+        .with_statement_timeout(Some(Duration::from_millis(50)));
+        let err = source.get_tile(xyz, None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::MartinError::PostgresError(StatementTimeout(..))
+        ));
+
+        // the timeout only applies to the queries of the source
+        let conn = pool.get().await?;
+        let timeout: String = conn.query_one("SHOW statement_timeout", &[]).await?.get(0);
+        assert_eq!(timeout, "0");
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod};
+use futures::future::try_join_all;
 use log::{info, warn};
 use postgres::config::SslMode;
 use semver::Version;
@@ -7,9 +8,9 @@ use crate::pg::PgError::{
     BadPostgisVersion, BadPostgresVersion, PostgisTooOld, PostgresError, PostgresPoolBuildError,
     PostgresPoolConnError, PostgresqlTooOld,
 };
-use crate::pg::PgResult;
 use crate::pg::config::PgConfig;
-use crate::pg::tls::{SslModeOverride, make_connector, parse_conn_str};
+use crate::pg::tls::{SslModeOverride, make_connector, parse_conn_str, redact_conn_str};
+use crate::pg::{PgError, PgResult};
 
 pub const POOL_SIZE_DEFAULT: usize = 20;
 
@@ -68,7 +69,7 @@ impl PgPool {
         let supports_tile_margin = postgis_ver >= ST_TILE_ENVELOPE_POSTGIS_VERSION;
         if !supports_tile_margin {
             warn!(
-                "PostGIS {postgis_ver} is older than {ST_TILE_ENVELOPE_POSTGIS_VERSION}. Margin parameter in ST_TileEnvelope is not supported, so tiles may be cut off at the edges."
+                "PostGIS {postgis_ver} is older than {ST_TILE_ENVELOPE_POSTGIS_VERSION}. Margin parameter in ST_TileEnvelope is not supported, so the tile envelopes are expanded with ST_Expand instead."
             );
         }
         if postgis_ver < MISSING_GEOM_FIXED_POSTGIS_VERSION {
@@ -78,12 +79,41 @@ impl PgPool {
         }
 
         info!("Connected to PostgreSQL {pg_ver} / PostGIS {postgis_ver} for source {id}");
+        drop(conn);
 
-        Ok(Self {
+        let pool = Self {
             id,
             pool,
             supports_tile_margin,
-        })
+        };
+        if let Some(count) = config.warmup_connections.filter(|v| *v > 0) {
+            pool.warmup(count).await?;
+        }
+
+        Ok(pool)
+    }
+
+    /// Open up to `count` connections at the same time, running a trivial query on each,
+    /// so that the first tile requests after startup do not wait for new connections.
+    async fn warmup(&self, count: usize) -> PgResult<()> {
+        let max_size = self.pool.status().max_size;
+        if count > max_size {
+            warn!(
+                "warmup_connections={count} is larger than the pool size {max_size} of {}, opening {max_size} connections",
+                self.id
+            );
+        }
+        // Hold all connections until each one is ready, so the pool cannot reuse them
+        let conns = try_join_all((0..count.min(max_size)).map(|_| async {
+            let conn = self.get().await?;
+            conn.simple_query("SELECT 1")
+                .await
+                .map_err(|e| PostgresError(e, "warming up a connection"))?;
+            Ok::<_, PgError>(conn)
+        }))
+        .await?;
+        info!("Opened {} connections to {}", conns.len(), self.id);
+        Ok(())
     }
 
     fn parse_config(config: &PgConfig) -> PgResult<(String, Manager)> {
@@ -99,20 +129,22 @@ impl PgPool {
             recycling_method: RecyclingMethod::Fast,
         };
 
+        // Only the redacted connection string is logged, so the password never ends up in the logs
+        let redacted = redact_conn_str(conn_str);
         let mgr = if pg_cfg.get_ssl_mode() == SslMode::Disable {
-            info!("Connecting without SSL support: {pg_cfg:?}");
+            info!("Connecting without SSL support: {redacted}");
             let connector = deadpool_postgres::tokio_postgres::NoTls {};
             Manager::from_config(pg_cfg, connector, mgr_config)
         } else {
             match ssl_mode {
                 SslModeOverride::Unmodified(_) => {
-                    info!("Connecting with SSL support: {pg_cfg:?}");
+                    info!("Connecting with SSL support: {redacted}");
                 }
                 SslModeOverride::VerifyCa => {
-                    info!("Using sslmode=verify-ca to connect: {pg_cfg:?}");
+                    info!("Using sslmode=verify-ca to connect: {redacted}");
                 }
                 SslModeOverride::VerifyFull => {
-                    info!("Using sslmode=verify-full to connect: {pg_cfg:?}");
+                    info!("Using sslmode=verify-full to connect: {redacted}");
                 }
             };
             let connector = make_connector(&config.ssl_certificates, ssl_mode)?;
@@ -134,7 +166,7 @@ impl PgPool {
     /// Indicates if `ST_TileEnvelope` supports the margin parameter.
     ///
     /// `true` if running postgis >= `3.1`
-    /// This being false indicates that tiles may be cut off at the edges.
+    /// Otherwise the tile envelopes are expanded with `ST_Expand` instead.
     #[must_use]
     pub fn supports_tile_margin(&self) -> bool {
         self.supports_tile_margin
@@ -193,37 +225,41 @@ SELECT (regexp_matches(
 }
 
 #[cfg(test)]
-mod tests {
-    use deadpool_postgres::tokio_postgres::Config;
-    use postgres::NoTls;
+pub mod tests {
     use testcontainers_modules::postgres::Postgres;
-    use testcontainers_modules::testcontainers::ImageExt as _;
     use testcontainers_modules::testcontainers::runners::AsyncRunner as _;
+    use testcontainers_modules::testcontainers::{ContainerAsync, ImageExt as _};
 
     use super::*;
 
-    #[tokio::test]
-    async fn parse_version() -> anyhow::Result<()> {
+    /// Start a `PostGIS` database for a test, and get the configuration of a connection to it.
+    /// The database is removed once the returned container is dropped.
+    pub async fn test_config() -> anyhow::Result<(ContainerAsync<Postgres>, PgConfig)> {
         let node = Postgres::default()
             .with_name("postgis/postgis")
             .with_tag("11-3.0") // purposely very old and stable
             .start()
             .await?;
-
-        let pg_config = Config::new()
-            .host(node.get_host().await?.to_string())
-            .port(node.get_host_port_ipv4(5432).await?)
-            .dbname("postgres")
-            .user("postgres")
-            .password("postgres")
-            .to_owned();
-
-        let mgr_config = ManagerConfig {
-            recycling_method: RecyclingMethod::Fast,
+        let config = PgConfig {
+            connection_string: Some(format!(
+                "postgres://postgres:postgres@{}:{}/postgres?sslmode=disable",
+                node.get_host().await?,
+                node.get_host_port_ipv4(5432).await?
+            )),
+            ..Default::default()
         };
+        Ok((node, config))
+    }
+
+    /// Start a `PostGIS` database for a test, and connect to it with the default configuration
+    pub async fn test_pool() -> anyhow::Result<(ContainerAsync<Postgres>, PgPool)> {
+        let (node, config) = test_config().await?;
+        Ok((node, PgPool::new(&config).await?))
+    }
 
-        let mgr = Manager::from_config(pg_config, NoTls, mgr_config);
-        let pool = Pool::builder(mgr).max_size(2).build()?;
+    #[tokio::test]
+    async fn parse_version() -> anyhow::Result<()> {
+        let (_node, pool) = test_pool().await?;
         let conn = pool.get().await?;
 
         let pg_version = get_postgres_version(&conn).await?;
@@ -238,4 +274,28 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn warmup_connections() -> anyhow::Result<()> {
+        let (_node, config) = test_config().await?;
+        let config = |warmup_connections| PgConfig {
+            pool_size: Some(4),
+            warmup_connections,
+            ..config.clone()
+        };
+
+        // only the connection used for the version checks is open
+        let pool = PgPool::new(&config(None)).await?;
+        assert_eq!(pool.pool.status().size, 1);
+
+        let pool = PgPool::new(&config(Some(3))).await?;
+        assert_eq!(pool.pool.status().size, 3);
+        assert_eq!(pool.pool.status().available, 3);
+
+        // limited to the pool size
+        let pool = PgPool::new(&config(Some(10))).await?;
+        assert_eq!(pool.pool.status().size, 4);
+
+        Ok(())
+    }
 }
@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
 
 use futures::pin_mut;
 use log::{debug, warn};
+use martin_tile_utils::TileCoord;
 use postgis::ewkb;
 use postgres_protocol::escape::{escape_identifier, escape_literal};
 use serde_json::Value;
@@ -9,21 +11,36 @@ use tilejson::Bounds;
 use tokio::time::timeout;
 
 use crate::args::{BoundsCalcType, DEFAULT_BOUNDS_TIMEOUT};
-use crate::pg::PgError::PostgresError;
+use crate::pg::PgError::{
+    DuplicateFeatureIds, DuplicateGeometryColumn, InvalidCurveTolerance, InvalidDensify,
+    InvalidGeometryTransform, InvalidNullDefault, InvalidPointClusters, InvalidTileGrid,
+    InvalidTileOffset, MixedGeometrySrids, PostgresError,
+};
 use crate::pg::PgResult;
 use crate::pg::builder::SqlTableInfoMapMapMap;
-use crate::pg::config::PgInfo;
-use crate::pg::config_table::TableInfo;
+use crate::pg::config::{DuplicateGeometryPolicy, PgInfo};
+use crate::pg::config_table::{
+    Antimeridian, DuplicateIds, LABELS_LAYER_SUFFIX, MixedSrids, POINT_COUNT_PROPERTY, TableInfo,
+};
 use crate::pg::pg_source::PgSqlInfo;
 use crate::pg::pool::PgPool;
 use crate::pg::utils::{json_to_hashmap, polygon_to_bbox};
+use crate::source::{TILE_SIZE_DEFAULT, TileGrid};
 
-static DEFAULT_EXTENT: u32 = 4096;
-static DEFAULT_BUFFER: u32 = 64;
-static DEFAULT_CLIP_GEOM: bool = true;
+pub static DEFAULT_EXTENT: u32 = 4096;
+pub static DEFAULT_BUFFER: u32 = 64;
+pub static DEFAULT_CLIP_GEOM: bool = true;
+static DEFAULT_CLUSTER_RADIUS: u32 = 256;
+/// Number of rows sampled to detect geometries in several SRIDs
+const SRID_SAMPLE_SIZE: u32 = 10_000;
+/// Number of rows sampled to detect duplicate feature IDs
+const ID_SAMPLE_SIZE: u32 = 100_000;
 
 /// Examine a database to get a list of all tables that have geometry columns.
-pub async fn query_available_tables(pool: &PgPool) -> PgResult<SqlTableInfoMapMapMap> {
+pub async fn query_available_tables(
+    pool: &PgPool,
+    duplicates: DuplicateGeometryPolicy,
+) -> PgResult<SqlTableInfoMapMapMap> {
     let rows = pool
         .get()
         .await?
@@ -52,14 +69,23 @@ pub async fn query_available_tables(pool: &PgPool) -> PgResult<SqlTableInfoMapMa
             None
         };
 
+        let is_geography = row.get::<_, String>("column_type") == "geography";
+        let mut srid: i32 = row.get("srid");
+        if is_geography && srid == 0 {
+            // Geography values without an explicit SRID are in WGS84, so unconstrained columns can be used as is
+            srid = 4326;
+        }
         let info = TableInfo {
             schema,
             table,
             geometry_column: row.get("geom"),
             geometry_index: row.get("geom_idx"),
             is_view: row.get("is_view"),
-            srid: row.get("srid"), // casting i32 to u32?
+            is_geography: Some(is_geography),
+            srid_unconstrained: Some(srid == 0),
+            srid, // casting i32 to u32?
             geometry_type: row.get("type"),
+            coord_dimension: row.get("coord_dimension"),
             properties: Some(json_to_hashmap(&row.get("properties"))),
             tilejson,
             ..Default::default()
@@ -74,20 +100,67 @@ pub async fn query_available_tables(pool: &PgPool) -> PgResult<SqlTableInfoMapMa
             );
         }
 
-        if let Some(v) = res
-            .entry(info.schema.clone())
-            .or_default()
-            .entry(info.table.clone())
-            .or_default()
-            .insert(info.geometry_column.clone(), info)
-        {
-            warn!("Unexpected duplicate table {}", v.format_id());
-        }
+        insert_table_info(&mut res, info, duplicates)?;
     }
 
     Ok(res)
 }
 
+/// Suggest a `CREATE INDEX` statement if the table is known to have no spatial index on its geometry column.
+/// Views cannot have indexes, so they never get a recommendation.
+pub fn spatial_index_recommendation(info: &TableInfo) -> Option<String> {
+    if let (Some(false), Some(false)) = (info.geometry_index, info.is_view) {
+        // Tile queries compare geography columns as geometries, see `bbox_filter_sql`
+        let column = if info.is_geography == Some(true) {
+            format!("({}::geometry)", escape_identifier(&info.geometry_column))
+        } else {
+            escape_identifier(&info.geometry_column)
+        };
+        Some(format!(
+            "CREATE INDEX ON {}.{} USING GIST ({column});",
+            escape_identifier(&info.schema),
+            escape_identifier(&info.table),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Add a discovered table geometry column, handling duplicates according to the policy
+fn insert_table_info(
+    res: &mut SqlTableInfoMapMapMap,
+    info: TableInfo,
+    duplicates: DuplicateGeometryPolicy,
+) -> PgResult<()> {
+    let columns = res
+        .entry(info.schema.clone())
+        .or_default()
+        .entry(info.table.clone())
+        .or_default();
+    match columns.entry(info.geometry_column.clone()) {
+        Entry::Vacant(v) => {
+            v.insert(info);
+        }
+        Entry::Occupied(mut v) => match duplicates {
+            DuplicateGeometryPolicy::Overwrite => {
+                warn!(
+                    "Unexpected duplicate table {}, replacing the previous one",
+                    info.format_id()
+                );
+                v.insert(info);
+            }
+            DuplicateGeometryPolicy::KeepFirst => {
+                warn!(
+                    "Unexpected duplicate table {}, keeping the first one",
+                    info.format_id()
+                );
+            }
+            DuplicateGeometryPolicy::Error => Err(DuplicateGeometryColumn(info.format_id()))?,
+        },
+    }
+    Ok(())
+}
+
 /// Generate an SQL snippet to escape a column name, and optionally alias it.
 /// Assumes to not be the first column in a SELECT statement.
 fn escape_with_alias(mapping: &HashMap<String, String>, field: &str) -> String {
@@ -103,8 +176,20 @@ fn escape_with_alias(mapping: &HashMap<String, String>, field: &str) -> String {
     }
 }
 
+/// Generate an SQL snippet replacing NULL values of a column with the given SQL value, aliased as the field name.
+/// Assumes to not be the first column in a SELECT statement.
+fn coalesce_with_alias(mapping: &HashMap<String, String>, field: &str, value: &str) -> String {
+    let column = mapping.get(field).map_or(field, |v| v.as_str());
+    format!(
+        ", COALESCE({}, {value}) AS {}",
+        escape_identifier(column),
+        escape_identifier(field),
+    )
+}
+
 /// Generate a query to fetch tiles from a table.
 /// The function is async because it may need to query the database for the table bounds (could be very slow).
+#[allow(clippy::too_many_lines)]
 pub async fn table_to_query(
     id: String,
     mut info: TableInfo,
@@ -117,12 +202,54 @@ pub async fn table_to_query(
     let geometry_column = escape_identifier(&info.geometry_column);
     let srid = info.srid;
 
+    // Only columns without an SRID constraint may contain geometries in several SRIDs
+    if info.srid_unconstrained == Some(true) {
+        let srids = query_srids(&pool, &schema, &table, &geometry_column).await?;
+        if srids.len() > 1 {
+            match info.mixed_srids.unwrap_or_default() {
+                MixedSrids::Error => {
+                    return Err(MixedGeometrySrids(id, info.format_id(), srids));
+                }
+                MixedSrids::Transform => {
+                    warn!(
+                        "Table {} has geometries in SRIDs {srids:?}, source {id} transforms them to SRID={srid}",
+                        info.format_id()
+                    );
+                    info.has_mixed_srids = Some(true);
+                }
+            }
+        }
+    }
+    if let Some(id_column) = &info.id_column {
+        let policy = info.duplicate_ids.unwrap_or_default();
+        if policy != DuplicateIds::Ignore {
+            let column = info.prop_mapping.get(id_column).unwrap_or(id_column);
+            if has_duplicate_ids(&pool, &info, column).await? {
+                if policy == DuplicateIds::Error {
+                    return Err(DuplicateFeatureIds(id, id_column.clone(), info.format_id()));
+                }
+                warn!(
+                    "Source {id} has duplicate values in its id_column {id_column} in table {}. Clients may treat features with the same ID as one feature.",
+                    info.format_id()
+                );
+            }
+        }
+    }
+
+    // Bounds are computed from the transformed geometries, as the extent ignores their SRIDs
+    let geometry_column = if info.has_mixed_srids == Some(true) {
+        geometry_column_sql(&info)
+    } else {
+        geometry_column
+    };
+
     if info.bounds.is_none() {
         match bounds_type {
             BoundsCalcType::Skip => {}
             BoundsCalcType::Calc => {
                 debug!("Computing {} table bounds for {id}", info.format_id());
                 info.bounds = calc_bounds(&pool, &schema, &table, &geometry_column, srid).await?;
+                info.is_empty = Some(info.bounds.is_none());
             }
             BoundsCalcType::Quick => {
                 debug!(
@@ -134,6 +261,7 @@ pub async fn table_to_query(
                 pin_mut!(bounds);
                 if let Ok(bounds) = timeout(DEFAULT_BOUNDS_TIMEOUT, &mut bounds).await {
                     info.bounds = bounds?;
+                    info.is_empty = Some(info.bounds.is_none());
                 } else {
                     warn!(
                         "Timeout computing {} bounds for {id}, aborting query. Use --auto-bounds=calc to wait until complete, or check the table for missing indices.",
@@ -151,14 +279,270 @@ pub async fn table_to_query(
         }
     }
 
-    let properties = if let Some(props) = &info.properties {
-        props
-            .keys()
-            .map(|column| escape_with_alias(&info.prop_mapping, column))
-            .collect::<String>()
+    if info.geometry_only.unwrap_or_default() {
+        strip_properties(&mut info);
+    }
+
+    if let (Some(null_properties), Some(props)) = (&info.null_properties, &info.properties) {
+        null_properties
+            .validate(props)
+            .map_err(|(property, reason)| InvalidNullDefault(id.clone(), property, reason))?;
+    }
+
+    if let Some(tolerance) = info.curve_tolerance {
+        if !(tolerance.is_finite() && tolerance > 0.0) {
+            return Err(InvalidCurveTolerance(id, tolerance));
+        }
+    }
+
+    if let Some(max_length) = info.densify {
+        if !(max_length.is_finite() && max_length > 0.0) {
+            return Err(InvalidDensify(id, max_length));
+        }
+    }
+
+    if let Some(grid) = info.tile_grid {
+        grid.validate()
+            .map_err(|e| InvalidTileGrid(id.clone(), e))?;
+    }
+
+    if let Some(offset) = info.tile_offset {
+        offset
+            .validate(info.tile_grid.unwrap_or_default())
+            .map_err(|e| InvalidTileOffset(id.clone(), e))?;
+    }
+
+    for transform in info.geometry_transforms.iter().flatten() {
+        transform
+            .validate()
+            .map_err(|e| InvalidGeometryTransform(id.clone(), e))?;
+    }
+
+    if let Some(clusters) = info.point_clusters {
+        clusters
+            .validate(info.geometry_type.as_deref())
+            .map_err(|e| InvalidPointClusters(id.clone(), e))?;
+    }
+
+    if let Some(ewkb_property) = &info.ewkb_property {
+        warn!(
+            "Source {id} includes the full geometry of each feature in the {ewkb_property} property. This may significantly increase the tile size."
+        );
+    }
+
+    if let Some(z_property) = &info.z_property {
+        if info.has_z() == Some(false) {
+            warn!(
+                "Source {id} has the {z_property} property for the Z coordinate, but {} has no Z dimension. The property will always be empty.",
+                info.format_id()
+            );
+        }
+    }
+
+    let query = table_to_sql(&id, &info, pool.supports_tile_margin(), max_feature_count);
+
+    Ok((id, PgSqlInfo::new(query, false, info.format_id()), info))
+}
+
+/// Remove all properties of a geometry-only table, including the EWKB and Z properties,
+/// so that its tiles and its `TileJSON` layer have no fields
+pub fn strip_properties(info: &mut TableInfo) {
+    info.properties = Some(BTreeMap::new());
+    info.null_properties = None;
+    info.ewkb_property = None;
+    info.z_property = None;
+}
+
+/// Scale the table extent and buffer from the default 256px tile size to the given logical tile size,
+/// keeping the same coordinate resolution per pixel. For example, a 512px tile has twice the extent.
+pub fn scale_to_tile_size(info: &mut TableInfo, tile_size: u32) {
+    let scale = |v: u32| v.saturating_mul(tile_size) / TILE_SIZE_DEFAULT;
+    info.extent = Some(scale(info.get_extent().unwrap_or(DEFAULT_EXTENT)));
+    info.precision = None;
+    info.buffer = Some(scale(info.buffer.unwrap_or(DEFAULT_BUFFER)));
+    if let Some(clusters) = &mut info.point_clusters {
+        clusters.radius = Some(scale(clusters.radius.unwrap_or(DEFAULT_CLUSTER_RADIUS)));
+    }
+}
+
+/// Generate the SQL query to count the features of a table in a tile, without querying the database.
+/// The count includes all features whose bounding box intersects the buffered tile envelope,
+/// so it may be slightly higher than the number of features in the generated tile.
+#[must_use]
+pub fn table_to_count_sql(
+    info: &TableInfo,
+    supports_tile_margin: bool,
+    max_feature_count: Option<usize>,
+) -> String {
+    let schema = escape_identifier(&info.schema);
+    let table = escape_identifier(&info.table);
+    let extent = info.get_extent().unwrap_or(DEFAULT_EXTENT);
+    let buffer = info.buffer.unwrap_or(DEFAULT_BUFFER);
+    let grid = info.tile_grid.unwrap_or_default();
+    let bbox_filter = bbox_filter_sql(
+        info,
+        &tile_bbox_sql(grid, buffer, extent, supports_tile_margin),
+    );
+    let limit_clause = max_feature_count.map_or(String::new(), |v| format!("LIMIT {v}"));
+    format!(
+        r"
+SELECT
+  COUNT(*)
+FROM (
+  SELECT 1
+  FROM
+    {schema}.{table}
+  WHERE
+    {bbox_filter}
+  {limit_clause}
+) AS tile;
+"
+    )
+}
+
+/// Generate the SQL expression of the tile envelope in the tile grid's SRID, optionally expanded by a margin.
+/// `WorldCRS84Quad` tiles are the tiles of the next zoom level within a square of 360 degrees
+/// that extends below the south pole, so that only its upper half, i.e. the whole world, is used.
+/// Custom grids use their zoom 0 tile as the bounds.
+fn tile_envelope_sql(grid: TileGrid, margin: Option<f64>) -> String {
+    let margin = margin.map_or(String::new(), |v| format!(", margin => {v}"));
+    match grid {
+        TileGrid::WebMercatorQuad => {
+            format!("ST_TileEnvelope($1::integer, $2::integer, $3::integer{margin})")
+        }
+        TileGrid::WorldCrs84Quad => format!(
+            "ST_TileEnvelope($1::integer + 1, $2::integer, $3::integer, ST_MakeEnvelope(-180, -270, 180, 90, 4326){margin})"
+        ),
+        TileGrid::Custom(custom) => {
+            let [left, top] = custom.origin;
+            let (right, bottom) = (left + custom.tile_extent, top - custom.tile_extent);
+            format!(
+                "ST_TileEnvelope($1::integer, $2::integer, $3::integer, ST_MakeEnvelope({left}, {bottom}, {right}, {top}, {}){margin})",
+                custom.srid
+            )
+        }
+    }
+}
+
+/// Generate the SQL expression of the tile envelope, expanded by the buffer.
+/// `PostGIS` < v3.1 has no `margin` parameter, so the envelope is expanded by the same distance with `ST_Expand`,
+/// computed from the size of the zoom 0 tile, as the size of a tile halves with each zoom level.
+fn tile_bbox_sql(grid: TileGrid, buffer: u32, extent: u32, supports_tile_margin: bool) -> String {
+    let margin = f64::from(buffer) / f64::from(extent);
+    if buffer == 0 {
+        tile_envelope_sql(grid, None)
+    } else if supports_tile_margin {
+        tile_envelope_sql(grid, Some(margin))
+    } else {
+        let [min_x, _, max_x, _] = grid.envelope(TileCoord { z: 0, x: 0, y: 0 });
+        let distance = (max_x - min_x) * margin;
+        format!(
+            "ST_Expand({}, {distance} / 2 ^ $1::integer)",
+            tile_envelope_sql(grid, None)
+        )
+    }
+}
+
+/// Generate the SQL expression of the geometry column. Geometries in several SRIDs are transformed to the
+/// SRID of the source, assuming that the geometries without an SRID already use it.
+fn geometry_column_sql(info: &TableInfo) -> String {
+    let column = escape_identifier(&info.geometry_column);
+    if info.has_mixed_srids == Some(true) {
+        let srid = info.srid;
+        format!(
+            "ST_Transform(CASE WHEN ST_SRID({column}::geometry) = 0 THEN ST_SetSRID({column}::geometry, {srid}) ELSE {column}::geometry END, {srid})"
+        )
+    } else {
+        format!("{column}::geometry")
+    }
+}
+
+/// Add vertices along the great circles between the points of the geometry with the geography `ST_Segmentize`.
+/// Geography only supports WGS84, so geometries in other SRIDs are transformed to it and back.
+fn densify_sql(geometry: &str, srid: i32, max_length: f64) -> String {
+    if srid == 4326 {
+        format!("ST_Segmentize({geometry}::geography, {max_length})::geometry")
+    } else {
+        format!(
+            "ST_Transform(ST_Segmentize(ST_Transform({geometry}, 4326)::geography, {max_length})::geometry, {srid})"
+        )
+    }
+}
+
+/// Generate the SQL condition selecting the features within the tile bounding box.
+/// Features split at the antimeridian may be stored with longitudes beyond 180,
+/// so they are also searched in the bounding box shifted by 360 degrees.
+fn bbox_filter_sql(info: &TableInfo, bbox_search: &str) -> String {
+    // Geometries in several SRIDs can only be compared after transforming them, which prevents using an index.
+    // Geography columns are compared as geometries too, as the geography of a large tile box, e.g. the whole
+    // world at zoom 0, follows great circles instead of the box edges. Such columns need a `geometry` expression index.
+    let geometry_column = if info.has_mixed_srids == Some(true) || info.is_geography == Some(true) {
+        geometry_column_sql(info)
     } else {
-        String::new()
+        escape_identifier(&info.geometry_column)
+    };
+    let bbox = format!("ST_Transform({bbox_search}, {})", info.srid);
+    let bbox_filter = match info.antimeridian.unwrap_or_default() {
+        Antimeridian::Keep => format!("{geometry_column} && {bbox}"),
+        Antimeridian::Split => format!(
+            "({geometry_column} && {bbox} OR {geometry_column} && ST_Translate({bbox}, 360, 0))"
+        ),
     };
+    // The configured filter is trusted SQL, so it is not escaped, only parenthesized to keep its own precedence
+    match &info.filter {
+        Some(filter) => format!("{bbox_filter} AND ({filter})"),
+        None => bbox_filter,
+    }
+}
+
+/// Generate the SQL list of the property columns, each starting with a comma
+fn properties_sql(info: &TableInfo) -> String {
+    let Some(props) = &info.properties else {
+        return String::new();
+    };
+    props
+        .iter()
+        .map(|(column, column_type)| {
+            match info
+                .null_properties
+                .as_ref()
+                .and_then(|v| v.get_sql_value(column, column_type))
+            {
+                Some(value) => coalesce_with_alias(&info.prop_mapping, column, &value),
+                None => escape_with_alias(&info.prop_mapping, column),
+            }
+        })
+        .collect()
+}
+
+/// Generate the SQL query to fetch tiles from a table, without querying the database.
+#[must_use]
+#[allow(clippy::too_many_lines)]
+pub fn table_to_sql(
+    id: &str,
+    info: &TableInfo,
+    supports_tile_margin: bool,
+    max_feature_count: Option<usize>,
+) -> String {
+    let schema = escape_identifier(&info.schema);
+    let table = escape_identifier(&info.table);
+    let geometry_column = escape_identifier(&info.geometry_column);
+
+    let properties = properties_sql(info);
+
+    let ewkb_property = info.ewkb_property.as_ref().map_or(String::new(), |v| {
+        format!(
+            ", encode(ST_AsEWKB({geometry_column}::geometry), 'hex') AS {}",
+            escape_identifier(v)
+        )
+    });
+
+    let z_property = info.z_property.as_ref().map_or(String::new(), |v| {
+        format!(
+            ", CASE WHEN ST_GeometryType({geometry_column}::geometry) = 'ST_Point' THEN ST_Z({geometry_column}::geometry) END AS {}",
+            escape_identifier(v)
+        )
+    });
 
     let (id_name, id_field) = if let Some(id_column) = &info.id_column {
         (
@@ -169,50 +553,129 @@ pub async fn table_to_query(
         (String::new(), String::new())
     };
 
-    let extent = info.extent.unwrap_or(DEFAULT_EXTENT);
-    let buffer = info.buffer.unwrap_or(DEFAULT_BUFFER);
-
-    let bbox_search = if buffer == 0 {
-        "ST_TileEnvelope($1::integer, $2::integer, $3::integer)".to_string()
-    } else if pool.supports_tile_margin() {
-        let margin = f64::from(buffer) / f64::from(extent);
-        format!("ST_TileEnvelope($1::integer, $2::integer, $3::integer, margin => {margin})")
+    let column = geometry_column_sql(info);
+    let mut geometry = if !info.uses_curve_to_line() {
+        column
+    } else if let Some(tolerance) = info.curve_tolerance {
+        format!("ST_CurveToLine({column}, {tolerance}, 1)")
     } else {
-        // TODO: we should use ST_Expand here, but it may require a bit more math work,
-        //       so might not be worth it as it is only used for PostGIS < v3.1.
-        //       v3.1 has been out for 2+ years (december 2020)
-        // let val = EARTH_CIRCUMFERENCE * buffer as f64 / extent as f64;
-        // format!("ST_Expand(ST_TileEnvelope($1::integer, $2::integer, $3::integer), {val}/2^$1::integer)")
-        "ST_TileEnvelope($1::integer, $2::integer, $3::integer)".to_string()
+        format!("ST_CurveToLine({column})")
     };
+    if let Some(max_length) = info.densify {
+        geometry = densify_sql(&geometry, info.srid, max_length);
+    }
+    if info.antimeridian == Some(Antimeridian::Split) {
+        // Move all longitudes to 0..360, then move the parts east of 180 back by 360 degrees
+        geometry = format!("ST_WrapX(ST_ShiftLongitude({geometry}), 180, -360)");
+    }
+    for transform in info.geometry_transforms.iter().flatten() {
+        geometry = transform.apply(&geometry);
+    }
+
+    let extent = info.get_extent().unwrap_or(DEFAULT_EXTENT);
+    let buffer = info.buffer.unwrap_or(DEFAULT_BUFFER);
 
+    let grid = info.tile_grid.unwrap_or_default();
+    let bbox_filter = bbox_filter_sql(
+        info,
+        &tile_bbox_sql(grid, buffer, extent, supports_tile_margin),
+    );
+
+    let grid_srid = grid.srid();
+    let tile_envelope = tile_envelope_sql(grid, None);
     let limit_clause = max_feature_count.map_or(String::new(), |v| format!("LIMIT {v}"));
-    let layer_id = escape_literal(info.layer_id.as_ref().unwrap_or(&id));
+    let layer_id = info.layer_id.as_deref().unwrap_or(id);
     let clip_geom = info.clip_geom.unwrap_or(DEFAULT_CLIP_GEOM);
-    let query = format!(
+    let tile_layer_id = escape_literal(layer_id);
+    let tile_sql = format!(
         r"
 SELECT
-  ST_AsMVT(tile, {layer_id}, {extent}, 'geom'{id_name})
+  ST_AsMVT(tile, {tile_layer_id}, {extent}, 'geom'{id_name})
 FROM (
   SELECT
     ST_AsMVTGeom(
-        ST_Transform(ST_CurveToLine({geometry_column}::geometry), 3857),
-        ST_TileEnvelope($1::integer, $2::integer, $3::integer),
+        ST_Transform({geometry}, {grid_srid}),
+        {tile_envelope},
+        {extent}, {buffer}, {clip_geom}
+    ) AS geom
+    {id_field}{properties}{ewkb_property}{z_property}
+  FROM
+    {schema}.{table}
+  WHERE
+    {bbox_filter}
+  {limit_clause}
+) AS tile"
+    );
+
+    // Points are grouped by a grid relative to the tile size, so the clusters look the same at every zoom level.
+    // The query of the other zoom levels is not evaluated.
+    let tile_sql = if let Some(clusters) = info.point_clusters {
+        let maxzoom = clusters.maxzoom;
+        let cell_ratio =
+            f64::from(clusters.radius.unwrap_or(DEFAULT_CLUSTER_RADIUS)) / f64::from(extent);
+        let point_count = escape_identifier(POINT_COUNT_PROPERTY);
+        format!(
+            r"
+SELECT CASE WHEN $1::integer <= {maxzoom} THEN (
+SELECT
+  ST_AsMVT(tile, {tile_layer_id}, {extent}, 'geom')
+FROM (
+  SELECT
+    ST_AsMVTGeom(
+        ST_Centroid(ST_Collect(points.geom)),
+        {tile_envelope},
+        {extent}, {buffer}, {clip_geom}
+    ) AS geom,
+    count(*) AS {point_count}
+  FROM (
+    SELECT ST_Transform({geometry}, {grid_srid}) AS geom
+    FROM
+      {schema}.{table}
+    WHERE
+      {bbox_filter}
+  ) AS points,
+  (SELECT (ST_XMax(envelope) - ST_XMin(envelope)) * {cell_ratio} AS size FROM {tile_envelope} AS envelope) AS cell
+  GROUP BY ST_SnapToGrid(points.geom, cell.size)
+) AS tile
+) ELSE ({tile_sql}
+) END"
+        )
+    } else {
+        tile_sql
+    };
+
+    let Some(label_points) = info.label_points else {
+        return format!("{tile_sql};").trim().to_string();
+    };
+    // Label points are computed from the whole polygon, so each label is only in the tiles containing it.
+    // The second layer is appended to the tile, as MVT layers can simply be concatenated.
+    let label_geometry = label_points.apply(&geometry);
+    let labels_layer_id = escape_literal(&format!("{layer_id}{LABELS_LAYER_SUFFIX}"));
+    format!(
+        r"
+SELECT ({tile_sql}
+) || (
+SELECT
+  ST_AsMVT(labels, {labels_layer_id}, {extent}, 'geom'{id_name})
+FROM (
+  SELECT
+    ST_AsMVTGeom(
+        ST_Transform({label_geometry}, {grid_srid}),
+        {tile_envelope},
         {extent}, {buffer}, {clip_geom}
     ) AS geom
     {id_field}{properties}
   FROM
     {schema}.{table}
   WHERE
-    {geometry_column} && ST_Transform({bbox_search}, {srid})
+    {bbox_filter} AND ST_Dimension({geometry_column}::geometry) = 2
   {limit_clause}
-) AS tile;
+) AS labels
+);
 "
     )
     .trim()
-    .to_string();
-
-    Ok((id, PgSqlInfo::new(query, false, info.format_id()), info))
+    .to_string()
 }
 
 /// Compute the bounds of a table. This could be slow if the table is large or has no geo index.
@@ -243,3 +706,793 @@ FROM {schema}.{table};
         .get::<_, Option<ewkb::Polygon>>("bounds")
         .and_then(|p| polygon_to_bbox(&p)))
 }
+
+/// Get the distinct SRIDs of a sample of the geometries in a table
+async fn query_srids(
+    pool: &PgPool,
+    schema: &str,
+    table: &str,
+    geometry_column: &str,
+) -> PgResult<Vec<i32>> {
+    Ok(pool
+        .get()
+        .await?
+        .query(
+            &format!(
+                r"
+SELECT DISTINCT ST_SRID({geometry_column}::geometry) AS srid
+FROM (SELECT {geometry_column}
+      FROM {schema}.{table}
+      WHERE {geometry_column} IS NOT NULL
+      LIMIT {SRID_SAMPLE_SIZE}) AS sample
+ORDER BY srid"
+            ),
+            &[],
+        )
+        .await
+        .map_err(|e| PostgresError(e, "querying table SRIDs"))?
+        .iter()
+        .map(|row| row.get("srid"))
+        .collect())
+}
+
+/// Check whether the ID column has duplicate values. Columns with a unique index are unique by definition,
+/// otherwise a sample of the rows is checked, so duplicates may be missed in large tables.
+async fn has_duplicate_ids(pool: &PgPool, info: &TableInfo, column: &str) -> PgResult<bool> {
+    let conn = pool.get().await?;
+    let is_unique: bool = conn
+        .query_one(
+            r"
+SELECT EXISTS (SELECT 1
+               FROM pg_index ix
+                        JOIN pg_attribute attr
+                             ON attr.attrelid = ix.indrelid AND attr.attnum = ix.indkey[0]
+               WHERE ix.indrelid = to_regclass(quote_ident($1) || '.' || quote_ident($2))
+                 AND ix.indisunique
+                 AND ix.indnkeyatts = 1
+                 AND ix.indpred IS NULL
+                 AND attr.attname = $3)",
+            &[&info.schema, &info.table, &column],
+        )
+        .await
+        .and_then(|row| row.try_get(0))
+        .map_err(|e| PostgresError(e, "querying unique indexes"))?;
+    if is_unique {
+        return Ok(false);
+    }
+
+    let schema = escape_identifier(&info.schema);
+    let table = escape_identifier(&info.table);
+    let column = escape_identifier(column);
+    let duplicates: i64 = conn
+        .query_one(
+            &format!(
+                r"
+SELECT COUNT(*) - COUNT(DISTINCT id)
+FROM (SELECT {column} AS id
+      FROM {schema}.{table}
+      WHERE {column} IS NOT NULL
+      LIMIT {ID_SAMPLE_SIZE}) AS sample"
+            ),
+            &[],
+        )
+        .await
+        .and_then(|row| row.try_get(0))
+        .map_err(|e| PostgresError(e, "querying duplicate feature IDs"))?;
+    Ok(duplicates > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::pg::config_table::{
+        Antimeridian, CurveToLine, GeometryTransform, LabelPoints, NullProperties, PointClusters,
+    };
+    use crate::pg::pool::tests::test_pool;
+    use crate::source::TILE_MATRIX_SET_KEY;
+
+    fn table(geometry_type: &str) -> TableInfo {
+        TableInfo {
+            schema: "public".to_string(),
+            table: "points".to_string(),
+            geometry_column: "geom".to_string(),
+            geometry_type: Some(geometry_type.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn insert_twice(policy: DuplicateGeometryPolicy) -> PgResult<Option<String>> {
+        let mut res = SqlTableInfoMapMapMap::new();
+        insert_table_info(&mut res, table("POINT"), policy)?;
+        insert_table_info(&mut res, table("MULTIPOINT"), policy)?;
+        Ok(res["public"]["points"]["geom"].geometry_type.clone())
+    }
+
+    #[test]
+    fn duplicate_geometry_overwrite() {
+        let res = insert_twice(DuplicateGeometryPolicy::Overwrite).unwrap();
+        assert_eq!(res.as_deref(), Some("MULTIPOINT"));
+    }
+
+    #[test]
+    fn duplicate_geometry_keep_first() {
+        let res = insert_twice(DuplicateGeometryPolicy::KeepFirst).unwrap();
+        assert_eq!(res.as_deref(), Some("POINT"));
+    }
+
+    #[test]
+    fn duplicate_geometry_error() {
+        let res = insert_twice(DuplicateGeometryPolicy::Error);
+        assert!(matches!(res, Err(DuplicateGeometryColumn(id)) if id == "public.points.geom"));
+    }
+
+    #[test]
+    fn spatial_index_recommendation_missing_index() {
+        let mut info = table("POINT");
+        info.table = "My Points".to_string();
+        info.geometry_index = Some(false);
+        info.is_view = Some(false);
+        assert_eq!(
+            spatial_index_recommendation(&info).as_deref(),
+            Some(r#"CREATE INDEX ON "public"."My Points" USING GIST ("geom");"#)
+        );
+
+        info.geometry_index = Some(true);
+        assert_eq!(spatial_index_recommendation(&info), None);
+
+        // Views cannot be indexed
+        info.geometry_index = Some(false);
+        info.is_view = Some(true);
+        assert_eq!(spatial_index_recommendation(&info), None);
+
+        // Geography columns are searched as geometries
+        info.is_view = Some(false);
+        info.is_geography = Some(true);
+        assert_eq!(
+            spatial_index_recommendation(&info).as_deref(),
+            Some(r#"CREATE INDEX ON "public"."My Points" USING GIST (("geom"::geometry));"#)
+        );
+    }
+
+    #[test]
+    fn table_sql_ewkb_property() {
+        let mut info = table("POINT");
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(!sql.contains("ST_AsEWKB"));
+
+        info.ewkb_property = Some("geom_ewkb".to_string());
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(r#"encode(ST_AsEWKB("geom"::geometry), 'hex') AS "geom_ewkb""#));
+    }
+
+    #[test]
+    fn table_sql_z_property() {
+        let mut info = table("POINT");
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(!sql.contains("ST_Z"));
+
+        info.z_property = Some("elevation".to_string());
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(
+            r#"CASE WHEN ST_GeometryType("geom"::geometry) = 'ST_Point' THEN ST_Z("geom"::geometry) END AS "elevation""#
+        ));
+    }
+
+    #[test]
+    fn table_sql_null_properties() {
+        let mut info = table("POINT");
+        info.properties = Some(
+            [("name", "text"), ("population", "int4")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+        info.prop_mapping
+            .insert("name".to_string(), "Name".to_string());
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(r#", "Name" AS "name", "population""#));
+
+        info.null_properties = Some(NullProperties::Marker("null".to_string()));
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(r#", COALESCE("Name", 'null') AS "name", "population""#));
+    }
+
+    #[test]
+    fn table_tile_size() {
+        let mut info = table("POINT");
+        scale_to_tile_size(&mut info, 512);
+        assert_eq!(info.extent, Some(8192));
+        assert_eq!(info.buffer, Some(128));
+
+        let mut info = TableInfo {
+            precision: Some(10),
+            buffer: Some(0),
+            ..table("POINT")
+        };
+        scale_to_tile_size(&mut info, 1024);
+        assert_eq!(info.get_extent(), Some(4096));
+        assert_eq!(info.precision, None);
+        assert_eq!(info.buffer, Some(0));
+
+        let mut info = TableInfo {
+            extent: Some(512),
+            ..table("POINT")
+        };
+        scale_to_tile_size(&mut info, 256);
+        assert_eq!(info.extent, Some(512));
+    }
+
+    #[test]
+    fn table_count_sql() {
+        let info = table("POINT");
+        let sql = table_to_count_sql(&info, true, Some(100));
+        assert!(sql.contains("COUNT(*)"));
+        assert!(sql.contains(
+            r#"FROM
+    "public"."points""#
+        ));
+        assert!(sql.contains("margin => 0.015625"));
+        assert!(sql.contains("LIMIT 100"));
+    }
+
+    #[test]
+    fn table_sql_tile_margin_fallback() {
+        // Without the margin parameter, the envelope is expanded by the same buffer of 64/4096 tile,
+        // i.e. 0.015625 of 40075016.6855785 meters at zoom 0, so both queries select the same features
+        let info = table("POINT");
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(
+            "ST_TileEnvelope($1::integer, $2::integer, $3::integer, margin => 0.015625)"
+        ));
+        let sql = table_to_sql("src", &info, false, None);
+        assert!(!sql.contains("margin =>"));
+        assert!(sql.contains(
+            "ST_Expand(ST_TileEnvelope($1::integer, $2::integer, $3::integer), 626172.1357121641 / 2 ^ $1::integer)"
+        ));
+        let sql = table_to_count_sql(&info, false, None);
+        assert!(sql.contains("626172.1357121641 / 2 ^ $1::integer"));
+
+        // Tiles of WorldCRS84Quad are 180 degrees wide at zoom 0
+        let mut info = table("POINT");
+        info.tile_grid = Some(TileGrid::WorldCrs84Quad);
+        let sql = table_to_sql("src", &info, false, None);
+        assert!(
+            sql.contains("ST_MakeEnvelope(-180, -270, 180, 90, 4326)), 2.8125 / 2 ^ $1::integer)")
+        );
+
+        // Tiles without a buffer are never expanded
+        let mut info = table("POINT");
+        info.buffer = Some(0);
+        let sql = table_to_sql("src", &info, false, None);
+        assert!(!sql.contains("ST_Expand"));
+    }
+
+    #[test]
+    fn table_sql_tile_grid() {
+        let mut info = table("POINT");
+        info.srid = 4326;
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains("ST_Transform(ST_CurveToLine(\"geom\"::geometry), 3857)"));
+        assert!(sql.contains("ST_TileEnvelope($1::integer, $2::integer, $3::integer),"));
+
+        info.tile_grid = Some(TileGrid::WorldCrs84Quad);
+        let sql = table_to_sql("src", &info, true, None);
+        let envelope = "ST_TileEnvelope($1::integer + 1, $2::integer, $3::integer, ST_MakeEnvelope(-180, -270, 180, 90, 4326)";
+        assert!(sql.contains("ST_Transform(ST_CurveToLine(\"geom\"::geometry), 4326)"));
+        assert!(sql.contains(&format!("{envelope}),")));
+        assert!(sql.contains(&format!(
+            "ST_Transform({envelope}, margin => 0.015625), 4326)"
+        )));
+    }
+
+    #[test]
+    fn table_sql_custom_tile_grid() {
+        let yaml = indoc! {"
+            schema: public
+            table: communes
+            srid: 2056
+            geometry_column: geom
+            tile_grid:
+              custom:
+                srid: 2056
+                origin: [2420000, 1350000]
+                tile_extent: 512000
+        "};
+        let info: TableInfo = serde_yaml::from_str(yaml).unwrap();
+        let sql = table_to_sql("communes", &info, true, None);
+        let envelope = "ST_TileEnvelope($1::integer, $2::integer, $3::integer, ST_MakeEnvelope(2420000, 838000, 2932000, 1350000, 2056)";
+        assert!(sql.contains("ST_Transform(ST_CurveToLine(\"geom\"::geometry), 2056)"));
+        assert!(sql.contains(&format!("{envelope}),")));
+        assert!(sql.contains(&format!(
+            "ST_Transform({envelope}, margin => 0.015625), 2056)"
+        )));
+
+        // The grid is advertised with all its parameters, so that the source validates requests against it
+        let tilejson = info.to_tilejson("communes".to_string());
+        assert_eq!(
+            tilejson.other[TILE_MATRIX_SET_KEY]["custom"]["tile_extent"],
+            512_000.0
+        );
+        let grid: TileGrid =
+            serde_json::from_value(tilejson.other[TILE_MATRIX_SET_KEY].clone()).unwrap();
+        assert_eq!(Some(grid), info.tile_grid);
+        assert!(grid.is_valid_tile(TileCoord { z: 1, x: 1, y: 1 }));
+        assert!(!grid.is_valid_tile(TileCoord { z: 1, x: 2, y: 0 }));
+    }
+
+    #[test]
+    fn table_sql_curve_to_line() {
+        let mut info = table("POINT");
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains("ST_Transform(ST_CurveToLine(\"geom\"::geometry), 3857)"));
+
+        info.curve_to_line = Some(CurveToLine::Auto);
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains("ST_Transform(\"geom\"::geometry, 3857)"));
+
+        info.geometry_type = Some("CURVEPOLYGON".to_string());
+        info.curve_tolerance = Some(0.5);
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains("ST_Transform(ST_CurveToLine(\"geom\"::geometry, 0.5, 1), 3857)"));
+
+        info.curve_to_line = Some(CurveToLine::Never);
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains("ST_Transform(\"geom\"::geometry, 3857)"));
+    }
+
+    #[test]
+    fn table_sql_geometry_transforms() {
+        let mut info = table("POLYGON");
+        info.geometry_transforms = Some(vec![
+            GeometryTransform::MakeValid,
+            GeometryTransform::SnapToGrid(0.5),
+            GeometryTransform::Simplify(10.0),
+        ]);
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(
+            "ST_Transform(ST_Simplify(ST_SnapToGrid(ST_MakeValid(ST_CurveToLine(\"geom\"::geometry)), 0.5), 10), 3857)"
+        ));
+
+        info.curve_to_line = Some(CurveToLine::Never);
+        info.geometry_transforms = Some(vec![
+            GeometryTransform::SimplifyPreserveTopology(1.5),
+            GeometryTransform::MakeValid,
+        ]);
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(
+            "ST_Transform(ST_MakeValid(ST_SimplifyPreserveTopology(\"geom\"::geometry, 1.5)), 3857)"
+        ));
+    }
+
+    #[test]
+    fn table_sql_filter() {
+        let mut info = table("POLYGON");
+        info.srid = 4326;
+        let sql = table_to_sql("parcels", &info, true, None);
+        assert!(sql.contains("\"geom\" && ST_Transform("));
+        assert!(!sql.contains(") AND ("));
+
+        info.filter = Some("status = 'active' AND region = 'north'".to_string());
+        let sql = table_to_sql("parcels", &info, true, None);
+        assert!(sql.contains(", 4326) AND (status = 'active' AND region = 'north')"));
+        let sql = table_to_count_sql(&info, true, None);
+        assert!(sql.contains(", 4326) AND (status = 'active' AND region = 'north')"));
+    }
+
+    #[test]
+    fn table_sql_densify() {
+        let mut info = table("LINESTRING");
+        info.srid = 3857;
+        info.curve_to_line = Some(CurveToLine::Never);
+        info.densify = Some(50000.0);
+        info.geometry_transforms = Some(vec![GeometryTransform::MakeValid]);
+        let sql = table_to_sql("routes", &info, true, None);
+        assert!(sql.contains(
+            "ST_Transform(ST_MakeValid(ST_Transform(ST_Segmentize(ST_Transform(\"geom\"::geometry, 4326)::geography, 50000)::geometry, 3857)), 3857)"
+        ));
+
+        info.srid = 4326;
+        let sql = table_to_sql("routes", &info, true, None);
+        assert!(sql.contains(
+            "ST_Transform(ST_MakeValid(ST_Segmentize(\"geom\"::geometry::geography, 50000)::geometry), 3857)"
+        ));
+    }
+
+    #[tokio::test]
+    async fn densify_long_line() -> anyhow::Result<()> {
+        let (_node, pool) = test_pool().await?;
+        let conn = pool.get().await?;
+
+        // A flight from New York to Paris, stored as a single straight segment
+        let line = "'SRID=4326;LINESTRING(-74.0 40.7, 2.35 48.85)'::geometry";
+        let geometry = densify_sql(line, 4326, 100_000.0);
+        let row = conn
+            .query_one(
+                &format!("SELECT ST_NPoints({geometry}), ST_YMax({geometry})"),
+                &[],
+            )
+            .await?;
+        // The 5800 km route gets a vertex at least every 100 km, curving north along the great circle
+        assert!(row.get::<_, i32>(0) > 58);
+        assert!(row.get::<_, f64>(1) > 50.0);
+
+        // Geometries in other SRIDs are densified the same way, and stay in their SRID
+        let geometry = densify_sql(&format!("ST_Transform({line}, 3857)"), 3857, 100_000.0);
+        let row = conn
+            .query_one(
+                &format!("SELECT ST_NPoints({geometry}), ST_SRID({geometry})"),
+                &[],
+            )
+            .await?;
+        assert!(row.get::<_, i32>(0) > 58);
+        assert_eq!(row.get::<_, i32>(1), 3857);
+
+        Ok(())
+    }
+
+    #[test]
+    fn table_sql_antimeridian() {
+        // A Pacific polygon from 170°E to 170°W, stored as it crosses ±180°
+        let mut info = table("POLYGON");
+        info.srid = 4326;
+        info.curve_to_line = Some(CurveToLine::Never);
+        info.geometry_transforms = Some(vec![GeometryTransform::MakeValid]);
+        let bbox = "ST_Transform(ST_TileEnvelope($1::integer, $2::integer, $3::integer, margin => 0.015625), 4326)";
+
+        let sql = table_to_sql("pacific", &info, true, None);
+        assert!(!sql.contains("ST_ShiftLongitude"));
+        assert!(sql.contains(&format!("\"geom\" && {bbox}\n")));
+
+        // Its longitudes are moved to 170..190, and the part beyond 180 is moved back to -180..-170
+        info.antimeridian = Some(Antimeridian::Split);
+        let sql = table_to_sql("pacific", &info, true, None);
+        assert!(sql.contains(
+            "ST_Transform(ST_MakeValid(ST_WrapX(ST_ShiftLongitude(\"geom\"::geometry), 180, -360)), 3857)"
+        ));
+        // Polygons already stored with longitudes beyond 180 are found by tiles west of the antimeridian
+        let filter = format!("(\"geom\" && {bbox} OR \"geom\" && ST_Translate({bbox}, 360, 0))");
+        assert!(sql.contains(&filter));
+        assert!(table_to_count_sql(&info, true, None).contains(&filter));
+    }
+
+    #[test]
+    fn table_sql_label_points() {
+        let mut info = table("POLYGON");
+        info.curve_to_line = Some(CurveToLine::Never);
+        info.properties = Some([("name".to_string(), "text".to_string())].into());
+        let sql = table_to_sql("buildings", &info, true, None);
+        assert!(!sql.contains("buildings_labels"));
+        assert!(sql.ends_with(") AS tile;"));
+        let layers = info.to_tilejson("buildings".to_string()).vector_layers;
+        assert_eq!(layers.unwrap().len(), 1);
+
+        info.label_points = Some(LabelPoints::PointOnSurface);
+        let sql = table_to_sql("buildings", &info, true, Some(10));
+        assert!(sql.contains("ST_AsMVT(tile, 'buildings', 4096, 'geom')"));
+        assert!(sql.contains("ST_AsMVT(labels, 'buildings_labels', 4096, 'geom')"));
+        assert!(sql.contains("ST_Transform(ST_PointOnSurface(\"geom\"::geometry), 3857)"));
+        assert!(sql.contains("AND ST_Dimension(\"geom\"::geometry) = 2"));
+        assert_eq!(sql.matches(", \"name\"").count(), 2);
+        assert_eq!(sql.matches("LIMIT 10").count(), 2);
+
+        info.layer_id = Some("footprints".to_string());
+        info.label_points = Some(LabelPoints::Centroid);
+        let sql = table_to_sql("buildings", &info, true, None);
+        assert!(sql.contains("ST_AsMVT(labels, 'footprints_labels', 4096, 'geom')"));
+        assert!(sql.contains("ST_Centroid(\"geom\"::geometry)"));
+
+        let layers = info
+            .to_tilejson("buildings".to_string())
+            .vector_layers
+            .unwrap();
+        let ids: Vec<_> = layers.iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, ["footprints", "footprints_labels"]);
+        assert!(layers[1].fields.contains_key("name"));
+    }
+
+    #[test]
+    fn table_sql_geometry_only() {
+        let mut info = table("POLYGON");
+        info.id_column = Some("gid".to_string());
+        info.properties = Some([("name".to_string(), "text".to_string())].into());
+        info.null_properties = Some(NullProperties::Marker("null".to_string()));
+        info.z_property = Some("elevation".to_string());
+        let sql = table_to_sql("land", &info, true, None);
+        assert!(sql.contains(r#"COALESCE("name", 'null') AS "name""#));
+
+        strip_properties(&mut info);
+        let sql = table_to_sql("land", &info, true, None);
+        assert!(sql.contains(
+            r#") AS geom
+    , "gid"
+  FROM"#
+        ));
+        let layers = info.to_tilejson("land".to_string()).vector_layers.unwrap();
+        assert!(layers[0].fields.is_empty());
+    }
+
+    #[test]
+    fn table_sql_mixed_srids() {
+        let mut info = table("POINT");
+        info.srid = 4326;
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(r#""geom" && ST_Transform("#));
+
+        info.has_mixed_srids = Some(true);
+        let sql = table_to_sql("src", &info, true, None);
+        let column = r#"ST_Transform(CASE WHEN ST_SRID("geom"::geometry) = 0 THEN ST_SetSRID("geom"::geometry, 4326) ELSE "geom"::geometry END, 4326)"#;
+        assert!(sql.contains(&format!("ST_Transform(ST_CurveToLine({column}), 3857)")));
+        assert!(sql.contains(&format!("{column} && ST_Transform(")));
+        assert!(!sql.contains(r#""geom" && "#));
+    }
+
+    #[test]
+    fn table_sql_geography() {
+        let mut info = table("POINT");
+        info.srid = 4326;
+        info.is_geography = Some(true);
+        info.antimeridian = Some(Antimeridian::Split);
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains(r#""geom"::geometry && ST_Transform("#));
+        assert!(!sql.contains(r#""geom" && "#));
+    }
+
+    #[tokio::test]
+    async fn detect_mixed_srids() -> anyhow::Result<()> {
+        let (_node, pool) = test_pool().await?;
+        pool.get()
+            .await?
+            .batch_execute(
+                "CREATE TABLE mixed (gid int, geom geometry(Point));
+             INSERT INTO mixed VALUES (1, ST_SetSRID(ST_MakePoint(10, 10), 4326)),
+                                      (2, ST_SetSRID(ST_MakePoint(1113194.9, 1118890.0), 3857));
+             CREATE TABLE single (gid int, geom geometry(Point));
+             INSERT INTO single VALUES (1, ST_SetSRID(ST_MakePoint(10, 10), 4326));",
+            )
+            .await?;
+
+        let info = |table: &str, mixed_srids| TableInfo {
+            schema: "public".to_string(),
+            table: table.to_string(),
+            geometry_column: "geom".to_string(),
+            srid: 4326,
+            srid_unconstrained: Some(true),
+            mixed_srids,
+            ..Default::default()
+        };
+        let query = |info| {
+            table_to_query(
+                "src".to_string(),
+                info,
+                pool.clone(),
+                BoundsCalcType::Calc,
+                None,
+            )
+        };
+
+        let err = query(info("mixed", None)).await.unwrap_err();
+        assert!(matches!(err, MixedGeometrySrids(_, _, ref srids) if srids == &[3857, 4326]));
+
+        let (_, _, res) = query(info("mixed", Some(MixedSrids::Transform))).await?;
+        assert_eq!(res.has_mixed_srids, Some(true));
+        // both points are at about 10°E, 10°N, instead of the raw 3857 coordinates
+        let bounds = res.bounds.unwrap();
+        assert!(bounds.left > 8.9 && bounds.right < 11.1);
+        assert!(bounds.bottom > 8.9 && bounds.top < 11.1);
+
+        let (_, _, res) = query(info("single", None)).await?;
+        assert_eq!(res.has_mixed_srids, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_duplicate_ids() -> anyhow::Result<()> {
+        let (_node, pool) = test_pool().await?;
+        pool.get()
+            .await?
+            .batch_execute(
+                "CREATE TABLE duplicates (gid int, geom geometry(Point, 4326));
+             INSERT INTO duplicates VALUES (1, 'SRID=4326;POINT(10 10)'), (1, 'SRID=4326;POINT(20 20)'),
+                                           (NULL, 'SRID=4326;POINT(30 30)');
+             CREATE TABLE distinct_ids (gid int, geom geometry(Point, 4326));
+             INSERT INTO distinct_ids VALUES (1, 'SRID=4326;POINT(10 10)'), (2, 'SRID=4326;POINT(20 20)'),
+                                             (NULL, 'SRID=4326;POINT(30 30)'), (NULL, 'SRID=4326;POINT(40 40)');
+             CREATE TABLE primary_key (gid int PRIMARY KEY, geom geometry(Point, 4326));
+             INSERT INTO primary_key VALUES (1, 'SRID=4326;POINT(10 10)');",
+            )
+            .await?;
+
+        let info = |table: &str, duplicate_ids| TableInfo {
+            schema: "public".to_string(),
+            table: table.to_string(),
+            geometry_column: "geom".to_string(),
+            srid: 4326,
+            id_column: Some("gid".to_string()),
+            duplicate_ids,
+            ..Default::default()
+        };
+        let query = |info| {
+            table_to_query(
+                "src".to_string(),
+                info,
+                pool.clone(),
+                BoundsCalcType::Skip,
+                None,
+            )
+        };
+
+        let err = query(info("duplicates", Some(DuplicateIds::Error)))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DuplicateFeatureIds(_, ref column, _) if column == "gid"));
+        // the source is published with a warning by default
+        query(info("duplicates", None)).await?;
+        query(info("duplicates", Some(DuplicateIds::Ignore))).await?;
+
+        // NULL values are not IDs, and a unique index guarantees unique IDs
+        assert!(!has_duplicate_ids(&pool, &info("distinct_ids", None), "gid").await?);
+        assert!(!has_duplicate_ids(&pool, &info("primary_key", None), "gid").await?);
+        query(info("distinct_ids", Some(DuplicateIds::Error))).await?;
+        query(info("primary_key", Some(DuplicateIds::Error))).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn snap_to_grid_tiles() -> anyhow::Result<()> {
+        use deadpool_postgres::tokio_postgres::types::Type;
+        let (_node, pool) = test_pool().await?;
+        let conn = pool.get().await?;
+        conn.batch_execute(
+            "CREATE TABLE precise (gid int, geom geometry(Geometry, 4326));
+             INSERT INTO precise VALUES (1, 'SRID=4326;POINT(10.312345678 20.287654321)');
+             CREATE TABLE rounded (gid int, geom geometry(Geometry, 4326));
+             INSERT INTO rounded VALUES (1, 'SRID=4326;POINT(10 20)');
+             CREATE TABLE arc (gid int, geom geometry(Geometry, 4326));
+             INSERT INTO arc SELECT 1, ST_SetSRID(ST_MakeLine(array(
+                 SELECT ST_MakePoint(10 * cos(pi() * i / 1000), 10 * sin(pi() * i / 1000))
+                 FROM generate_series(0, 1000) AS i)), 4326);",
+        )
+        .await?;
+
+        let get_tile = async |table: &str, snap: Option<f64>| -> anyhow::Result<Vec<u8>> {
+            let info = TableInfo {
+                schema: "public".to_string(),
+                table: table.to_string(),
+                geometry_column: "geom".to_string(),
+                srid: 4326,
+                id_column: Some("gid".to_string()),
+                geometry_transforms: snap.map(|v| vec![GeometryTransform::SnapToGrid(v)]),
+                ..Default::default()
+            };
+            let sql = table_to_sql("src", &info, false, None);
+            let query = conn
+                .prepare_typed(&sql, &[Type::INT2, Type::INT8, Type::INT8])
+                .await?;
+            let row = conn.query_one(&query, &[&0_i16, &0_i64, &0_i64]).await?;
+            Ok(row.get::<_, Option<Vec<u8>>>(0).unwrap_or_default())
+        };
+
+        // the snapped coordinates are exactly those of the rounded point
+        let snapped = get_tile("precise", Some(1.0)).await?;
+        assert_eq!(snapped, get_tile("rounded", None).await?);
+        assert_ne!(snapped, get_tile("precise", None).await?);
+
+        // a coarse grid leaves fewer vertices of the arc
+        let raw = get_tile("arc", None).await?;
+        let snapped = get_tile("arc", Some(2.0)).await?;
+        assert!(!snapped.is_empty());
+        assert!(snapped.len() < raw.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn geography_tiles() -> anyhow::Result<()> {
+        use deadpool_postgres::tokio_postgres::types::Type;
+        let (_node, pool) = test_pool().await?;
+        let conn = pool.get().await?;
+        conn.batch_execute(
+            "CREATE TABLE geog (gid int, geom geography);
+             INSERT INTO geog VALUES (1, 'POINT(10 20)'), (2, 'POINT(-170 -60)');
+             CREATE INDEX ON geog USING GIST ((geom::geometry));
+             CREATE TABLE geog_typed (gid int, geom geography(Point, 4326));
+             INSERT INTO geog_typed VALUES (1, 'SRID=4326;POINT(10 20)'), (2, 'SRID=4326;POINT(-170 -60)');
+             CREATE INDEX ON geog_typed USING GIST (geom);
+             CREATE TABLE geom (gid int, geom geometry(Point, 4326));
+             INSERT INTO geom VALUES (1, 'SRID=4326;POINT(10 20)'), (2, 'SRID=4326;POINT(-170 -60)');",
+        )
+        .await?;
+
+        let tables = query_available_tables(&pool, DuplicateGeometryPolicy::default()).await?;
+        let info = |table: &str| tables["public"][table]["geom"].clone();
+        // geography values without an SRID are in WGS84
+        let geog = info("geog");
+        assert_eq!(geog.is_geography, Some(true));
+        assert_eq!((geog.srid, geog.srid_unconstrained), (4326, Some(false)));
+        assert_eq!(geog.geometry_index, Some(true));
+        // a geography index cannot be used to search for geometries
+        let geog_typed = info("geog_typed");
+        assert_eq!(geog_typed.is_geography, Some(true));
+        assert_eq!(geog_typed.geometry_index, Some(false));
+        assert_eq!(
+            spatial_index_recommendation(&geog_typed).as_deref(),
+            Some(r#"CREATE INDEX ON "public"."geog_typed" USING GIST (("geom"::geometry));"#)
+        );
+        assert_eq!(info("geom").is_geography, Some(false));
+
+        let get_tile =
+            async |info: &TableInfo, z: i16, x: i64, y: i64| -> anyhow::Result<Vec<u8>> {
+                let info = TableInfo {
+                    id_column: Some("gid".to_string()),
+                    ..info.clone()
+                };
+                let sql = table_to_sql("src", &info, true, None);
+                let query = conn
+                    .prepare_typed(&sql, &[Type::INT2, Type::INT8, Type::INT8])
+                    .await?;
+                let row = conn.query_one(&query, &[&z, &x, &y]).await?;
+                Ok(row.get::<_, Option<Vec<u8>>>(0).unwrap_or_default())
+            };
+
+        // the world tile has both points, like the same geometries, and so do the tiles of each point
+        for (z, x, y) in [(0, 0, 0), (3, 4, 3), (3, 0, 5)] {
+            let expected = get_tile(&info("geom"), z, x, y).await?;
+            assert!(!expected.is_empty());
+            assert_eq!(get_tile(&geog, z, x, y).await?, expected);
+            assert_eq!(get_tile(&geog_typed, z, x, y).await?, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn table_sql_point_clusters() {
+        let mut info = table("POINT");
+        info.srid = 3857;
+        info.id_column = Some("gid".to_string());
+        info.properties = Some([("name".to_string(), "text".to_string())].into());
+        let raw = table_to_sql("src", &info, true, None);
+        assert!(!raw.contains("point_count"));
+
+        info.point_clusters = Some(PointClusters {
+            maxzoom: 8,
+            radius: Some(512),
+        });
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.starts_with("SELECT CASE WHEN $1::integer <= 8 THEN ("));
+        assert!(sql.contains(r#"count(*) AS "point_count""#));
+        assert!(sql.contains("* 0.125 AS size"));
+        assert!(sql.contains("GROUP BY ST_SnapToGrid(points.geom, cell.size)"));
+        // clusters have no ID and no properties, the raw points above maxzoom are unchanged
+        assert!(sql.contains("ST_AsMVT(tile, 'src', 4096, 'geom')"));
+        let raw = raw.trim_end_matches(';');
+        assert!(sql.ends_with(&format!("ELSE (\n{raw}\n) END;")));
+
+        // clusters cover the same area with larger tiles
+        scale_to_tile_size(&mut info, 512);
+        assert_eq!(info.point_clusters.unwrap().radius, Some(1024));
+        let sql = table_to_sql("src", &info, true, None);
+        assert!(sql.contains("* 0.125 AS size"));
+
+        let layers = info.to_tilejson("src".to_string()).vector_layers.unwrap();
+        assert_eq!(layers[0].fields["point_count"], "number");
+
+        let clusters = PointClusters {
+            maxzoom: 8,
+            radius: None,
+        };
+        assert!(clusters.validate(Some("POINT")).is_ok());
+        assert!(clusters.validate(Some("MULTIPOINT")).is_ok());
+        assert!(clusters.validate(None).is_ok());
+        assert!(clusters.validate(Some("POLYGON")).is_err());
+        let clusters = PointClusters {
+            radius: Some(0),
+            ..clusters
+        };
+        assert!(clusters.validate(Some("POINT")).is_err());
+    }
+}
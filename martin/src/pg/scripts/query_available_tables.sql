@@ -28,9 +28,27 @@ WITH
                  JOIN pg_opclass op ON
                     op.oid = ix.indclass[0] AND
                     op.opcname IN ('gist_geometry_ops_2d', 'spgist_geometry_ops_2d',
-                                   'brin_geometry_inclusion_ops_2d',
-                                   'gist_geography_ops')
-        GROUP BY 1, 2, 3),
+                                   'brin_geometry_inclusion_ops_2d')
+        GROUP BY 1, 2, 3
+        UNION
+        -- geography columns are searched as geometries, using an index on the column cast to geometry
+        SELECT ns.nspname    AS table_schema,
+               class.relname AS table_name,
+               attr.attname  AS column_name
+        FROM pg_attribute attr
+                 JOIN pg_class class on class.oid = attr.attrelid
+                 JOIN pg_namespace ns on ns.oid = class.relnamespace
+                 JOIN pg_index ix on
+                    ix.indrelid = class.oid and
+                    ix.indnkeyatts = 1 and
+                    ix.indkey[0] = 0 -- expression index
+                 JOIN pg_opclass op ON
+                    op.oid = ix.indclass[0] AND
+                    op.opcname IN ('gist_geometry_ops_2d', 'spgist_geometry_ops_2d',
+                                   'brin_geometry_inclusion_ops_2d')
+        WHERE attr.attnum > 0
+          AND pg_get_indexdef(ix.indexrelid, 1, true) IN (format('%I::geometry', attr.attname),
+                                                          format('(%I::geometry)', attr.attname))),
     --
     annotated_geometry_columns AS (
         -- list of geometry columns with additional metadata
@@ -39,7 +57,8 @@ WITH
                f_geometry_column                    AS geom,
                srid,
                type,
-               -- 'geometry' AS column_type
+               coord_dimension,
+               'geometry'                           AS column_type,
                COALESCE(class.relkind = 'v', false) AS is_view,
                bool_or(sic.column_name is not null) as geom_idx
         FROM geometry_columns
@@ -51,7 +70,7 @@ WITH
                     geometry_columns.f_table_schema = sic.table_schema AND
                     geometry_columns.f_table_name = sic.table_name AND
                     geometry_columns.f_geometry_column = sic.column_name
-        GROUP BY 1, 2, 3, 4, 5, 6),
+        GROUP BY 1, 2, 3, 4, 5, 6, 7, 8),
     --
     annotated_geography_columns AS (
         -- list of geography columns with additional metadata
@@ -60,7 +79,8 @@ WITH
                f_geography_column                   AS geom,
                srid,
                type,
-               -- 'geography' AS column_type
+               coord_dimension,
+               'geography'                          AS column_type,
                COALESCE(class.relkind = 'v', false) AS is_view,
                bool_or(sic.column_name is not null) as geom_idx
         FROM geography_columns
@@ -72,7 +92,7 @@ WITH
                     geography_columns.f_table_schema = sic.table_schema AND
                     geography_columns.f_table_name = sic.table_name AND
                     geography_columns.f_geography_column = sic.column_name
-        GROUP BY 1, 2, 3, 4, 5, 6),
+        GROUP BY 1, 2, 3, 4, 5, 6, 7, 8),
     --
     annotated_geo_columns AS (
         SELECT * FROM annotated_geometry_columns
@@ -95,6 +115,8 @@ SELECT schema,
        geom,
        srid,
        type,
+       coord_dimension,
+       column_type,
        is_view,
        geom_idx,
        COALESCE(
@@ -115,4 +137,4 @@ FROM annotated_geo_columns AS gc
          LEFT JOIN descriptions AS dc on
             gc.schema = dc.schema_name AND
             gc.name = dc.table_name
-GROUP BY gc.schema, gc.name, gc.geom, gc.srid, gc.type, gc.is_view, gc.geom_idx,dc.description;
+GROUP BY gc.schema, gc.name, gc.geom, gc.srid, gc.type, gc.coord_dimension, gc.column_type, gc.is_view, gc.geom_idx,dc.description;
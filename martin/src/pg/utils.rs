@@ -1,13 +1,15 @@
 use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use deadpool_postgres::tokio_postgres::types::Json;
+use futures::future::join_all;
 use futures::pin_mut;
 use itertools::Itertools as _;
 use log::{error, info, warn};
 use postgis::{LineString, Point, Polygon, ewkb};
 use tilejson::{Bounds, TileJSON};
+use tokio::sync::Semaphore;
 use tokio::time::timeout;
 
 use crate::source::UrlQuery;
@@ -44,6 +46,40 @@ pub async fn on_slow<T, S: FnOnce()>(
     }
 }
 
+/// Await the future, and if it took longer than the `threshold` to complete,
+/// call `fn_on_slow` with the actual elapsed time. Does nothing extra if `threshold` is `None`.
+pub async fn on_slow_completion<T, S: FnOnce(Duration)>(
+    future: impl Future<Output = T>,
+    threshold: Option<Duration>,
+    fn_on_slow: S,
+) -> T {
+    let Some(threshold) = threshold else {
+        return future.await;
+    };
+    let start = Instant::now();
+    let result = future.await;
+    let elapsed = start.elapsed();
+    if elapsed > threshold {
+        fn_on_slow(elapsed);
+    }
+    result
+}
+
+/// Run all futures concurrently with at most `limit` of them in progress at the same time.
+/// The results are returned in the same order as the futures.
+pub async fn join_all_limited<F: Future>(
+    futures: impl IntoIterator<Item = F>,
+    limit: usize,
+) -> Vec<F::Output> {
+    let semaphore = Semaphore::new(limit.max(1));
+    join_all(futures.into_iter().map(|future| async {
+        // The semaphore is never closed, so acquiring a permit cannot fail
+        let _permit = semaphore.acquire().await.ok();
+        future.await
+    }))
+    .await
+}
+
 #[must_use]
 pub fn json_to_hashmap(value: &serde_json::Value) -> InfoMap<String> {
     let mut result = BTreeMap::new();
@@ -192,3 +228,71 @@ pub fn find_kv_ignore_case<'a, T>(
         Err(multiple)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use tokio::time::sleep;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn join_all_limited_bounds_concurrency() {
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+        let query = |i: usize| {
+            let (active, max_active) = (&active, &max_active);
+            async move {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                max_active.fetch_max(now, Ordering::SeqCst);
+                sleep(Duration::from_millis(10)).await;
+                active.fetch_sub(1, Ordering::SeqCst);
+                i
+            }
+        };
+
+        let res = join_all_limited((0..10).map(query), 3).await;
+        assert_eq!(res, (0..10).collect::<Vec<_>>());
+        assert_eq!(max_active.load(Ordering::SeqCst), 3);
+
+        max_active.store(0, Ordering::SeqCst);
+        join_all_limited((0..3).map(query), 0).await;
+        assert_eq!(max_active.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn slow_completion_is_reported() {
+        let reported = AtomicBool::new(false);
+        let slow_query = async {
+            sleep(Duration::from_millis(50)).await;
+            42
+        };
+        let res = on_slow_completion(slow_query, Some(Duration::from_millis(10)), |elapsed| {
+            assert!(elapsed >= Duration::from_millis(50));
+            reported.store(true, Ordering::SeqCst);
+        })
+        .await;
+        assert_eq!(res, 42);
+        assert!(reported.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn fast_or_disabled_completion_is_not_reported() {
+        let res = on_slow_completion(async { 1 }, Some(Duration::from_secs(10)), |_| {
+            panic!("fast query must not be reported");
+        })
+        .await;
+        assert_eq!(res, 1);
+
+        let slow_query = async {
+            sleep(Duration::from_millis(20)).await;
+            2
+        };
+        let res = on_slow_completion(slow_query, None, |_| {
+            panic!("disabled threshold must not report");
+        })
+        .await;
+        assert_eq!(res, 2);
+    }
+}
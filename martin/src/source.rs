@@ -1,59 +1,134 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use actix_web::error::ErrorNotFound;
+use actix_web::error::{ErrorBadRequest, ErrorNotFound};
 use async_trait::async_trait;
 use dashmap::DashMap;
-use log::debug;
-use martin_tile_utils::{TileCoord, TileInfo};
+use enum_display::EnumDisplay;
+use log::{Level, debug};
+use martin_tile_utils::{
+    EARTH_CIRCUMFERENCE, MAX_ZOOM, TileCoord, TileInfo, tile_index, webmercator_to_wgs84,
+};
 use serde::{Deserialize, Serialize};
 use tilejson::TileJSON;
 
 use crate::MartinResult;
+use crate::srv::RESERVED_KEYWORDS;
 
 pub type TileData = Vec<u8>;
 pub type UrlQuery = HashMap<String, String>;
 
+/// Logical tile size in pixels of the tiles generated with the source's configured extent
+pub const TILE_SIZE_DEFAULT: u32 = 256;
+/// Supported values of the `tile_size` URL query parameter
+pub const TILE_SIZES: [u32; 3] = [256, 512, 1024];
+
+/// `TileJSON` key with the OGC tile matrix set of the source, if not `WebMercatorQuad`
+pub const TILE_MATRIX_SET_KEY: &str = "tile_matrix_set";
+
+/// `TileJSON` key with the time the data of the source was created, e.g. `2024-01-31T12:00:00Z`
+pub const CREATED_KEY: &str = "created";
+
+/// `TileJSON` key with the time the data of the source was last updated, e.g. `2024-01-31T12:00:00Z`
+pub const UPDATED_KEY: &str = "updated";
+
 pub type TileInfoSource = Box<dyn Source>;
 
 pub type TileInfoSources = Vec<TileInfoSource>;
 
+/// Tile sources by ID. Clones share the same sources, so replacing them is seen by all clones.
 #[derive(Default, Clone)]
-pub struct TileSources(DashMap<String, TileInfoSource>);
+pub struct TileSources(Arc<RwLock<Arc<HashMap<String, TileInfoSource>>>>);
 pub type TileCatalog = DashMap<String, CatalogSourceEntry>;
 
 impl TileSources {
     #[must_use]
     pub fn new(sources: Vec<TileInfoSources>) -> Self {
-        Self(
+        Self(Arc::new(RwLock::new(Arc::new(
             sources
                 .into_iter()
                 .flatten()
                 .map(|src| (src.get_id().to_string(), src))
                 .collect(),
-        )
+        ))))
+    }
+
+    /// Current sources, kept unchanged by a later replacement
+    fn current(&self) -> Arc<HashMap<String, TileInfoSource>> {
+        self.0.read().expect("tile sources lock poisoned").clone()
+    }
+
+    /// Whether a source with this ID exists
+    #[must_use]
+    pub fn contains(&self, id: &str) -> bool {
+        self.current().contains_key(id)
+    }
+
+    /// IDs of all sources
+    #[must_use]
+    pub fn get_ids(&self) -> BTreeSet<String> {
+        self.current().keys().cloned().collect()
+    }
+
+    /// Replace all sources with the given ones at once. A request reading the sources from a single
+    /// [`TileSources::snapshot`] uses either all the old or all the new sources, and never a mix of both.
+    pub fn replace(&self, sources: &Self) {
+        let sources = sources.current();
+        *self.0.write().expect("tile sources lock poisoned") = sources;
+    }
+
+    /// Current sources, kept unchanged by a later replacement, so that all reads of a request,
+    /// e.g. its scheme, access checks, and tiles, use the same sources
+    #[must_use]
+    pub fn snapshot(&self) -> Self {
+        Self(Arc::new(RwLock::new(self.current())))
     }
 
     #[must_use]
     pub fn get_catalog(&self) -> TileCatalog {
-        self.0
+        self.current()
             .iter()
-            .map(|v| (v.key().to_string(), v.get_catalog_entry()))
+            .map(|(id, src)| (id.to_string(), src.get_catalog_entry()))
             .collect()
     }
 
+    /// Get a copy of all sources
+    #[must_use]
+    pub fn get_all_sources(&self) -> TileInfoSources {
+        self.current().values().map(|v| v.clone_source()).collect()
+    }
+
     pub fn get_source(&self, id: &str) -> actix_web::Result<TileInfoSource> {
-        Ok(self
-            .0
+        self.current()
             .get(id)
-            .ok_or_else(|| ErrorNotFound(format!("Source {id} does not exist")))?
-            .value()
-            .clone())
+            .cloned()
+            .ok_or_else(|| ErrorNotFound(format!("Source {id} does not exist")))
+    }
+
+    /// Tile addressing scheme of the requests for the given comma-separated sources, see [`TileScheme::composite`].
+    /// Missing sources are ignored. Sources with different schemes are a `400 Bad Request`.
+    pub fn get_scheme(&self, source_ids: &str) -> actix_web::Result<TileScheme> {
+        let current = self.current();
+        TileScheme::composite(
+            source_ids
+                .split(',')
+                .filter_map(|id| current.get(id).map(|v| v.get_scheme())),
+        )
+        .ok_or_else(|| {
+            ErrorBadRequest(format!(
+                "Sources {source_ids}: cannot merge sources with different tile addressing schemes"
+            ))
+        })
     }
 
     /// Get a list of sources, and the tile info for the merged sources.
-    /// Ensure that all sources have the same format and encoding.
+    /// Ensure that all sources have the same format, encoding, tile addressing scheme, and tile grid.
     /// If zoom is specified, filter out sources that do not support it.
+    ///
+    /// All invalid source IDs are reported together in a single error. The error is a `404 Not Found`
+    /// if every problem is a missing source, and a `400 Bad Request` otherwise.
     pub fn get_sources(
         &self,
         source_ids: &str,
@@ -61,23 +136,71 @@ impl TileSources {
     ) -> actix_web::Result<(Vec<TileInfoSource>, bool, TileInfo)> {
         let mut sources = Vec::new();
         let mut info: Option<TileInfo> = None;
+        let mut scheme: Option<TileScheme> = None;
+        let mut grid: Option<TileGrid> = None;
         let mut use_url_query = false;
+        let mut errors = Vec::new();
+        let mut all_missing = true;
+        let current = self.current();
 
         for id in source_ids.split(',') {
-            let src = self.get_source(id)?;
+            let Some(src) = current.get(id).cloned() else {
+                if RESERVED_KEYWORDS.contains(&id) {
+                    all_missing = false;
+                    errors.push(format!("Source {id} is a reserved keyword"));
+                } else {
+                    errors.push(format!("Source {id} does not exist"));
+                }
+                continue;
+            };
             let src_inf = src.get_tile_info();
-            use_url_query |= src.support_url_query();
+            let src_scheme = src.get_scheme();
+            let src_grid = src.get_tile_grid();
+
+            // make sure all sources use the same tile addressing scheme
+            match scheme {
+                Some(v) if v != src_scheme => {
+                    all_missing = false;
+                    errors.push(format!(
+                        "Source {id}: cannot merge sources with {v} scheme with {src_scheme} scheme"
+                    ));
+                    continue;
+                }
+                _ => {}
+            }
+
+            // make sure all sources use the same tile grid
+            match grid {
+                Some(v) if v != src_grid => {
+                    all_missing = false;
+                    errors.push(format!(
+                        "Source {id}: cannot merge sources with {} tile grid with {} tile grid",
+                        v.as_str(),
+                        src_grid.as_str()
+                    ));
+                    continue;
+                }
+                _ => {}
+            }
 
             // make sure all sources have the same format and encoding
             // TODO: support multiple encodings of the same format
             match info {
-                Some(inf) if inf == src_inf => {}
-                Some(inf) => Err(ErrorNotFound(format!(
-                    "Cannot merge sources with {inf} with {src_inf}"
-                )))?,
-                None => info = Some(src_inf),
+                Some(inf) if inf != src_inf => {
+                    all_missing = false;
+                    errors.push(format!(
+                        "Source {id}: cannot merge sources with {inf} with {src_inf}"
+                    ));
+                    continue;
+                }
+                _ => {}
             }
 
+            scheme = Some(src_scheme);
+            grid = Some(src_grid);
+            info = Some(src_inf);
+            use_url_query |= src.support_url_query();
+
             // TODO: Use chained-if-let once available
             if match zoom {
                 Some(zoom) if Self::check_zoom(&*src, id, zoom) => true,
@@ -88,6 +211,15 @@ impl TileSources {
             }
         }
 
+        if !errors.is_empty() {
+            let msg = errors.join("\n");
+            return Err(if all_missing {
+                ErrorNotFound(msg)
+            } else {
+                ErrorBadRequest(msg)
+            });
+        }
+
         // format is guaranteed to be Some() here
         Ok((sources, use_url_query, info.unwrap()))
     }
@@ -102,7 +234,7 @@ impl TileSources {
 }
 
 #[async_trait]
-pub trait Source: Send + Debug {
+pub trait Source: Send + Sync + Debug {
     fn get_id(&self) -> &str;
 
     fn get_tilejson(&self) -> &TileJSON;
@@ -115,12 +247,117 @@ pub trait Source: Send + Debug {
         false
     }
 
+    /// Whether the source can limit returned feature properties with the `fields` URL query parameter.
+    /// Requests using `fields` on sources without this capability are rejected with `501 Not Implemented`.
+    fn supports_fields_filter(&self) -> bool {
+        false
+    }
+
+    /// Whether the source can scale its tile extent with the `tile_size` URL query parameter.
+    /// Requests using `tile_size` on sources without this capability are rejected with `501 Not Implemented`.
+    fn supports_tile_size(&self) -> bool {
+        false
+    }
+
+    /// Names of the URL query parameters used by the source if it does not support arbitrary URL queries.
+    /// Only these parameters are passed to the source and used in the cache keys, all others are ignored.
+    fn url_query_params(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// Database schema of this source, if any. Used to sort the catalog.
+    fn get_schema(&self) -> Option<&str> {
+        None
+    }
+
+    /// Catalog group of this source, if configured, e.g. to show related sources together in a UI
+    fn get_group(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether the tiles of this source never change, so clients and CDNs may cache them forever.
+    fn is_immutable(&self) -> bool {
+        false
+    }
+
+    /// Whether the uncompressed tiles of this source are worth compressing for clients that accept it.
+    /// Sources with tiny tiles may opt out, so that their tiles are always served uncompressed.
+    fn is_compressible(&self) -> bool {
+        true
+    }
+
+    /// Level of the per-request log messages of this source, e.g. the generated SQL and the tile timing.
+    fn get_log_level(&self) -> Level {
+        Level::Debug
+    }
+
+    /// Whether the source is known to contain no data, so all of its tiles will be empty.
+    fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Coordinate dimensions of the source geometries, e.g. `XYZ`, if known.
+    /// Tile geometries are always 2D regardless of this value.
+    fn get_dimensions(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Tile addressing scheme of this source, as advertised in its `TileJSON`.
+    /// Tile requests are given in this scheme, but are always passed to [`Source::get_tile`] as XYZ.
+    fn get_scheme(&self) -> TileScheme {
+        match self.get_tilejson().scheme.as_deref() {
+            Some("tms") => TileScheme::Tms,
+            _ => TileScheme::Xyz,
+        }
+    }
+
+    /// Tile grid of this source, as advertised with the `tile_matrix_set` key of its `TileJSON`.
+    fn get_tile_grid(&self) -> TileGrid {
+        self.get_tilejson()
+            .other
+            .get(TILE_MATRIX_SET_KEY)
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default()
+    }
+
     async fn get_tile(
         &self,
         xyz: TileCoord,
         url_query: Option<&UrlQuery>,
     ) -> MartinResult<TileData>;
 
+    /// Whether the number of features in a tile is reported with the `X-Feature-Count` header of `HEAD` tile requests.
+    /// The features are counted in the generated tile, so only sources of MVT tiles may support it.
+    fn supports_feature_count(&self) -> bool {
+        false
+    }
+
+    /// Get the query plan used to generate the tile, or `None` if the source does not support it.
+    /// Used by the `/{source_id}/explain/{z}/{x}/{y}` endpoint.
+    async fn explain_tile(
+        &self,
+        _xyz: TileCoord,
+        _url_query: Option<&UrlQuery>,
+    ) -> MartinResult<Option<serde_json::Value>> {
+        Ok(None)
+    }
+
+    /// How often the data of this source is refreshed with [`Source::refresh`] in the background, if at all.
+    fn get_refresh_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Refresh the data of this source, e.g. its materialized view, and return how long it took,
+    /// or `None` if the source does not support it. Used by the `/refresh/{source_id}` endpoint.
+    async fn refresh(&self) -> MartinResult<Option<Duration>> {
+        Ok(None)
+    }
+
+    /// Effective configuration of this source, listed by `/catalog?verbose=true` for debugging
+    fn get_catalog_config(&self) -> Option<CatalogSourceConfig> {
+        None
+    }
+
     fn is_valid_zoom(&self, zoom: u8) -> bool {
         let tj = self.get_tilejson();
         tj.minzoom.is_none_or(|minzoom| zoom >= minzoom)
@@ -137,6 +374,21 @@ pub trait Source: Send + Debug {
             name: tilejson.name.as_ref().filter(|v| *v != id).cloned(),
             description: tilejson.description.clone(),
             attribution: tilejson.attribution.clone(),
+            version: tilejson.version.clone(),
+            updated: tilejson
+                .other
+                .get(UPDATED_KEY)
+                .and_then(|v| v.as_str())
+                .map(ToString::to_string),
+            empty: self.is_empty().then_some(true),
+            dimensions: self
+                .get_dimensions()
+                .filter(|v| *v != "XY")
+                .map(ToString::to_string),
+            schema: self.get_schema().map(ToString::to_string),
+            group: self.get_group().map(ToString::to_string),
+            links: None,
+            config: None,
         }
     }
 }
@@ -147,6 +399,251 @@ impl Clone for TileInfoSource {
     }
 }
 
+/// Tile addressing scheme. `tms` has the Y axis origin at the bottom instead of the top.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, EnumDisplay)]
+#[serde(rename_all = "lowercase")]
+#[enum_display(case = "Lower")]
+pub enum TileScheme {
+    #[default]
+    Xyz,
+    Tms,
+}
+
+impl TileScheme {
+    /// Convert tile coordinates given in this scheme to the XYZ scheme.
+    /// Returns `None` if the coordinates are not valid for the zoom level.
+    #[must_use]
+    pub fn to_xyz(self, xyz: TileCoord) -> Option<TileCoord> {
+        match self {
+            Self::Xyz => Some(xyz),
+            Self::Tms => {
+                let tiles = 1_u32.checked_shl(u32::from(xyz.z))?;
+                let y = tiles.checked_sub(1)?.checked_sub(xyz.y)?;
+                Some(TileCoord { y, ..xyz })
+            }
+        }
+    }
+
+    /// Scheme of a composite source, i.e. the scheme of all of its sources, or `None` if they differ.
+    /// Sources with different schemes cannot be combined, as their tiles with the same Y coordinate are mirrored.
+    #[must_use]
+    pub fn composite(schemes: impl IntoIterator<Item = Self>) -> Option<Self> {
+        let mut schemes = schemes.into_iter();
+        let first = schemes.next().unwrap_or_default();
+        schemes.all(|v| v == first).then_some(first)
+    }
+}
+
+/// OGC tile matrix set of a source, i.e. the coordinate system and the number of tiles at each zoom level.
+/// Non-default tile grids are advertised with the `tile_matrix_set` key of the source's `TileJSON`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TileGrid {
+    /// Web Mercator (EPSG:3857) with a single tile at zoom 0
+    #[default]
+    WebMercatorQuad,
+    /// WGS84 (EPSG:4326) with two tiles, west and east, at zoom 0
+    #[serde(rename = "WorldCRS84Quad")]
+    WorldCrs84Quad,
+    /// A regional grid in any projection, with a single square tile at zoom 0
+    #[serde(rename = "custom")]
+    Custom(CustomGrid),
+}
+
+/// A regional tile grid, e.g. in a national projection. Its zoom 0 tile covers the square
+/// from the origin to the east and to the south, and each zoom level splits the tiles in four.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CustomGrid {
+    /// SRID of the grid, e.g. 2056 for the Swiss LV95 projection
+    pub srid: i32,
+    /// Top-left corner `[x, y]` of the zoom 0 tile, in the units of the SRID
+    pub origin: [f64; 2],
+    /// Width and height of the zoom 0 tile, in the units of the SRID
+    pub tile_extent: f64,
+}
+
+impl TileGrid {
+    /// OGC identifier of the tile matrix set, or `custom` for regional grids
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::WebMercatorQuad => "WebMercatorQuad",
+            Self::WorldCrs84Quad => "WorldCRS84Quad",
+            Self::Custom(_) => "custom",
+        }
+    }
+
+    /// Ensure that a custom grid has a positive SRID, a finite origin and a positive tile extent,
+    /// and that its zoom 0 tile lies within the valid coordinates of the Web Mercator and WGS84 SRIDs.
+    /// Like `WorldCRS84Quad`, a WGS84 grid may extend below the south pole to keep its tiles square.
+    pub fn validate(self) -> Result<(), String> {
+        let Self::Custom(grid) = self else {
+            return Ok(());
+        };
+        if grid.srid <= 0 {
+            return Err(format!(
+                "custom tile grid must have a positive srid, got {}",
+                grid.srid
+            ));
+        }
+        if !(grid.origin.iter().all(|v| v.is_finite())
+            && grid.tile_extent.is_finite()
+            && grid.tile_extent > 0.0)
+        {
+            return Err(format!(
+                "custom tile grid must have a finite origin and a positive tile_extent, got {grid:?}"
+            ));
+        }
+        let [left, top] = grid.origin;
+        let (right, bottom) = (left + grid.tile_extent, top - grid.tile_extent);
+        let limit = EARTH_CIRCUMFERENCE / 2.0;
+        let is_valid = match grid.srid {
+            3857 => left >= -limit && right <= limit && bottom >= -limit && top <= limit,
+            4326 => left >= -180.0 && right <= 180.0 && top <= 90.0 && bottom >= -270.0,
+            _ => true,
+        };
+        if is_valid {
+            Ok(())
+        } else {
+            Err(format!(
+                "custom tile grid bounds [{left}, {bottom}, {right}, {top}] are outside of the valid coordinates of SRID {}",
+                grid.srid
+            ))
+        }
+    }
+
+    /// SRID of the tile envelopes and of the generated tile geometries
+    #[must_use]
+    pub fn srid(self) -> i32 {
+        match self {
+            Self::WebMercatorQuad => 3857,
+            Self::WorldCrs84Quad => 4326,
+            Self::Custom(grid) => grid.srid,
+        }
+    }
+
+    /// Number of tile columns and rows at the given zoom level, or `None` if the zoom is above [`MAX_ZOOM`]
+    #[must_use]
+    pub fn matrix_size(self, zoom: u8) -> Option<(u32, u32)> {
+        if zoom > MAX_ZOOM {
+            return None;
+        }
+        let rows = 1_u32 << zoom;
+        match self {
+            Self::WebMercatorQuad | Self::Custom(_) => Some((rows, rows)),
+            Self::WorldCrs84Quad => Some((rows.checked_mul(2)?, rows)),
+        }
+    }
+
+    /// Check that XYZ tile coordinates exist in this grid
+    #[must_use]
+    pub fn is_valid_tile(self, xyz: TileCoord) -> bool {
+        self.matrix_size(xyz.z)
+            .is_some_and(|(cols, rows)| xyz.x < cols && xyz.y < rows)
+    }
+
+    /// Tile envelope `[min_x, min_y, max_x, max_y]` of XYZ tile coordinates, in meters for `WebMercatorQuad`,
+    /// in degrees for `WorldCRS84Quad`, and in the units of the SRID for custom grids
+    #[must_use]
+    pub fn envelope(self, xyz: TileCoord) -> [f64; 4] {
+        let (left, top, size) = match self {
+            Self::WebMercatorQuad => (
+                EARTH_CIRCUMFERENCE * -0.5,
+                EARTH_CIRCUMFERENCE * 0.5,
+                EARTH_CIRCUMFERENCE,
+            ),
+            Self::WorldCrs84Quad => (-180.0, 90.0, 180.0),
+            Self::Custom(grid) => (grid.origin[0], grid.origin[1], grid.tile_extent),
+        };
+        let size = size / f64::from(xyz.z).exp2();
+        let min_x = left + f64::from(xyz.x) * size;
+        let max_y = top - f64::from(xyz.y) * size;
+        [min_x, max_y - size, min_x + size, max_y]
+    }
+
+    /// Tile envelope `[west, south, east, north]` of XYZ tile coordinates in WGS84 degrees.
+    /// Custom grids would need the database to reproject their envelope, so they have none.
+    #[must_use]
+    pub fn wgs84_envelope(self, xyz: TileCoord) -> Option<[f64; 4]> {
+        let [min_x, min_y, max_x, max_y] = self.envelope(xyz);
+        match self {
+            Self::WebMercatorQuad => {
+                let (west, south) = webmercator_to_wgs84(min_x, min_y);
+                let (east, north) = webmercator_to_wgs84(max_x, max_y);
+                Some([west, south, east, north])
+            }
+            Self::WorldCrs84Quad => Some([min_x, min_y, max_x, max_y]),
+            Self::Custom(_) => None,
+        }
+    }
+
+    /// XYZ tile column and row containing the WGS84 point at the given zoom level.
+    /// WGS84 points cannot be projected to custom grids here, so their top-left tile is used instead.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn tile_index(self, lng: f64, lat: f64, zoom: u8) -> (u32, u32) {
+        match self {
+            Self::WebMercatorQuad => tile_index(lng, lat, zoom),
+            Self::Custom(_) => (0, 0),
+            Self::WorldCrs84Quad => {
+                let (cols, rows) = self.matrix_size(zoom).unwrap_or((1, 1));
+                let size = 180.0 / f64::from(rows);
+                let col = (((lng + 180.0) / size).max(0.0) as u32).min(cols - 1);
+                let row = (((90.0 - lat) / size).max(0.0) as u32).min(rows - 1);
+                (col, row)
+            }
+        }
+    }
+}
+
+/// Position of the zoom 0 tile of a source in its tile grid, for sources covering a single region of the grid.
+/// A request for tile `z/x/y` is served with the grid tile `zoom + z`, `x + offset_x * 2^z`, `y + offset_y * 2^z`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileOffset {
+    /// Zoom level of the grid at which the region of the source is a single tile
+    pub zoom: u8,
+    /// Column of the region's tile at that zoom level
+    pub x: u32,
+    /// Row of the region's tile at that zoom level
+    pub y: u32,
+}
+
+impl TileOffset {
+    /// Check that the region's tile exists in the tile grid
+    pub fn validate(self, grid: TileGrid) -> Result<(), String> {
+        let tile = TileCoord {
+            z: self.zoom,
+            x: self.x,
+            y: self.y,
+        };
+        if grid.is_valid_tile(tile) {
+            Ok(())
+        } else {
+            Err(format!(
+                "tile {tile:#} does not exist in the {} tile grid",
+                grid.as_str()
+            ))
+        }
+    }
+
+    /// Convert the requested tile coordinates to the coordinates of the same tile in the tile grid,
+    /// or `None` if the tile is beyond the grid's maximum zoom level or outside of the grid.
+    #[must_use]
+    pub fn apply(self, xyz: TileCoord, grid: TileGrid) -> Option<TileCoord> {
+        let z = self.zoom.checked_add(xyz.z).filter(|z| *z <= MAX_ZOOM)?;
+        let shift = |offset: u32, v: u32| {
+            offset
+                .checked_mul(1 << xyz.z)
+                .and_then(|offset| offset.checked_add(v))
+        };
+        let tile = TileCoord {
+            z,
+            x: shift(self.x, xyz.x)?,
+            y: shift(self.y, xyz.y)?,
+        };
+        grid.is_valid_tile(tile).then_some(tile)
+    }
+}
+
 #[serde_with::skip_serializing_none]
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub struct CatalogSourceEntry {
@@ -155,10 +652,57 @@ pub struct CatalogSourceEntry {
     pub name: Option<String>,
     pub description: Option<String>,
     pub attribution: Option<String>,
+    /// Version of the source data, from its `TileJSON`
+    pub version: Option<String>,
+    /// Time the source data was last updated, from the `updated` key of its `TileJSON`
+    pub updated: Option<String>,
+    /// Set if the source is known to contain no data, e.g. an empty table
+    pub empty: Option<bool>,
+    /// Coordinate dimensions of the source geometries, only set if they are not plain `XY`, e.g. `XYZ`
+    pub dimensions: Option<String>,
+    /// Database schema of the source, only used for sorting
+    #[serde(skip)]
+    pub schema: Option<String>,
+    /// Configured group of related sources, used by `/catalog?group_by=group`
+    pub group: Option<String>,
+    /// Links to the `TileJSON` and the tiles of the source, only set if requested with `/catalog?links=true`
+    pub links: Option<Vec<CatalogLink>>,
+    /// Effective configuration of the source, only set if requested with `/catalog?verbose=true`
+    pub config: Option<CatalogSourceConfig>,
+}
+
+/// Effective configuration of a table source, with the defaults applied
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CatalogSourceConfig {
+    pub srid: Option<i32>,
+    pub extent: Option<u32>,
+    pub buffer: Option<u32>,
+    pub clip_geom: Option<bool>,
+    pub geometry_type: Option<String>,
+    pub id_column: Option<String>,
+    /// Columns of the tile properties, by property name
+    pub prop_mapping: Option<BTreeMap<String, String>>,
+}
+
+/// A link of a catalog entry, following the OGC API conventions
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CatalogLink {
+    /// Relation of the linked resource to the source, e.g. `self` or `item`
+    pub rel: String,
+    /// Content type of the linked resource
+    #[serde(rename = "type")]
+    pub content_type: String,
+    pub href: String,
+    /// Set if `href` is a URL template with the `{z}`, `{x}`, and `{y}` placeholders
+    pub templated: Option<bool>,
 }
 
 #[cfg(test)]
 mod tests {
+    use martin_tile_utils::webmercator_to_wgs84;
+
     use super::*;
 
     #[test]
@@ -167,6 +711,233 @@ mod tests {
         assert_eq!(format!("{xyz}"), "1,2,3");
         assert_eq!(format!("{xyz:#}"), "1/2/3");
     }
+
+    #[test]
+    fn tms_to_xyz() {
+        let tms = |z, x, y| TileScheme::Tms.to_xyz(TileCoord { z, x, y });
+        assert_eq!(tms(0, 0, 0), Some(TileCoord { z: 0, x: 0, y: 0 }));
+        assert_eq!(tms(1, 1, 0), Some(TileCoord { z: 1, x: 1, y: 1 }));
+        assert_eq!(tms(3, 2, 1), Some(TileCoord { z: 3, x: 2, y: 6 }));
+        assert_eq!(tms(3, 2, 7), Some(TileCoord { z: 3, x: 2, y: 0 }));
+        assert_eq!(tms(3, 2, 8), None);
+        assert_eq!(tms(32, 0, 0), None);
+
+        let xyz = TileCoord { z: 3, x: 2, y: 1 };
+        assert_eq!(TileScheme::Xyz.to_xyz(xyz), Some(xyz));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn tile_grid_envelope() {
+        let env = |grid: TileGrid, z, x, y| grid.envelope(TileCoord { z, x, y });
+        let half = EARTH_CIRCUMFERENCE / 2.0;
+
+        // A single Web Mercator tile covers the whole world at zoom 0, but WGS84 needs two
+        assert_eq!(TileGrid::WebMercatorQuad.matrix_size(0), Some((1, 1)));
+        assert_eq!(TileGrid::WorldCrs84Quad.matrix_size(0), Some((2, 1)));
+        assert_eq!(TileGrid::WorldCrs84Quad.matrix_size(3), Some((16, 8)));
+        assert_eq!(
+            env(TileGrid::WebMercatorQuad, 0, 0, 0),
+            [-half, -half, half, half]
+        );
+        assert_eq!(
+            env(TileGrid::WorldCrs84Quad, 0, 0, 0),
+            [-180.0, -90.0, 0.0, 90.0]
+        );
+        assert_eq!(
+            env(TileGrid::WorldCrs84Quad, 0, 1, 0),
+            [0.0, -90.0, 180.0, 90.0]
+        );
+
+        // The north-east quarter of the world at zoom 1 in Web Mercator is the second tile of the first row in WGS84
+        assert_eq!(
+            env(TileGrid::WebMercatorQuad, 1, 1, 0),
+            [0.0, 0.0, half, half]
+        );
+        assert_eq!(
+            env(TileGrid::WorldCrs84Quad, 1, 2, 0),
+            [0.0, 0.0, 90.0, 90.0]
+        );
+        assert_eq!(
+            env(TileGrid::WorldCrs84Quad, 2, 7, 3),
+            [135.0, -90.0, 180.0, -45.0]
+        );
+
+        // The same tile coordinates cover the same longitudes, but the WGS84 grid has twice as many columns
+        let merc = env(TileGrid::WebMercatorQuad, 3, 5, 2);
+        let wgs = env(TileGrid::WorldCrs84Quad, 2, 5, 2);
+        assert!((webmercator_to_wgs84(merc[0], 0.0).0 - wgs[0]).abs() < 1e-9);
+        assert!((webmercator_to_wgs84(merc[2], 0.0).0 - wgs[2]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tile_grid_wgs84_envelope() {
+        let env = |grid: TileGrid, z, x, y| grid.wgs84_envelope(TileCoord { z, x, y }).unwrap();
+        let assert_close = |actual: [f64; 4], expected: [f64; 4]| {
+            for (a, e) in actual.iter().zip(expected) {
+                assert!((a - e).abs() < 1e-9, "{actual:?} != {expected:?}");
+            }
+        };
+        assert_close(
+            env(TileGrid::WebMercatorQuad, 0, 0, 0),
+            [-180.0, -85.051_128_779_806_6, 180.0, 85.051_128_779_806_6],
+        );
+        assert_close(
+            env(TileGrid::WebMercatorQuad, 1, 1, 1),
+            [0.0, -85.051_128_779_806_6, 180.0, 0.0],
+        );
+        assert_close(
+            env(TileGrid::WorldCrs84Quad, 1, 2, 0),
+            [0.0, 0.0, 90.0, 90.0],
+        );
+    }
+
+    #[test]
+    fn tile_grid_validation() {
+        let valid = |grid: TileGrid, z, x, y| grid.is_valid_tile(TileCoord { z, x, y });
+        assert!(valid(TileGrid::WebMercatorQuad, 0, 0, 0));
+        assert!(!valid(TileGrid::WebMercatorQuad, 0, 1, 0));
+        assert!(valid(TileGrid::WorldCrs84Quad, 0, 1, 0));
+        assert!(!valid(TileGrid::WorldCrs84Quad, 0, 0, 1));
+        assert!(valid(TileGrid::WorldCrs84Quad, 3, 15, 7));
+        assert!(!valid(TileGrid::WorldCrs84Quad, 3, 16, 7));
+        assert!(!valid(TileGrid::WebMercatorQuad, 32, 0, 0));
+
+        // Zoom 30 is the highest supported zoom level, even if the coordinates would fit at zoom 31
+        let max = (1_u32 << 30) - 1;
+        assert!(valid(TileGrid::WebMercatorQuad, 30, max, max));
+        assert!(!valid(TileGrid::WebMercatorQuad, 30, max + 1, max));
+        assert!(valid(TileGrid::WorldCrs84Quad, 30, u32::MAX >> 1, max));
+        assert!(!valid(TileGrid::WorldCrs84Quad, 30, u32::MAX >> 1, max + 1));
+        assert!(!valid(TileGrid::WebMercatorQuad, 31, 0, 0));
+        assert!(!valid(TileGrid::WorldCrs84Quad, 31, 0, 0));
+        assert_eq!(
+            TileGrid::WorldCrs84Quad.matrix_size(30),
+            Some((1 << 31, 1 << 30))
+        );
+        assert_eq!(TileGrid::WebMercatorQuad.matrix_size(31), None);
+
+        assert_eq!(TileGrid::WorldCrs84Quad.tile_index(-180.0, 90.0, 0), (0, 0));
+        assert_eq!(TileGrid::WorldCrs84Quad.tile_index(180.0, -90.0, 0), (1, 0));
+        assert_eq!(TileGrid::WorldCrs84Quad.tile_index(10.0, 10.0, 2), (4, 1));
+        assert_eq!(TileGrid::WebMercatorQuad.tile_index(10.0, 10.0, 2), (2, 1));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn tile_grid_custom() {
+        // A Swiss LV95 grid of 512 km, from the west and north of the country
+        let grid = TileGrid::Custom(CustomGrid {
+            srid: 2056,
+            origin: [2_420_000.0, 1_350_000.0],
+            tile_extent: 512_000.0,
+        });
+        assert_eq!(grid.srid(), 2056);
+        assert_eq!(grid.matrix_size(2), Some((4, 4)));
+        assert!(grid.validate().is_ok());
+
+        let env = |z, x, y| grid.envelope(TileCoord { z, x, y });
+        assert_eq!(
+            env(0, 0, 0),
+            [2_420_000.0, 838_000.0, 2_932_000.0, 1_350_000.0]
+        );
+        assert_eq!(
+            env(2, 1, 2),
+            [2_548_000.0, 966_000.0, 2_676_000.0, 1_094_000.0]
+        );
+        assert!(
+            grid.wgs84_envelope(TileCoord { z: 0, x: 0, y: 0 })
+                .is_none()
+        );
+
+        let valid = |z, x, y| grid.is_valid_tile(TileCoord { z, x, y });
+        assert!(valid(0, 0, 0));
+        assert!(!valid(0, 1, 0));
+        assert!(valid(3, 7, 7));
+        assert!(!valid(3, 7, 8));
+
+        // Advertised in the TileJSON, and read back from it
+        let value = serde_json::to_value(grid).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"custom": {"srid": 2056, "origin": [2_420_000.0, 1_350_000.0], "tile_extent": 512_000.0}})
+        );
+        assert_eq!(serde_json::from_value::<TileGrid>(value).unwrap(), grid);
+        assert_eq!(
+            serde_json::from_value::<TileGrid>("WorldCRS84Quad".into()).unwrap(),
+            TileGrid::WorldCrs84Quad
+        );
+
+        let custom = |srid, origin, tile_extent| {
+            TileGrid::Custom(CustomGrid {
+                srid,
+                origin,
+                tile_extent,
+            })
+        };
+        assert!(custom(2056, [0.0, f64::NAN], 1.0).validate().is_err());
+        assert!(custom(2056, [0.0, 0.0], 0.0).validate().is_err());
+        assert!(custom(0, [0.0, 0.0], 1.0).validate().is_err());
+        assert!(custom(-1, [0.0, 0.0], 1.0).validate().is_err());
+
+        // Bounds are checked for the SRIDs with known valid coordinates
+        let half = EARTH_CIRCUMFERENCE / 2.0;
+        assert!(custom(3857, [-half, half], 2.0 * half).validate().is_ok());
+        assert!(custom(3857, [0.0, 0.0], 100_000.0).validate().is_ok());
+        assert!(custom(3857, [-half, half], 2.1 * half).validate().is_err());
+        assert!(custom(3857, [half, 0.0], 1.0).validate().is_err());
+        assert!(custom(4326, [-180.0, 90.0], 360.0).validate().is_ok());
+        assert!(custom(4326, [5.0, 48.0], 5.0).validate().is_ok());
+        assert!(custom(4326, [-180.0, 91.0], 10.0).validate().is_err());
+        assert!(custom(4326, [175.0, 0.0], 10.0).validate().is_err());
+        assert!(custom(2056, [-1e9, 1e9], 1e9).validate().is_ok());
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn tile_offset() {
+        // A 1024 m square grid, with the source covering its zoom 2 tile at column 1 and row 3
+        let grid = TileGrid::Custom(CustomGrid {
+            srid: 2056,
+            origin: [0.0, 1024.0],
+            tile_extent: 1024.0,
+        });
+        let offset = TileOffset {
+            zoom: 2,
+            x: 1,
+            y: 3,
+        };
+        assert!(offset.validate(grid).is_ok());
+        let env = |z, x, y| {
+            offset
+                .apply(TileCoord { z, x, y }, grid)
+                .map(|v| grid.envelope(v))
+        };
+
+        // The zoom 0 tile of the source is the offset tile, and its children split it in four
+        assert_eq!(env(0, 0, 0), Some([256.0, 0.0, 512.0, 256.0]));
+        assert_eq!(env(1, 0, 0), Some([256.0, 128.0, 384.0, 256.0]));
+        assert_eq!(env(1, 1, 1), Some([384.0, 0.0, 512.0, 128.0]));
+        assert_eq!(
+            offset.apply(TileCoord { z: 3, x: 5, y: 2 }, grid),
+            Some(TileCoord { z: 5, x: 13, y: 26 })
+        );
+
+        // Tiles past the last row of the grid, or beyond its maximum zoom level, do not exist
+        assert_eq!(env(1, 0, 2), None);
+        assert_eq!(env(29, 0, 0), None);
+        assert!(env(28, 0, 0).is_some());
+
+        let offset = |zoom, x, y| TileOffset { zoom, x, y };
+        assert!(offset(0, 1, 0).validate(TileGrid::WorldCrs84Quad).is_ok());
+        assert!(offset(0, 1, 0).validate(TileGrid::WebMercatorQuad).is_err());
+        assert!(offset(3, 8, 0).validate(grid).is_err());
+        assert!(offset(31, 0, 0).validate(grid).is_err());
+        assert_eq!(
+            offset(0, 1, 0).apply(TileCoord { z: 1, x: 1, y: 1 }, TileGrid::WorldCrs84Quad),
+            Some(TileCoord { z: 1, x: 3, y: 1 })
+        );
+    }
 }
 
 #[derive(Debug, Clone)]
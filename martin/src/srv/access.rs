@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+
+use actix_web::Result as ActixResult;
+use actix_web::error::ErrorForbidden;
+use martin_tile_utils::{MAX_ZOOM, TileCoord};
+use serde::{Deserialize, Serialize};
+use tilejson::Bounds;
+
+use crate::MartinError::TileAccessError;
+use crate::MartinResult;
+use crate::source::{TileScheme, TileSources};
+
+/// Zoom levels and area of the tiles that may be requested from a source
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccessPolicy {
+    /// Lowest allowed zoom level [default: 0]
+    pub minzoom: Option<u8>,
+    /// Highest allowed zoom level [default: 30]
+    pub maxzoom: Option<u8>,
+    /// Only tiles intersecting these WGS84 bounds are allowed [default: no restriction]
+    pub bounds: Option<Bounds>,
+}
+
+/// Access policy of a source, with a different policy for the clients presenting one of the given API keys
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceAccess {
+    /// Policy of the requests without a listed API key
+    #[serde(flatten)]
+    pub policy: AccessPolicy,
+    /// Policies of the requests with an `Authorization: Bearer <key>` header, by key
+    pub keys: Option<BTreeMap<String, AccessPolicy>>,
+}
+
+impl AccessPolicy {
+    fn validate(&self, source_id: &str) -> MartinResult<()> {
+        let err = |msg| Err(TileAccessError(source_id.to_string(), msg));
+        let minzoom = self.minzoom.unwrap_or(0);
+        let maxzoom = self.maxzoom.unwrap_or(MAX_ZOOM);
+        if maxzoom > MAX_ZOOM {
+            return err("maxzoom must not be above 30");
+        }
+        if minzoom > maxzoom {
+            return err("minzoom must not be above maxzoom");
+        }
+        if let Some(b) = self.bounds {
+            let is_valid = [b.left, b.bottom, b.right, b.top]
+                .iter()
+                .all(|v| v.is_finite());
+            if !is_valid || b.left >= b.right || b.bottom >= b.top {
+                return err(
+                    "bounds must be [west, south, east, north] with west < east and south < north",
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn check(
+        &self,
+        source_id: &str,
+        sources: &TileSources,
+        scheme: TileScheme,
+        xyz: TileCoord,
+    ) -> ActixResult<()> {
+        let minzoom = self.minzoom.unwrap_or(0);
+        let maxzoom = self.maxzoom.unwrap_or(MAX_ZOOM);
+        if xyz.z < minzoom || xyz.z > maxzoom {
+            return Err(ErrorForbidden(format!(
+                "Zoom level {} of source {source_id} is not allowed, must be between {minzoom} and {maxzoom}",
+                xyz.z
+            )));
+        }
+        if let Some(bounds) = self.bounds {
+            // Tiles of custom grids have no WGS84 envelope, so they are never known to be within the bounds
+            let src = sources.get_source(source_id)?;
+            let grid = src.get_tile_grid();
+            let is_within = scheme
+                .to_xyz(xyz)
+                .filter(|v| grid.is_valid_tile(*v))
+                .and_then(|v| grid.wgs84_envelope(v))
+                .is_some_and(|[west, south, east, north]| {
+                    west < bounds.right
+                        && east > bounds.left
+                        && south < bounds.top
+                        && north > bounds.bottom
+                });
+            if !is_within {
+                return Err(ErrorForbidden(format!(
+                    "Tile {xyz} of source {source_id} is outside of the allowed bounds"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SourceAccess {
+    pub fn validate(&self, source_id: &str) -> MartinResult<()> {
+        self.policy.validate(source_id)?;
+        for policy in self.keys.iter().flat_map(BTreeMap::values) {
+            policy.validate(source_id)?;
+        }
+        Ok(())
+    }
+
+    /// Get the policy of the given API key, or the default policy if the key is not listed
+    fn get_policy(&self, key: Option<&str>) -> &AccessPolicy {
+        key.and_then(|key| self.keys.as_ref()?.get(key))
+            .unwrap_or(&self.policy)
+    }
+}
+
+/// Check the tile request with coordinates in the given scheme against the access policies of all requested
+/// sources before any of them is queried. Requests outside of the policy are rejected with `403 Forbidden`.
+pub fn check_tile_access(
+    tile_access: &BTreeMap<String, SourceAccess>,
+    key: Option<&str>,
+    sources: &TileSources,
+    source_ids: &str,
+    xyz: TileCoord,
+    scheme: TileScheme,
+) -> ActixResult<()> {
+    for source_id in source_ids.split(',') {
+        if let Some(access) = tile_access.get(source_id) {
+            access
+                .get_policy(key)
+                .check(source_id, sources, scheme, xyz)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    fn status(res: ActixResult<()>) -> Option<StatusCode> {
+        res.err().map(|e| e.as_response_error().status_code())
+    }
+
+    #[test]
+    fn access_policy() {
+        let sources = TileSources::new(vec![vec![
+            Box::new(TestSource {
+                id: "roads",
+                tj: tilejson! { tiles: vec![] },
+                data: Vec::default(),
+            }),
+            Box::new(TestSource {
+                id: "poi",
+                tj: tilejson! { tiles: vec![] },
+                data: Vec::default(),
+            }),
+        ]]);
+        let tile_access: BTreeMap<String, SourceAccess> = serde_yaml::from_str(
+            "
+roads:
+  maxzoom: 10
+  keys:
+    premium: {}
+    europe:
+      bounds: [-10, 35, 30, 70]
+",
+        )
+        .unwrap();
+        for access in tile_access.values() {
+            access.validate("roads").unwrap();
+        }
+        let check = |key, source_ids, z, x, y| {
+            status(check_tile_access(
+                &tile_access,
+                key,
+                &sources,
+                source_ids,
+                TileCoord { z, x, y },
+                sources.get_scheme(source_ids).unwrap(),
+            ))
+        };
+
+        // low zoom levels are free, higher ones require a key
+        assert_eq!(check(None, "roads", 10, 0, 0), None);
+        assert_eq!(check(None, "roads", 11, 0, 0), Some(StatusCode::FORBIDDEN));
+        assert_eq!(
+            check(None, "poi,roads", 11, 0, 0),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            check(Some("unknown"), "roads", 11, 0, 0),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(check(Some("premium"), "roads", 14, 0, 0), None);
+        // sources without a policy are not restricted
+        assert_eq!(check(None, "poi", 14, 0, 0), None);
+
+        // tiles over Berlin at zoom 10 and 14, and over New York at zoom 10
+        assert_eq!(check(Some("europe"), "roads", 10, 550, 335), None);
+        assert_eq!(check(Some("europe"), "roads", 14, 8801, 5374), None);
+        assert_eq!(
+            check(Some("europe"), "roads", 10, 301, 385),
+            Some(StatusCode::FORBIDDEN)
+        );
+        // the single tile of zoom 0 intersects any bounds
+        assert_eq!(check(Some("europe"), "roads", 0, 0, 0), None);
+    }
+
+    #[test]
+    fn access_policy_validate() {
+        let policy = |minzoom, maxzoom, bounds: Option<[f64; 4]>| SourceAccess {
+            policy: AccessPolicy {
+                minzoom,
+                maxzoom,
+                bounds: bounds
+                    .map(|[west, south, east, north]| Bounds::new(west, south, east, north)),
+            },
+            keys: None,
+        };
+        assert!(policy(None, None, None).validate("src").is_ok());
+        assert!(policy(Some(5), Some(5), None).validate("src").is_ok());
+        assert!(policy(Some(6), Some(5), None).validate("src").is_err());
+        assert!(policy(None, Some(31), None).validate("src").is_err());
+        assert!(
+            policy(None, None, Some([-10.0, 35.0, 30.0, 70.0]))
+                .validate("src")
+                .is_ok()
+        );
+        assert!(
+            policy(None, None, Some([30.0, 35.0, -10.0, 70.0]))
+                .validate("src")
+                .is_err()
+        );
+        assert!(
+            policy(None, None, Some([-10.0, f64::NAN, 30.0, 70.0]))
+                .validate("src")
+                .is_err()
+        );
+
+        let mut access = policy(None, None, None);
+        access.keys = Some(BTreeMap::from([(
+            "key".to_string(),
+            AccessPolicy {
+                minzoom: Some(10),
+                maxzoom: Some(2),
+                bounds: None,
+            },
+        )]));
+        assert!(access.validate("src").is_err());
+    }
+}
@@ -1,9 +1,20 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
 use serde::{Deserialize, Serialize};
+use url::Url;
 
+use crate::MartinError::{IndexRedirectError, TilesUrlTemplateError};
+use crate::MartinResult;
 use crate::args::PreferredEncoding;
+use crate::srv::access::SourceAccess;
+use crate::srv::health::HealthCheckConfig;
 
 pub const KEEP_ALIVE_DEFAULT: u64 = 75;
 pub const LISTEN_ADDRESSES_DEFAULT: &str = "0.0.0.0:3000";
+pub const ROBOTS_TXT_DEFAULT: &str = "User-agent: *\nDisallow: /\n";
+pub const MAX_PAYLOAD_SIZE_DEFAULT: usize = 64 * 1024;
+pub const CORS_MAX_AGE_DEFAULT: usize = 3600;
 
 #[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
@@ -13,8 +24,187 @@ pub struct SrvConfig {
     pub base_path: Option<String>,
     pub worker_processes: Option<usize>,
     pub preferred_encoding: Option<PreferredEncoding>,
+    pub health_check: Option<HealthCheckConfig>,
+    /// Maximum number of requests from the same client IP processed at the same time.
+    /// Additional requests are rejected with `429 Too Many Requests` and a `Retry-After` header. Disabled if not set.
+    pub max_connections_per_ip: Option<usize>,
+    /// IP addresses of the reverse proxies trusted to set the client IP of `max_connections_per_ip`
+    /// with the `Forwarded` or `X-Forwarded-For` header. Other requests use the IP of their connection.
+    pub trusted_proxies: Option<Vec<IpAddr>>,
+    /// Number of times a failed tile request is retried before reporting an error [default: 0]
+    pub tile_retries: Option<u32>,
+    /// Total time budget of a tile request in milliseconds, including all retries.
+    /// Requests exceeding it fail with `504 Gateway Timeout`. Disabled if not set.
+    pub tile_budget_ms: Option<u64>,
+    /// Enables the `/{source_id}/explain/{z}/{x}/{y}` endpoint, which requires this value
+    /// as a bearer token in the `Authorization` header. Disabled if not set.
+    pub explain_token: Option<String>,
+    /// Enables the `POST /refresh/{source_id}` endpoint, which requires this value
+    /// as a bearer token in the `Authorization` header. Disabled if not set.
+    pub refresh_token: Option<String>,
+    /// Enables the `POST /reload` endpoint, which requires this value
+    /// as a bearer token in the `Authorization` header. Disabled if not set.
+    pub reload_token: Option<String>,
+    /// Enables the `/metrics` endpoint with Prometheus metrics of the tile requests by source [default: false]
+    pub metrics: Option<bool>,
+    /// Enables the `/{source_ids}/style.json` endpoint with a minimal generated `MapLibre` style [default: false]
+    pub style_json: Option<bool>,
+    /// Maximum size of a request body in bytes, e.g. of the admin `POST` endpoints.
+    /// Larger bodies are rejected with `413 Payload Too Large` [default: 65536]
+    pub max_payload_size: Option<usize>,
+    /// How long browsers may cache the response to a CORS preflight request, in seconds,
+    /// i.e. the `Access-Control-Max-Age` header. Set to 0 to disable caching [default: 3600]
+    pub cors_max_age: Option<usize>,
+    /// Default order of the tile sources in the catalog, unless the request sets the `sort` parameter [default: `id`]
+    pub catalog_sort: Option<CatalogSort>,
+    /// Response to tile requests whose sources exist, but none of them has tiles at the requested zoom [default: `not_found`]
+    pub out_of_zoom: Option<OutOfZoomResponse>,
+    /// Content of `/robots.txt` [default: disallow crawling everything]
+    pub robots_txt: Option<String>,
+    /// Add the `X-Robots-Tag: noindex` header to tile responses [default: false]
+    pub noindex_tiles: Option<bool>,
+    /// How the bounds of the sources are combined in the `TileJSON` of a composite source [default: `union`]
+    pub composite_bounds: Option<CompositeBounds>,
+    /// How a field is typed in the `TileJSON` of a composite source if its sources have a vector layer
+    /// with the same ID, but with different types of the field [default: `widen`]
+    pub layer_field_conflicts: Option<LayerFieldConflicts>,
+    /// Add the `X-Tile-Bounds` header with the WGS84 bounds of the tile to tile responses, for debugging [default: false]
+    pub tile_bounds_header: Option<bool>,
+    /// Response of the `/` root path, unless it is used by the web UI [default: a short message]
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub index: Option<IndexConfig>,
+    /// Public URL of the tiles advertised in `TileJSON`, instead of deriving it from the request
+    pub tiles_url: Option<TilesUrlConfig>,
+    /// Allowed zoom levels and bounds of the tiles of each source, by source ID, optionally per API key.
+    /// Requests outside of the policy are rejected with `403 Forbidden`. Sources without a policy are not restricted.
+    pub tile_access: Option<BTreeMap<String, SourceAccess>>,
+    /// Request header selecting the tenant of a request, e.g. `X-Tenant`. Enables multi-tenancy:
+    /// the sources of a `PostgreSQL` connection with a `tenant` are only available to the requests of that tenant.
+    /// Disabled if not set.
+    pub tenant_header: Option<String>,
     #[cfg(feature = "webui")]
     pub web_ui: Option<crate::args::WebUiMode>,
+    /// Also serve tiles with gRPC on this address, e.g. `0.0.0.0:50051`. Disabled if not set.
+    #[cfg(feature = "grpc")]
+    pub grpc_listen_address: Option<String>,
+}
+
+/// Order of the tile sources in the catalog
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CatalogSort {
+    /// Sort by source ID
+    #[default]
+    Id,
+    /// Sort by the source name, falling back to the ID for sources without a name
+    Name,
+    /// Group by the database schema, then sort by source ID. Sources without a schema are listed last.
+    Schema,
+}
+
+/// Response to tile requests outside of the zoom range of all requested sources
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutOfZoomResponse {
+    /// Respond with `404 Not Found`
+    #[default]
+    NotFound,
+    /// Respond with `204 No Content`, the same as an empty tile
+    NoContent,
+}
+
+/// How the bounds of multiple sources are combined in the `TileJSON` of a composite source.
+/// Sources without bounds are ignored either way.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompositeBounds {
+    /// The smallest bounds containing the bounds of all sources
+    #[default]
+    Union,
+    /// The area covered by all sources, or the union if the sources do not overlap
+    Intersection,
+}
+
+/// How the conflicting types of a field are resolved when vector layers with the same ID are combined
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerFieldConflicts {
+    /// Use the `String` type, which can describe any value, and log a warning
+    #[default]
+    Widen,
+    /// Use the type of the first source with the field
+    KeepFirst,
+    /// Reject the request with `400 Bad Request`
+    Error,
+}
+
+/// Response of the `/` root path
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexConfig {
+    /// Respond with a custom plain text message
+    Message(String),
+    /// Redirect to an absolute http(s) URL, or to a path on this server, e.g. the docs or a map viewer
+    Redirect(String),
+    /// Respond with the catalog of all sources, same as `/catalog`
+    Catalog,
+}
+
+impl IndexConfig {
+    pub fn validate(&self) -> MartinResult<()> {
+        if let Self::Redirect(url) = self {
+            let is_path = url.starts_with('/') && !url.starts_with("//");
+            let is_url = Url::parse(url).is_ok_and(|v| matches!(v.scheme(), "http" | "https"));
+            if !is_path && !is_url {
+                return Err(IndexRedirectError(url.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A public tiles URL template, e.g. `https://{s}.tiles.example.com/{source_ids}/{z}/{x}/{y}`
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct TilesUrlConfig {
+    /// URL template with the `{source_ids}` and `{z}/{x}/{y}` placeholders, and an optional `{s}` subdomain placeholder
+    pub template: String,
+    /// Values of the `{s}` placeholder. `TileJSON` lists one tiles URL per subdomain.
+    pub subdomains: Option<Vec<String>>,
+}
+
+impl TilesUrlConfig {
+    pub fn validate(&self) -> MartinResult<()> {
+        let err = |msg| Err(TilesUrlTemplateError(self.template.clone(), msg));
+        if !self.template.contains("{z}/{x}/{y}") {
+            return err("must contain {z}/{x}/{y}");
+        }
+        if !self.template.contains("{source_ids}") {
+            return err("must contain {source_ids}");
+        }
+        let has_subdomains = self.subdomains.as_ref().is_some_and(|v| !v.is_empty());
+        match (self.template.contains("{s}"), has_subdomains) {
+            (true, false) => err("the {s} placeholder requires a non-empty list of subdomains"),
+            (false, true) => err("subdomains require the {s} placeholder"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the tiles URLs for the given sources, one per subdomain, with the request query string appended if not empty
+    #[must_use]
+    pub fn get_tiles_urls(&self, source_ids: &str, query_string: &str) -> Vec<String> {
+        let mut url = self.template.replace("{source_ids}", source_ids);
+        if !query_string.is_empty() {
+            url.push(if url.contains('?') { '&' } else { '?' });
+            url.push_str(query_string);
+        }
+        match &self.subdomains {
+            Some(subdomains) if !subdomains.is_empty() => {
+                subdomains.iter().map(|s| url.replace("{s}", s)).collect()
+            }
+            _ => vec![url],
+        }
+    }
 }
 
 #[cfg(test)]
@@ -73,4 +263,93 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn tiles_url() {
+        let cfg = |template: &str, subdomains: Option<&[&str]>| TilesUrlConfig {
+            template: template.to_string(),
+            subdomains: subdomains.map(|v| v.iter().map(ToString::to_string).collect()),
+        };
+
+        let c = cfg(
+            "https://{s}.example.com/{source_ids}/{z}/{x}/{y}",
+            Some(&["a", "b"]),
+        );
+        assert!(c.validate().is_ok());
+        assert_eq!(
+            c.get_tiles_urls("src1,src2", ""),
+            vec![
+                "https://a.example.com/src1,src2/{z}/{x}/{y}",
+                "https://b.example.com/src1,src2/{z}/{x}/{y}",
+            ]
+        );
+        assert_eq!(
+            c.get_tiles_urls("src", "year=2024"),
+            vec![
+                "https://a.example.com/src/{z}/{x}/{y}?year=2024",
+                "https://b.example.com/src/{z}/{x}/{y}?year=2024",
+            ]
+        );
+
+        let c = cfg("https://example.com/{source_ids}/{z}/{x}/{y}?key=1", None);
+        assert!(c.validate().is_ok());
+        assert_eq!(
+            c.get_tiles_urls("src", "year=2024"),
+            vec!["https://example.com/src/{z}/{x}/{y}?key=1&year=2024"]
+        );
+
+        assert!(
+            cfg("https://example.com/{source_ids}/{z}/{y}/{x}", None)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            cfg("https://example.com/tiles/{z}/{x}/{y}", None)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            cfg("https://{s}.example.com/{source_ids}/{z}/{x}/{y}", None)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            cfg(
+                "https://{s}.example.com/{source_ids}/{z}/{x}/{y}",
+                Some(&[])
+            )
+            .validate()
+            .is_err()
+        );
+        assert!(
+            cfg("https://example.com/{source_ids}/{z}/{x}/{y}", Some(&["a"]))
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn index_config() {
+        let parse = |v: &str| serde_yaml::from_str::<SrvConfig>(v).unwrap().index;
+        assert_eq!(parse("index: catalog"), Some(IndexConfig::Catalog));
+        assert_eq!(
+            parse("index: { message: Hello }"),
+            Some(IndexConfig::Message("Hello".to_string()))
+        );
+        assert_eq!(
+            parse("index:\n  redirect: https://example.org/docs"),
+            Some(IndexConfig::Redirect(
+                "https://example.org/docs".to_string()
+            ))
+        );
+
+        let redirect = |v: &str| IndexConfig::Redirect(v.to_string()).validate();
+        assert!(redirect("https://example.org/docs").is_ok());
+        assert!(redirect("http://localhost:8080").is_ok());
+        assert!(redirect("/catalog").is_ok());
+        assert!(redirect("//example.org").is_err());
+        assert!(redirect("ftp://example.org").is_err());
+        assert!(redirect("javascript:alert(1)").is_err());
+        assert!(redirect("catalog").is_err());
+    }
 }
@@ -0,0 +1,291 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+use actix_web::{Error, HttpResponse};
+use dashmap::DashMap;
+use log::debug;
+use serde::Serialize;
+
+/// Minimum number of seconds a rejected client is asked to wait before retrying, see [`ClientConnLimiter::retry_after`]
+pub const MIN_RETRY_AFTER_SECS: u64 = 1;
+
+/// JSON body of `429 Too Many Requests` responses, so that programmatic clients can back off
+#[derive(Debug, Serialize)]
+struct TooManyRequests {
+    error: String,
+    /// Maximum number of concurrent requests per client IP
+    limit: usize,
+    /// Seconds to wait before retrying, same as the `Retry-After` header
+    retry_after: u64,
+}
+
+/// Limits the number of requests from the same client IP that are processed at the same time
+#[derive(Clone, Debug)]
+pub struct ClientConnLimiter {
+    max_per_ip: usize,
+    /// Proxies whose forwarded headers set the client IP, see [`ClientConnLimiter::client_ip`]
+    trusted_proxies: Vec<IpAddr>,
+    active: Arc<DashMap<IpAddr, usize>>,
+    /// Moving average of the duration of the completed requests in milliseconds
+    avg_duration_ms: Arc<AtomicU64>,
+}
+
+impl ClientConnLimiter {
+    #[must_use]
+    pub fn new(max_per_ip: usize) -> Self {
+        Self {
+            max_per_ip,
+            trusted_proxies: Vec::new(),
+            active: Arc::default(),
+            avg_duration_ms: Arc::default(),
+        }
+    }
+
+    /// Trust the `Forwarded` and `X-Forwarded-For` headers of the requests from these proxies
+    #[must_use]
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// IP of the client sending the request. This is the IP of the connection, unless it is a trusted proxy
+    /// with a valid forwarded header, as any client could set these headers to bypass the limit.
+    /// Requests without a peer address, e.g. over a Unix socket, have no client IP.
+    fn client_ip(&self, req: &ServiceRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr()?.ip();
+        if !self.trusted_proxies.contains(&peer) {
+            return Some(peer);
+        }
+        let forwarded = req.connection_info().realip_remote_addr().and_then(|v| {
+            v.parse::<IpAddr>()
+                .or_else(|_| v.parse::<SocketAddr>().map(|v| v.ip()))
+                .ok()
+        });
+        Some(forwarded.unwrap_or(peer))
+    }
+
+    /// Register a new in-flight request from the given IP, or return `None` if the IP is at its limit.
+    /// The request is considered complete when the returned guard is dropped.
+    fn try_acquire(&self, ip: IpAddr) -> Option<ClientConnGuard> {
+        let mut count = self.active.entry(ip).or_default();
+        if *count >= self.max_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(ClientConnGuard {
+            active: self.active.clone(),
+            avg_duration_ms: self.avg_duration_ms.clone(),
+            ip,
+            start: Instant::now(),
+        })
+    }
+
+    /// Seconds a rejected client should wait before retrying. Unlike a rate limiter, a concurrency limiter
+    /// has no refill rate telling when a slot becomes free, so this is the average duration of the
+    /// recently completed requests, rounded up, and at least [`MIN_RETRY_AFTER_SECS`].
+    fn retry_after(&self) -> u64 {
+        let avg_duration_ms = self.avg_duration_ms.load(Ordering::Relaxed);
+        avg_duration_ms.div_ceil(1000).max(MIN_RETRY_AFTER_SECS)
+    }
+}
+
+/// Add the duration of a completed request to the moving average, weighting it as 1/8 of the total
+fn record_duration(avg_duration_ms: &AtomicU64, duration: Duration) {
+    let duration_ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+    let _ = avg_duration_ms.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+        Some(avg - avg / 8 + duration_ms / 8)
+    });
+}
+
+struct ClientConnGuard {
+    active: Arc<DashMap<IpAddr, usize>>,
+    avg_duration_ms: Arc<AtomicU64>,
+    ip: IpAddr,
+    start: Instant,
+}
+
+impl Drop for ClientConnGuard {
+    fn drop(&mut self) {
+        record_duration(&self.avg_duration_ms, self.start.elapsed());
+        // Remove the entry once the last request completes to keep the map small
+        self.active.remove_if_mut(&self.ip, |_, count| {
+            *count -= 1;
+            *count == 0
+        });
+    }
+}
+
+/// Middleware rejecting requests with `429 Too Many Requests` if the client IP already has the
+/// maximum number of requests in progress. Does nothing unless a [`ClientConnLimiter`] is registered
+/// as app data. Health checks and requests without a client IP are never limited.
+pub async fn limit_client_connections(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limiter = req.app_data::<Data<ClientConnLimiter>>().cloned();
+    let Some(limiter) = limiter.filter(|_| req.path() != "/health") else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+
+    let Some(ip) = limiter.client_ip(&req) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let Some(_guard) = limiter.try_acquire(ip) else {
+        debug!("Too many concurrent requests from {ip}");
+        let retry_after = limiter.retry_after();
+        let response = HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, retry_after))
+            .json(TooManyRequests {
+                error: format!("Too many concurrent requests from {ip}"),
+                limit: limiter.max_per_ip,
+                retry_after,
+            });
+        return Ok(req.into_response(response).map_into_right_body());
+    };
+
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+    use actix_web::{App, web};
+
+    use super::*;
+
+    async fn slow_handler() -> HttpResponse {
+        actix_rt::time::sleep(Duration::from_millis(100)).await;
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_rt::test]
+    async fn test_limit_client_connections() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ClientConnLimiter::new(2)))
+                .wrap(from_fn(limit_client_connections))
+                .route("/health", web::get().to(slow_handler))
+                .route("/tile", web::get().to(slow_handler)),
+        )
+        .await;
+        let get = |path: &str, ip: &str| {
+            TestRequest::get()
+                .uri(path)
+                .peer_addr(SocketAddr::new(ip.parse().unwrap(), 50000))
+                .to_request()
+        };
+
+        let (r1, r2, r3, r4, r5) = futures::join!(
+            call_service(&app, get("/tile", "10.0.0.1")),
+            call_service(&app, get("/tile", "10.0.0.1")),
+            call_service(&app, get("/tile", "10.0.0.1")),
+            call_service(&app, get("/tile", "10.0.0.2")),
+            call_service(&app, get("/health", "10.0.0.1")),
+        );
+        assert_eq!(r1.status(), StatusCode::OK);
+        assert_eq!(r2.status(), StatusCode::OK);
+        assert_eq!(r3.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(r4.status(), StatusCode::OK);
+        assert_eq!(r5.status(), StatusCode::OK);
+
+        // rejected clients are told when to retry, both in the header and in the JSON body
+        assert_eq!(r3.headers().get(RETRY_AFTER).unwrap(), "1");
+        let body: serde_json::Value = read_body_json(r3).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": "Too many concurrent requests from 10.0.0.1",
+                "limit": 2,
+                "retry_after": 1,
+            })
+        );
+
+        // completed requests no longer count towards the limit
+        let response = call_service(&app, get("/tile", "10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let limiter =
+            ClientConnLimiter::new(1).with_trusted_proxies(vec!["10.0.0.1".parse().unwrap()]);
+        let client_ip = |peer: Option<&str>, forwarded: Option<&str>| {
+            let mut req = TestRequest::get();
+            if let Some(peer) = peer {
+                req = req.peer_addr(SocketAddr::new(peer.parse().unwrap(), 50000));
+            }
+            if let Some(forwarded) = forwarded {
+                req = req.insert_header(("x-forwarded-for", forwarded));
+            }
+            limiter
+                .client_ip(&req.to_srv_request())
+                .map(|v| v.to_string())
+        };
+
+        // any client could set the header, so it is only trusted from the proxies
+        assert_eq!(
+            client_ip(Some("10.0.0.2"), None).as_deref(),
+            Some("10.0.0.2")
+        );
+        assert_eq!(
+            client_ip(Some("10.0.0.2"), Some("192.0.2.1")).as_deref(),
+            Some("10.0.0.2")
+        );
+        assert_eq!(
+            client_ip(Some("10.0.0.1"), Some("192.0.2.1")).as_deref(),
+            Some("192.0.2.1")
+        );
+        assert_eq!(
+            client_ip(Some("10.0.0.1"), Some("192.0.2.1:1234")).as_deref(),
+            Some("192.0.2.1")
+        );
+        // the proxy is the client if its header is missing or invalid
+        assert_eq!(
+            client_ip(Some("10.0.0.1"), None).as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(
+            client_ip(Some("10.0.0.1"), Some("unknown")).as_deref(),
+            Some("10.0.0.1")
+        );
+        // requests without a peer address are not limited
+        assert_eq!(client_ip(None, Some("192.0.2.1")), None);
+    }
+
+    #[test]
+    fn test_guard_release() {
+        let limiter = ClientConnLimiter::new(1);
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let guard = limiter.try_acquire(ip);
+        assert!(guard.is_some());
+        assert!(limiter.try_acquire(ip).is_none());
+        drop(guard);
+        assert!(limiter.active.is_empty());
+        assert!(limiter.try_acquire(ip).is_some());
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let limiter = ClientConnLimiter::new(1);
+        assert_eq!(limiter.retry_after(), MIN_RETRY_AFTER_SECS);
+
+        // the average converges towards the duration of the recent requests
+        for _ in 0..50 {
+            record_duration(&limiter.avg_duration_ms, Duration::from_millis(4200));
+        }
+        assert_eq!(limiter.retry_after(), 5);
+        for _ in 0..50 {
+            record_duration(&limiter.avg_duration_ms, Duration::from_millis(10));
+        }
+        assert_eq!(limiter.retry_after(), MIN_RETRY_AFTER_SECS);
+    }
+}
@@ -0,0 +1,164 @@
+use std::slice;
+
+use actix_web::error::{ErrorBadRequest, ErrorNotImplemented, ErrorUnauthorized};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::web::{Data, Path, Query};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, route};
+use martin_tile_utils::TileCoord;
+use serde::Deserialize;
+
+use crate::source::{TileSources, UrlQuery};
+use crate::srv::SrvConfig;
+use crate::srv::server::map_internal_error;
+use crate::srv::tenants::check_tenant;
+use crate::srv::tiles::known_query;
+
+#[derive(Deserialize)]
+pub struct ExplainRequest {
+    source_id: String,
+    z: u8,
+    x: u32,
+    y: u32,
+}
+
+/// Query plan of a single tile, for tuning the database. The plan reveals schema details,
+/// so the endpoint is only registered if the `explain_token` is configured, and requires it.
+#[route("/{source_id}/explain/{z}/{x}/{y}", method = "GET")]
+async fn get_explain(
+    req: HttpRequest,
+    srv_config: Data<SrvConfig>,
+    path: Path<ExplainRequest>,
+    sources: Data<TileSources>,
+) -> ActixResult<HttpResponse> {
+    if !is_authorized(&req, srv_config.explain_token.as_deref()) {
+        return Err(ErrorUnauthorized("Missing or invalid explain token"));
+    }
+
+    check_tenant(&req, &path.source_id)?;
+    let src = sources.get_source(&path.source_id)?;
+    let xyz = TileCoord {
+        z: path.z,
+        x: path.x,
+        y: path.y,
+    };
+    let xyz = src
+        .get_scheme()
+        .to_xyz(xyz)
+        .filter(|v| src.is_valid_zoom(v.z) && src.get_tile_grid().is_valid_tile(*v))
+        .ok_or_else(|| ErrorBadRequest(format!("Invalid tile coordinates {xyz:#}")))?;
+
+    let query = if src.support_url_query() && !req.query_string().is_empty() {
+        Some(Query::<UrlQuery>::from_query(req.query_string())?.into_inner())
+    } else {
+        known_query(req.query_string(), slice::from_ref(&src))
+            .and_then(|v| Query::<UrlQuery>::from_query(&v).ok())
+            .map(Query::into_inner)
+    };
+
+    match src
+        .explain_tile(xyz, query.as_ref())
+        .await
+        .map_err(map_internal_error)?
+    {
+        Some(plan) => Ok(HttpResponse::Ok().json(plan)),
+        None => Err(ErrorNotImplemented(format!(
+            "Source {} does not support query plans",
+            path.source_id
+        ))),
+    }
+}
+
+pub(crate) fn is_authorized(req: &HttpRequest, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return false;
+    };
+    bearer_token(req).is_some_and(|v| constant_time_eq(v.as_bytes(), token.as_bytes()))
+}
+
+/// Compare a secret in a time that does not depend on the position of the first differing byte,
+/// so that the token cannot be guessed byte by byte from the response times. Only its length may leak.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && std::hint::black_box(a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y))) == 0
+}
+
+/// Get the bearer token of the `Authorization` header, if any
+pub(crate) fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, web};
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    #[actix_rt::test]
+    async fn test_explain_auth() {
+        let sources = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "test_source",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        })]]);
+        let srv_config = SrvConfig {
+            explain_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(sources))
+                .app_data(Data::new(srv_config))
+                .service(get_explain)
+                .route("/{source_ids}/{z}/{x}/{y}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let get = |path: &str, auth: Option<&str>| {
+            let req = TestRequest::get().uri(path);
+            match auth {
+                Some(auth) => req.insert_header((AUTHORIZATION, auth)),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let path = "/test_source/explain/0/0/0";
+        let response = call_service(&app, get(path, None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = call_service(&app, get(path, Some("Bearer wrong"))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = call_service(&app, get(path, Some("secret"))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // only table sources support query plans
+        let response = call_service(&app, get(path, Some("Bearer secret"))).await;
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+
+        let path = "/missing/explain/0/0/0";
+        let response = call_service(&app, get(path, Some("Bearer secret"))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let path = "/test_source/explain/1/2/0";
+        let response = call_service(&app, get(path, Some("Bearer secret"))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // tile requests are not affected
+        let response = call_service(&app, get("/test_source/0/0/0", None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"Secret"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
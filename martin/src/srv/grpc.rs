@@ -0,0 +1,241 @@
+use std::net::TcpListener;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use log::error;
+use martin_tile_utils::{MAX_ZOOM, TileCoord};
+use tokio::time::Instant;
+use tonic::transport::Server;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+use crate::MartinError::BindingError;
+use crate::MartinResult;
+use crate::source::TileSources;
+use crate::srv::tiles::DynTileSource;
+use crate::srv::{SrvConfig, Tenants, check_tile_access};
+use crate::utils::{MainCache, OptMainCache};
+
+pub mod proto {
+    #![allow(clippy::pedantic, unused_qualifications)]
+    tonic::include_proto!("martin.tiles.v1");
+}
+
+use proto::tiles_server::{Tiles, TilesServer};
+use proto::{GetTileRequest, GetTileResponse};
+
+/// gRPC tiles service, serving the same tiles as the `/{source_ids}/{z}/{x}/{y}` endpoint
+#[derive(Clone)]
+pub struct TilesService {
+    sources: TileSources,
+    cache: OptMainCache,
+    srv_config: SrvConfig,
+    tenants: Tenants,
+}
+
+impl TilesService {
+    #[must_use]
+    pub fn new(sources: TileSources, cache: OptMainCache, srv_config: SrvConfig) -> Self {
+        Self {
+            sources,
+            cache,
+            srv_config,
+            tenants: Tenants::default(),
+        }
+    }
+
+    /// Only serve the sources of a tenant to the requests of that tenant, selected by the `tenant_header` metadata
+    #[must_use]
+    pub fn with_tenants(self, tenants: Tenants) -> Self {
+        Self { tenants, ..self }
+    }
+}
+
+#[tonic::async_trait]
+impl Tiles for TilesService {
+    async fn get_tile(
+        &self,
+        request: Request<GetTileRequest>,
+    ) -> Result<Response<GetTileResponse>, Status> {
+        // API keys are sent the same way as with HTTP, e.g. `authorization: Bearer <key>`
+        let key = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(ToString::to_string);
+        let tenant = self.srv_config.tenant_header.as_ref().map(|header| {
+            // gRPC metadata keys are always lowercase
+            request
+                .metadata()
+                .get(header.to_ascii_lowercase().as_str())
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty())
+                .map(ToString::to_string)
+        });
+        let req = request.into_inner();
+        let z = u8::try_from(req.z)
+            .ok()
+            .filter(|z| *z <= MAX_ZOOM)
+            .ok_or_else(|| {
+                Status::invalid_argument(format!(
+                    "Invalid zoom level {}, must not be above {MAX_ZOOM}",
+                    req.z
+                ))
+            })?;
+        let xyz = TileCoord {
+            z,
+            x: req.x,
+            y: req.y,
+        };
+
+        // Tiles of the sources read below are not cached once the cache was invalidated after a reload
+        let cache_generation = self.cache.as_ref().map_or(0, MainCache::generation);
+        // All reads of the request use the same sources, even if they are reloaded meanwhile
+        let sources = self.sources.snapshot();
+        if let Some(tenant) = tenant {
+            self.tenants
+                .check(tenant.as_deref(), &req.source_ids)
+                .map_err(|e| to_status(&e))?;
+        }
+        if let Some(tile_access) = &self.srv_config.tile_access {
+            let scheme = sources
+                .get_scheme(&req.source_ids)
+                .map_err(|e| to_status(&e))?;
+            check_tile_access(
+                tile_access,
+                key.as_deref(),
+                &sources,
+                &req.source_ids,
+                xyz,
+                scheme,
+            )
+            .map_err(|e| to_status(&e))?;
+        }
+
+        let start = Instant::now();
+        let src = DynTileSource::new(
+            &sources,
+            &req.source_ids,
+            Some(z),
+            &req.query,
+            None,
+            None,
+            self.cache.as_ref(),
+        )
+        .map_err(|e| to_status(&e))?
+        .with_cache_generation(cache_generation)
+        .with_retries(
+            self.srv_config.tile_retries.unwrap_or_default(),
+            self.srv_config
+                .tile_budget_ms
+                .map(|v| start + Duration::from_millis(v)),
+        );
+        if src.sources.is_empty() {
+            return Err(Status::not_found(format!(
+                "No valid sources found at zoom {z}"
+            )));
+        }
+
+        // Without an accepted encoding, the tile is always returned uncompressed
+        let tile = src.get_tile_content(xyz).await.map_err(|e| to_status(&e))?;
+        Ok(Response::new(GetTileResponse {
+            data: tile.data,
+            content_type: tile.info.format.content_type().to_string(),
+        }))
+    }
+}
+
+/// Convert an HTTP error of the tile request to the gRPC status with the same meaning
+fn to_status(e: &actix_web::Error) -> Status {
+    let message = e.to_string();
+    match e.as_response_error().status_code() {
+        StatusCode::BAD_REQUEST => Status::invalid_argument(message),
+        StatusCode::FORBIDDEN => Status::permission_denied(message),
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::NOT_IMPLEMENTED => Status::unimplemented(message),
+        StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+        StatusCode::GATEWAY_TIMEOUT => Status::deadline_exceeded(message),
+        _ => Status::internal(message),
+    }
+}
+
+/// Bind the gRPC listening address, and serve the tiles service on it in the background.
+/// Binding is done right away, so that an unavailable address prevents Martin from starting.
+pub fn spawn_grpc_server(address: &str, service: TilesService) -> MartinResult<()> {
+    let listener = TcpListener::bind(address)
+        .and_then(|v| v.set_nonblocking(true).map(|()| v))
+        .map_err(|e| BindingError(e, address.to_string()))?;
+    let listener = tokio::net::TcpListener::from_std(listener)
+        .map_err(|e| BindingError(e, address.to_string()))?;
+    let address = address.to_string();
+    actix_rt::spawn(async move {
+        let incoming = TcpIncoming::from_listener(listener, true, None)
+            .expect("a bound listener is always accepted");
+        if let Err(e) = Server::builder()
+            .add_service(TilesServer::new(service))
+            .serve_with_incoming(incoming)
+            .await
+        {
+            error!("gRPC server on {address} failed: {e}");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use martin_tile_utils::Format;
+    use tilejson::tilejson;
+
+    use super::proto::tiles_client::TilesClient;
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    #[actix_rt::test]
+    async fn test_grpc_get_tile() {
+        let sources = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "test_source",
+            tj: tilejson! { tiles: vec![], minzoom: 2 },
+            data: vec![1_u8, 2, 3],
+        })]]);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let service = TilesService::new(sources, None, SrvConfig::default());
+        spawn_grpc_server(&address, service).unwrap();
+
+        let mut client = TilesClient::connect(format!("http://{address}"))
+            .await
+            .unwrap();
+        let request = |source_ids: &str, z| GetTileRequest {
+            source_ids: source_ids.to_string(),
+            z,
+            x: 0,
+            y: 0,
+            query: String::new(),
+        };
+
+        let tile = client
+            .get_tile(request("test_source", 2))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(tile.data, [1, 2, 3]);
+        assert_eq!(tile.content_type, Format::Mvt.content_type());
+
+        let err = client.get_tile(request("missing", 2)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        // the source has no tiles below its minzoom
+        let err = client
+            .get_tile(request("test_source", 1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let err = client
+            .get_tile(request("test_source", 31))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::web::{Data, Query};
+use actix_web::{HttpResponse, Responder, route};
+use futures::StreamExt as _;
+use futures::stream;
+use log::{debug, warn};
+use martin_tile_utils::TileCoord;
+use serde::{Deserialize, Serialize};
+
+use crate::source::{TileInfoSource, TileSources};
+
+/// How often to render the sample tiles, in seconds
+pub const HEALTH_CHECK_INTERVAL_DEFAULT: u64 = 60;
+/// Maximum time to render a single sample tile, in milliseconds
+pub const HEALTH_CHECK_TIMEOUT_DEFAULT: u64 = 5000;
+/// Maximum number of sample tiles rendered at the same time
+const HEALTH_CHECK_CONCURRENCY: usize = 4;
+
+/// Periodically render a sample tile from every source, and report the result with `/health?deep=true`
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct HealthCheckConfig {
+    /// How often to render the sample tiles, in seconds [default: 60]
+    pub interval_secs: Option<u64>,
+    /// Maximum time to render a single sample tile, in milliseconds [default: 5000]
+    pub timeout_ms: Option<u64>,
+    /// Zoom level of the sample tile at the center of the source bounds.
+    /// The value is clamped to the source zoom range [default: source's minzoom]
+    pub sample_zoom: Option<u8>,
+}
+
+/// The result of the last deep health check, shared between all server workers
+#[derive(Clone, Debug, Default)]
+pub struct HealthStatus(Arc<RwLock<Option<Vec<String>>>>);
+
+impl HealthStatus {
+    /// Get the list of failed sample tiles, or `None` if no check has completed yet
+    #[must_use]
+    pub fn get_failures(&self) -> Option<Vec<String>> {
+        self.0.read().expect("health status lock poisoned").clone()
+    }
+
+    fn set_failures(&self, failures: Vec<String>) {
+        *self.0.write().expect("health status lock poisoned") = Some(failures);
+    }
+}
+
+/// Run the deep health check forever, updating the status after each run
+pub async fn run_health_checks(cfg: HealthCheckConfig, sources: TileSources, status: HealthStatus) {
+    let interval = cfg.interval_secs.unwrap_or(HEALTH_CHECK_INTERVAL_DEFAULT);
+    let mut interval = actix_rt::time::interval(Duration::from_secs(interval.max(1)));
+    loop {
+        interval.tick().await;
+        let failures = check_sources(&cfg, &sources).await;
+        if !failures.is_empty() {
+            warn!("Deep health check failed: {}", failures.join("; "));
+        }
+        status.set_failures(failures);
+    }
+}
+
+/// Render a sample tile from each source, returning the list of failures
+pub async fn check_sources(cfg: &HealthCheckConfig, sources: &TileSources) -> Vec<String> {
+    let timeout = Duration::from_millis(cfg.timeout_ms.unwrap_or(HEALTH_CHECK_TIMEOUT_DEFAULT));
+    let mut failures: Vec<String> = stream::iter(sources.get_all_sources())
+        .map(|src| async move {
+            let xyz = sample_tile(&src, cfg.sample_zoom);
+            debug!("Checking health of {} with tile {xyz:#}", src.get_id());
+            match actix_rt::time::timeout(timeout, src.get_tile(xyz, None)).await {
+                Ok(Ok(_)) => None,
+                Ok(Err(e)) => Some(format!("{} at {xyz:#}: {e}", src.get_id())),
+                Err(_) => Some(format!("{} at {xyz:#}: timed out", src.get_id())),
+            }
+        })
+        .buffer_unordered(HEALTH_CHECK_CONCURRENCY)
+        .filter_map(|v| async { v })
+        .collect()
+        .await;
+    failures.sort();
+    failures
+}
+
+/// Get the tile at the center of the source bounds, at the given zoom clamped to the source zoom range
+fn sample_tile(src: &TileInfoSource, zoom: Option<u8>) -> TileCoord {
+    let tj = src.get_tilejson();
+    let minzoom = tj.minzoom.unwrap_or(0);
+    let maxzoom = tj
+        .maxzoom
+        .unwrap_or(martin_tile_utils::MAX_ZOOM)
+        .max(minzoom);
+    let z = zoom.unwrap_or(minzoom).clamp(minzoom, maxzoom);
+    let bounds = tj.bounds.unwrap_or_default();
+    let lng = f64::midpoint(bounds.left, bounds.right);
+    let lat = f64::midpoint(bounds.bottom, bounds.top);
+    let (x, y) = src.get_tile_grid().tile_index(lng, lat, z);
+    TileCoord { z, x, y }
+}
+
+#[derive(Deserialize)]
+struct HealthRequest {
+    deep: Option<bool>,
+}
+
+/// Return 200 OK if healthy. Used for readiness and liveness probes.
+/// With `?deep=true`, returns 503 if any source failed to render its sample tile during the last check.
+#[route("/health", method = "GET", method = "HEAD")]
+#[allow(clippy::unused_async)]
+async fn get_health(
+    query: Query<HealthRequest>,
+    status: Option<Data<HealthStatus>>,
+) -> impl Responder {
+    if query.deep.unwrap_or_default() {
+        if let Some(status) = status {
+            let body = match status.get_failures() {
+                None => Some("Deep health check has not completed yet".to_string()),
+                Some(v) if !v.is_empty() => Some(v.join("\n")),
+                Some(_) => None,
+            };
+            if let Some(body) = body {
+                return HttpResponse::ServiceUnavailable()
+                    .insert_header((CACHE_CONTROL, "no-cache"))
+                    .body(body);
+            }
+        }
+    }
+
+    HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .body("OK")
+}
+
+#[cfg(test)]
+mod tests {
+    use tilejson::{Bounds, tilejson};
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    #[test]
+    fn test_sample_tile() {
+        let src = |minzoom, maxzoom, bounds| -> TileInfoSource {
+            let mut tj = tilejson! { tiles: vec![] };
+            tj.minzoom = minzoom;
+            tj.maxzoom = maxzoom;
+            tj.bounds = bounds;
+            Box::new(TestSource {
+                id: "test",
+                tj,
+                data: Vec::new(),
+            })
+        };
+        let xyz = |z, x, y| TileCoord { z, x, y };
+
+        assert_eq!(sample_tile(&src(None, None, None), None), xyz(0, 0, 0));
+        assert_eq!(sample_tile(&src(None, None, None), Some(2)), xyz(2, 2, 2));
+        assert_eq!(sample_tile(&src(Some(3), None, None), None), xyz(3, 4, 4));
+        assert_eq!(
+            sample_tile(&src(Some(3), None, None), Some(1)),
+            xyz(3, 4, 4)
+        );
+        assert_eq!(
+            sample_tile(&src(None, Some(1), None), Some(5)),
+            xyz(1, 1, 1)
+        );
+
+        let bounds = Some(Bounds::new(10.0, 10.0, 20.0, 20.0));
+        assert_eq!(sample_tile(&src(Some(4), None, bounds), None), xyz(4, 8, 7));
+    }
+
+    #[actix_rt::test]
+    async fn test_check_sources() {
+        let sources = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "test",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        })]]);
+        let failures = check_sources(&HealthCheckConfig::default(), &sources).await;
+        assert!(failures.is_empty());
+
+        let status = HealthStatus::default();
+        assert_eq!(status.get_failures(), None);
+        status.set_failures(failures);
+        assert_eq!(status.get_failures(), Some(Vec::new()));
+    }
+}
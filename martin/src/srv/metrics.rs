@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::web::Data;
+use actix_web::{HttpResponse, route};
+
+use crate::source::TileSources;
+
+/// Upper bounds of the tile request duration histogram buckets, in seconds
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Content type of the Prometheus text exposition format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Prometheus metrics of the tile requests by source, registered as app data if `metrics` is enabled.
+/// Clones share the same metrics.
+#[derive(Clone, Debug, Default)]
+pub struct TileMetrics(Arc<Mutex<BTreeMap<String, SourceMetrics>>>);
+
+#[derive(Debug, Default)]
+struct SourceMetrics {
+    /// Number of requests by HTTP status
+    requests: BTreeMap<u16, u64>,
+    /// Number of requests of tiles without data
+    empty: u64,
+    /// Number of requests in each duration bucket, the last one counting the requests slower than all buckets
+    durations: [u64; DURATION_BUCKETS.len() + 1],
+    /// Total duration of the requests in seconds
+    duration_sum: f64,
+}
+
+impl TileMetrics {
+    /// Record a tile request of the given comma-separated sources. A composite request is counted once
+    /// for each of its sources. Only existing sources are used as labels, so that requests of random IDs
+    /// cannot create new series, and requests without any existing source are counted with an empty `source`.
+    pub fn record(
+        &self,
+        sources: &TileSources,
+        source_ids: &str,
+        status: StatusCode,
+        is_empty: bool,
+        duration: Duration,
+    ) {
+        let mut ids: Vec<&str> = source_ids
+            .split(',')
+            .filter(|id| sources.contains(id))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        if ids.is_empty() {
+            ids.push("");
+        }
+
+        let seconds = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|v| seconds <= *v)
+            .unwrap_or(DURATION_BUCKETS.len());
+        let mut metrics = self.0.lock().expect("tile metrics lock poisoned");
+        for id in ids {
+            let m = metrics.entry(id.to_string()).or_default();
+            *m.requests.entry(status.as_u16()).or_default() += 1;
+            if is_empty {
+                m.empty += 1;
+            }
+            m.durations[bucket] += 1;
+            m.duration_sum += seconds;
+        }
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    #[must_use]
+    pub fn render(&self) -> String {
+        let metrics = self.0.lock().expect("tile metrics lock poisoned");
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP martin_tile_requests_total Tile requests by source and HTTP status.\n",
+        );
+        out.push_str("# TYPE martin_tile_requests_total counter\n");
+        for (id, m) in metrics.iter() {
+            let source = escape_label(id);
+            for (status, count) in &m.requests {
+                let _ = writeln!(
+                    out,
+                    "martin_tile_requests_total{{source=\"{source}\",status=\"{status}\"}} {count}"
+                );
+            }
+        }
+
+        out.push_str("# HELP martin_tile_empty_total Tile requests by source returning a tile without data.\n");
+        out.push_str("# TYPE martin_tile_empty_total counter\n");
+        for (id, m) in metrics.iter() {
+            let source = escape_label(id);
+            let _ = writeln!(
+                out,
+                "martin_tile_empty_total{{source=\"{source}\"}} {}",
+                m.empty
+            );
+        }
+
+        out.push_str(
+            "# HELP martin_tile_request_duration_seconds Duration of the tile requests by source.\n",
+        );
+        out.push_str("# TYPE martin_tile_request_duration_seconds histogram\n");
+        for (id, m) in metrics.iter() {
+            let source = escape_label(id);
+            let mut count = 0;
+            for (le, bucket) in DURATION_BUCKETS.iter().zip(m.durations) {
+                count += bucket;
+                let _ = writeln!(
+                    out,
+                    "martin_tile_request_duration_seconds_bucket{{source=\"{source}\",le=\"{le}\"}} {count}"
+                );
+            }
+            count += m.durations[DURATION_BUCKETS.len()];
+            let _ = writeln!(
+                out,
+                "martin_tile_request_duration_seconds_bucket{{source=\"{source}\",le=\"+Inf\"}} {count}"
+            );
+            let _ = writeln!(
+                out,
+                "martin_tile_request_duration_seconds_sum{{source=\"{source}\"}} {}",
+                m.duration_sum
+            );
+            let _ = writeln!(
+                out,
+                "martin_tile_request_duration_seconds_count{{source=\"{source}\"}} {count}"
+            );
+        }
+        out
+    }
+}
+
+/// Escape a label value of the text exposition format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Tile request metrics in the Prometheus text format, only registered if `metrics` is enabled
+#[route("/metrics", method = "GET")]
+#[allow(clippy::unused_async)]
+async fn get_metrics(metrics: Data<TileMetrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(METRICS_CONTENT_TYPE)
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .body(metrics.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_and_read_body, call_service, init_service};
+    use regex::Regex;
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::NO_MAIN_CACHE;
+    use crate::srv::server::tests::TestSource;
+    use crate::srv::{SrvConfig, router};
+
+    #[actix_rt::test]
+    async fn test_metrics() {
+        let sources = TileSources::new(vec![vec![
+            Box::new(TestSource {
+                id: "roads",
+                tj: tilejson! { tiles: vec![], minzoom: 2 },
+                data: vec![1_u8, 2, 3],
+            }),
+            Box::new(TestSource {
+                id: "empty",
+                tj: tilejson! { tiles: vec![] },
+                data: Vec::default(),
+            }),
+        ]]);
+        let srv_config = SrvConfig {
+            metrics: Some(true),
+            ..Default::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(sources))
+                .app_data(Data::new(srv_config.clone()))
+                .app_data(Data::new(NO_MAIN_CACHE))
+                .app_data(Data::new(TileMetrics::default()))
+                .configure(|c| router(c, &srv_config)),
+        )
+        .await;
+        for path in [
+            "/roads/2/0/0",
+            "/roads/3/1/1",
+            "/roads,empty/2/0/0",
+            "/empty/0/0/0",
+            "/missing/0/0/0",
+        ] {
+            call_service(&app, TestRequest::get().uri(path).to_request()).await;
+        }
+
+        let response = call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            METRICS_CONTENT_TYPE
+        );
+        let body = call_and_read_body(&app, TestRequest::get().uri("/metrics").to_request()).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        // every line is a comment or a sample with a name, labels, and a number
+        let sample = Regex::new(r#"^[a-z_]+\{([a-z]+="[^"]*",?)+\} [0-9.e+-]+$"#).unwrap();
+        for line in body.lines() {
+            assert!(
+                line.starts_with("# HELP ") || line.starts_with("# TYPE ") || sample.is_match(line),
+                "invalid line: {line}"
+            );
+        }
+        for expected in [
+            r#"martin_tile_requests_total{source="roads",status="200"} 3"#,
+            r#"martin_tile_requests_total{source="empty",status="204"} 1"#,
+            r#"martin_tile_requests_total{source="empty",status="200"} 1"#,
+            r#"martin_tile_requests_total{source="",status="404"} 1"#,
+            r#"martin_tile_empty_total{source="empty"} 1"#,
+            r#"martin_tile_empty_total{source="roads"} 0"#,
+            r#"martin_tile_request_duration_seconds_bucket{source="roads",le="+Inf"} 3"#,
+            r#"martin_tile_request_duration_seconds_count{source="roads"} 3"#,
+        ] {
+            assert!(body.contains(expected), "missing {expected} in {body}");
+        }
+        // tile coordinates are never used as labels
+        assert!(!body.contains("missing") && !body.contains("0/0/0"));
+    }
+
+    #[test]
+    fn metrics_label_escaping() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape_label("a\nb"), r"a\nb");
+    }
+}
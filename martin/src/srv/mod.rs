@@ -1,11 +1,45 @@
+mod access;
+pub use access::{AccessPolicy, SourceAccess, check_tile_access};
+
 mod config;
-pub use config::{KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT, SrvConfig};
+pub use config::{
+    CORS_MAX_AGE_DEFAULT, CatalogSort, CompositeBounds, IndexConfig, KEEP_ALIVE_DEFAULT,
+    LISTEN_ADDRESSES_DEFAULT, LayerFieldConflicts, MAX_PAYLOAD_SIZE_DEFAULT, OutOfZoomResponse,
+    ROBOTS_TXT_DEFAULT, SrvConfig, TilesUrlConfig,
+};
+
+mod conn_limit;
+pub use conn_limit::{ClientConnLimiter, limit_client_connections};
+
+mod explain;
 
 #[cfg(feature = "fonts")]
 mod fonts;
 
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
+mod health;
+pub use health::{HealthCheckConfig, HealthStatus};
+
+mod metrics;
+pub use metrics::TileMetrics;
+
+mod refresh;
+pub use refresh::refresh_source;
+
 mod server;
-pub use server::{Catalog, RESERVED_KEYWORDS, new_server, router};
+pub use server::{
+    CATALOG_DEFAULT_GROUP, Catalog, GroupedCatalog, RESERVED_KEYWORDS, ServedCatalog,
+    SortedCatalog, new_server, router,
+};
+
+mod status;
+
+mod style;
+
+mod tenants;
+pub use tenants::{TenantRouting, Tenants, check_tenant};
 
 mod tiles;
 pub use tiles::{DynTileSource, TileRequest};
@@ -0,0 +1,320 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_rt::task::JoinHandle;
+
+use actix_web::error::{ErrorNotImplemented, ErrorUnauthorized};
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, route};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::MartinResult;
+use crate::config::SourceReloader;
+use crate::source::{TileInfoSource, TileSources};
+use crate::srv::explain::is_authorized;
+use crate::srv::health::{HealthCheckConfig, HealthStatus, run_health_checks};
+use crate::srv::server::map_internal_error;
+use crate::srv::{ServedCatalog, SrvConfig};
+use crate::utils::OptMainCache;
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    source_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshResponse {
+    source_id: String,
+    duration_ms: u128,
+}
+
+/// Refresh the data of a source, e.g. its materialized view, while its tiles keep being served.
+/// Refreshing may be expensive, so the endpoint is only registered if the `refresh_token` is configured, and requires it.
+#[route("/refresh/{source_id}", method = "POST")]
+async fn post_refresh(
+    req: HttpRequest,
+    srv_config: Data<SrvConfig>,
+    path: Path<RefreshRequest>,
+    sources: Data<TileSources>,
+    cache: Data<OptMainCache>,
+) -> ActixResult<HttpResponse> {
+    if !is_authorized(&req, srv_config.refresh_token.as_deref()) {
+        return Err(ErrorUnauthorized("Missing or invalid refresh token"));
+    }
+
+    let src = sources.get_source(&path.source_id)?;
+    match refresh_source(&src, &cache)
+        .await
+        .map_err(map_internal_error)?
+    {
+        Some(duration) => Ok(HttpResponse::Ok().json(RefreshResponse {
+            source_id: path.into_inner().source_id,
+            duration_ms: duration.as_millis(),
+        })),
+        None => Err(ErrorNotImplemented(format!(
+            "Source {} does not support refreshing",
+            path.source_id
+        ))),
+    }
+}
+
+/// Resolve the tile sources of the configuration again, e.g. to publish the tables created since the start,
+/// without restarting the server. If any source cannot be resolved, the served sources are kept.
+/// The endpoint is only registered if the `reload_token` is configured, and requires it.
+#[route("/reload", method = "POST")]
+async fn post_reload(
+    req: HttpRequest,
+    srv_config: Data<SrvConfig>,
+    reloader: Data<SourceReloader>,
+    catalog: Data<ServedCatalog>,
+    tasks: Data<SourceTasks>,
+    health: Data<HealthStatus>,
+    cache: Data<OptMainCache>,
+) -> ActixResult<HttpResponse> {
+    if !is_authorized(&req, srv_config.reload_token.as_deref()) {
+        return Err(ErrorUnauthorized("Missing or invalid reload token"));
+    }
+
+    let summary = reloader
+        .reload(|sources| {
+            catalog.replace_tiles(sources.get_catalog());
+            tasks.restart(sources, srv_config.health_check.as_ref(), &health, &cache);
+        })
+        .await
+        .map_err(map_internal_error)?;
+    info!(
+        "Reloaded sources: {} added, {} removed, {} unchanged",
+        summary.added.len(),
+        summary.removed.len(),
+        summary.unchanged.len()
+    );
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+/// Background tasks of the served sources, i.e. their scheduled refreshes and the deep health checks.
+/// They are started again after the sources were reloaded, so that the removed sources are no longer
+/// refreshed, and the added ones are. Clones share the same tasks.
+#[derive(Clone, Debug, Default)]
+pub struct SourceTasks(Arc<Mutex<Vec<JoinHandle<()>>>>);
+
+impl SourceTasks {
+    /// Stop the running tasks, and start the tasks of the given sources
+    pub fn restart(
+        &self,
+        sources: &TileSources,
+        health_check: Option<&HealthCheckConfig>,
+        health: &HealthStatus,
+        cache: &OptMainCache,
+    ) {
+        let mut tasks = self.0.lock().expect("source tasks lock poisoned");
+        for task in tasks.drain(..) {
+            task.abort();
+        }
+        if let Some(cfg) = health_check {
+            let checks = run_health_checks(cfg.clone(), sources.clone(), health.clone());
+            tasks.push(actix_rt::spawn(checks));
+        }
+        for src in sources.get_all_sources() {
+            if let Some(interval) = src.get_refresh_interval() {
+                let refresh = run_scheduled_refresh(src, interval, cache.clone());
+                tasks.push(actix_rt::spawn(refresh));
+            }
+        }
+    }
+}
+
+/// Refresh the data of a source, and remove its cached tiles once the refresh succeeded.
+/// Returns `None` if the source does not support refreshing.
+pub async fn refresh_source(
+    src: &TileInfoSource,
+    cache: &OptMainCache,
+) -> MartinResult<Option<Duration>> {
+    let duration = src.refresh().await?;
+    if let (Some(_), Some(cache)) = (duration, cache) {
+        cache.invalidate_source(src.get_id()).await;
+    }
+    Ok(duration)
+}
+
+/// Refresh the source forever, waiting for the given interval after each refresh
+pub async fn run_scheduled_refresh(src: TileInfoSource, interval: Duration, cache: OptMainCache) {
+    loop {
+        actix_rt::time::sleep(interval).await;
+        if let Err(e) = refresh_source(&src, &cache).await {
+            error!("Scheduled refresh of source {} failed: {e}", src.get_id());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    #[actix_rt::test]
+    async fn test_refresh_auth() {
+        let sources = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "test_source",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        })]]);
+        let srv_config = SrvConfig {
+            refresh_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(sources))
+                .app_data(Data::new(srv_config))
+                .app_data(Data::new(OptMainCache::None))
+                .service(post_refresh),
+        )
+        .await;
+        let post = |path: &str, auth: Option<&str>| {
+            let req = TestRequest::post().uri(path);
+            match auth {
+                Some(auth) => req.insert_header((AUTHORIZATION, auth)),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let path = "/refresh/test_source";
+        let response = call_service(&app, post(path, None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = call_service(&app, post(path, Some("Bearer wrong"))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // only sources backed by a materialized view support refreshing
+        let response = call_service(&app, post(path, Some("Bearer secret"))).await;
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+
+        let response = call_service(&app, post("/refresh/missing", Some("Bearer secret"))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn replace_sources_under_load() {
+        use std::collections::BTreeSet;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // all sources of a set have the same minzoom, so that a mix of both sets is detected
+        let sources = |ids: [&'static str; 2], minzoom: u8| {
+            TileSources::new(vec![
+                ids.into_iter()
+                    .map(|id| {
+                        Box::new(TestSource {
+                            id,
+                            tj: tilejson! { tiles: vec![], minzoom: minzoom },
+                            data: Vec::default(),
+                        }) as TileInfoSource
+                    })
+                    .collect(),
+            ])
+        };
+        let old = sources(["roads", "parcels"], 1);
+        let new = sources(["roads", "rivers"], 2);
+        let served = TileSources::default();
+        served.replace(&old);
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let all = served.get_all_sources();
+                        let mut ids: Vec<_> = all.iter().map(|v| v.get_id()).collect();
+                        ids.sort_unstable();
+                        let minzooms: BTreeSet<_> =
+                            all.iter().map(|v| v.get_tilejson().minzoom).collect();
+                        assert!(
+                            (ids == ["parcels", "roads"] && minzooms == BTreeSet::from([Some(1)]))
+                                || (ids == ["rivers", "roads"]
+                                    && minzooms == BTreeSet::from([Some(2)])),
+                            "mixed sources {ids:?} with minzooms {minzooms:?}"
+                        );
+                    }
+                });
+            }
+            for i in 0..1000 {
+                served.replace(if i % 2 == 0 { &new } else { &old });
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+    }
+
+    #[cfg(feature = "pmtiles")]
+    #[actix_rt::test]
+    async fn test_reload() {
+        use actix_web::test::call_and_read_body_json;
+        use serde_json::{Value, json};
+
+        use crate::Config;
+        use crate::file_config::FileConfigEnum;
+        use crate::srv::Catalog;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("sources");
+        std::fs::create_dir(&dir).unwrap();
+        let fixture = "../tests/fixtures/pmtiles/png.pmtiles";
+        std::fs::copy(fixture, dir.join("first.pmtiles")).unwrap();
+        let mut config = Config {
+            pmtiles: FileConfigEnum::Path(dir.clone()),
+            srv: SrvConfig {
+                reload_token: Some("secret".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.finalize().unwrap();
+        let state = config.resolve().await.unwrap();
+        let catalog = ServedCatalog::new(Catalog::new(&state).unwrap());
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(state.cache.clone()))
+                .app_data(Data::new(catalog.clone()))
+                .app_data(Data::new(SourceTasks::default()))
+                .app_data(Data::new(HealthStatus::default()))
+                .app_data(Data::new(state.reloader.clone().unwrap()))
+                .app_data(Data::new(config.srv.clone()))
+                .service(post_reload),
+        )
+        .await;
+        let post = |auth: &str| {
+            TestRequest::post()
+                .uri("/reload")
+                .insert_header((AUTHORIZATION, auth))
+                .to_request()
+        };
+
+        let response = call_service(&app, post("Bearer wrong")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // files added since the start are published
+        std::fs::copy(fixture, dir.join("second.pmtiles")).unwrap();
+        let summary: Value = call_and_read_body_json(&app, post("Bearer secret")).await;
+        assert_eq!(
+            summary,
+            json!({"added": ["second"], "removed": [], "unchanged": ["first"]})
+        );
+        assert!(state.tiles.get_source("second").is_ok());
+        assert!(catalog.get().tiles.contains_key("second"));
+
+        // the sources are kept if they cannot be resolved
+        std::fs::remove_dir_all(&dir).unwrap();
+        let response = call_service(&app, post("Bearer secret")).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            state.tiles.get_ids().into_iter().collect::<Vec<_>>(),
+            ["first", "second"]
+        );
+        assert!(catalog.get().tiles.contains_key("first"));
+    }
+}
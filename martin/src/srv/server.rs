@@ -1,14 +1,17 @@
 use std::future::Future;
 use std::pin::Pin;
 use std::string::ToString;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 use actix_cors::Cors;
 use actix_web::error::ErrorInternalServerError;
-use actix_web::http::header::CACHE_CONTROL;
+use actix_web::http::header::{ContentType, LOCATION};
 use actix_web::middleware::TrailingSlash;
-use actix_web::web::Data;
-use actix_web::{App, HttpResponse, HttpServer, Responder, middleware, route, web};
+use actix_web::web::{Data, JsonConfig, PayloadConfig, Query};
+use actix_web::{
+    App, HttpRequest, HttpResponse, HttpServer, Result as ActixResult, middleware, route, web,
+};
 use futures::TryFutureExt;
 #[cfg(feature = "lambda")]
 use lambda_web::{is_running_on_lambda, run_actix_on_lambda};
@@ -20,10 +23,21 @@ use crate::MartinResult;
 #[cfg(feature = "webui")]
 use crate::args::WebUiMode;
 use crate::config::ServerState;
-use crate::source::TileCatalog;
-use crate::srv::config::{KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT, SrvConfig};
-use crate::srv::tiles::get_tile;
-use crate::srv::tiles_info::get_source_info;
+use crate::source::{CatalogLink, CatalogSourceEntry, TileCatalog, TileSources};
+use crate::srv::config::{
+    CORS_MAX_AGE_DEFAULT, CatalogSort, IndexConfig, KEEP_ALIVE_DEFAULT, LISTEN_ADDRESSES_DEFAULT,
+    MAX_PAYLOAD_SIZE_DEFAULT, ROBOTS_TXT_DEFAULT, SrvConfig,
+};
+use crate::srv::conn_limit::{ClientConnLimiter, limit_client_connections};
+use crate::srv::explain::get_explain;
+use crate::srv::health::{HealthStatus, get_health};
+use crate::srv::metrics::{TileMetrics, get_metrics};
+use crate::srv::refresh::{SourceTasks, post_refresh, post_reload};
+use crate::srv::status::get_status;
+use crate::srv::style::get_style_json;
+use crate::srv::tenants::{TenantRouting, is_tenant_source};
+use crate::srv::tiles::{get_quadkey_tile, get_tile};
+use crate::srv::tiles_info::{get_absolute_url, get_public_path, get_source_info};
 
 #[cfg(feature = "webui")]
 mod webui {
@@ -60,6 +74,182 @@ impl Catalog {
             fonts: state.fonts.get_catalog(),
         })
     }
+
+    /// Get a copy of the catalog with the tile sources in the given order
+    #[must_use]
+    pub fn sorted(&self, sort: CatalogSort) -> SortedCatalog {
+        let mut tiles: Vec<_> = self
+            .tiles
+            .iter()
+            .map(|v| (v.key().clone(), v.value().clone()))
+            .collect();
+        match sort {
+            CatalogSort::Id => tiles.sort_by(|(a, _), (b, _)| a.cmp(b)),
+            CatalogSort::Name => tiles.sort_by(|(a_id, a), (b_id, b)| {
+                let a_name = a.name.as_ref().unwrap_or(a_id);
+                let b_name = b.name.as_ref().unwrap_or(b_id);
+                (a_name, a_id).cmp(&(b_name, b_id))
+            }),
+            CatalogSort::Schema => tiles.sort_by(|(a_id, a), (b_id, b)| {
+                // sources without a schema are listed last
+                let a_schema = (a.schema.is_none(), &a.schema);
+                let b_schema = (b.schema.is_none(), &b.schema);
+                (a_schema, a_id).cmp(&(b_schema, b_id))
+            }),
+        }
+        SortedCatalog {
+            tiles,
+            #[cfg(feature = "sprites")]
+            sprites: self.sprites.clone(),
+            #[cfg(feature = "fonts")]
+            fonts: self.fonts.clone(),
+        }
+    }
+}
+
+/// Catalog served to the requests. Clones share the same catalog, so that replacing its tile sources
+/// after a reload is seen by all workers at once.
+#[derive(Debug, Clone, Default)]
+pub struct ServedCatalog(Arc<RwLock<Arc<Catalog>>>);
+
+impl ServedCatalog {
+    #[must_use]
+    pub fn new(catalog: Catalog) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(catalog))))
+    }
+
+    /// Current catalog, kept unchanged by a later replacement
+    #[must_use]
+    pub fn get(&self) -> Arc<Catalog> {
+        self.0.read().expect("catalog lock poisoned").clone()
+    }
+
+    /// Replace the tile sources of the catalog at once, e.g. after they were reloaded
+    pub fn replace_tiles(&self, tiles: TileCatalog) {
+        let mut catalog = self.0.write().expect("catalog lock poisoned");
+        *catalog = Arc::new(Catalog {
+            tiles,
+            ..(**catalog).clone()
+        });
+    }
+}
+
+/// Catalog serialized with the tile sources in a specific order
+#[serde_with::serde_as]
+#[derive(Debug, Serialize)]
+pub struct SortedCatalog {
+    #[serde_as(as = "serde_with::Map<_, _>")]
+    pub tiles: Vec<(String, CatalogSourceEntry)>,
+    #[cfg(feature = "sprites")]
+    pub sprites: crate::sprites::SpriteCatalog,
+    #[cfg(feature = "fonts")]
+    pub fonts: crate::fonts::FontCatalog,
+}
+
+/// Name of the catalog group of the sources without a configured group
+pub const CATALOG_DEFAULT_GROUP: &str = "default";
+
+/// Catalog with the tile sources grouped by their configured group, keeping the order within each group
+#[serde_with::serde_as]
+#[derive(Debug, Serialize)]
+pub struct GroupedCatalog {
+    #[serde_as(as = "serde_with::Map<_, serde_with::Map<_, _>>")]
+    pub tiles: Vec<(String, Vec<(String, CatalogSourceEntry)>)>,
+    #[cfg(feature = "sprites")]
+    pub sprites: crate::sprites::SpriteCatalog,
+    #[cfg(feature = "fonts")]
+    pub fonts: crate::fonts::FontCatalog,
+}
+
+/// Field to group the tile sources of the catalog by
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum CatalogGroupBy {
+    /// The `group` setting of each source
+    Group,
+}
+
+#[derive(Deserialize)]
+struct CatalogRequest {
+    sort: Option<CatalogSort>,
+    links: Option<bool>,
+    verbose: Option<bool>,
+    group_by: Option<CatalogGroupBy>,
+}
+
+impl SortedCatalog {
+    /// Group the tile sources by their group, sorted by the group name.
+    /// Sources without a group are listed last, in the [`CATALOG_DEFAULT_GROUP`] group.
+    #[must_use]
+    pub fn grouped(self) -> GroupedCatalog {
+        let mut groups: Vec<(String, Vec<_>)> = Vec::new();
+        for (id, entry) in self.tiles {
+            let group = entry.group.as_deref().unwrap_or(CATALOG_DEFAULT_GROUP);
+            if let Some((_, entries)) = groups.iter_mut().find(|(g, _)| g == group) {
+                entries.push((id, entry));
+            } else {
+                groups.push((group.to_string(), vec![(id, entry)]));
+            }
+        }
+        // stable sort keeps the order of the sources within each group
+        groups.sort_by(|(a, _), (b, _)| {
+            (a == CATALOG_DEFAULT_GROUP, a).cmp(&(b == CATALOG_DEFAULT_GROUP, b))
+        });
+        GroupedCatalog {
+            tiles: groups,
+            #[cfg(feature = "sprites")]
+            sprites: self.sprites,
+            #[cfg(feature = "fonts")]
+            fonts: self.fonts,
+        }
+    }
+
+    /// Add the effective configuration of each tile source that reports one
+    fn add_config(&mut self, sources: &TileSources) {
+        for (id, entry) in &mut self.tiles {
+            entry.config = sources
+                .get_source(id)
+                .ok()
+                .and_then(|src| src.get_catalog_config());
+        }
+    }
+
+    /// Add links to the `TileJSON` and the tiles of each tile source, relative to the catalog request
+    fn add_links(&mut self, req: &HttpRequest, srv_config: &SrvConfig) -> ActixResult<()> {
+        let base_path = if let Some(base_path) = &srv_config.base_path {
+            base_path.clone()
+        } else {
+            let path = get_public_path(req);
+            path.trim_end_matches('/')
+                .strip_suffix("/catalog")
+                .unwrap_or_default()
+                .to_string()
+        };
+        for (id, entry) in &mut self.tiles {
+            let mut links = vec![CatalogLink {
+                rel: "self".to_string(),
+                content_type: "application/json".to_string(),
+                href: get_absolute_url(req, format!("{base_path}/{id}"))?,
+                templated: None,
+            }];
+            // A configured public tiles URL takes precedence over the one derived from the request
+            let tiles_urls = match &srv_config.tiles_url {
+                Some(tiles_url) => tiles_url.get_tiles_urls(id, ""),
+                None => vec![get_absolute_url(
+                    req,
+                    format!("{base_path}/{id}/{{z}}/{{x}}/{{y}}"),
+                )?],
+            };
+            links.extend(tiles_urls.into_iter().map(|href| CatalogLink {
+                rel: "item".to_string(),
+                content_type: entry.content_type.clone(),
+                href,
+                templated: Some(true),
+            }));
+            entry.links = Some(links);
+        }
+        Ok(())
+    }
 }
 
 pub fn map_internal_error<T: std::fmt::Display>(e: T) -> actix_web::Error {
@@ -67,53 +257,117 @@ pub fn map_internal_error<T: std::fmt::Display>(e: T) -> actix_web::Error {
     ErrorInternalServerError(e.to_string())
 }
 
-/// Root path in case web front is disabled.
+/// Default response of the root path in case web front is disabled.
 #[cfg(not(feature = "webui"))]
-#[route("/", method = "GET", method = "HEAD")]
-#[allow(clippy::unused_async)]
-async fn get_index_no_ui() -> &'static str {
-    "Martin server is running. The WebUI feature was disabled at the compile time.\n\n\
+const INDEX_DEFAULT_MESSAGE: &str = "Martin server is running. The WebUI feature was disabled at the compile time.\n\n\
     A list of all available sources is at /catalog\n\n\
-    See documentation https://github.com/maplibre/martin"
-}
+    See documentation https://github.com/maplibre/martin";
 
-/// Root path in case web front is disabled and the WebUI feature is enabled.
+/// Default response of the root path in case web front is disabled and the WebUI feature is enabled.
 #[cfg(feature = "webui")]
-#[route("/", method = "GET", method = "HEAD")]
-#[allow(clippy::unused_async)]
-async fn get_index_ui_disabled() -> &'static str {
-    "Martin server is running.\n\n
+const INDEX_DEFAULT_MESSAGE: &str = "Martin server is running.\n\n
     The WebUI feature can be enabled with the --webui enable-for-all CLI flag or in the config file, making it available to all users.\n\n
     A list of all available sources is at /catalog\n\n\
-    See documentation https://github.com/maplibre/martin"
+    See documentation https://github.com/maplibre/martin";
+
+/// Root path in case web front is disabled, configured with the `index` setting.
+#[route("/", method = "GET", method = "HEAD")]
+#[allow(clippy::unused_async)]
+async fn get_index(srv_config: Data<SrvConfig>, catalog: Data<ServedCatalog>) -> HttpResponse {
+    match &srv_config.index {
+        None => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(INDEX_DEFAULT_MESSAGE),
+        Some(IndexConfig::Message(message)) => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(message.clone()),
+        Some(IndexConfig::Redirect(url)) => HttpResponse::Found()
+            .insert_header((LOCATION, url.as_str()))
+            .finish(),
+        Some(IndexConfig::Catalog) => {
+            let sort = srv_config.catalog_sort.unwrap_or_default();
+            HttpResponse::Ok().json(catalog.get().sorted(sort))
+        }
+    }
 }
 
-/// Return 200 OK if healthy. Used for readiness and liveness probes.
-#[route("/health", method = "GET", method = "HEAD")]
+/// Crawler rules. Tile URLs are expensive to crawl, so by default everything is disallowed.
+#[route("/robots.txt", method = "GET", method = "HEAD")]
 #[allow(clippy::unused_async)]
-async fn get_health() -> impl Responder {
+async fn get_robots_txt(srv_config: Data<SrvConfig>) -> HttpResponse {
+    let content = srv_config
+        .robots_txt
+        .as_deref()
+        .unwrap_or(ROBOTS_TXT_DEFAULT);
     HttpResponse::Ok()
-        .insert_header((CACHE_CONTROL, "no-cache"))
-        .message_body("OK")
+        .content_type(ContentType::plaintext())
+        .body(content.to_string())
 }
 
 #[route(
-    "/catalog",
+    "/catalog{trailing_slash:/?}",
     method = "GET",
     method = "HEAD",
     wrap = "middleware::Compress::default()"
 )]
 #[allow(clippy::unused_async)]
-async fn get_catalog(catalog: Data<Catalog>) -> impl Responder {
-    HttpResponse::Ok().json(catalog)
+async fn get_catalog(
+    req: HttpRequest,
+    catalog: Data<ServedCatalog>,
+    srv_config: Data<SrvConfig>,
+    sources: Data<TileSources>,
+    query: Query<CatalogRequest>,
+) -> ActixResult<HttpResponse> {
+    let sort = query.sort.or(srv_config.catalog_sort).unwrap_or_default();
+    let mut catalog = catalog.get().sorted(sort);
+    catalog.tiles.retain(|(id, _)| is_tenant_source(&req, id));
+    if query.links.unwrap_or_default() {
+        catalog.add_links(&req, &srv_config)?;
+    }
+    if query.verbose.unwrap_or_default() {
+        catalog.add_config(&sources);
+    }
+    Ok(match query.group_by {
+        Some(CatalogGroupBy::Group) => HttpResponse::Ok().json(catalog.grouped()),
+        None => HttpResponse::Ok().json(catalog),
+    })
 }
 
-pub fn router(cfg: &mut web::ServiceConfig, #[allow(unused_variables)] usr_cfg: &SrvConfig) {
+pub fn router(cfg: &mut web::ServiceConfig, usr_cfg: &SrvConfig) {
+    // Request bodies above the limit are rejected with 413 by the extractors of all endpoints
+    let max_payload_size = usr_cfg.max_payload_size.unwrap_or(MAX_PAYLOAD_SIZE_DEFAULT);
+    cfg.app_data(PayloadConfig::new(max_payload_size))
+        .app_data(JsonConfig::default().limit(max_payload_size));
+
+    // Registered before the source TileJSON, as `/metrics` would match `/{source_ids}` too
+    if usr_cfg.metrics.unwrap_or_default() {
+        cfg.service(get_metrics);
+    }
+
     cfg.service(get_health)
+        .service(get_status)
+        .service(get_robots_txt)
         .service(get_catalog)
         .service(get_source_info)
+        .service(get_quadkey_tile)
         .service(get_tile);
 
+    if usr_cfg.explain_token.is_some() {
+        cfg.service(get_explain);
+    }
+
+    if usr_cfg.refresh_token.is_some() {
+        cfg.service(post_refresh);
+    }
+
+    if usr_cfg.reload_token.is_some() {
+        cfg.service(post_reload);
+    }
+
+    if usr_cfg.style_json.unwrap_or_default() {
+        cfg.service(get_style_json);
+    }
+
     #[cfg(feature = "sprites")]
     cfg.service(crate::srv::sprites::get_sprite_sdf_json)
         .service(crate::srv::sprites::get_sprite_json)
@@ -133,19 +387,63 @@ pub fn router(cfg: &mut web::ServiceConfig, #[allow(unused_variables)] usr_cfg:
                 webui::generate(),
             ));
         } else {
-            cfg.service(get_index_ui_disabled);
+            cfg.service(get_index);
         }
     }
 
     #[cfg(not(feature = "webui"))]
-    cfg.service(get_index_no_ui);
+    cfg.service(get_index);
 }
 
 type Server = Pin<Box<dyn Future<Output = MartinResult<()>>>>;
 
+/// CORS middleware allowing `GET` requests from any origin.
+/// Browsers may cache preflight responses for `cors_max_age` seconds instead of repeating them for every request.
+fn cors(config: &SrvConfig) -> Cors {
+    Cors::default()
+        .allow_any_origin()
+        .allowed_methods(vec!["GET"])
+        .max_age(config.cors_max_age.unwrap_or(CORS_MAX_AGE_DEFAULT))
+}
+
 /// Create a future for an Actix web server together with the listening address.
 pub fn new_server(config: SrvConfig, state: ServerState) -> MartinResult<(Server, String)> {
-    let catalog = Catalog::new(&state)?;
+    // The catalog is shared by all workers, so that reloading the sources updates it everywhere
+    let catalog = ServedCatalog::new(Catalog::new(&state)?);
+
+    let health = HealthStatus::default();
+    let tasks = SourceTasks::default();
+    tasks.restart(
+        &state.tiles,
+        config.health_check.as_ref(),
+        &health,
+        &state.cache,
+    );
+
+    #[cfg(feature = "grpc")]
+    if let Some(address) = &config.grpc_listen_address {
+        let service = crate::srv::grpc::TilesService::new(
+            state.tiles.clone(),
+            state.cache.clone(),
+            config.clone(),
+        )
+        .with_tenants(state.tenants.clone());
+        crate::srv::grpc::spawn_grpc_server(address, service)?;
+        log::info!("Serving tiles with gRPC on {address}");
+    }
+
+    let conn_limiter = config.max_connections_per_ip.map(|max_per_ip| {
+        ClientConnLimiter::new(max_per_ip)
+            .with_trusted_proxies(config.trusted_proxies.clone().unwrap_or_default())
+    });
+    let metrics = config
+        .metrics
+        .unwrap_or_default()
+        .then(TileMetrics::default);
+    let tenant_routing = config
+        .tenant_header
+        .clone()
+        .map(|header| TenantRouting::new(header, state.tenants.clone()));
 
     let keep_alive = Duration::from_secs(config.keep_alive.unwrap_or(KEEP_ALIVE_DEFAULT));
     let worker_processes = config.worker_processes.unwrap_or_else(num_cpus::get);
@@ -155,9 +453,7 @@ pub fn new_server(config: SrvConfig, state: ServerState) -> MartinResult<(Server
         .unwrap_or_else(|| LISTEN_ADDRESSES_DEFAULT.to_string());
 
     let factory = move || {
-        let cors_middleware = Cors::default()
-            .allow_any_origin()
-            .allowed_methods(vec!["GET"]);
+        let cors_middleware = cors(&config);
 
         let app = App::new()
             .app_data(Data::new(state.tiles.clone()))
@@ -169,8 +465,35 @@ pub fn new_server(config: SrvConfig, state: ServerState) -> MartinResult<(Server
         #[cfg(feature = "fonts")]
         let app = app.app_data(Data::new(state.fonts.clone()));
 
+        let app = if let Some(limiter) = &conn_limiter {
+            app.app_data(Data::new(limiter.clone()))
+        } else {
+            app
+        };
+
+        let app = if let Some(routing) = &tenant_routing {
+            app.app_data(Data::new(routing.clone()))
+        } else {
+            app
+        };
+
+        let app = if let Some(metrics) = &metrics {
+            app.app_data(Data::new(metrics.clone()))
+        } else {
+            app
+        };
+
+        let app = if let Some(reloader) = &state.reloader {
+            app.app_data(Data::new(reloader.clone()))
+                .app_data(Data::new(tasks.clone()))
+        } else {
+            app
+        };
+
         app.app_data(Data::new(catalog.clone()))
             .app_data(Data::new(config.clone()))
+            .app_data(Data::new(health.clone()))
+            .wrap(middleware::from_fn(limit_client_connections))
             .wrap(cors_middleware)
             .wrap(middleware::NormalizePath::new(TrailingSlash::MergeOnly))
             .wrap(middleware::Logger::default())
@@ -197,6 +520,9 @@ pub fn new_server(config: SrvConfig, state: ServerState) -> MartinResult<(Server
 
 #[cfg(test)]
 pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::{ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN};
+    use actix_web::test::{TestRequest, call_service, init_service};
     use async_trait::async_trait;
     use martin_tile_utils::{Encoding, Format, TileCoord, TileInfo};
     use tilejson::TileJSON;
@@ -238,4 +564,174 @@ pub mod tests {
             Ok(self.data.clone())
         }
     }
+
+    #[test]
+    fn catalog_sort() {
+        let entry = |name: Option<&str>, schema: Option<&str>| CatalogSourceEntry {
+            name: name.map(ToString::to_string),
+            schema: schema.map(ToString::to_string),
+            ..Default::default()
+        };
+        let catalog = Catalog {
+            tiles: [
+                ("a", entry(Some("zebra"), Some("public"))),
+                ("b", entry(None, None)),
+                ("c", entry(Some("apple"), Some("osm"))),
+                ("d", entry(None, Some("public"))),
+            ]
+            .into_iter()
+            .map(|(id, v)| (id.to_string(), v))
+            .collect(),
+            ..Default::default()
+        };
+        let ids = |sort| {
+            catalog
+                .sorted(sort)
+                .tiles
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(CatalogSort::Id), ["a", "b", "c", "d"]);
+        assert_eq!(ids(CatalogSort::Name), ["c", "b", "d", "a"]);
+        assert_eq!(ids(CatalogSort::Schema), ["c", "a", "d", "b"]);
+
+        // the order is preserved in the serialized catalog
+        let json = serde_json::to_string(&catalog.sorted(CatalogSort::Name)).unwrap();
+        let pos = |id: &str| json.find(&format!("\"{id}\":")).unwrap();
+        assert!(pos("c") < pos("b") && pos("b") < pos("d") && pos("d") < pos("a"));
+    }
+
+    #[test]
+    fn catalog_grouped() {
+        let entry = |group: Option<&str>| CatalogSourceEntry {
+            group: group.map(ToString::to_string),
+            ..Default::default()
+        };
+        let catalog = Catalog {
+            tiles: [
+                ("a", entry(Some("transport"))),
+                ("b", entry(None)),
+                ("c", entry(Some("basemaps"))),
+                ("d", entry(Some("transport"))),
+                ("e", entry(Some("zoning"))),
+            ]
+            .into_iter()
+            .map(|(id, v)| (id.to_string(), v))
+            .collect(),
+            ..Default::default()
+        };
+        let grouped = catalog.sorted(CatalogSort::Id).grouped();
+        let groups: Vec<_> = grouped
+            .tiles
+            .iter()
+            .map(|(group, entries)| {
+                let ids: Vec<_> = entries.iter().map(|(id, _)| id.as_str()).collect();
+                (group.as_str(), ids)
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                ("basemaps", vec!["c"]),
+                ("transport", vec!["a", "d"]),
+                ("zoning", vec!["e"]),
+                (CATALOG_DEFAULT_GROUP, vec!["b"]),
+            ]
+        );
+
+        let json = serde_json::to_value(&grouped).unwrap();
+        assert_eq!(json["tiles"]["transport"]["d"]["group"], "transport");
+        assert!(json["tiles"]["default"]["b"].get("group").is_none());
+    }
+
+    #[test]
+    fn catalog_entry_freshness() {
+        let mut tj = tilejson::tilejson! { tiles: vec![] };
+        let src = |tj: &TileJSON| TestSource {
+            id: "id",
+            tj: tj.clone(),
+            data: Vec::default(),
+        };
+        let entry = src(&tj).get_catalog_entry();
+        assert_eq!((entry.version, entry.updated), (None, None));
+
+        tj.version = Some("1.2.3".to_string());
+        tj.other
+            .insert("updated".to_string(), "2024-01-31T12:00:00Z".into());
+        let entry = src(&tj).get_catalog_entry();
+        assert_eq!(entry.version.as_deref(), Some("1.2.3"));
+        assert_eq!(entry.updated.as_deref(), Some("2024-01-31T12:00:00Z"));
+    }
+
+    #[actix_rt::test]
+    async fn payload_size_limit() {
+        let srv_config = SrvConfig {
+            max_payload_size: Some(16),
+            ..Default::default()
+        };
+        let app = init_service(
+            App::new()
+                .route(
+                    "/bytes",
+                    web::post().to(|body: web::Bytes| async move { HttpResponse::Ok().body(body) }),
+                )
+                .route(
+                    "/json",
+                    web::post().to(|body: web::Json<serde_json::Value>| async move {
+                        HttpResponse::Ok().json(body.into_inner())
+                    }),
+                )
+                .configure(|c| router(c, &srv_config)),
+        )
+        .await;
+        let post = |path: &str, body: &'static str| {
+            TestRequest::post()
+                .uri(path)
+                .insert_header(ContentType::json())
+                .set_payload(body)
+                .to_request()
+        };
+
+        let small = r#"{"a":1}"#;
+        let large = r#"{"a":"0123456789abcdef"}"#;
+        for path in ["/bytes", "/json"] {
+            let response = call_service(&app, post(path, small)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let response = call_service(&app, post(path, large)).await;
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn cors_preflight_max_age() {
+        let max_age = |srv_config: SrvConfig| async move {
+            let app = init_service(
+                App::new()
+                    .wrap(cors(&srv_config))
+                    .route("/", web::get().to(|| async { HttpResponse::Ok().finish() })),
+            )
+            .await;
+            let req = TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/")
+                .insert_header((ORIGIN, "https://example.com"))
+                .insert_header((ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+                .to_request();
+            let response = call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            response
+                .headers()
+                .get(ACCESS_CONTROL_MAX_AGE)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        assert_eq!(max_age(SrvConfig::default()).await.as_deref(), Some("3600"));
+        let srv_config = SrvConfig {
+            cors_max_age: Some(600),
+            ..Default::default()
+        };
+        assert_eq!(max_age(srv_config).await.as_deref(), Some("600"));
+    }
 }
@@ -0,0 +1,68 @@
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::web::Data;
+use actix_web::{HttpResponse, route};
+use serde::Serialize;
+
+use crate::utils::{CacheStats, MainCache, OptMainCache};
+
+#[derive(Debug, Serialize)]
+struct ServerStatus {
+    /// Usage of the main cache, or `null` if caching is disabled
+    cache: Option<CacheStats>,
+}
+
+/// Current status of the server, e.g. the size and the hit rate of the tile cache, for monitoring.
+#[route("/status", method = "GET", method = "HEAD")]
+#[allow(clippy::unused_async)]
+async fn get_status(cache: Data<OptMainCache>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .json(ServerStatus {
+            cache: cache.get_ref().as_ref().map(MainCache::get_stats),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_and_read_body_json, init_service};
+    use martin_tile_utils::TileCoord;
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::utils::{CacheKey, CacheValue};
+
+    #[actix_rt::test]
+    async fn test_status() {
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(None::<MainCache>))
+                .service(get_status),
+        )
+        .await;
+        let req = TestRequest::get().uri("/status").to_request();
+        let status: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(status, json!({ "cache": null }));
+
+        let cache = MainCache::new(1000);
+        cache
+            .insert(
+                CacheKey::Tile("src".to_string(), TileCoord { z: 0, x: 0, y: 0 }),
+                CacheValue::Tile(vec![0; 10]),
+            )
+            .await;
+        assert!(cache.get(&CacheKey::PmtDirectory(0, 0)).await.is_none());
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(Some(cache)))
+                .service(get_status),
+        )
+        .await;
+        let req = TestRequest::get().uri("/status").to_request();
+        let status: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(status["cache"]["max_size_bytes"], 1000);
+        assert_eq!(status["cache"]["hits"], 0);
+        assert_eq!(status["cache"]["misses"], 1);
+        assert_eq!(status["cache"]["hit_rate"], 0.0);
+    }
+}
@@ -0,0 +1,235 @@
+use actix_web::error::ErrorBadRequest;
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, middleware, route};
+use martin_tile_utils::Format;
+use serde_json::{Value, json};
+
+use crate::source::TileSources;
+use crate::srv::SrvConfig;
+use crate::srv::tenants::check_tenant;
+use crate::srv::tiles_info::{SourceIDsRequest, get_absolute_url, get_public_path, merge_tilejson};
+
+/// Colors of the generated layers, one per vector layer in turn
+const LAYER_COLORS: &[&str] = &[
+    "#e41a1c", "#377eb8", "#4daf4a", "#984ea3", "#ff7f00", "#a65628", "#f781bf", "#999999",
+];
+
+/// A minimal `MapLibre` style of the vector sources, for a quick look at their data.
+/// Each vector layer of the merged `TileJSON` is drawn with a fill, a line, and a circle layer,
+/// each showing only the matching geometry type, as the geometry type of a layer is not known in advance.
+#[route(
+    "/{source_ids}/style.json",
+    method = "GET",
+    method = "HEAD",
+    wrap = "middleware::Compress::default()"
+)]
+#[allow(clippy::unused_async)]
+async fn get_style_json(
+    req: HttpRequest,
+    path: Path<SourceIDsRequest>,
+    sources: Data<TileSources>,
+    srv_config: Data<SrvConfig>,
+) -> ActixResult<HttpResponse> {
+    check_tenant(&req, &path.source_ids)?;
+    let (sources, _, info) = sources.get_sources(&path.source_ids, None)?;
+    if info.format != Format::Mvt {
+        return Err(ErrorBadRequest(format!(
+            "Source {} has no vector tiles, its tiles are {}",
+            path.source_ids, info.format
+        )));
+    }
+
+    let tilejson_path = if let Some(base_path) = &srv_config.base_path {
+        format!("{base_path}/{}", path.source_ids)
+    } else {
+        let path = get_public_path(&req);
+        path.strip_suffix("/style.json")
+            .unwrap_or_default()
+            .to_string()
+    };
+    let query_string = req.query_string();
+    let tilejson_url = get_absolute_url(
+        &req,
+        if query_string.is_empty() {
+            tilejson_path
+        } else {
+            format!("{tilejson_path}?{query_string}")
+        },
+    )?;
+
+    let bounds = srv_config.composite_bounds.unwrap_or_default();
+    let field_conflicts = srv_config.layer_field_conflicts.unwrap_or_default();
+    let tilejson = merge_tilejson(&sources, String::new(), bounds, field_conflicts)
+        .map_err(ErrorBadRequest)?;
+    let name = tilejson.name.unwrap_or_else(|| path.source_ids.clone());
+    let layer_ids = tilejson
+        .vector_layers
+        .iter()
+        .flatten()
+        .map(|v| v.id.as_str());
+    Ok(HttpResponse::Ok().json(style_json(
+        &name,
+        &path.source_ids,
+        &tilejson_url,
+        layer_ids,
+    )))
+}
+
+/// Build the style of a vector source with the given layers
+fn style_json<'a>(
+    name: &str,
+    source_id: &str,
+    tilejson_url: &str,
+    layer_ids: impl Iterator<Item = &'a str>,
+) -> Value {
+    let mut layers = vec![json!({
+        "id": "background",
+        "type": "background",
+        "paint": {"background-color": "#ffffff"},
+    })];
+    for (idx, layer_id) in layer_ids.enumerate() {
+        let color = LAYER_COLORS[idx % LAYER_COLORS.len()];
+        let layer = |suffix: &str, kind: &str, types: [&str; 2], paint: Value| {
+            json!({
+                "id": format!("{layer_id}_{suffix}"),
+                "type": kind,
+                "source": source_id,
+                "source-layer": layer_id,
+                "filter": ["match", ["geometry-type"], types, true, false],
+                "paint": paint,
+            })
+        };
+        layers.push(layer(
+            "fill",
+            "fill",
+            ["Polygon", "MultiPolygon"],
+            json!({"fill-color": color, "fill-opacity": 0.4, "fill-outline-color": color}),
+        ));
+        layers.push(layer(
+            "line",
+            "line",
+            ["LineString", "MultiLineString"],
+            json!({"line-color": color, "line-width": 1.5}),
+        ));
+        layers.push(layer(
+            "circle",
+            "circle",
+            ["Point", "MultiPoint"],
+            json!({"circle-color": color, "circle-radius": 3}),
+        ));
+    }
+
+    json!({
+        "version": 8,
+        "name": name,
+        "sources": {
+            source_id: {"type": "vector", "url": tilejson_url},
+        },
+        "layers": layers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_and_read_body_json, call_service, init_service};
+    use tilejson::{VectorLayer, tilejson};
+
+    use super::*;
+    use crate::srv::server::tests::TestSource;
+
+    #[actix_rt::test]
+    async fn test_style_json() {
+        let layer = |id: &str| VectorLayer::new(id.to_string(), BTreeMap::new());
+        let sources = TileSources::new(vec![vec![
+            Box::new(TestSource {
+                id: "roads",
+                tj: tilejson! {
+                    tiles: vec![],
+                    name: "Roads".to_string(),
+                    vector_layers: vec![layer("roads")],
+                },
+                data: Vec::default(),
+            }),
+            Box::new(TestSource {
+                id: "poi",
+                tj: tilejson! {
+                    tiles: vec![],
+                    vector_layers: vec![layer("poi"), layer("poi_labels")],
+                },
+                data: Vec::default(),
+            }),
+        ]]);
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(sources))
+                .app_data(Data::new(SrvConfig::default()))
+                .service(get_style_json),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/roads/style.json").to_request();
+        let style: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(style["version"], 8);
+        assert_eq!(style["name"], "Roads");
+        assert_eq!(
+            style["sources"],
+            json!({"roads": {"type": "vector", "url": "http://localhost:8080/roads"}})
+        );
+        let layers = style["layers"].as_array().unwrap();
+        assert_eq!(layers.len(), 4);
+        assert_eq!(layers[0]["type"], "background");
+        assert_eq!(
+            layers[1],
+            json!({
+                "id": "roads_fill",
+                "type": "fill",
+                "source": "roads",
+                "source-layer": "roads",
+                "filter": ["match", ["geometry-type"], ["Polygon", "MultiPolygon"], true, false],
+                "paint": {"fill-color": "#e41a1c", "fill-opacity": 0.4, "fill-outline-color": "#e41a1c"},
+            })
+        );
+        assert_eq!(layers[2]["type"], "line");
+        assert_eq!(layers[3]["type"], "circle");
+        assert_eq!(layers[3]["paint"]["circle-color"], "#e41a1c");
+
+        // composite sources have the layers of all their sources, and keep the query of the request
+        let req = TestRequest::get()
+            .uri("/roads,poi/style.json?date=2024")
+            .to_request();
+        let style: Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            style["sources"]["roads,poi"]["url"],
+            "http://localhost:8080/roads,poi?date=2024"
+        );
+        let ids: Vec<&str> = style["layers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|v| v["source-layer"].as_str())
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "roads",
+                "roads",
+                "roads",
+                "poi",
+                "poi",
+                "poi",
+                "poi_labels",
+                "poi_labels",
+                "poi_labels"
+            ]
+        );
+        assert_eq!(style["layers"][4]["paint"]["fill-color"], "#377eb8");
+
+        let req = TestRequest::get().uri("/missing/style.json").to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use actix_web::error::ErrorNotFound;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, Result as ActixResult};
+
+use crate::source::TileInfoSource;
+
+/// Tenant of each source that belongs to one, by source ID.
+/// Sources without a tenant are shared by all tenants. Clones share the same tenants.
+#[derive(Clone, Debug, Default)]
+pub struct Tenants(Arc<RwLock<Arc<HashMap<String, String>>>>);
+
+impl Tenants {
+    /// Make the given sources available only to the requests of the tenant
+    pub fn add(&mut self, tenant: &str, sources: &[TileInfoSource]) {
+        let mut tenants = self.0.write().expect("tenants lock poisoned");
+        let tenants = Arc::make_mut(&mut tenants);
+        for src in sources {
+            tenants.insert(src.get_id().to_string(), tenant.to_string());
+        }
+    }
+
+    /// Current tenants, kept unchanged by a later replacement
+    fn current(&self) -> Arc<HashMap<String, String>> {
+        self.0.read().expect("tenants lock poisoned").clone()
+    }
+
+    /// Add the tenants of other sources at once, replacing the tenant of the sources already listed
+    pub fn extend(&self, tenants: &Self) {
+        let other = tenants.current();
+        let mut tenants = self.0.write().expect("tenants lock poisoned");
+        Arc::make_mut(&mut tenants).extend(other.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    /// Replace the tenants of all sources with the given ones at once
+    pub fn replace(&self, tenants: &Self) {
+        let tenants = tenants.current();
+        *self.0.write().expect("tenants lock poisoned") = tenants;
+    }
+
+    /// Whether the source may be used by the requests of the given tenant, or by requests without a tenant
+    #[must_use]
+    pub fn is_available(&self, tenant: Option<&str>, source_id: &str) -> bool {
+        is_available(&self.current(), tenant, source_id)
+    }
+
+    /// Check that all requested sources are available to the tenant. The sources of other tenants
+    /// are reported the same way as missing sources, so that their IDs are not revealed.
+    pub fn check(&self, tenant: Option<&str>, source_ids: &str) -> ActixResult<()> {
+        let tenants = self.current();
+        match source_ids
+            .split(',')
+            .find(|id| !is_available(&tenants, tenant, id))
+        {
+            Some(id) => Err(ErrorNotFound(format!("Source {id} does not exist"))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_available(tenants: &HashMap<String, String>, tenant: Option<&str>, source_id: &str) -> bool {
+    tenants
+        .get(source_id)
+        .is_none_or(|v| Some(v.as_str()) == tenant)
+}
+
+/// Tenant selection of the requests, registered as app data if the `tenant_header` is configured
+#[derive(Clone, Debug)]
+pub struct TenantRouting {
+    header: String,
+    tenants: Tenants,
+}
+
+impl TenantRouting {
+    #[must_use]
+    pub fn new(header: String, tenants: Tenants) -> Self {
+        Self { header, tenants }
+    }
+
+    fn get_tenant<'a>(&self, req: &'a HttpRequest) -> Option<&'a str> {
+        req.headers()
+            .get(&self.header)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+    }
+}
+
+/// Check that the requested sources are available to the tenant selected by the request header.
+/// All sources are available if multi-tenancy is not enabled.
+pub fn check_tenant(req: &HttpRequest, source_ids: &str) -> ActixResult<()> {
+    match req.app_data::<Data<TenantRouting>>() {
+        Some(routing) => routing.tenants.check(routing.get_tenant(req), source_ids),
+        None => Ok(()),
+    }
+}
+
+/// Whether the source is available to the tenant selected by the request header, see [`check_tenant`]
+#[must_use]
+pub fn is_tenant_source(req: &HttpRequest, source_id: &str) -> bool {
+    req.app_data::<Data<TenantRouting>>().is_none_or(|routing| {
+        routing
+            .tenants
+            .is_available(routing.get_tenant(req), source_id)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_and_read_body_json, call_service, init_service};
+    use serde_json::Value;
+    use tilejson::tilejson;
+
+    use super::*;
+    use crate::NO_MAIN_CACHE;
+    use crate::source::TileSources;
+    use crate::srv::server::tests::TestSource;
+    use crate::srv::{Catalog, ServedCatalog, SrvConfig, router};
+
+    fn source(id: &'static str) -> TileInfoSource {
+        Box::new(TestSource {
+            id,
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        })
+    }
+
+    #[test]
+    fn tenant_sources() {
+        let mut tenants = Tenants::default();
+        tenants.add("acme", &[source("acme_roads")]);
+        tenants.add("globex", &[source("globex_roads")]);
+
+        assert!(tenants.check(Some("acme"), "acme_roads").is_ok());
+        assert!(tenants.check(Some("acme"), "acme_roads,basemap").is_ok());
+        assert!(tenants.check(Some("acme"), "globex_roads").is_err());
+        assert!(
+            tenants
+                .check(Some("acme"), "acme_roads,globex_roads")
+                .is_err()
+        );
+        assert!(tenants.check(None, "acme_roads").is_err());
+        assert!(tenants.check(Some("unknown"), "acme_roads").is_err());
+        // shared sources are available to all requests
+        assert!(tenants.check(None, "basemap").is_ok());
+        assert!(tenants.check(Some("globex"), "basemap").is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn tenant_isolation() {
+        let acme = vec![source("acme_roads")];
+        let globex = vec![source("globex_roads")];
+        let mut tenants = Tenants::default();
+        tenants.add("acme", &acme);
+        tenants.add("globex", &globex);
+        let sources = TileSources::new(vec![acme, globex, vec![source("basemap")]]);
+        let catalog = Catalog {
+            tiles: sources.get_catalog(),
+            ..Default::default()
+        };
+        let srv_config = SrvConfig::default();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(sources))
+                .app_data(Data::new(ServedCatalog::new(catalog)))
+                .app_data(Data::new(srv_config.clone()))
+                .app_data(Data::new(NO_MAIN_CACHE))
+                .app_data(Data::new(TenantRouting::new(
+                    "x-tenant".to_string(),
+                    tenants,
+                )))
+                .configure(|c| router(c, &srv_config)),
+        )
+        .await;
+        let get = |path: &str, tenant: Option<&str>| {
+            let req = TestRequest::get().uri(path);
+            match tenant {
+                Some(tenant) => req.insert_header(("x-tenant", tenant)),
+                None => req,
+            }
+            .to_request()
+        };
+
+        for (path, tenant, status) in [
+            ("/acme_roads/0/0/0", Some("acme"), StatusCode::OK),
+            ("/acme_roads", Some("acme"), StatusCode::OK),
+            ("/acme_roads,basemap/0/0/0", Some("acme"), StatusCode::OK),
+            ("/basemap/0/0/0", None, StatusCode::OK),
+            ("/acme_roads/0/0/0", None, StatusCode::NOT_FOUND),
+            ("/acme_roads/0/0/0", Some("globex"), StatusCode::NOT_FOUND),
+            ("/acme_roads", Some("globex"), StatusCode::NOT_FOUND),
+            (
+                "/acme_roads,globex_roads/0/0/0",
+                Some("acme"),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                "/globex_roads/quadkey/0",
+                Some("acme"),
+                StatusCode::NOT_FOUND,
+            ),
+            ("/globex_roads/quadkey/0", Some("globex"), StatusCode::OK),
+        ] {
+            let response = call_service(&app, get(path, tenant)).await;
+            assert_eq!(response.status(), status, "{path} of tenant {tenant:?}");
+        }
+
+        // the catalog only lists the sources of the tenant, and the shared sources
+        for (tenant, expected) in [
+            (Some("acme"), vec!["acme_roads", "basemap"]),
+            (Some("globex"), vec!["basemap", "globex_roads"]),
+            (None, vec!["basemap"]),
+        ] {
+            let catalog: Value = call_and_read_body_json(&app, get("/catalog", tenant)).await;
+            let mut ids: Vec<&str> = catalog["tiles"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect();
+            ids.sort_unstable();
+            assert_eq!(ids, expected);
+        }
+    }
+}
@@ -1,25 +1,41 @@
+use std::time::Duration;
+
 use actix_http::ContentEncoding;
 use actix_http::header::Quality;
-use actix_web::error::{ErrorBadRequest, ErrorNotAcceptable, ErrorNotFound};
+use actix_web::error::{
+    ErrorBadRequest, ErrorGatewayTimeout, ErrorNotAcceptable, ErrorNotImplemented,
+    ErrorServiceUnavailable,
+};
 use actix_web::http::header::{
-    AcceptEncoding, CONTENT_ENCODING, Encoding as HeaderEnc, Preference,
+    AcceptEncoding, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING, ContentDisposition,
+    ContentType, ETAG, ETag, Encoding as HeaderEnc, EntityTag, HeaderName, HeaderValue,
+    IfNoneMatch, Preference, TryIntoHeaderValue as _,
 };
+use actix_web::http::{Method, StatusCode};
 use actix_web::web::{Data, Path, Query};
 use actix_web::{HttpMessage, HttpRequest, HttpResponse, Result as ActixResult, route};
 use futures::future::try_join_all;
-use log::trace;
+use log::{log, trace, warn};
 use martin_tile_utils::{
-    Encoding, Format, TileCoord, TileInfo, decode_brotli, decode_gzip, encode_brotli, encode_gzip,
+    Encoding, Format, MAX_ZOOM, TileCoord, TileInfo, count_mvt_features, decode_brotli,
+    decode_gzip, encode_brotli, encode_gzip,
 };
 use serde::Deserialize;
+use tokio::time::{Instant, timeout_at};
+use xxhash_rust::xxh3::xxh3_64;
 
+use crate::MartinError::{SourceBusy, TileBudgetExceeded};
 use crate::args::PreferredEncoding;
-use crate::source::{TileInfoSources, TileSources, UrlQuery};
-use crate::srv::SrvConfig;
+use crate::source::{
+    TILE_SIZES, TileInfoSource, TileInfoSources, TileScheme, TileSources, UrlQuery,
+};
+use crate::srv::explain::bearer_token;
 use crate::srv::server::map_internal_error;
+use crate::srv::tenants::check_tenant;
+use crate::srv::{OutOfZoomResponse, SrvConfig, TileMetrics, check_tile_access};
 use crate::utils::cache::get_or_insert_cached_value;
 use crate::utils::{CacheKey, CacheValue, MainCache, OptMainCache};
-use crate::{Tile, TileData};
+use crate::{MartinResult, Tile, TileData};
 
 static SUPPORTED_ENC: &[HeaderEnc] = &[
     HeaderEnc::gzip(),
@@ -35,6 +51,12 @@ pub struct TileRequest {
     y: u32,
 }
 
+#[derive(Deserialize, Clone)]
+pub struct QuadkeyTileRequest {
+    source_ids: String,
+    quadkey: String,
+}
+
 #[route("/{source_ids}/{z}/{x}/{y}", method = "GET", method = "HEAD")]
 async fn get_tile(
     req: HttpRequest,
@@ -43,32 +65,280 @@ async fn get_tile(
     sources: Data<TileSources>,
     cache: Data<OptMainCache>,
 ) -> ActixResult<HttpResponse> {
-    let src = DynTileSource::new(
-        sources.as_ref(),
+    let xyz = TileCoord {
+        z: path.z,
+        x: path.x,
+        y: path.y,
+    };
+    get_tile_response(
+        &req,
+        &srv_config,
         &path.source_ids,
-        Some(path.z),
+        xyz,
+        None,
+        &sources,
+        &cache,
+    )
+    .await
+}
+
+/// Tiles addressed by a Bing Maps quadkey instead of `z/x/y`
+#[route("/{source_ids}/quadkey/{quadkey}", method = "GET", method = "HEAD")]
+async fn get_quadkey_tile(
+    req: HttpRequest,
+    srv_config: Data<SrvConfig>,
+    path: Path<QuadkeyTileRequest>,
+    sources: Data<TileSources>,
+    cache: Data<OptMainCache>,
+) -> ActixResult<HttpResponse> {
+    let xyz = TileCoord::from_quadkey(&path.quadkey).ok_or_else(|| {
+        ErrorBadRequest(format!(
+            "Invalid quadkey {}, must only contain the digits 0 to 3, one per zoom level up to {MAX_ZOOM}",
+            path.quadkey
+        ))
+    })?;
+    // Quadkeys always address tiles from the top of the map, whatever the scheme of the sources
+    get_tile_response(
+        &req,
+        &srv_config,
+        &path.source_ids,
+        xyz,
+        Some(TileScheme::Xyz),
+        &sources,
+        &cache,
+    )
+    .await
+}
+
+/// Get the tile response of the coordinates in the given scheme, or in the scheme of the sources if `None`,
+/// and record it in the metrics if they are enabled
+async fn get_tile_response(
+    req: &HttpRequest,
+    srv_config: &SrvConfig,
+    source_ids: &str,
+    xyz: TileCoord,
+    scheme: Option<TileScheme>,
+    sources: &TileSources,
+    cache: &OptMainCache,
+) -> ActixResult<HttpResponse> {
+    let start = Instant::now();
+    let response = tile_response(req, srv_config, source_ids, xyz, scheme, sources, cache).await;
+    if let Some(metrics) = req.app_data::<Data<TileMetrics>>() {
+        let (status, is_empty) = match &response {
+            Ok(v) => (
+                v.status(),
+                v.headers()
+                    .get(X_TILE_STATUS)
+                    .is_some_and(|v| v == TILE_STATUS_EMPTY),
+            ),
+            Err(e) => (e.as_response_error().status_code(), false),
+        };
+        metrics.record(sources, source_ids, status, is_empty, start.elapsed());
+    }
+    response
+}
+
+#[allow(clippy::too_many_lines)]
+async fn tile_response(
+    req: &HttpRequest,
+    srv_config: &SrvConfig,
+    source_ids: &str,
+    xyz: TileCoord,
+    scheme: Option<TileScheme>,
+    sources: &TileSources,
+    cache: &OptMainCache,
+) -> ActixResult<HttpResponse> {
+    // Tiles of the sources read below are not cached once the cache was invalidated after a reload
+    let cache_generation = cache.as_ref().map_or(0, MainCache::generation);
+    // All reads of the request use the same sources, even if they are reloaded meanwhile
+    let sources = &sources.snapshot();
+    // Tile coordinates and envelopes are only accurate up to this zoom, whatever the zoom range of the sources
+    if xyz.z > MAX_ZOOM {
+        return Err(ErrorBadRequest(format!(
+            "Invalid zoom level {}, must not be above {MAX_ZOOM}",
+            xyz.z
+        )));
+    }
+    check_tenant(req, source_ids)?;
+    let scheme = match scheme {
+        Some(scheme) => scheme,
+        None => sources.get_scheme(source_ids)?,
+    };
+    if let Some(tile_access) = &srv_config.tile_access {
+        let key = bearer_token(req);
+        check_tile_access(tile_access, key, sources, source_ids, xyz, scheme)?;
+    }
+    let start = Instant::now();
+    let src = DynTileSource::new(
+        sources,
+        source_ids,
+        Some(xyz.z),
         req.query_string(),
         req.get_header::<AcceptEncoding>(),
         srv_config.preferred_encoding,
-        cache.as_ref().as_ref(),
-    )?;
+        cache.as_ref(),
+    )?
+    .with_scheme(scheme)
+    .with_cache_generation(cache_generation)
+    .with_retries(
+        srv_config.tile_retries.unwrap_or_default(),
+        srv_config
+            .tile_budget_ms
+            .map(|v| start + Duration::from_millis(v)),
+    );
 
-    src.get_http_response(TileCoord {
-        z: path.z,
-        x: path.x,
-        y: path.y,
-    })
-    .await
+    // All sources exist, but none of them has tiles at this zoom
+    if src.sources.is_empty() {
+        let status = (X_TILE_STATUS, TILE_STATUS_OUT_OF_RANGE);
+        return Ok(match srv_config.out_of_zoom.unwrap_or_default() {
+            OutOfZoomResponse::NotFound => HttpResponse::NotFound()
+                .content_type(ContentType::plaintext())
+                .insert_header(status)
+                .body(format!("No valid sources found at zoom {}", xyz.z)),
+            OutOfZoomResponse::NoContent => {
+                HttpResponse::NoContent().insert_header(status).finish()
+            }
+        });
+    }
+
+    let tile = src.get_tile_content(xyz).await?;
+    // HEAD requests may report the number of features without downloading the tile
+    let feature_count = if req.method() == Method::HEAD {
+        src.count_features(&tile)?
+    } else {
+        None
+    };
+    let mut response = tile_http_response(tile);
+
+    // Tells clients and caches apart an empty tile from a zoom level without any tiles
+    let status = if response.status() == StatusCode::NO_CONTENT {
+        TILE_STATUS_EMPTY
+    } else {
+        TILE_STATUS_OK
+    };
+    response
+        .headers_mut()
+        .insert(X_TILE_STATUS, HeaderValue::from_static(status));
+
+    // Tiles of versioned sources never change, so they may be cached forever
+    if src.sources.iter().all(|s| s.is_immutable()) {
+        response.headers_mut().insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static(CACHE_CONTROL_IMMUTABLE),
+        );
+    }
+
+    // Browsers save non-MVT tiles, e.g. images, as a file instead of displaying them with `?download=1`
+    if src.info.format != Format::Mvt && is_download(req.query_string()) {
+        let filename = format!(
+            "{}_{}_{}_{}.{}",
+            source_ids.replace(',', "_"),
+            xyz.z,
+            xyz.x,
+            xyz.y,
+            src.info.format.metadata_format_value()
+        );
+        let value = ContentDisposition::attachment(filename)
+            .try_into_value()
+            .map_err(map_internal_error)?;
+        response.headers_mut().insert(CONTENT_DISPOSITION, value);
+    }
+
+    if srv_config.noindex_tiles.unwrap_or_default() {
+        response
+            .headers_mut()
+            .insert(X_ROBOTS_TAG, HeaderValue::from_static("noindex"));
+    }
+
+    // All sources use the same grid, so the bounds can be checked against the server's coordinate math
+    if srv_config.tile_bounds_header.unwrap_or_default() {
+        let grid = src.sources[0].get_tile_grid();
+        if let Some([west, south, east, north]) = src
+            .scheme
+            .to_xyz(xyz)
+            .filter(|v| grid.is_valid_tile(*v))
+            .and_then(|v| grid.wgs84_envelope(v))
+        {
+            let value = HeaderValue::try_from(format!("{west},{south},{east},{north}"))
+                .map_err(map_internal_error)?;
+            response.headers_mut().insert(X_TILE_BOUNDS, value);
+        }
+    }
+
+    // Clients and caches that already have the tile revalidate it without downloading it again
+    if is_not_modified(req, &response) {
+        let mut not_modified = HttpResponse::NotModified();
+        for name in [ETAG, CACHE_CONTROL, X_TILE_STATUS] {
+            if let Some(value) = response.headers().get(&name) {
+                not_modified.insert_header((name, value.clone()));
+            }
+        }
+        return Ok(not_modified.finish());
+    }
+
+    if let Some(count) = feature_count {
+        response
+            .headers_mut()
+            .insert(X_FEATURE_COUNT, HeaderValue::from(count));
+    }
+
+    Ok(response)
 }
 
+/// Whether the `If-None-Match` header of the request matches the `ETag` of the tile response
+fn is_not_modified(req: &HttpRequest, response: &HttpResponse) -> bool {
+    let Some(etag) = response
+        .headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<EntityTag>().ok())
+    else {
+        return false;
+    };
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|v| v.weak_eq(&etag)),
+        None => false,
+    }
+}
+
+/// `Cache-Control` header value of tiles from immutable sources, cached for a year
+pub const CACHE_CONTROL_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Tells search engines not to index tiles, if enabled with `noindex_tiles`
+pub const X_ROBOTS_TAG: HeaderName = HeaderName::from_static("x-robots-tag");
+
+/// WGS84 bounds `west,south,east,north` of the requested tile, if enabled with `tile_bounds_header`
+pub const X_TILE_BOUNDS: HeaderName = HeaderName::from_static("x-tile-bounds");
+
+/// Number of features in a tile, reported by `HEAD` requests if supported by all sources
+pub const X_FEATURE_COUNT: HeaderName = HeaderName::from_static("x-feature-count");
+
+/// Whether the tile has data (`ok`), has no data (`empty`), or none of its sources has tiles at its zoom (`out-of-range`)
+pub const X_TILE_STATUS: HeaderName = HeaderName::from_static("x-tile-status");
+pub const TILE_STATUS_OK: &str = "ok";
+pub const TILE_STATUS_EMPTY: &str = "empty";
+pub const TILE_STATUS_OUT_OF_RANGE: &str = "out-of-range";
+
 pub struct DynTileSource<'a> {
     pub sources: TileInfoSources,
     pub info: TileInfo,
-    pub query_str: Option<&'a str>,
+    /// Tile addressing scheme of the requests, see [`TileScheme::composite`]
+    pub scheme: TileScheme,
+    /// URL query string of the cache keys, with the parameters in a canonical order, see [`canonical_query`]
+    pub query_str: Option<String>,
     pub query_obj: Option<UrlQuery>,
     pub accept_enc: Option<AcceptEncoding>,
     pub preferred_enc: Option<PreferredEncoding>,
+    /// Whether uncompressed tiles are compressed for the client, unless all sources opted out
+    pub compress: bool,
     pub cache: Option<&'a MainCache>,
+    /// Generation of the cache before the sources were read, see [`MainCache::insert_if_current`]
+    pub cache_generation: u64,
+    /// Number of times a failed source tile request is retried
+    pub retries: u32,
+    /// Time by which all tile requests, including retries, must complete
+    pub deadline: Option<Instant>,
 }
 
 impl<'a> DynTileSource<'a> {
@@ -81,63 +351,134 @@ impl<'a> DynTileSource<'a> {
         preferred_enc: Option<PreferredEncoding>,
         cache: Option<&'a MainCache>,
     ) -> ActixResult<Self> {
-        let (sources, use_url_query, info) = sources.get_sources(source_ids, zoom)?;
+        let cache_generation = cache.map_or(0, MainCache::generation);
+        let snapshot = sources.snapshot();
+        let scheme = snapshot.get_scheme(source_ids);
+        // report all invalid sources together, including the ones with a different scheme
+        let (sources, use_url_query, info) = snapshot.get_sources(source_ids, zoom)?;
+        let scheme = scheme?;
 
-        if sources.is_empty() {
-            return Err(ErrorNotFound("No valid sources found"));
+        if has_query_param(query, "fields") {
+            if let Some(src) = sources.iter().find(|s| !s.supports_fields_filter()) {
+                return Err(ErrorNotImplemented(format!(
+                    "Source {} does not support the fields parameter",
+                    src.get_id()
+                )));
+            }
+        }
+
+        if let Some(tile_size) = get_query_param(query, "tile_size") {
+            if !TILE_SIZES.iter().any(|v| v.to_string() == tile_size) {
+                return Err(ErrorBadRequest(format!(
+                    "Invalid tile_size {tile_size}, must be one of {TILE_SIZES:?}"
+                )));
+            }
+            if let Some(src) = sources.iter().find(|s| !s.supports_tile_size()) {
+                return Err(ErrorNotImplemented(format!(
+                    "Source {} does not support the tile_size parameter",
+                    src.get_id()
+                )));
+            }
         }
 
         let mut query_obj = None;
         let mut query_str = None;
         if use_url_query && !query.is_empty() {
             query_obj = Some(Query::<UrlQuery>::from_query(query)?.into_inner());
+            query_str = Some(canonical_query(query));
+        } else if let Some(query) = known_query(query, &sources) {
+            // invalid values are ignored by the sources, so they must not fail the request
+            query_obj = Query::<UrlQuery>::from_query(&query)
+                .ok()
+                .map(Query::into_inner);
             query_str = Some(query);
         }
 
+        let compress = sources.iter().any(|s| s.is_compressible());
+
         Ok(Self {
             sources,
             info,
+            scheme,
             query_str,
             query_obj,
             accept_enc,
             preferred_enc,
+            compress,
             cache,
+            cache_generation,
+            retries: 0,
+            deadline: None,
         })
     }
 
-    pub async fn get_http_response(&self, xyz: TileCoord) -> ActixResult<HttpResponse> {
-        let tile = self.get_tile_content(xyz).await?;
+    /// Use the given scheme for the tile coordinates of the requests instead of the scheme of the sources,
+    /// e.g. for coordinates that are always in the XYZ scheme
+    #[must_use]
+    pub fn with_scheme(self, scheme: TileScheme) -> Self {
+        Self { scheme, ..self }
+    }
 
-        Ok(if tile.data.is_empty() {
-            HttpResponse::NoContent().finish()
-        } else {
-            let mut response = HttpResponse::Ok();
-            response.content_type(tile.info.format.content_type());
-            if let Some(val) = tile.info.encoding.content_encoding() {
-                response.insert_header((CONTENT_ENCODING, val));
-            }
-            response.body(tile.data)
-        })
+    /// Use the generation of the cache from before the given sources were read, if they were read earlier
+    #[must_use]
+    pub fn with_cache_generation(self, cache_generation: u64) -> Self {
+        Self {
+            cache_generation,
+            ..self
+        }
+    }
+
+    /// Retry failed source tile requests up to `retries` times, as long as the `deadline` has not passed.
+    /// Tile requests still in progress at the deadline fail with `504 Gateway Timeout`.
+    #[must_use]
+    pub fn with_retries(self, retries: u32, deadline: Option<Instant>) -> Self {
+        Self {
+            retries,
+            deadline,
+            ..self
+        }
+    }
+
+    pub async fn get_http_response(&self, xyz: TileCoord) -> ActixResult<HttpResponse> {
+        Ok(tile_http_response(self.get_tile_content(xyz).await?))
     }
 
     pub async fn get_tile_content(&self, xyz: TileCoord) -> ActixResult<Tile> {
-        let mut tiles = try_join_all(self.sources.iter().map(|s| async {
-            get_or_insert_cached_value!(
-                self.cache,
-                CacheValue::Tile,
-                s.get_tile(xyz, self.query_obj.as_ref()),
-                {
-                    let id = s.get_id().to_string();
-                    if let Some(query_str) = self.query_str {
-                        CacheKey::TileWithQuery(id, xyz, query_str.to_string())
-                    } else {
-                        CacheKey::Tile(id, xyz)
+        // sources always receive tile coordinates in the XYZ scheme
+        let requests = self
+            .sources
+            .iter()
+            .map(|s| {
+                self.scheme
+                    .to_xyz(xyz)
+                    .filter(|v| s.get_tile_grid().is_valid_tile(*v))
+                    .map(|xyz| (s, xyz))
+                    .ok_or_else(|| {
+                        ErrorBadRequest(format!(
+                            "Invalid tile coordinates {xyz:#} for source {}",
+                            s.get_id()
+                        ))
+                    })
+            })
+            .collect::<ActixResult<Vec<_>>>()?;
+        // `actix_web::Error` is not `Send`, so the errors are only converted once all tiles were awaited,
+        // allowing the gRPC service to await the tiles as well
+        let mut tiles = try_join_all(requests.into_iter().map(|(s, xyz)| async move {
+            self.get_source_tile(s, xyz).await.map_err(|e| {
+                let to_error: fn(String) -> actix_web::Error = match e {
+                    TileBudgetExceeded(_) => ErrorGatewayTimeout,
+                    #[cfg(feature = "postgres")]
+                    crate::MartinError::PostgresError(crate::pg::PgError::StatementTimeout(..)) => {
+                        ErrorGatewayTimeout
                     }
-                }
-            )
+                    SourceBusy(_) => ErrorServiceUnavailable,
+                    _ => map_internal_error,
+                };
+                (to_error, e.to_string())
+            })
         }))
         .await
-        .map_err(map_internal_error)?;
+        .map_err(|(to_error, message)| to_error(message))?;
 
         let mut layer_count = 0;
         let mut last_non_empty_layer = 0;
@@ -173,6 +514,91 @@ impl<'a> DynTileSource<'a> {
         self.recompress(data)
     }
 
+    /// Count the features in all layers of the tile of these sources,
+    /// or `None` if any of the sources does not support counting them, or the tile is not a valid MVT tile.
+    pub fn count_features(&self, tile: &Tile) -> ActixResult<Option<u64>> {
+        if tile.info.format != Format::Mvt
+            || !self.sources.iter().all(|s| s.supports_feature_count())
+        {
+            return Ok(None);
+        }
+        Ok(match tile.info.encoding {
+            Encoding::Uncompressed => count_mvt_features(&tile.data),
+            Encoding::Gzip => count_mvt_features(&decode_gzip(&tile.data)?),
+            Encoding::Brotli => count_mvt_features(&decode_brotli(&tile.data)?),
+            _ => None,
+        })
+    }
+
+    async fn get_source_tile(
+        &self,
+        src: &TileInfoSource,
+        xyz: TileCoord,
+    ) -> MartinResult<TileData> {
+        // Tiles of too many distinct query strings are still served, but not cached
+        let cache = match self.query_str.as_deref() {
+            Some(query_str) => self
+                .cache
+                .filter(|cache| cache.accepts_query(src.get_id(), query_str)),
+            None => self.cache,
+        };
+        get_or_insert_cached_value!(
+            cache,
+            self.cache_generation,
+            CacheValue::Tile,
+            self.get_tile_with_retries(src, xyz),
+            {
+                let id = src.get_id().to_string();
+                if let Some(query_str) = &self.query_str {
+                    CacheKey::TileWithQuery(id, xyz, query_str.clone())
+                } else {
+                    CacheKey::Tile(id, xyz)
+                }
+            }
+        )
+    }
+
+    async fn get_tile_with_retries(
+        &self,
+        src: &TileInfoSource,
+        xyz: TileCoord,
+    ) -> MartinResult<TileData> {
+        let start = Instant::now();
+        let mut attempt = 0;
+        loop {
+            let tile = src.get_tile(xyz, self.query_obj.as_ref());
+            let result = if let Some(deadline) = self.deadline {
+                timeout_at(deadline, tile)
+                    .await
+                    .unwrap_or_else(|_| Err(TileBudgetExceeded(src.get_id().to_string())))
+            } else {
+                tile.await
+            };
+            match result {
+                Err(e)
+                    if attempt < self.retries
+                        && !matches!(e, TileBudgetExceeded(_) | SourceBusy(_)) =>
+                {
+                    attempt += 1;
+                    warn!(
+                        "Retrying tile {xyz} of source {} ({attempt}/{}) after error: {e}",
+                        src.get_id(),
+                        self.retries
+                    );
+                }
+                result => {
+                    log!(
+                        src.get_log_level(),
+                        "Tile {xyz} of source {} took {}ms",
+                        src.get_id(),
+                        start.elapsed().as_millis()
+                    );
+                    return result;
+                }
+            }
+        }
+    }
+
     /// Decide which encoding to use for the uncompressed tile data, based on the client's Accept-Encoding header
     fn decide_encoding(&self, accept_enc: &AcceptEncoding) -> ActixResult<Option<ContentEncoding>> {
         let mut q_gzip = None;
@@ -233,7 +659,7 @@ impl<'a> DynTileSource<'a> {
                 }
             }
 
-            if tile.info.encoding == Encoding::Uncompressed {
+            if tile.info.encoding == Encoding::Uncompressed && self.compress {
                 if let Some(enc) = self.decide_encoding(accept_enc)? {
                     // (re-)compress the tile into the preferred encoding
                     tile = encode(tile, enc)?;
@@ -248,6 +674,26 @@ impl<'a> DynTileSource<'a> {
     }
 }
 
+fn tile_http_response(tile: Tile) -> HttpResponse {
+    // The hash of the encoded data, so that each encoding of a tile has its own tag, including empty tiles
+    let etag = ETag(EntityTag::new_strong(format!(
+        "{:016x}",
+        xxh3_64(&tile.data)
+    )));
+
+    if tile.data.is_empty() {
+        HttpResponse::NoContent().insert_header(etag).finish()
+    } else {
+        let mut response = HttpResponse::Ok();
+        response.insert_header(etag);
+        response.content_type(tile.info.format.content_type());
+        if let Some(val) = tile.info.encoding.content_encoding() {
+            response.insert_header((CONTENT_ENCODING, val));
+        }
+        response.body(tile.data)
+    }
+}
+
 fn encode(tile: Tile, enc: ContentEncoding) -> ActixResult<Tile> {
     Ok(match enc {
         ContentEncoding::Brotli => Tile::new(
@@ -282,6 +728,52 @@ fn decode(tile: Tile) -> ActixResult<Tile> {
     })
 }
 
+/// Sort the parameters of the URL query string by name, so that the same parameters in a different order,
+/// e.g. `?a=1&b=2` and `?b=2&a=1`, share cached tiles. The sort is stable, so repeated parameters keep their order.
+fn canonical_query(query: &str) -> String {
+    let mut params: Vec<&str> = query.split('&').filter(|v| !v.is_empty()).collect();
+    params.sort_by_key(|v| v.split_once('=').map_or(*v, |(name, _)| name));
+    params.join("&")
+}
+
+/// Keep only the URL query parameters used by any of the sources, see [`Source::url_query_params`](crate::source::Source::url_query_params),
+/// in a canonical order. Returns `None` if there are none, so that other parameters do not split the cache.
+pub(crate) fn known_query(query: &str, sources: &[TileInfoSource]) -> Option<String> {
+    let query: Vec<&str> = query
+        .split('&')
+        .filter(|v| {
+            let name = v.split_once('=').map_or(*v, |(name, _)| name);
+            sources.iter().any(|s| s.url_query_params().contains(&name))
+        })
+        .collect();
+    if query.is_empty() {
+        None
+    } else {
+        Some(canonical_query(&query.join("&")))
+    }
+}
+
+/// Check if the URL query string contains the given parameter
+fn has_query_param(query: &str, name: &str) -> bool {
+    get_query_param(query, name).is_some()
+}
+
+/// Check if the URL query string asks to download the tile as a file, e.g. `?download=1`
+fn is_download(query: &str) -> bool {
+    get_query_param(query, "download").is_some_and(|v| !matches!(v.as_str(), "0" | "false"))
+}
+
+/// Get the value of the given URL query string parameter
+fn get_query_param(query: &str, name: &str) -> Option<String> {
+    if query.is_empty() {
+        return None;
+    }
+    Query::<UrlQuery>::from_query(query)
+        .ok()?
+        .into_inner()
+        .remove(name)
+}
+
 pub fn to_encoding(val: ContentEncoding) -> Option<Encoding> {
     Some(match val {
         ContentEncoding::Identity => Encoding::Uncompressed,
@@ -294,12 +786,153 @@ pub fn to_encoding(val: ContentEncoding) -> Option<Encoding> {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::http::StatusCode;
+    use async_trait::async_trait;
     use rstest::rstest;
-    use tilejson::tilejson;
+    use tilejson::{TileJSON, tilejson};
 
     use super::*;
+    use crate::source::Source;
     use crate::srv::server::tests::TestSource;
 
+    /// Returns the requested tile coordinates as the tile content
+    #[derive(Debug, Clone)]
+    struct XyzSource {
+        id: &'static str,
+        tj: TileJSON,
+    }
+
+    #[async_trait]
+    impl Source for XyzSource {
+        fn get_id(&self) -> &str {
+            self.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            TileInfo::new(Format::Mvt, Encoding::Uncompressed)
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            _url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            Ok(xyz.to_string().into_bytes())
+        }
+    }
+
+    /// A test source whose tiles report their number of features
+    #[derive(Debug, Clone)]
+    struct CountedSource(TestSource);
+
+    #[async_trait]
+    impl Source for CountedSource {
+        fn get_id(&self) -> &str {
+            self.0.get_id()
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            self.0.get_tilejson()
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            self.0.get_tile_info()
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        fn supports_feature_count(&self) -> bool {
+            true
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            self.0.get_tile(xyz, url_query).await
+        }
+    }
+
+    /// A test source whose tiles are not worth compressing
+    #[derive(Debug, Clone)]
+    struct UncompressibleSource(TestSource);
+
+    #[async_trait]
+    impl Source for UncompressibleSource {
+        fn get_id(&self) -> &str {
+            self.0.get_id()
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            self.0.get_tilejson()
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            self.0.get_tile_info()
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        fn is_compressible(&self) -> bool {
+            false
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            self.0.get_tile(xyz, url_query).await
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_uncompressible_source() {
+        let test_source = |id| TestSource {
+            id,
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        };
+        let sources = TileSources::new(vec![vec![
+            Box::new(UncompressibleSource(test_source("tiny"))),
+            Box::new(UncompressibleSource(test_source("tiny2"))),
+            Box::new(test_source("test")),
+        ]]);
+
+        for (source_id, expected_enc) in [
+            ("tiny", Encoding::Uncompressed),
+            ("tiny,tiny2", Encoding::Uncompressed),
+            ("test", Encoding::Gzip),
+            // composite sources are compressed unless all of their sources opted out
+            ("tiny,test", Encoding::Gzip),
+        ] {
+            let accept_enc = Some(AcceptEncoding(vec!["gzip".parse().unwrap()]));
+            let src =
+                DynTileSource::new(&sources, source_id, None, "", accept_enc, None, None).unwrap();
+            let tile = src
+                .get_tile_content(TileCoord { z: 0, x: 0, y: 0 })
+                .await
+                .unwrap();
+            assert_eq!(tile.info.encoding, expected_enc, "{source_id}");
+        }
+    }
+
     #[actix_rt::test]
     async fn test_deleteme() {
         test_enc_preference(&["gzip", "deflate", "br", "zstd"], None, Encoding::Gzip).await;
@@ -345,6 +978,50 @@ mod tests {
         assert_eq!(tile.info.encoding, expected_enc);
     }
 
+    #[actix_rt::test]
+    async fn test_feature_count() {
+        // a tile with one layer of two features
+        let mvt = vec![
+            0x1a, 0x09, 0x78, 0x02, 0x0a, 0x01, b'a', 0x12, 0x00, 0x12, 0x00,
+        ];
+        let sources = TileSources::new(vec![vec![
+            Box::new(CountedSource(TestSource {
+                id: "counted",
+                tj: tilejson! { tiles: vec![] },
+                data: mvt.clone(),
+            })),
+            Box::new(CountedSource(TestSource {
+                id: "invalid",
+                tj: tilejson! { tiles: vec![] },
+                data: vec![1_u8, 2, 3],
+            })),
+            Box::new(TestSource {
+                id: "test",
+                tj: tilejson! { tiles: vec![] },
+                data: mvt,
+            }),
+        ]]);
+
+        for (source_id, accept_enc, expected) in [
+            ("counted", None, Some(2)),
+            ("counted", Some("gzip"), Some(2)),
+            ("counted", Some("br"), Some(2)),
+            ("counted,counted", None, Some(4)),
+            ("invalid", None, None),
+            ("test", None, None),
+            ("counted,test", None, None),
+        ] {
+            let accept_enc = accept_enc.map(|v| AcceptEncoding(vec![v.parse().unwrap()]));
+            let src =
+                DynTileSource::new(&sources, source_id, None, "", accept_enc, None, None).unwrap();
+            let tile = src
+                .get_tile_content(TileCoord { z: 0, x: 0, y: 0 })
+                .await
+                .unwrap();
+            assert_eq!(src.count_features(&tile).unwrap(), expected, "{source_id}");
+        }
+    }
+
     #[actix_rt::test]
     async fn test_tile_content() {
         let non_empty_source = TestSource {
@@ -377,4 +1054,533 @@ mod tests {
             assert_eq!(expected, &src.get_tile_content(xyz).await.unwrap().data);
         }
     }
+
+    #[actix_rt::test]
+    async fn test_tile_scheme() {
+        let mut tms_tj = tilejson! { tiles: vec![] };
+        tms_tj.scheme = Some("tms".to_string());
+        let sources = TileSources::new(vec![vec![
+            Box::new(XyzSource {
+                id: "xyz",
+                tj: tilejson! { tiles: vec![] },
+            }),
+            Box::new(XyzSource {
+                id: "tms",
+                tj: tms_tj,
+            }),
+        ]]);
+
+        for (source_id, (z, x, y), expected) in [
+            ("xyz", (0, 0, 0), "0,0,0"),
+            ("tms", (0, 0, 0), "0,0,0"),
+            ("xyz", (3, 2, 1), "3,2,1"),
+            ("tms", (3, 2, 1), "3,2,6"),
+            ("tms", (3, 2, 7), "3,2,0"),
+        ] {
+            let src = DynTileSource::new(&sources, source_id, None, "", None, None, None).unwrap();
+            let tile = src.get_tile_content(TileCoord { z, x, y }).await.unwrap();
+            assert_eq!(expected, String::from_utf8(tile.data).unwrap());
+        }
+
+        let src = DynTileSource::new(&sources, "tms", None, "", None, None, None).unwrap();
+        let xyz = TileCoord { z: 3, x: 2, y: 8 };
+        assert!(src.get_tile_content(xyz).await.is_err());
+
+        // Zoom 30 is the highest supported zoom level
+        let max = (1_u32 << 30) - 1;
+        for source_id in ["xyz", "tms"] {
+            let src = DynTileSource::new(&sources, source_id, None, "", None, None, None).unwrap();
+            let xyz = TileCoord {
+                z: 30,
+                x: max,
+                y: 0,
+            };
+            assert!(src.get_tile_content(xyz).await.is_ok());
+            let xyz = TileCoord { z: 31, x: 0, y: 0 };
+            assert!(src.get_tile_content(xyz).await.is_err());
+        }
+
+        // composites of TMS sources use TMS, while sources with different schemes cannot be combined
+        let src = DynTileSource::new(&sources, "tms,tms", None, "", None, None, None).unwrap();
+        assert_eq!(src.scheme, TileScheme::Tms);
+        let xyz = TileCoord { z: 3, x: 2, y: 1 };
+        let tile = src.get_tile_content(xyz).await.unwrap();
+        assert_eq!("3,2,63,2,6", String::from_utf8(tile.data).unwrap());
+        for source_id in ["xyz,tms", "tms,xyz"] {
+            let Err(err) = DynTileSource::new(&sources, source_id, None, "", None, None, None)
+            else {
+                panic!("{source_id} must be rejected");
+            };
+            assert_eq!(
+                err.as_response_error().status_code(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_quadkey_tile_scheme() {
+        use actix_web::App;
+        use actix_web::test::{TestRequest, call_and_read_body, call_service, init_service};
+
+        use crate::NO_MAIN_CACHE;
+        use crate::srv::router;
+
+        let mut tms_tj = tilejson! { tiles: vec![] };
+        tms_tj.scheme = Some("tms".to_string());
+        let sources = TileSources::new(vec![vec![
+            Box::new(XyzSource {
+                id: "xyz",
+                tj: tilejson! { tiles: vec![] },
+            }),
+            Box::new(XyzSource {
+                id: "tms",
+                tj: tms_tj,
+            }),
+        ]]);
+        let srv_config = SrvConfig::default();
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(sources))
+                .app_data(Data::new(srv_config.clone()))
+                .app_data(Data::new(NO_MAIN_CACHE))
+                .configure(|c| router(c, &srv_config)),
+        )
+        .await;
+
+        // the quadkey 213 is the XYZ tile 3/3/5, i.e. the TMS tile 3/3/2
+        for (path, expected) in [
+            ("/xyz/quadkey/213", "3,3,5"),
+            ("/tms/quadkey/213", "3,3,5"),
+            ("/xyz/3/3/5", "3,3,5"),
+            ("/tms/3/3/2", "3,3,5"),
+        ] {
+            let body = call_and_read_body(&app, TestRequest::get().uri(path).to_request()).await;
+            assert_eq!(body, expected, "{path}");
+        }
+
+        // sources with different schemes cannot be combined
+        for path in ["/xyz,tms/3/3/5", "/tms,xyz/quadkey/213"] {
+            let response = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{path}");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_tile_during_replace() {
+        use std::sync::atomic::AtomicBool;
+
+        use actix_web::App;
+        use actix_web::test::{TestRequest, call_service, init_service, read_body};
+
+        use crate::NO_MAIN_CACHE;
+        use crate::srv::router;
+
+        // only the northern half of the world may be requested, so the scheme decides if a tile is allowed
+        let sources = |data: &[u8], scheme: &str| {
+            let mut tj = tilejson! { tiles: vec![] };
+            tj.scheme = Some(scheme.to_string());
+            TileSources::new(vec![vec![Box::new(TestSource {
+                id: "roads",
+                tj,
+                data: data.to_vec(),
+            })]])
+        };
+        let old = sources(b"old", "xyz");
+        let new = sources(b"new", "tms");
+        let served = TileSources::default();
+        served.replace(&old);
+        let srv_config = SrvConfig {
+            tile_access: Some(
+                serde_yaml::from_str("roads: { bounds: [-180, 1, 180, 85] }").unwrap(),
+            ),
+            ..Default::default()
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(served.clone()))
+                .app_data(Data::new(srv_config.clone()))
+                .app_data(Data::new(NO_MAIN_CACHE))
+                .configure(|c| router(c, &srv_config)),
+        )
+        .await;
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reloads = std::thread::spawn({
+            let done = done.clone();
+            move || {
+                let mut i = 0;
+                while !done.load(Ordering::Relaxed) {
+                    served.replace(if i % 2 == 0 { &new } else { &old });
+                    i += 1;
+                }
+            }
+        });
+        // the XYZ tile 1/0/0 of the old sources is in the north, the TMS tile 1/0/0 of the new ones in the south
+        for _ in 0..2000 {
+            let req = TestRequest::get().uri("/roads/1/0/0").to_request();
+            let response = call_service(&app, req).await;
+            let status = response.status();
+            let body = read_body(response).await;
+            assert!(
+                (status == StatusCode::OK && body == "old") || status == StatusCode::FORBIDDEN,
+                "mixed sources: {status} {body:?}"
+            );
+        }
+        done.store(true, Ordering::Relaxed);
+        reloads.join().unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_unsupported_fields_filter() {
+        let sources = TileSources::new(vec![vec![Box::new(TestSource {
+            id: "test_source",
+            tj: tilejson! { tiles: vec![] },
+            data: vec![1_u8, 2, 3],
+        })]]);
+
+        let new_src =
+            |query| DynTileSource::new(&sources, "test_source", None, query, None, None, None);
+        assert!(new_src("").is_ok());
+        assert!(new_src("foo=bar").is_ok());
+        let err = new_src("fields=name").err().unwrap();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::NOT_IMPLEMENTED
+        );
+    }
+
+    /// Fails on the first request, and responds slowly to all other requests
+    #[derive(Debug, Clone)]
+    struct FlakySource {
+        id: &'static str,
+        tj: TileJSON,
+        requests: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Source for FlakySource {
+        fn get_id(&self) -> &str {
+            self.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            TileInfo::new(Format::Mvt, Encoding::Uncompressed)
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            _xyz: TileCoord,
+            _url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            if self.requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(std::io::Error::other("connection reset").into());
+            }
+            actix_rt::time::sleep(Duration::from_millis(100)).await;
+            Ok(vec![1_u8, 2, 3])
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_retries_budget() {
+        let get_status = |retries, budget_ms: Option<u64>| async move {
+            let sources = TileSources::new(vec![vec![Box::new(FlakySource {
+                id: "flaky",
+                tj: tilejson! { tiles: vec![] },
+                requests: Arc::default(),
+            })]]);
+            let deadline = budget_ms.map(|v| Instant::now() + Duration::from_millis(v));
+            let src = DynTileSource::new(&sources, "flaky", None, "", None, None, None)
+                .unwrap()
+                .with_retries(retries, deadline);
+            let xyz = TileCoord { z: 0, x: 0, y: 0 };
+            match src.get_tile_content(xyz).await {
+                Ok(_) => StatusCode::OK,
+                Err(e) => e.as_response_error().status_code(),
+            }
+        };
+
+        assert_eq!(get_status(0, None).await, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(get_status(1, None).await, StatusCode::OK);
+        assert_eq!(get_status(1, Some(1000)).await, StatusCode::OK);
+        // the retry starts within the budget, but does not complete in time
+        assert_eq!(get_status(1, Some(50)).await, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            get_status(0, Some(50)).await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    /// Holds a query permit of its source while responding slowly
+    #[cfg(feature = "postgres")]
+    #[derive(Debug, Clone)]
+    struct BlockingSource {
+        id: &'static str,
+        tj: TileJSON,
+        limit: crate::utils::QueryLimit,
+    }
+
+    #[cfg(feature = "postgres")]
+    #[async_trait]
+    impl Source for BlockingSource {
+        fn get_id(&self) -> &str {
+            self.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            TileInfo::new(Format::Mvt, Encoding::Uncompressed)
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            _xyz: TileCoord,
+            _url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            let _permit = self.limit.acquire(self.id).await?;
+            actix_rt::time::sleep(Duration::from_millis(100)).await;
+            Ok(vec![1_u8, 2, 3])
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    #[actix_rt::test]
+    async fn test_source_query_limit() {
+        use crate::utils::QueryLimit;
+
+        let source = |id, limit| -> TileInfoSource {
+            Box::new(BlockingSource {
+                id,
+                tj: tilejson! { tiles: vec![] },
+                limit,
+            })
+        };
+        let sources = TileSources::new(vec![vec![
+            source("limited", QueryLimit::new(1, Some(0))),
+            source("queued", QueryLimit::new(1, None)),
+        ]]);
+        let get_status = |ids: &'static str, delay_ms| {
+            let sources = &sources;
+            async move {
+                actix_rt::time::sleep(Duration::from_millis(delay_ms)).await;
+                let src = DynTileSource::new(sources, ids, None, "", None, None, None).unwrap();
+                match src.get_tile_content(TileCoord { z: 0, x: 0, y: 0 }).await {
+                    Ok(_) => StatusCode::OK,
+                    Err(e) => e.as_response_error().status_code(),
+                }
+            }
+        };
+
+        // a busy source rejects additional requests, including those merging it with other sources
+        let statuses = futures::join!(
+            get_status("limited", 0),
+            get_status("limited", 20),
+            get_status("queued,limited", 20),
+            get_status("queued", 20),
+        );
+        assert_eq!(
+            statuses,
+            (
+                StatusCode::OK,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::OK
+            )
+        );
+
+        // requests wait for their turn if the queue is not limited
+        let statuses = futures::join!(get_status("queued", 0), get_status("queued", 20));
+        assert_eq!(statuses, (StatusCode::OK, StatusCode::OK));
+        assert_eq!(get_status("limited", 0).await, StatusCode::OK);
+    }
+
+    /// Returns the `v` URL query parameter as the tile content, counting the requests
+    #[derive(Debug, Clone)]
+    struct QuerySource {
+        id: &'static str,
+        tj: TileJSON,
+        requests: Arc<AtomicUsize>,
+        /// Whether the source receives the whole URL query, or only the `v` parameter
+        use_url_query: bool,
+    }
+
+    #[async_trait]
+    impl Source for QuerySource {
+        fn get_id(&self) -> &str {
+            self.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            TileInfo::new(Format::Mvt, Encoding::Uncompressed)
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        fn support_url_query(&self) -> bool {
+            self.use_url_query
+        }
+
+        fn url_query_params(&self) -> &[&'static str] {
+            &["v"]
+        }
+
+        async fn get_tile(
+            &self,
+            _xyz: TileCoord,
+            url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let value = url_query.and_then(|q| q.get("v")).cloned();
+            Ok(value.unwrap_or_default().into_bytes())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_cache_query_variants() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let sources = TileSources::new(vec![vec![Box::new(QuerySource {
+            id: "query",
+            tj: tilejson! { tiles: vec![] },
+            requests: requests.clone(),
+            use_url_query: true,
+        })]]);
+        let cache = MainCache::new(1 << 20).with_max_query_variants(Some(2));
+        let fetch_tile = async |query: &str| {
+            let src = DynTileSource::new(&sources, "query", None, query, None, None, Some(&cache))
+                .unwrap();
+            let tile = src
+                .get_tile_content(TileCoord { z: 0, x: 0, y: 0 })
+                .await
+                .unwrap();
+            String::from_utf8(tile.data).unwrap()
+        };
+
+        // only the tiles of the first two query strings are cached, but all of them are served
+        for query in ["v=1", "v=2", "v=3", "v=1", "v=2", "v=3"] {
+            assert_eq!(fetch_tile(query).await, &query[2..]);
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // tiles without a query string are cached regardless of the limit
+        fetch_tile("").await;
+        fetch_tile("").await;
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+    }
+
+    #[actix_rt::test]
+    async fn test_cache_query_order() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let sources = TileSources::new(vec![vec![Box::new(QuerySource {
+            id: "query",
+            tj: tilejson! { tiles: vec![] },
+            requests: requests.clone(),
+            use_url_query: true,
+        })]]);
+        let cache = MainCache::new(1 << 20);
+        let fetch_tile = async |query: &str| {
+            let src = DynTileSource::new(&sources, "query", None, query, None, None, Some(&cache))
+                .unwrap();
+            let tile = src
+                .get_tile_content(TileCoord { z: 0, x: 0, y: 0 })
+                .await
+                .unwrap();
+            String::from_utf8(tile.data).unwrap()
+        };
+
+        // the same parameters in a different order share the cached tile
+        assert_eq!(fetch_tile("v=1&w=2").await, "1");
+        assert_eq!(fetch_tile("w=2&v=1").await, "1");
+        assert_eq!(fetch_tile("&w=2&&v=1").await, "1");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(fetch_tile("v=2&w=2").await, "2");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_cache_known_query_params() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let sources = TileSources::new(vec![vec![Box::new(QuerySource {
+            id: "query",
+            tj: tilejson! { tiles: vec![] },
+            requests: requests.clone(),
+            use_url_query: false,
+        })]]);
+        let cache = MainCache::new(1 << 20);
+        let fetch_tile = async |query: &str| {
+            let src = DynTileSource::new(&sources, "query", None, query, None, None, Some(&cache))
+                .unwrap();
+            let tile = src
+                .get_tile_content(TileCoord { z: 0, x: 0, y: 0 })
+                .await
+                .unwrap();
+            String::from_utf8(tile.data).unwrap()
+        };
+
+        // only the known parameters are passed to the source and used in the cache key
+        assert_eq!(fetch_tile("v=1&w=2").await, "1");
+        assert_eq!(fetch_tile("w=3&v=1").await, "1");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(fetch_tile("w=2").await, "");
+        assert_eq!(fetch_tile("").await, "");
+        assert_eq!(fetch_tile("w=4&_=123").await, "");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_cache_invalidated_during_request() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let sources = TileSources::new(vec![vec![Box::new(QuerySource {
+            id: "query",
+            tj: tilejson! { tiles: vec![] },
+            requests: requests.clone(),
+            use_url_query: true,
+        })]]);
+        let cache = MainCache::new(1 << 20);
+        let xyz = TileCoord { z: 0, x: 0, y: 0 };
+
+        // a tile of the sources read before the cache was invalidated, e.g. by a reload, is not cached
+        let src =
+            DynTileSource::new(&sources, "query", None, "", None, None, Some(&cache)).unwrap();
+        cache.invalidate_all().await;
+        src.get_tile_content(xyz).await.unwrap();
+        src.get_tile_content(xyz).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let src =
+            DynTileSource::new(&sources, "query", None, "", None, None, Some(&cache)).unwrap();
+        src.get_tile_content(xyz).await.unwrap();
+        src.get_tile_content(xyz).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_canonical_query() {
+        assert_eq!(canonical_query("foo=1&bar=2"), "bar=2&foo=1");
+        assert_eq!(canonical_query("bar=2&foo=1"), "bar=2&foo=1");
+        assert_eq!(canonical_query("b&a=1&&c="), "a=1&b&c=");
+        // repeated parameters keep their order, as it may change their meaning
+        assert_eq!(canonical_query("v=2&a=1&v=1"), "a=1&v=2&v=1");
+        assert_eq!(canonical_query(""), "");
+    }
 }
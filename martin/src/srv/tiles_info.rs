@@ -1,3 +1,4 @@
+use std::collections::btree_map::Entry;
 use std::string::ToString;
 
 use actix_web::error::ErrorBadRequest;
@@ -5,19 +6,28 @@ use actix_web::http::Uri;
 use actix_web::web::{Data, Path};
 use actix_web::{HttpRequest, HttpResponse, Result as ActixResult, middleware, route};
 use itertools::Itertools as _;
+use log::warn;
 use serde::Deserialize;
-use tilejson::{TileJSON, tilejson};
+use tilejson::{Bounds, TileJSON, VectorLayer, tilejson};
 
-use crate::source::{TileInfoSource, TileSources};
-use crate::srv::SrvConfig;
+use crate::MartinError::LayerFieldConflict;
+use crate::MartinResult;
+use crate::source::{TileInfoSource, TileScheme, TileSources};
+use crate::srv::tenants::check_tenant;
+use crate::srv::{CompositeBounds, LayerFieldConflicts, SrvConfig};
+
+/// Type of a field with conflicting types in the combined vector layers, with the `widen` policy
+const WIDENED_FIELD_TYPE: &str = "String";
 
 #[derive(Deserialize)]
 pub struct SourceIDsRequest {
     pub source_ids: String,
 }
 
+/// Requests with a trailing slash, e.g. `/{source_ids}/`, get the same `TileJSON`.
+/// `NormalizePath` only merges repeated slashes, so the route has to match it explicitly.
 #[route(
-    "/{source_ids}",
+    "/{source_ids}{trailing_slash:/?}",
     method = "GET",
     method = "HEAD",
     wrap = "middleware::Compress::default()"
@@ -29,16 +39,23 @@ async fn get_source_info(
     sources: Data<TileSources>,
     srv_config: Data<SrvConfig>,
 ) -> ActixResult<HttpResponse> {
+    check_tenant(&req, &path.source_ids)?;
     let sources = sources.get_sources(&path.source_ids, None)?.0;
+    let bounds = srv_config.composite_bounds.unwrap_or_default();
+    let field_conflicts = srv_config.layer_field_conflicts.unwrap_or_default();
+
+    // A configured public tiles URL takes precedence over the one derived from the request
+    if let Some(tiles_url) = &srv_config.tiles_url {
+        let mut tilejson = merge_tilejson(&sources, String::new(), bounds, field_conflicts)
+            .map_err(ErrorBadRequest)?;
+        tilejson.tiles = tiles_url.get_tiles_urls(&path.source_ids, req.query_string());
+        return Ok(HttpResponse::Ok().json(tilejson));
+    }
 
     let tiles_path = if let Some(base_path) = &srv_config.base_path {
         format!("{base_path}/{}", path.source_ids)
     } else {
-        req.headers()
-            .get("x-rewrite-url")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.parse::<Uri>().ok())
-            .map_or_else(|| req.path().to_string(), |v| v.path().to_string())
+        get_public_path(&req).trim_end_matches('/').to_string()
     };
 
     let query_string = req.query_string();
@@ -49,29 +66,55 @@ async fn get_source_info(
     };
 
     // Construct a tiles URL from the request info, including the query string if present.
+    let tiles_url = get_absolute_url(&req, path_and_query)?;
+
+    let tilejson =
+        merge_tilejson(&sources, tiles_url, bounds, field_conflicts).map_err(ErrorBadRequest)?;
+    Ok(HttpResponse::Ok().json(tilejson))
+}
+
+/// Path of the request as seen by the client. A reverse proxy that rewrote the path
+/// may report the original one with the `X-Rewrite-URL` header.
+#[must_use]
+pub fn get_public_path(req: &HttpRequest) -> String {
+    req.headers()
+        .get("x-rewrite-url")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<Uri>().ok())
+        .map_or_else(|| req.path().to_string(), |v| v.path().to_string())
+}
+
+/// Build an absolute URL of the given path and query, using the scheme and the host of the request
+pub fn get_absolute_url(req: &HttpRequest, path_and_query: String) -> ActixResult<String> {
     let info = req.connection_info();
-    let tiles_url = Uri::builder()
+    Uri::builder()
         .scheme(info.scheme())
         .authority(info.host())
         .path_and_query(path_and_query)
         .build()
-        .map(|tiles_url| tiles_url.to_string())
-        .map_err(|e| ErrorBadRequest(format!("Can't build tiles URL: {e}")))?;
-
-    Ok(HttpResponse::Ok().json(merge_tilejson(&sources, tiles_url)))
+        .map(|url| url.to_string())
+        .map_err(|e| ErrorBadRequest(format!("Can't build URL: {e}")))
 }
 
-#[must_use]
-pub fn merge_tilejson(sources: &[TileInfoSource], tiles_url: String) -> TileJSON {
+/// Combine the `TileJSON` of multiple sources into the `TileJSON` of a composite source.
+/// Sources without bounds do not widen the combined bounds to the whole world.
+/// Vector layers with the same ID are combined, resolving the conflicting types of their fields with `field_conflicts`.
+pub fn merge_tilejson(
+    sources: &[TileInfoSource],
+    tiles_url: String,
+    composite_bounds: CompositeBounds,
+    field_conflicts: LayerFieldConflicts,
+) -> MartinResult<TileJSON> {
     if sources.len() == 1 {
         let mut tj = sources[0].get_tilejson().clone();
         tj.tiles = vec![tiles_url];
-        return tj;
+        return Ok(tj);
     }
 
     let mut attributions = vec![];
     let mut descriptions = vec![];
     let mut names = vec![];
+    let mut all_bounds = vec![];
     let mut result = tilejson! {
         tiles: vec![tiles_url],
     };
@@ -80,11 +123,8 @@ pub fn merge_tilejson(sources: &[TileInfoSource], tiles_url: String) -> TileJSON
         let tj = src.get_tilejson();
 
         if let Some(vector_layers) = &tj.vector_layers {
-            if let Some(ref mut a) = result.vector_layers {
-                a.extend(vector_layers.iter().cloned());
-            } else {
-                result.vector_layers = Some(vector_layers.clone());
-            }
+            let merged = result.vector_layers.get_or_insert_with(Vec::new);
+            merge_vector_layers(merged, src.get_id(), vector_layers, field_conflicts)?;
         }
 
         if let Some(v) = &tj.attribution {
@@ -94,11 +134,7 @@ pub fn merge_tilejson(sources: &[TileInfoSource], tiles_url: String) -> TileJSON
         }
 
         if let Some(bounds) = tj.bounds {
-            if let Some(a) = result.bounds {
-                result.bounds = Some(a + bounds);
-            } else {
-                result.bounds = tj.bounds;
-            }
+            all_bounds.push(bounds);
         }
 
         if result.center.is_none() {
@@ -139,6 +175,14 @@ pub fn merge_tilejson(sources: &[TileInfoSource], tiles_url: String) -> TileJSON
         }
     }
 
+    result.bounds = merge_bounds(&all_bounds, composite_bounds);
+
+    // sources with different schemes are rejected before they are merged
+    let scheme = TileScheme::composite(sources.iter().map(|v| v.get_scheme()));
+    if let Some(scheme) = scheme.filter(|v| *v != TileScheme::default()) {
+        result.scheme = Some(scheme.to_string());
+    }
+
     if !attributions.is_empty() {
         result.attribution = Some(attributions.into_iter().join("\n"));
     }
@@ -151,7 +195,85 @@ pub fn merge_tilejson(sources: &[TileInfoSource], tiles_url: String) -> TileJSON
         result.name = Some(names.into_iter().join(","));
     }
 
-    result
+    Ok(result)
+}
+
+/// Add the vector layers of a source to the combined layers. A layer with the same ID as a combined one
+/// adds its fields to it, and widens its zoom range.
+fn merge_vector_layers(
+    merged: &mut Vec<VectorLayer>,
+    source_id: &str,
+    layers: &[VectorLayer],
+    field_conflicts: LayerFieldConflicts,
+) -> MartinResult<()> {
+    for layer in layers {
+        let Some(target) = merged.iter_mut().find(|v| v.id == layer.id) else {
+            merged.push(layer.clone());
+            continue;
+        };
+        // Clients cannot tell the features of the sources apart, as their tiles contain a single layer with this ID
+        warn!(
+            "Layer {} of source {source_id} has the same ID as a layer of another source, combining them into one layer",
+            layer.id
+        );
+        // a layer without a zoom limit has data at all zoom levels
+        target.minzoom = target.minzoom.zip(layer.minzoom).map(|(a, b)| a.min(b));
+        target.maxzoom = target.maxzoom.zip(layer.maxzoom).map(|(a, b)| a.max(b));
+        for (field, field_type) in &layer.fields {
+            let mut entry = match target.fields.entry(field.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(field_type.clone());
+                    continue;
+                }
+                Entry::Occupied(entry) => entry,
+            };
+            if entry.get() == field_type {
+                continue;
+            }
+            match field_conflicts {
+                LayerFieldConflicts::Widen => {
+                    warn!(
+                        "Field {field} of layer {} is {} in one source and {field_type} in another, using {WIDENED_FIELD_TYPE}",
+                        layer.id,
+                        entry.get()
+                    );
+                    entry.insert(WIDENED_FIELD_TYPE.to_string());
+                }
+                LayerFieldConflicts::KeepFirst => {}
+                LayerFieldConflicts::Error => {
+                    return Err(LayerFieldConflict(
+                        layer.id.clone(),
+                        field.clone(),
+                        entry.get().clone(),
+                        field_type.clone(),
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Combine the bounds of multiple sources. The intersection falls back to the union
+/// if the sources do not share any area, e.g. if they are only adjacent.
+fn merge_bounds(bounds: &[Bounds], composite_bounds: CompositeBounds) -> Option<Bounds> {
+    let union = bounds.iter().copied().reduce(|a, b| a + b)?;
+    if composite_bounds == CompositeBounds::Union {
+        return Some(union);
+    }
+    let intersection = bounds.iter().copied().reduce(|a, b| {
+        Bounds::new(
+            a.left.max(b.left),
+            a.bottom.max(b.bottom),
+            a.right.min(b.right),
+            a.top.min(b.top),
+        )
+    })?;
+    if intersection.left < intersection.right && intersection.bottom < intersection.top {
+        Some(intersection)
+    } else {
+        Some(union)
+    }
 }
 
 #[cfg(test)]
@@ -183,7 +305,13 @@ pub mod tests {
             },
             data: Vec::default(),
         };
-        let tj = merge_tilejson(&[Box::new(src1.clone())], url.clone());
+        let tj = merge_tilejson(
+            &[Box::new(src1.clone())],
+            url.clone(),
+            CompositeBounds::Union,
+            LayerFieldConflicts::Widen,
+        )
+        .unwrap();
         assert_eq!(
             TileJSON {
                 tiles: vec![url.clone()],
@@ -210,7 +338,13 @@ pub mod tests {
             data: Vec::default(),
         };
 
-        let tj = merge_tilejson(&[Box::new(src1.clone()), Box::new(src2)], url.clone());
+        let tj = merge_tilejson(
+            &[Box::new(src1.clone()), Box::new(src2)],
+            url.clone(),
+            CompositeBounds::Union,
+            LayerFieldConflicts::Widen,
+        )
+        .unwrap();
         assert_eq!(tj.tiles, vec![url]);
         assert_eq!(tj.name, Some("layer1,layer2".to_string()));
         assert_eq!(tj.minzoom, Some(5));
@@ -230,4 +364,171 @@ pub mod tests {
             ])
         );
     }
+
+    #[test]
+    fn test_merge_tilejson_layer_ids() {
+        let src = |id, layers: &[&str]| -> TileInfoSource {
+            let layers = layers
+                .iter()
+                .map(|v| VectorLayer::new((*v).to_string(), BTreeMap::new()))
+                .collect::<Vec<_>>();
+            Box::new(TestSource {
+                id,
+                tj: tilejson! { tiles: vec![], vector_layers: layers },
+                data: Vec::default(),
+            })
+        };
+        let layer_ids = |sources: &[TileInfoSource]| {
+            let tj = merge_tilejson(
+                sources,
+                String::new(),
+                CompositeBounds::Union,
+                LayerFieldConflicts::Error,
+            )
+            .unwrap();
+            tj.vector_layers
+                .unwrap()
+                .into_iter()
+                .map(|v| v.id)
+                .collect::<Vec<_>>()
+        };
+
+        // distinct layers are all kept, in the order of the sources
+        let sources = [src("a", &["roads", "rivers"]), src("b", &["parks"])];
+        assert_eq!(layer_ids(&sources), ["roads", "rivers", "parks"]);
+
+        // layers with the same ID are combined into one
+        let sources = [src("a", &["roads", "rivers"]), src("b", &["roads"])];
+        assert_eq!(layer_ids(&sources), ["roads", "rivers"]);
+    }
+
+    #[test]
+    fn test_merge_tilejson_field_conflicts() {
+        let src = |fields: &[(&str, &str)], minzoom| -> TileInfoSource {
+            let mut layer = VectorLayer::new(
+                "roads".to_string(),
+                fields
+                    .iter()
+                    .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                    .collect(),
+            );
+            layer.minzoom = minzoom;
+            Box::new(TestSource {
+                id: "id",
+                tj: tilejson! { tiles: vec![], vector_layers: vec![layer] },
+                data: Vec::default(),
+            })
+        };
+        let sources = [
+            src(&[("name", "Number"), ("lanes", "Number")], Some(5)),
+            src(&[("name", "String"), ("surface", "String")], Some(2)),
+        ];
+        let merge = |field_conflicts| {
+            merge_tilejson(
+                &sources,
+                String::new(),
+                CompositeBounds::Union,
+                field_conflicts,
+            )
+            .map(|tj| tj.vector_layers.unwrap())
+        };
+
+        let layers = merge(LayerFieldConflicts::Widen).unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].minzoom, Some(2));
+        assert_eq!(
+            layers[0].fields,
+            BTreeMap::from([
+                ("lanes".to_string(), "Number".to_string()),
+                ("name".to_string(), "String".to_string()),
+                ("surface".to_string(), "String".to_string()),
+            ])
+        );
+
+        let layers = merge(LayerFieldConflicts::KeepFirst).unwrap();
+        assert_eq!(layers[0].fields["name"], "Number");
+        assert_eq!(layers[0].fields.len(), 3);
+
+        let err = merge(LayerFieldConflicts::Error).unwrap_err();
+        assert!(matches!(err, LayerFieldConflict(layer, field, a, b)
+            if layer == "roads" && field == "name" && a == "Number" && b == "String"));
+
+        // the same field type in both sources is not a conflict
+        let sources = [
+            src(&[("name", "String")], None),
+            src(&[("name", "String")], Some(2)),
+        ];
+        let tj = merge_tilejson(
+            &sources,
+            String::new(),
+            CompositeBounds::Union,
+            LayerFieldConflicts::Error,
+        )
+        .unwrap();
+        let layers = tj.vector_layers.unwrap();
+        assert_eq!(layers[0].fields["name"], "String");
+        assert_eq!(layers[0].minzoom, None);
+    }
+
+    #[test]
+    fn test_merge_bounds() {
+        let merge = |bounds: &[Bounds], mode| merge_bounds(bounds, mode);
+        let a = Bounds::new(-10.0, -20.0, 10.0, 20.0);
+        let overlapping = Bounds::new(0.0, 10.0, 30.0, 40.0);
+        let adjacent = Bounds::new(10.0, -20.0, 30.0, 20.0);
+        let disjoint = Bounds::new(100.0, 50.0, 120.0, 60.0);
+
+        assert_eq!(merge(&[], CompositeBounds::Union), None);
+        assert_eq!(merge(&[], CompositeBounds::Intersection), None);
+        assert_eq!(merge(&[a], CompositeBounds::Intersection), Some(a));
+
+        assert_eq!(
+            merge(&[a, overlapping], CompositeBounds::Union),
+            Some(Bounds::new(-10.0, -20.0, 30.0, 40.0))
+        );
+        assert_eq!(
+            merge(&[a, overlapping], CompositeBounds::Intersection),
+            Some(Bounds::new(0.0, 10.0, 10.0, 20.0))
+        );
+
+        let union = Some(Bounds::new(-10.0, -20.0, 30.0, 20.0));
+        assert_eq!(merge(&[a, adjacent], CompositeBounds::Union), union);
+        assert_eq!(merge(&[a, adjacent], CompositeBounds::Intersection), union);
+
+        let union = Some(Bounds::new(-10.0, -20.0, 120.0, 60.0));
+        assert_eq!(merge(&[a, disjoint], CompositeBounds::Union), union);
+        assert_eq!(merge(&[a, disjoint], CompositeBounds::Intersection), union);
+    }
+
+    #[test]
+    fn test_merge_tilejson_missing_bounds() {
+        let src = |bounds: Option<Bounds>| -> TileInfoSource {
+            let mut tj = tilejson! { tiles: vec![] };
+            tj.bounds = bounds;
+            Box::new(TestSource {
+                id: "id",
+                tj,
+                data: Vec::default(),
+            })
+        };
+        let a = Bounds::new(-10.0, -20.0, 10.0, 20.0);
+        for mode in [CompositeBounds::Union, CompositeBounds::Intersection] {
+            let tj = merge_tilejson(
+                &[src(Some(a)), src(None)],
+                String::new(),
+                mode,
+                LayerFieldConflicts::Widen,
+            )
+            .unwrap();
+            assert_eq!(tj.bounds, Some(a));
+            let tj = merge_tilejson(
+                &[src(None), src(None)],
+                String::new(),
+                mode,
+                LayerFieldConflicts::Widen,
+            )
+            .unwrap();
+            assert_eq!(tj.bounds, None);
+        }
+    }
 }
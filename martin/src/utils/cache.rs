@@ -1,9 +1,36 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+use std::sync::{Arc, Mutex};
+
 use martin_tile_utils::TileCoord;
 use moka::future::Cache;
+use moka::policy::EvictionPolicy;
+use serde::Serialize;
+use tokio::sync::RwLock;
 
 use crate::TileData;
 
-pub type MainCache = Cache<CacheKey, CacheValue>;
+/// Cache of tiles and `PMTiles` directories, limited by the total byte size of its values.
+/// The least recently used values are evicted once the limit is exceeded.
+/// Clones share the same cache and statistics.
+#[derive(Clone, Debug)]
+pub struct MainCache {
+    cache: Cache<CacheKey, CacheValue>,
+    max_size: u64,
+    max_entries: Option<u64>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    max_query_variants: Option<usize>,
+    /// Distinct URL query strings of each source whose tiles may be cached
+    query_variants: Arc<Mutex<HashMap<String, HashSet<String>>>>,
+    /// Number of invalidations of the cache. Values generated before an invalidation may be outdated,
+    /// so they are not inserted after it, see [`MainCache::insert_if_current`].
+    generation: Arc<AtomicU64>,
+    /// Held while inserting a value of a generation, so that the generation cannot change meanwhile
+    invalidation: Arc<RwLock<()>>,
+}
+
 pub type OptMainCache = Option<MainCache>;
 pub const NO_MAIN_CACHE: OptMainCache = None;
 
@@ -24,6 +51,196 @@ pub enum CacheValue {
     PmtDirectory(pmtiles::Directory),
 }
 
+impl CacheValue {
+    /// Approximate number of bytes used by the value
+    #[must_use]
+    pub fn byte_size(&self) -> usize {
+        match self {
+            Self::Tile(v) => v.len(),
+            #[cfg(feature = "pmtiles")]
+            Self::PmtDirectory(v) => v.get_approx_byte_size(),
+        }
+    }
+}
+
+/// Current usage and effectiveness of the main cache
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub entries: u64,
+    pub size_bytes: u64,
+    pub max_size_bytes: u64,
+    pub max_entries: Option<u64>,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of the lookups that were found in the cache, or 0 if there were none
+    pub hit_rate: f64,
+}
+
+impl MainCache {
+    /// Create a cache holding up to `max_size` bytes of values
+    #[must_use]
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            cache: Self::build_cache(max_size, None),
+            max_size,
+            max_entries: None,
+            hits: Arc::default(),
+            misses: Arc::default(),
+            max_query_variants: None,
+            query_variants: Arc::default(),
+            generation: Arc::default(),
+            invalidation: Arc::default(),
+        }
+    }
+
+    /// Also limit the number of values in the cache, evicting the least recently used ones once it is exceeded.
+    /// Each value then counts as at least `max_size / max_entries` bytes towards the size limit,
+    /// so that neither limit can be exceeded. Unlimited if `None`.
+    #[must_use]
+    pub fn with_max_entries(mut self, max_entries: Option<u64>) -> Self {
+        self.cache = Self::build_cache(self.max_size, max_entries);
+        self.max_entries = max_entries;
+        self
+    }
+
+    fn build_cache(max_size: u64, max_entries: Option<u64>) -> Cache<CacheKey, CacheValue> {
+        let min_weight = max_entries
+            .filter(|v| *v > 0)
+            .map_or(0, |v| max_size.div_ceil(v));
+        let min_weight = u32::try_from(min_weight).unwrap_or(u32::MAX);
+        Cache::builder()
+            .name("main")
+            .weigher(move |_key, value: &CacheValue| -> u32 {
+                let size = value.byte_size().try_into().unwrap_or(u32::MAX);
+                size.max(min_weight)
+            })
+            .max_capacity(max_size)
+            .eviction_policy(EvictionPolicy::lru())
+            .support_invalidation_closures()
+            .build()
+    }
+
+    /// Limit the number of distinct URL query strings whose tiles are cached for each source,
+    /// so that requests with random query parameters cannot fill the cache. Unlimited if `None`.
+    #[must_use]
+    pub fn with_max_query_variants(mut self, max_query_variants: Option<usize>) -> Self {
+        self.max_query_variants = max_query_variants;
+        self
+    }
+
+    /// Check whether the tiles of a source requested with this URL query string may be cached.
+    /// The first query strings of each source up to the limit are accepted, any other one is not,
+    /// even after their tiles have been evicted.
+    #[must_use]
+    pub fn accepts_query(&self, source_id: &str, query: &str) -> bool {
+        let Some(max) = self.max_query_variants else {
+            return true;
+        };
+        let mut variants = self
+            .query_variants
+            .lock()
+            .expect("query variants lock poisoned");
+        let variants = variants.entry(source_id.to_string()).or_default();
+        if variants.contains(query) {
+            true
+        } else if variants.len() < max {
+            variants.insert(query.to_string());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Get a value, counting the lookup as a hit or a miss
+    pub async fn get(&self, key: &CacheKey) -> Option<CacheValue> {
+        let value = self.cache.get(key).await;
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Relaxed);
+        value
+    }
+
+    /// Insert a value, evicting the least recently used values if the cache becomes too large.
+    /// Values larger than the whole cache are not stored.
+    pub async fn insert(&self, key: CacheKey, value: CacheValue) {
+        self.cache.insert(key, value).await;
+    }
+
+    /// Current generation of the cache, to get before generating a value, see [`MainCache::insert_if_current`]
+    #[must_use]
+    pub fn generation(&self) -> u64 {
+        self.generation.load(SeqCst)
+    }
+
+    /// Insert a value generated since the given generation, unless the cache was invalidated since then
+    pub async fn insert_if_current(&self, generation: u64, key: CacheKey, value: CacheValue) {
+        let _guard = self.invalidation.read().await;
+        if self.generation() == generation {
+            self.insert(key, value).await;
+        }
+    }
+
+    /// Remove all values, e.g. after the sources were reloaded and may produce different tiles
+    pub async fn invalidate_all(&self) {
+        let _guard = self.invalidation.write().await;
+        self.generation.fetch_add(1, SeqCst);
+        self.cache.invalidate_all();
+    }
+
+    /// Remove the tiles of a source, e.g. after its data was refreshed
+    pub async fn invalidate_source(&self, source_id: &str) {
+        let _guard = self.invalidation.write().await;
+        self.generation.fetch_add(1, SeqCst);
+        let source_id = source_id.to_string();
+        self.cache
+            .invalidate_entries_if(move |key, _| match key {
+                CacheKey::Tile(id, _) | CacheKey::TileWithQuery(id, _, _) => *id == source_id,
+                CacheKey::PmtDirectory(..) => false,
+            })
+            .expect("cache must support invalidation closures");
+    }
+
+    #[must_use]
+    pub fn name(&self) -> Option<&str> {
+        self.cache.name()
+    }
+
+    #[must_use]
+    pub fn entry_count(&self) -> u64 {
+        self.cache.entry_count()
+    }
+
+    #[must_use]
+    pub fn weighted_size(&self) -> u64 {
+        self.cache.weighted_size()
+    }
+
+    #[must_use]
+    pub fn get_stats(&self) -> CacheStats {
+        let hits = self.hits.load(Relaxed);
+        let misses = self.misses.load(Relaxed);
+        let lookups = hits + misses;
+        #[allow(clippy::cast_precision_loss)]
+        let hit_rate = if lookups == 0 {
+            0.0
+        } else {
+            hits as f64 / lookups as f64
+        };
+        CacheStats {
+            entries: self.entry_count(),
+            size_bytes: self.weighted_size(),
+            max_size_bytes: self.max_size,
+            max_entries: self.max_entries,
+            hits,
+            misses,
+            hit_rate,
+        }
+    }
+}
+
 macro_rules! trace_cache {
     ($typ: literal, $cache: expr, $key: expr) => {
         trace!(
@@ -71,7 +288,7 @@ macro_rules! get_cached_value {
 }
 
 macro_rules! get_or_insert_cached_value {
-    ($cache: expr, $value_type: path, $make_item:expr, $make_key: expr) => {{
+    ($cache: expr, $generation: expr, $value_type: path, $make_item:expr, $make_key: expr) => {{
         if let Some(cache) = $cache {
             let key = $make_key;
             Ok(if let Some(data) = cache.get(&key).await {
@@ -80,7 +297,9 @@ macro_rules! get_or_insert_cached_value {
             } else {
                 $crate::utils::cache::trace_cache!("MISS", cache, key);
                 let data = $make_item.await?;
-                cache.insert(key, $value_type(data.clone())).await;
+                cache
+                    .insert_if_current($generation, key, $value_type(data.clone()))
+                    .await;
                 data
             })
         } else {
@@ -92,3 +311,139 @@ macro_rules! get_or_insert_cached_value {
 #[cfg(feature = "pmtiles")]
 pub(crate) use get_cached_value;
 pub(crate) use {from_cache_value, get_or_insert_cached_value, trace_cache};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile_key(x: u32) -> CacheKey {
+        CacheKey::Tile("src".to_string(), TileCoord { z: 10, x, y: 0 })
+    }
+
+    #[actix_rt::test]
+    async fn test_size_eviction() {
+        let cache = MainCache::new(1000);
+        for x in 0..4 {
+            cache
+                .insert(tile_key(x), CacheValue::Tile(vec![0; 300]))
+                .await;
+        }
+        cache.cache.run_pending_tasks().await;
+        let stats = cache.get_stats();
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.size_bytes, 900);
+        assert!(cache.get(&tile_key(0)).await.is_none());
+        assert!(cache.get(&tile_key(3)).await.is_some());
+
+        // a large tile evicts the least recently used ones
+        assert!(cache.get(&tile_key(1)).await.is_some());
+        cache
+            .insert(tile_key(5), CacheValue::Tile(vec![0; 600]))
+            .await;
+        cache.cache.run_pending_tasks().await;
+        assert!(cache.get(&tile_key(1)).await.is_some());
+        assert!(cache.get(&tile_key(2)).await.is_none());
+        assert!(cache.get(&tile_key(3)).await.is_none());
+        assert!(cache.get(&tile_key(5)).await.is_some());
+
+        let stats = cache.get_stats();
+        assert_eq!(stats.size_bytes, 900);
+        assert_eq!((stats.hits, stats.misses), (4, 3));
+        assert!((stats.hit_rate - 4.0 / 7.0).abs() < f64::EPSILON);
+
+        // a tile larger than the whole cache is not stored
+        cache
+            .insert(tile_key(4), CacheValue::Tile(vec![0; 2000]))
+            .await;
+        cache.cache.run_pending_tasks().await;
+        assert!(cache.get(&tile_key(4)).await.is_none());
+        assert!(cache.get_stats().size_bytes <= 1000);
+    }
+
+    #[actix_rt::test]
+    async fn test_entry_count_eviction() {
+        let cache = MainCache::new(1000).with_max_entries(Some(4));
+        for x in 0..6 {
+            cache
+                .insert(tile_key(x), CacheValue::Tile(vec![0; 10]))
+                .await;
+        }
+        cache.cache.run_pending_tasks().await;
+        let stats = cache.get_stats();
+        assert_eq!(stats.entries, 4);
+        assert_eq!(stats.max_entries, Some(4));
+        assert!(cache.get(&tile_key(0)).await.is_none());
+        assert!(cache.get(&tile_key(5)).await.is_some());
+
+        // the size limit still applies to large values
+        cache
+            .insert(tile_key(6), CacheValue::Tile(vec![0; 900]))
+            .await;
+        cache.cache.run_pending_tasks().await;
+        assert_eq!(cache.get_stats().entries, 1);
+        assert!(cache.get(&tile_key(6)).await.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn test_insert_if_current() {
+        let cache = MainCache::new(1000);
+        let generation = cache.generation();
+        cache
+            .insert_if_current(generation, tile_key(0), CacheValue::Tile(vec![0]))
+            .await;
+
+        // values generated before an invalidation are not inserted after it
+        let outdated = cache.generation();
+        cache.invalidate_all().await;
+        assert!(cache.get(&tile_key(0)).await.is_none());
+        cache
+            .insert_if_current(outdated, tile_key(1), CacheValue::Tile(vec![1]))
+            .await;
+        assert!(cache.get(&tile_key(1)).await.is_none());
+        cache
+            .insert_if_current(cache.generation(), tile_key(2), CacheValue::Tile(vec![2]))
+            .await;
+        assert!(cache.get(&tile_key(2)).await.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn test_invalidate_source() {
+        let cache = MainCache::new(1000);
+        let xyz = TileCoord { z: 0, x: 0, y: 0 };
+        let key = |id: &str, query: Option<&str>| match query {
+            Some(query) => CacheKey::TileWithQuery(id.to_string(), xyz, query.to_string()),
+            None => CacheKey::Tile(id.to_string(), xyz),
+        };
+        for (id, query) in [("a", None), ("a", Some("v=1")), ("b", None)] {
+            cache
+                .insert(key(id, query), CacheValue::Tile(vec![0]))
+                .await;
+        }
+
+        cache.invalidate_source("a").await;
+        assert!(cache.get(&key("a", None)).await.is_none());
+        assert!(cache.get(&key("a", Some("v=1"))).await.is_none());
+        assert!(cache.get(&key("b", None)).await.is_some());
+
+        // tiles inserted after the invalidation are kept
+        cache
+            .insert(key("a", None), CacheValue::Tile(vec![1]))
+            .await;
+        assert!(cache.get(&key("a", None)).await.is_some());
+    }
+
+    #[test]
+    fn test_query_variants() {
+        let cache = MainCache::new(1000);
+        assert!((0..100).all(|v| cache.accepts_query("src", &format!("v={v}"))));
+
+        let cache = cache.with_max_query_variants(Some(2));
+        assert!(cache.accepts_query("src", "v=1"));
+        assert!(cache.accepts_query("src", "v=2"));
+        assert!(!cache.accepts_query("src", "v=3"));
+        // known query strings stay cacheable, and each source has its own limit
+        assert!(cache.accepts_query("src", "v=1"));
+        assert!(cache.accepts_query("other", "v=3"));
+        assert!(!cache.clone().accepts_query("src", "v=4"));
+    }
+}
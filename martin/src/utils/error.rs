@@ -37,6 +37,39 @@ pub enum MartinError {
     #[error("Base path must be a valid URL path, and must begin with a '/' symbol, but is '{0}'")]
     BasePathError(String),
 
+    #[error(
+        "Index redirect must be an http(s) URL or a path beginning with a single '/', but is '{0}'"
+    )]
+    IndexRedirectError(String),
+
+    #[error("Invalid tiles URL template '{0}': {1}")]
+    TilesUrlTemplateError(String, &'static str),
+
+    #[error(
+        "Field {1} of layer {0} is {2} in one source and {3} in another, so the sources cannot be combined"
+    )]
+    LayerFieldConflict(String, String, String, String),
+
+    #[error("Invalid tile access policy of source {0}: {1}")]
+    TileAccessError(String, &'static str),
+
+    #[error("Tenant header {0} is not a valid HTTP header name")]
+    InvalidTenantHeader(String),
+
+    #[error(
+        "PostgreSQL connection of tenant {0} requires the tenant_header setting to select the tenant of the requests"
+    )]
+    TenantWithoutHeader(String),
+
+    #[error("Tile request for source {0} exceeded its time budget")]
+    TileBudgetExceeded(String),
+
+    #[error("Unable to remove the stored tiles in {1}: {0}")]
+    TileStoreError(io::Error, PathBuf),
+
+    #[error("Source {0} is running too many queries, try again later")]
+    SourceBusy(String),
+
     #[error("Unable to load config file {1}: {0}")]
     ConfigLoadError(io::Error, PathBuf),
 
@@ -70,6 +103,10 @@ pub enum MartinError {
     #[error(transparent)]
     CogError(#[from] crate::cog::CogError),
 
+    #[cfg(feature = "object_store")]
+    #[error(transparent)]
+    ObjectStoreError(#[from] crate::object_store::ObjectStoreError),
+
     #[error(transparent)]
     FileError(#[from] crate::file_config::FileError),
 
@@ -1,5 +1,5 @@
 pub(crate) mod cache;
-pub use cache::{CacheKey, CacheValue, MainCache, NO_MAIN_CACHE, OptMainCache};
+pub use cache::{CacheKey, CacheStats, CacheValue, MainCache, NO_MAIN_CACHE, OptMainCache};
 
 mod cfg_containers;
 pub use cfg_containers::{OptBoolObj, OptOneMany};
@@ -10,8 +10,16 @@ pub use error::*;
 mod id_resolver;
 pub use id_resolver::IdResolver;
 
+#[cfg(feature = "postgres")]
+mod query_limit;
+#[cfg(feature = "postgres")]
+pub use query_limit::QueryLimit;
+
 mod rectangle;
 pub use rectangle::{TileRect, append_rect};
 
+mod tile_store;
+pub use tile_store::{StoredTileSource, TileStoreConfig};
+
 mod utilities;
 pub use utilities::*;
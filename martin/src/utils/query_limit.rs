@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::MartinError::SourceBusy;
+use crate::MartinResult;
+
+/// Limits the number of queries a single source runs at the same time, independent of the connection pool,
+/// so that a slow source cannot starve the other ones. Clones share the same limit.
+#[derive(Debug, Clone)]
+pub struct QueryLimit {
+    /// Permits to run a query
+    running: Arc<Semaphore>,
+    /// Permits to run or to wait for a query, if the number of waiting queries is limited
+    admitted: Option<Arc<Semaphore>>,
+}
+
+/// Allows running one query until it is dropped
+#[derive(Debug)]
+pub struct QueryPermit {
+    _running: OwnedSemaphorePermit,
+    _admitted: Option<OwnedSemaphorePermit>,
+}
+
+impl QueryLimit {
+    /// Allow `max_concurrent` queries at the same time, and up to `max_queued` more waiting for their turn.
+    /// The number of waiting queries is unlimited if `max_queued` is `None`.
+    #[must_use]
+    pub fn new(max_concurrent: usize, max_queued: Option<usize>) -> Self {
+        Self {
+            running: Arc::new(Semaphore::new(max_concurrent)),
+            admitted: max_queued.map(|v| Arc::new(Semaphore::new(max_concurrent + v))),
+        }
+    }
+
+    /// Wait until the source may run another query.
+    /// Fails with [`SourceBusy`] right away if too many queries are already waiting.
+    pub async fn acquire(&self, source_id: &str) -> MartinResult<QueryPermit> {
+        let admitted = match &self.admitted {
+            Some(v) => Some(
+                v.clone()
+                    .try_acquire_owned()
+                    .map_err(|_| SourceBusy(source_id.to_string()))?,
+            ),
+            None => None,
+        };
+        let running = self
+            .running
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| SourceBusy(source_id.to_string()))?;
+        Ok(QueryPermit {
+            _running: running,
+            _admitted: admitted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn queued_queries() {
+        let limit = QueryLimit::new(1, Some(1));
+        let first = limit.acquire("src").await.unwrap();
+
+        // the second query waits for the first one, and the third one is rejected
+        let clone = limit.clone();
+        let second = actix_rt::spawn(async move { clone.acquire("src").await.map(|_| ()) });
+        actix_rt::task::yield_now().await;
+        assert!(matches!(limit.acquire("src").await, Err(SourceBusy(_))));
+
+        drop(first);
+        second.await.unwrap().unwrap();
+        assert!(limit.acquire("src").await.is_ok());
+    }
+}
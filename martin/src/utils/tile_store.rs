@@ -0,0 +1,364 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use log::{Level, warn};
+use martin_tile_utils::{TileCoord, TileInfo};
+use serde::{Deserialize, Serialize};
+use tilejson::TileJSON;
+use tokio::fs;
+use tokio::sync::RwLock;
+
+use crate::MartinError::TileStoreError;
+use crate::MartinResult;
+use crate::source::{
+    CatalogSourceConfig, Source, TileData, TileGrid, TileInfoSource, TileScheme, UrlQuery,
+};
+
+/// Persistent store of the generated tiles of some sources, e.g. of read-heavy layers with stable data.
+/// Tiles are only generated by their source if they are not stored yet, and are stored right after.
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TileStoreConfig {
+    /// Directory of the stored tiles, with a `{source_id}/{z}/{x}/{y}` file per tile
+    pub path: PathBuf,
+    /// IDs of the sources whose tiles are stored
+    pub sources: Vec<String>,
+}
+
+/// Suffix of the temporary files, so that concurrent requests of the same tile never write to the same file
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A source whose tiles are served from the tile store, and generated by the wrapped source on a miss.
+/// Tiles requested with URL query parameters may differ per request, so they are never stored.
+#[derive(Clone, Debug)]
+pub struct StoredTileSource {
+    source: TileInfoSource,
+    /// Directory of the tiles of this source
+    dir: PathBuf,
+    /// Number of times the stored tiles were removed by a refresh, shared by all clones.
+    /// Tiles are only stored if no refresh removed the stored tiles since they started being generated,
+    /// as they may come from the data before the refresh.
+    generation: Arc<RwLock<u64>>,
+}
+
+impl StoredTileSource {
+    #[must_use]
+    pub fn new(source: TileInfoSource, path: &Path) -> Self {
+        let dir = path.join(source.get_id());
+        Self {
+            source,
+            dir,
+            generation: Arc::default(),
+        }
+    }
+
+    fn get_tile_path(&self, xyz: TileCoord) -> PathBuf {
+        self.dir
+            .join(xyz.z.to_string())
+            .join(xyz.x.to_string())
+            .join(xyz.y.to_string())
+    }
+
+    /// Write the tile to a temporary file first, so that a partially written tile is never served.
+    /// The tile is not stored if the stored tiles were removed since the given generation,
+    /// and a refresh cannot remove them while the tile is being stored.
+    async fn store_tile(&self, path: &Path, data: &[u8], generation: u64) -> std::io::Result<()> {
+        let current = self.generation.read().await;
+        if *current != generation {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp_path = path.with_extension(format!("{}.{counter}.tmp", std::process::id()));
+        fs::write(&temp_path, data).await?;
+        fs::rename(&temp_path, path).await
+    }
+}
+
+#[async_trait]
+impl Source for StoredTileSource {
+    fn get_id(&self) -> &str {
+        self.source.get_id()
+    }
+
+    fn get_tilejson(&self) -> &TileJSON {
+        self.source.get_tilejson()
+    }
+
+    fn get_tile_info(&self) -> TileInfo {
+        self.source.get_tile_info()
+    }
+
+    fn clone_source(&self) -> TileInfoSource {
+        Box::new(self.clone())
+    }
+
+    fn support_url_query(&self) -> bool {
+        self.source.support_url_query()
+    }
+
+    fn supports_fields_filter(&self) -> bool {
+        self.source.supports_fields_filter()
+    }
+
+    fn supports_tile_size(&self) -> bool {
+        self.source.supports_tile_size()
+    }
+
+    fn url_query_params(&self) -> &[&'static str] {
+        self.source.url_query_params()
+    }
+
+    fn get_schema(&self) -> Option<&str> {
+        self.source.get_schema()
+    }
+
+    fn get_group(&self) -> Option<&str> {
+        self.source.get_group()
+    }
+
+    fn is_immutable(&self) -> bool {
+        self.source.is_immutable()
+    }
+
+    fn is_compressible(&self) -> bool {
+        self.source.is_compressible()
+    }
+
+    fn get_log_level(&self) -> Level {
+        self.source.get_log_level()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.source.is_empty()
+    }
+
+    fn get_dimensions(&self) -> Option<&'static str> {
+        self.source.get_dimensions()
+    }
+
+    fn get_scheme(&self) -> TileScheme {
+        self.source.get_scheme()
+    }
+
+    fn get_tile_grid(&self) -> TileGrid {
+        self.source.get_tile_grid()
+    }
+
+    async fn get_tile(
+        &self,
+        xyz: TileCoord,
+        url_query: Option<&UrlQuery>,
+    ) -> MartinResult<TileData> {
+        if url_query.is_some_and(|v| !v.is_empty()) {
+            return self.source.get_tile(xyz, url_query).await;
+        }
+
+        let generation = *self.generation.read().await;
+        let path = self.get_tile_path(xyz);
+        match fs::read(&path).await {
+            Ok(data) => return Ok(data),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Unable to read stored tile {}: {e}", path.display()),
+        }
+
+        // A tile that cannot be stored is still served, and generated again on the next request
+        let data = self.source.get_tile(xyz, url_query).await?;
+        if let Err(e) = self.store_tile(&path, &data, generation).await {
+            warn!("Unable to store tile {}: {e}", path.display());
+        }
+        Ok(data)
+    }
+
+    fn supports_feature_count(&self) -> bool {
+        self.source.supports_feature_count()
+    }
+
+    async fn explain_tile(
+        &self,
+        xyz: TileCoord,
+        url_query: Option<&UrlQuery>,
+    ) -> MartinResult<Option<serde_json::Value>> {
+        self.source.explain_tile(xyz, url_query).await
+    }
+
+    fn get_refresh_interval(&self) -> Option<Duration> {
+        self.source.get_refresh_interval()
+    }
+
+    /// Refresh the wrapped source if it supports it, and remove all stored tiles,
+    /// so that they are generated again from the refreshed data
+    async fn refresh(&self) -> MartinResult<Option<Duration>> {
+        let start = Instant::now();
+        self.source.refresh().await?;
+        let mut generation = self.generation.write().await;
+        *generation += 1;
+        match fs::remove_dir_all(&self.dir).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(TileStoreError(e, self.dir.clone())),
+        }
+        Ok(Some(start.elapsed()))
+    }
+
+    fn get_catalog_config(&self) -> Option<CatalogSourceConfig> {
+        self.source.get_catalog_config()
+    }
+
+    fn is_valid_zoom(&self, zoom: u8) -> bool {
+        self.source.is_valid_zoom(zoom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use tilejson::tilejson;
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::source::TileSources;
+    use crate::srv::{DynTileSource, refresh_source};
+    use crate::utils::MainCache;
+
+    /// A source counting how many tiles it generated, waiting for the gate to open first if it has one
+    #[derive(Clone, Debug)]
+    struct CountingSource {
+        id: &'static str,
+        tj: TileJSON,
+        calls: Arc<AtomicUsize>,
+        gate: Option<Arc<Notify>>,
+    }
+
+    #[async_trait]
+    impl Source for CountingSource {
+        fn get_id(&self) -> &str {
+            self.id
+        }
+
+        fn get_tilejson(&self) -> &TileJSON {
+            &self.tj
+        }
+
+        fn get_tile_info(&self) -> TileInfo {
+            TileInfo::new(
+                martin_tile_utils::Format::Mvt,
+                martin_tile_utils::Encoding::Uncompressed,
+            )
+        }
+
+        fn clone_source(&self) -> TileInfoSource {
+            Box::new(self.clone())
+        }
+
+        async fn get_tile(
+            &self,
+            xyz: TileCoord,
+            _url_query: Option<&UrlQuery>,
+        ) -> MartinResult<TileData> {
+            if let Some(gate) = &self.gate {
+                gate.notified().await;
+            }
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Ok(xyz.to_string().into_bytes())
+        }
+    }
+
+    #[actix_rt::test]
+    async fn stored_tiles() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let source = StoredTileSource::new(
+            Box::new(CountingSource {
+                id: "counting",
+                tj: tilejson! { tiles: vec![] },
+                calls: calls.clone(),
+                gate: None,
+            }),
+            path,
+        );
+        let xyz = TileCoord { z: 3, x: 2, y: 1 };
+        let tile_path = path.join("counting").join("3").join("2").join("1");
+
+        // a miss generates the tile and stores it
+        assert_eq!(source.get_tile(xyz, None).await.unwrap(), b"3,2,1");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_eq!(std::fs::read(&tile_path).unwrap(), b"3,2,1");
+
+        // the next request is served from the store
+        std::fs::write(&tile_path, b"stored").unwrap();
+        assert_eq!(source.get_tile(xyz, None).await.unwrap(), b"stored");
+        assert_eq!(
+            source.clone_source().get_tile(xyz, None).await.unwrap(),
+            b"stored"
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        // tiles of requests with query parameters are never stored
+        let query = UrlQuery::from([("date".to_string(), "2024".to_string())]);
+        assert_eq!(source.get_tile(xyz, Some(&query)).await.unwrap(), b"3,2,1");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert_eq!(std::fs::read(&tile_path).unwrap(), b"stored");
+
+        // tiles served through the main cache are not read from the store again
+        let cache = Some(MainCache::new(1 << 20));
+        let sources = TileSources::new(vec![vec![Box::new(source)]]);
+        let get_tile = async || {
+            let src =
+                DynTileSource::new(&sources, "counting", None, "", None, None, cache.as_ref())
+                    .unwrap();
+            src.get_tile_content(xyz).await.unwrap().data
+        };
+        assert_eq!(get_tile().await, b"stored");
+        std::fs::write(&tile_path, b"outdated").unwrap();
+        assert_eq!(get_tile().await, b"stored");
+
+        // refreshing the source removes the stored and the cached tiles
+        let source = sources.get_source("counting").unwrap();
+        assert!(refresh_source(&source, &cache).await.unwrap().is_some());
+        assert!(!tile_path.exists());
+        assert_eq!(get_tile().await, b"3,2,1");
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert_eq!(get_tile().await, b"3,2,1");
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[actix_rt::test]
+    async fn tiles_generated_during_refresh() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let gate = Arc::new(Notify::new());
+        let source = StoredTileSource::new(
+            Box::new(CountingSource {
+                id: "gated",
+                tj: tilejson! { tiles: vec![] },
+                calls: Arc::default(),
+                gate: Some(gate.clone()),
+            }),
+            temp_dir.path(),
+        );
+        let xyz = TileCoord { z: 3, x: 2, y: 1 };
+
+        // the tile may come from the data before the refresh, so it is served but not stored
+        let generating = source.get_tile(xyz, None);
+        let refreshing = async {
+            source.refresh().await.unwrap();
+            gate.notify_one();
+        };
+        let (tile, ()) = tokio::join!(generating, refreshing);
+        assert_eq!(tile.unwrap(), b"3,2,1");
+        assert!(!source.get_tile_path(xyz).exists());
+
+        // tiles generated after the refresh are stored
+        gate.notify_one();
+        assert_eq!(source.get_tile(xyz, None).await.unwrap(), b"3,2,1");
+        assert!(source.get_tile_path(xyz).exists());
+    }
+}
@@ -1,9 +1,13 @@
-use actix_web::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use actix_web::dev::ServiceResponse;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    ACCEPT_ENCODING, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_TYPE, LOCATION,
+};
 use actix_web::test::{TestRequest, call_service, read_body, read_body_json};
 use ctor::ctor;
 use indoc::indoc;
 use insta::assert_yaml_snapshot;
-use martin::srv::SrvConfig;
+use martin::srv::{IndexConfig, OutOfZoomResponse, ROBOTS_TXT_DEFAULT, SrvConfig};
 use martin_tile_utils::{decode_brotli, decode_gzip};
 use tilejson::TileJSON;
 
@@ -20,9 +24,9 @@ macro_rules! create_app {
         let state = mock_sources(mock_cfg($sources)).await.0;
         ::actix_web::test::init_service(
             ::actix_web::App::new()
-                .app_data(actix_web::web::Data::new(
+                .app_data(actix_web::web::Data::new(::martin::srv::ServedCatalog::new(
                     ::martin::srv::Catalog::new(&state).unwrap(),
-                ))
+                )))
                 .app_data(actix_web::web::Data::new(::martin::NO_MAIN_CACHE))
                 .app_data(actix_web::web::Data::new(state.tiles))
                 .app_data(actix_web::web::Data::new(SrvConfig::default()))
@@ -36,6 +40,23 @@ fn test_get(path: &str) -> TestRequest {
     TestRequest::get().uri(path)
 }
 
+/// Call the app created with a custom server configuration
+async fn call_with_srv_config(srv: SrvConfig, path: &str) -> ServiceResponse {
+    let state = mock_sources(mock_cfg(CONFIG)).await.0;
+    let app = ::actix_web::test::init_service(
+        ::actix_web::App::new()
+            .app_data(actix_web::web::Data::new(::martin::srv::ServedCatalog::new(
+                ::martin::srv::Catalog::new(&state).unwrap(),
+            )))
+            .app_data(actix_web::web::Data::new(::martin::NO_MAIN_CACHE))
+            .app_data(actix_web::web::Data::new(state.tiles))
+            .app_data(actix_web::web::Data::new(srv.clone()))
+            .configure(|c| ::martin::srv::router(c, &srv)),
+    )
+    .await;
+    call_service(&app, test_get(path).to_request()).await
+}
+
 const CONFIG: &str = indoc! {"
         mbtiles:
             sources:
@@ -53,26 +74,77 @@ async fn mbt_get_catalog() {
     let response = call_service(&app, req).await;
     let response = assert_response(response).await;
     let body: serde_json::Value = read_body_json(response).await;
-    assert_yaml_snapshot!(body, @r"
+    assert_yaml_snapshot!(body, @r#"
     fonts: {}
     sprites: {}
     tiles:
       m_json:
         content_type: application/json
         name: Dummy json data
+        version: "2"
       m_mvt:
         content_encoding: gzip
         content_type: application/x-protobuf
         description: Major cities from Natural Earth data
         name: Major cities from Natural Earth data
+        version: "2"
       m_raw_mvt:
         content_type: application/x-protobuf
         description: Major cities from Natural Earth data
         name: Major cities from Natural Earth data
+        version: "2"
       m_webp:
         content_type: image/webp
         name: ne2sr
-    ");
+    "#);
+}
+
+#[actix_rt::test]
+async fn mbt_get_catalog_links() {
+    let app = create_app! { CONFIG };
+
+    let req = test_get("/catalog?links=true").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_yaml_snapshot!(body["tiles"]["m_webp"], @r#"
+    content_type: image/webp
+    links:
+      - href: "http://localhost:8080/m_webp"
+        rel: self
+        type: application/json
+      - href: "http://localhost:8080/m_webp/{z}/{x}/{y}"
+        rel: item
+        templated: true
+        type: image/webp
+    name: ne2sr
+    "#);
+
+    // links are relative to the public path of the catalog
+    let req = test_get("/catalog?links=true")
+        .insert_header(("x-rewrite-url", "/tiles/catalog?links=true"))
+        .to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(
+        body["tiles"]["m_mvt"]["links"][1]["href"],
+        "http://localhost:8080/tiles/m_mvt/{z}/{x}/{y}"
+    );
+
+    let srv = SrvConfig {
+        base_path: Some("/maps".to_string()),
+        ..Default::default()
+    };
+    let response = assert_response(call_with_srv_config(srv, "/catalog?links=true").await).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(
+        body["tiles"]["m_mvt"]["links"][0]["href"],
+        "http://localhost:8080/maps/m_mvt"
+    );
+
+    let req = test_get("/catalog").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert!(body["tiles"]["m_mvt"].get("links").is_none());
 }
 
 #[actix_rt::test]
@@ -84,26 +156,29 @@ async fn mbt_get_catalog_gzip() {
     let response = assert_response(response).await;
     let body = decode_gzip(&read_body(response).await).unwrap();
     let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
-    assert_yaml_snapshot!(body, @r"
+    assert_yaml_snapshot!(body, @r#"
     fonts: {}
     sprites: {}
     tiles:
       m_json:
         content_type: application/json
         name: Dummy json data
+        version: "2"
       m_mvt:
         content_encoding: gzip
         content_type: application/x-protobuf
         description: Major cities from Natural Earth data
         name: Major cities from Natural Earth data
+        version: "2"
       m_raw_mvt:
         content_type: application/x-protobuf
         description: Major cities from Natural Earth data
         name: Major cities from Natural Earth data
+        version: "2"
       m_webp:
         content_type: image/webp
         name: ne2sr
-    ");
+    "#);
 }
 
 #[actix_rt::test]
@@ -119,6 +194,43 @@ async fn mbt_get_tilejson() {
     assert_eq!(body.maxzoom, Some(6));
 }
 
+#[actix_rt::test]
+async fn mbt_get_tilejson_trailing_slash() {
+    let app = create_app! { CONFIG };
+    let app_ref = &app;
+    let get_tilejson = |req: TestRequest| async move {
+        let response = assert_response(call_service(app_ref, req.to_request()).await).await;
+        read_body_json::<TileJSON, _>(response).await
+    };
+
+    let tilejson = get_tilejson(test_get("/m_mvt")).await;
+    assert_eq!(
+        tilejson.tiles,
+        vec!["http://localhost:8080/m_mvt/{z}/{x}/{y}"]
+    );
+    assert_eq!(get_tilejson(test_get("/m_mvt/")).await, tilejson);
+
+    let tilejson = get_tilejson(test_get("/m_raw_mvt/?foo=bar")).await;
+    assert_eq!(
+        tilejson.tiles,
+        vec!["http://localhost:8080/m_raw_mvt/{z}/{x}/{y}?foo=bar"]
+    );
+
+    let req = test_get("/m_mvt/").insert_header(("x-rewrite-url", "/tiles/m_mvt/"));
+    assert_eq!(
+        get_tilejson(req).await.tiles,
+        vec!["http://localhost:8080/tiles/m_mvt/{z}/{x}/{y}"]
+    );
+
+    let req = test_get("/catalog/?links=true").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(
+        body["tiles"]["m_mvt"]["links"][1]["href"],
+        "http://localhost:8080/m_mvt/{z}/{x}/{y}"
+    );
+}
+
 #[actix_rt::test]
 async fn mbt_get_tilejson_gzip() {
     let app = create_app! { CONFIG };
@@ -146,6 +258,26 @@ async fn mbt_get_raster() {
     assert_eq!(body.len(), 11586);
 }
 
+#[actix_rt::test]
+async fn mbt_get_raster_download() {
+    let app = create_app! { CONFIG };
+    let req = test_get("/m_webp/0/0/0?download=1").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    assert_eq!(
+        response.headers().get(CONTENT_DISPOSITION).unwrap(),
+        "attachment; filename=\"m_webp_0_0_0.webp\""
+    );
+
+    let req = test_get("/m_webp/0/0/0?download=0").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    assert!(response.headers().get(CONTENT_DISPOSITION).is_none());
+
+    // MVT tiles are always inline
+    let req = test_get("/m_mvt/0/0/0?download=1").to_request();
+    let response = assert_response(call_service(&app, req).await).await;
+    assert!(response.headers().get(CONTENT_DISPOSITION).is_none());
+}
+
 /// get a raster tile with accepted gzip enc, but should still be non-gzipped
 #[actix_rt::test]
 async fn mbt_get_raster_gzip() {
@@ -175,6 +307,35 @@ async fn mbt_get_mvt() {
     assert_eq!(body.len(), 1828);
 }
 
+#[actix_rt::test]
+async fn mbt_get_mvt_quadkey() {
+    let app = create_app! { CONFIG };
+    for (quadkey, xyz) in [
+        ("0", "1/0/0"),
+        ("3", "1/1/1"),
+        ("12", "2/2/1"),
+        ("21", "2/1/2"),
+    ] {
+        let req = test_get(&format!("/m_mvt/quadkey/{quadkey}")).to_request();
+        let response = assert_response(call_service(&app, req).await).await;
+        let quadkey_body = read_body(response).await;
+        let req = test_get(&format!("/m_mvt/{xyz}")).to_request();
+        let response = assert_response(call_service(&app, req).await).await;
+        let xyz_body = read_body(response).await;
+        assert_eq!(quadkey_body, xyz_body, "quadkey {quadkey}");
+    }
+
+    for quadkey in ["4", "01x", &"0".repeat(31)] {
+        let req = test_get(&format!("/m_mvt/quadkey/{quadkey}")).to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "quadkey {quadkey}"
+        );
+    }
+}
+
 /// get an MVT tile with accepted gzip enc
 #[actix_rt::test]
 async fn mbt_get_mvt_gzip() {
@@ -306,3 +467,259 @@ async fn mbt_get_json_gzip() {
     let body = decode_gzip(&body).unwrap();
     assert_eq!(body.len(), 13);
 }
+
+#[actix_rt::test]
+async fn mbt_get_mvt_fields_not_implemented() {
+    let app = create_app! { CONFIG };
+    let req = test_get("/m_mvt/0/0/0?fields=name").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+}
+
+#[actix_rt::test]
+async fn mbt_get_mvt_in_memory() {
+    let app = create_app! { indoc! {"
+        mbtiles:
+            in_memory: [m_mvt]
+            sources:
+                m_mvt: ../tests/fixtures/mbtiles/world_cities.mbtiles
+    "} };
+    let req = test_get("/m_mvt/0/0/0").to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/x-protobuf"
+    );
+    let body = read_body(response).await;
+    assert_eq!(body.len(), 1828);
+
+    let req = test_get("/m_mvt/6/0/0").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[actix_rt::test]
+async fn mbt_get_tilejson_invalid_sources() {
+    let app = create_app! { CONFIG };
+
+    let req = test_get("/missing,m_mvt,missing2").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(
+        body,
+        "Source missing does not exist\nSource missing2 does not exist"
+    );
+
+    let req = test_get("/missing,m_mvt,m_webp,health").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    let errors: Vec<&str> = body.lines().collect();
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0], "Source missing does not exist");
+    assert!(errors[1].starts_with("Source m_webp: cannot merge sources with"));
+    assert_eq!(errors[2], "Source health is a reserved keyword");
+
+    let req = test_get("/missing,m_mvt/0/0/0").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn mbt_get_mvt_max_zoom() {
+    let app = create_app! { CONFIG };
+
+    // a supported zoom level, but above the maxzoom of the source
+    let req = test_get("/m_mvt/30/0/0").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let req = test_get("/m_mvt/31/0/0").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert_eq!(body, "Invalid zoom level 31, must not be above 30");
+}
+
+#[actix_rt::test]
+async fn mbt_get_mvt_tile_size() {
+    let app = create_app! { CONFIG };
+
+    let req = test_get("/m_mvt/0/0/0?tile_size=300").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let req = test_get("/m_mvt/0/0/0?tile_size=512").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+}
+
+#[actix_rt::test]
+async fn mbt_head_mvt() {
+    let app = create_app! { CONFIG };
+
+    let req = TestRequest::default()
+        .method(actix_web::http::Method::HEAD)
+        .uri("/m_mvt/0/0/0")
+        .to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    // MBTiles sources do not support counting features
+    assert!(response.headers().get("x-feature-count").is_none());
+}
+
+#[actix_rt::test]
+async fn mbt_get_index() {
+    let index = |index| {
+        let srv = SrvConfig {
+            index,
+            ..Default::default()
+        };
+        call_with_srv_config(srv, "/")
+    };
+
+    let response = assert_response(index(None).await).await;
+    let body = read_body(response).await;
+    assert!(body.starts_with(b"Martin server is running."));
+
+    let response = index(Some(IndexConfig::Message("Hello".to_string()))).await;
+    let response = assert_response(response).await;
+    assert_eq!(read_body(response).await, "Hello");
+
+    let url = "https://example.org/docs".to_string();
+    let response = index(Some(IndexConfig::Redirect(url))).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    assert_eq!(
+        response.headers().get(LOCATION).unwrap(),
+        "https://example.org/docs"
+    );
+
+    let response = assert_response(index(Some(IndexConfig::Catalog)).await).await;
+    let body: serde_json::Value = read_body_json(response).await;
+    assert!(body["tiles"]["m_mvt"].is_object());
+}
+
+#[actix_rt::test]
+async fn mbt_get_robots_txt() {
+    let response = call_with_srv_config(SrvConfig::default(), "/robots.txt").await;
+    let response = assert_response(response).await;
+    assert_eq!(read_body(response).await, ROBOTS_TXT_DEFAULT);
+
+    let srv = SrvConfig {
+        robots_txt: Some("User-agent: *\nAllow: /\n".to_string()),
+        ..Default::default()
+    };
+    let response = assert_response(call_with_srv_config(srv, "/robots.txt").await).await;
+    assert_eq!(read_body(response).await, "User-agent: *\nAllow: /\n");
+
+    let response = call_with_srv_config(SrvConfig::default(), "/m_mvt/0/0/0").await;
+    let response = assert_response(response).await;
+    assert!(response.headers().get("x-robots-tag").is_none());
+
+    let srv = SrvConfig {
+        noindex_tiles: Some(true),
+        ..Default::default()
+    };
+    let response = assert_response(call_with_srv_config(srv, "/m_mvt/0/0/0").await).await;
+    assert_eq!(response.headers().get("x-robots-tag").unwrap(), "noindex");
+}
+
+#[actix_rt::test]
+async fn mbt_get_tile_bounds_header() {
+    let response =
+        assert_response(call_with_srv_config(SrvConfig::default(), "/m_mvt/1/1/0").await).await;
+    assert!(response.headers().get("x-tile-bounds").is_none());
+
+    let srv = SrvConfig {
+        tile_bounds_header: Some(true),
+        ..Default::default()
+    };
+    let response = assert_response(call_with_srv_config(srv, "/m_mvt/1/1/0").await).await;
+    let bounds: Vec<f64> = response
+        .headers()
+        .get("x-tile-bounds")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .split(',')
+        .map(|v| v.parse().unwrap())
+        .collect();
+    let expected = [0.0, 0.0, 180.0, 85.051_128_779_806_6];
+    for (actual, expected) in bounds.iter().zip(expected) {
+        assert!((actual - expected).abs() < 1e-9, "{bounds:?}");
+    }
+}
+
+#[actix_rt::test]
+async fn mbt_get_out_of_zoom() {
+    let out_of_zoom = |out_of_zoom, path| {
+        let srv = SrvConfig {
+            out_of_zoom,
+            ..Default::default()
+        };
+        call_with_srv_config(srv, path)
+    };
+
+    // m_mvt has tiles up to zoom 6
+    let response = out_of_zoom(None, "/m_mvt/7/0/0").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = out_of_zoom(Some(OutOfZoomResponse::NoContent), "/m_mvt/7/0/0").await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // missing sources are still reported
+    let response = out_of_zoom(Some(OutOfZoomResponse::NoContent), "/missing,m_mvt/7/0/0").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn mbt_get_tile_status_header() {
+    let tile_status = |srv, path| async move {
+        let response = call_with_srv_config(srv, path).await;
+        let status = response.headers().get("x-tile-status").cloned();
+        (response.status(), status)
+    };
+
+    let (status, header) = tile_status(SrvConfig::default(), "/m_mvt/0/0/0").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(header.unwrap(), "ok");
+
+    // m_mvt has no cities in this tile
+    let (status, header) = tile_status(SrvConfig::default(), "/m_mvt/6/0/0").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(header.unwrap(), "empty");
+
+    // m_mvt has tiles up to zoom 6, whatever the configured response
+    let (status, header) = tile_status(SrvConfig::default(), "/m_mvt/7/0/0").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(header.unwrap(), "out-of-range");
+    let srv = SrvConfig {
+        out_of_zoom: Some(OutOfZoomResponse::NoContent),
+        ..Default::default()
+    };
+    let (status, header) = tile_status(srv, "/m_mvt/7/0/0").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(header.unwrap(), "out-of-range");
+
+    // errors are not tile responses
+    let (status, header) = tile_status(SrvConfig::default(), "/missing/0/0/0").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(header.is_none());
+}
+
+#[actix_rt::test]
+async fn mbt_get_catalog_sort() {
+    let app = create_app! { CONFIG };
+
+    for sort in ["id", "name", "schema"] {
+        let req = test_get(&format!("/catalog?sort={sort}")).to_request();
+        let response = assert_response(call_service(&app, req).await).await;
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["tiles"].as_object().unwrap().len(), 4);
+    }
+
+    let req = test_get("/catalog?sort=unknown").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -2,6 +2,7 @@
 
 use actix_http::Request;
 use actix_web::http::StatusCode;
+use actix_web::http::header::CACHE_CONTROL;
 use actix_web::test::{TestRequest, call_and_read_body_json, call_service, read_body};
 use ctor::ctor;
 use indoc::indoc;
@@ -24,9 +25,9 @@ macro_rules! create_app {
         let state = mock_sources(cfg).await.0;
         ::actix_web::test::init_service(
             ::actix_web::App::new()
-                .app_data(actix_web::web::Data::new(
+                .app_data(actix_web::web::Data::new(::martin::srv::ServedCatalog::new(
                     ::martin::srv::Catalog::new(&state).unwrap(),
-                ))
+                )))
                 .app_data(actix_web::web::Data::new(::martin::NO_MAIN_CACHE))
                 .app_data(actix_web::web::Data::new(state.tiles))
                 .app_data(actix_web::web::Data::new(SrvConfig::default()))
@@ -132,6 +133,47 @@ postgres:
     "#);
 }
 
+#[actix_rt::test]
+async fn pg_get_catalog_verbose() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  tables:
+    table_source:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      buffer: 32
+      properties:
+        gid: int4
+  functions:
+    function_zxy_query:
+      schema: public
+      function: function_zxy_query
+" };
+
+    // the summary is the default
+    let req = test_get("/catalog");
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert!(body["tiles"]["table_source"].get("config").is_none());
+
+    let req = test_get("/catalog?verbose=true");
+    let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+    assert_yaml_snapshot!(body["tiles"]["table_source"]["config"], @r"
+    buffer: 32
+    clip_geom: true
+    extent: 4096
+    geometry_type: GEOMETRY
+    prop_mapping:
+      gid: gid
+    srid: 4326
+    ");
+    // function sources have no table configuration
+    assert!(body["tiles"]["function_zxy_query"].get("config").is_none());
+}
+
 #[actix_rt::test]
 async fn pg_get_table_source_ok() {
     let app = create_app! { "
@@ -1096,9 +1138,9 @@ tables:
     let state = mock_sources(cfg.clone()).await.0;
     let app = ::actix_web::test::init_service(
         ::actix_web::App::new()
-            .app_data(actix_web::web::Data::new(
+            .app_data(actix_web::web::Data::new(::martin::srv::ServedCatalog::new(
                 ::martin::srv::Catalog::new(&state).unwrap(),
-            ))
+            )))
             .app_data(actix_web::web::Data::new(::martin::NO_MAIN_CACHE))
             .app_data(actix_web::web::Data::new(state.tiles))
             .app_data(actix_web::web::Data::new(SrvConfig::default()))
@@ -1115,3 +1157,199 @@ tables:
         assert_response(response).await;
     }
 }
+
+#[actix_rt::test]
+async fn pg_get_immutable_tiles() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  tables:
+    table_source:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      properties:
+        gid: int4
+    table_source.v2:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      immutable: true
+      properties:
+        gid: int4
+" };
+
+    let cache_control = |path: &str| {
+        let req = test_get(path);
+        async {
+            call_service(&app, req)
+                .await
+                .headers()
+                .get(CACHE_CONTROL)
+                .cloned()
+        }
+    };
+
+    assert_eq!(
+        cache_control("/table_source.v2/0/0/0").await.unwrap(),
+        "public, max-age=31536000, immutable"
+    );
+    assert!(cache_control("/table_source/0/0/0").await.is_none());
+    // composite sources are only immutable if all of their sources are
+    assert!(
+        cache_control("/table_source,table_source.v2/0/0/0")
+            .await
+            .is_none()
+    );
+}
+
+#[actix_rt::test]
+async fn pg_get_unclipped_tiles() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  tables:
+    table_source:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      properties:
+        gid: int4
+    table_source_debug:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      debug: true
+      properties:
+        gid: int4
+" };
+
+    let get_tile = |path: &str| {
+        let req = test_get(path);
+        async {
+            let response = call_service(&app, req).await;
+            let response = assert_response(response).await;
+            read_body(response).await
+        }
+    };
+
+    // the clip parameter is ignored unless the source is in debug mode
+    assert_eq!(
+        get_tile("/table_source/0/0/0?clip=false").await,
+        get_tile("/table_source/0/0/0").await
+    );
+    assert_eq!(
+        get_tile("/table_source_debug/0/0/0?clip=true").await,
+        get_tile("/table_source_debug/0/0/0").await
+    );
+    assert!(
+        !get_tile("/table_source_debug/0/0/0?clip=false")
+            .await
+            .is_empty()
+    );
+}
+
+#[actix_rt::test]
+async fn pg_get_geometry_only_tiles() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  tables:
+    table_source:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      properties:
+        gid: int4
+    table_source_geom:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      geometry_only: true
+      properties:
+        gid: int4
+" };
+
+    let req = test_get("/table_source_geom");
+    let result: TileJSON = call_and_read_body_json(&app, req).await;
+    let layers = result.vector_layers.unwrap();
+    assert_eq!(layers.len(), 1);
+    assert!(layers[0].fields.is_empty());
+
+    let get_tile = |path: &str| {
+        let req = test_get(path);
+        async {
+            let response = call_service(&app, req).await;
+            let response = assert_response(response).await;
+            read_body(response).await
+        }
+    };
+
+    // the same features without the gid property
+    let full = get_tile("/table_source/0/0/0").await;
+    let geometry_only = get_tile("/table_source_geom/0/0/0").await;
+    assert!(!geometry_only.is_empty());
+    assert!(geometry_only.len() < full.len());
+}
+
+#[actix_rt::test]
+async fn pg_get_min_features_tiles() {
+    let app = create_app! { "
+postgres:
+  connection_string: $DATABASE_URL
+  tables:
+    table_source:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      properties:
+        gid: int4
+    table_source_sparse:
+      schema: public
+      table: table_source
+      srid: 4326
+      geometry_column: geom
+      geometry_type: GEOMETRY
+      min_features_per_tile:
+        count: 1000
+        maxzoom: 3
+      properties:
+        gid: int4
+" };
+
+    let get_tile = |path: &str| {
+        let req = test_get(path);
+        async {
+            let response = call_service(&app, req).await;
+            let status = response.status();
+            (status, read_body(response).await)
+        }
+    };
+
+    // the table has far fewer features than the minimum, so low zoom tiles are skipped
+    let (status, full) = get_tile("/table_source/0/0/0").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!full.is_empty());
+    let (status, sparse) = get_tile("/table_source_sparse/0/0/0").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(sparse.is_empty());
+
+    // tiles above maxzoom are rendered as usual
+    let (status, full) = get_tile("/table_source/4/8/7").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(get_tile("/table_source_sparse/4/8/7").await, (status, full));
+}
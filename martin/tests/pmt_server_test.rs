@@ -1,4 +1,7 @@
-use actix_web::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+};
 use actix_web::test::{TestRequest, call_service, read_body, read_body_json};
 use ctor::ctor;
 use indoc::indoc;
@@ -20,9 +23,9 @@ macro_rules! create_app {
         let state = mock_sources(mock_cfg($sources)).await.0;
         ::actix_web::test::init_service(
             ::actix_web::App::new()
-                .app_data(actix_web::web::Data::new(
+                .app_data(actix_web::web::Data::new(::martin::srv::ServedCatalog::new(
                     ::martin::srv::Catalog::new(&state).unwrap(),
-                ))
+                )))
                 .app_data(actix_web::web::Data::new(::martin::NO_MAIN_CACHE))
                 .app_data(actix_web::web::Data::new(state.tiles))
                 .app_data(actix_web::web::Data::new(SrvConfig::default()))
@@ -118,6 +121,32 @@ async fn pmt_get_raster() {
     assert_eq!(body.len(), 18404);
 }
 
+#[actix_rt::test]
+async fn pmt_get_raster_not_modified() {
+    let app = create_app! { CONFIG };
+    let req = test_get("/p_png/0/0/0").to_request();
+    let response = call_service(&app, req).await;
+    let response = assert_response(response).await;
+    let etag = response.headers().get(ETAG).unwrap().clone();
+
+    let req = test_get("/p_png/0/0/0")
+        .insert_header((IF_NONE_MATCH, etag.clone()))
+        .to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+    assert!(response.headers().get(CONTENT_TYPE).is_none());
+    assert!(read_body(response).await.is_empty());
+
+    // another tile has a different tag, so it is sent in full
+    let req = test_get("/p_png/1/0/0")
+        .insert_header((IF_NONE_MATCH, etag))
+        .to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!read_body(response).await.is_empty());
+}
+
 /// get a raster tile with accepted gzip enc, but should still be non-gzipped
 #[actix_rt::test]
 async fn pmt_get_raster_gzip() {
@@ -131,3 +160,11 @@ async fn pmt_get_raster_gzip() {
     let body = read_body(response).await;
     assert_eq!(body.len(), 18404);
 }
+
+#[actix_rt::test]
+async fn pmt_get_raster_fields_not_implemented() {
+    let app = create_app! { CONFIG };
+    let req = test_get("/p_png/0/0/0?fields=name").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+}
@@ -8,7 +8,9 @@ use serde::{Deserialize, Serialize};
 use sqlite_compressions::{register_bsdiffraw_functions, register_gzip_functions};
 use sqlite_hashes::register_md5_functions;
 use sqlx::sqlite::SqliteConnectOptions;
-use sqlx::{Connection as _, Executor, SqliteConnection, SqliteExecutor, Statement, query};
+use sqlx::{
+    Connection as _, Executor, Row as _, SqliteConnection, SqliteExecutor, Statement, query,
+};
 
 use crate::bindiff::PatchType;
 use crate::errors::{MbtError, MbtResult};
@@ -152,6 +154,22 @@ impl Mbtiles {
         Ok(None)
     }
 
+    /// Get all tiles as `(z, x, y, tile_data)` tuples, with the `y` coordinate in the XYZ scheme
+    pub async fn get_all_tiles<T>(&self, conn: &mut T) -> MbtResult<Vec<(u8, u32, u32, Vec<u8>)>>
+    where
+        for<'e> &'e mut T: SqliteExecutor<'e>,
+    {
+        let sql = "SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles WHERE tile_data IS NOT NULL";
+        let rows = query(sql).fetch_all(conn).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let z: u8 = row.get(0);
+                (z, row.get(1), invert_y_value(z, row.get(2)), row.get(3))
+            })
+            .collect())
+    }
+
     pub async fn insert_tiles(
         &self,
         conn: &mut SqliteConnection,
@@ -27,4 +27,10 @@ impl MbtilesPool {
         let mut conn = self.pool.acquire().await?;
         self.mbtiles.get_tile(&mut *conn, z, x, y).await
     }
+
+    /// Get all tiles as `(z, x, y, tile_data)` tuples, with the `y` coordinate in the XYZ scheme
+    pub async fn get_all_tiles(&self) -> MbtResult<Vec<(u8, u32, u32, Vec<u8>)>> {
+        let mut conn = self.pool.acquire().await?;
+        self.mbtiles.get_all_tiles(&mut *conn).await
+    }
 }